@@ -0,0 +1,10 @@
+-- Hourly roll-up of redirects, used to rank links over a sliding time window
+create table if not exists link_redirects_hourly
+(
+    link_id text not null references links (id) on delete cascade,
+    bucket timestamp not null,
+    count_redirects bigint default 0 not null,
+    primary key (link_id, bucket)
+);
+
+CREATE INDEX IF NOT EXISTS link_redirects_hourly_bucket_idx ON link_redirects_hourly (bucket) ;
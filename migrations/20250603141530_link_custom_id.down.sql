@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE links DROP COLUMN IF EXISTS is_custom_id CASCADE ;
@@ -0,0 +1,2 @@
+-- Track whether a link's ID was provided by the user or generated
+ALTER TABLE links ADD column IF NOT EXISTS is_custom_id boolean DEFAULT false NOT NULL ;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS conversions CASCADE ;
+DROP TABLE IF EXISTS link_clicks CASCADE ;
+ALTER TABLE links DROP COLUMN IF EXISTS track_conversions CASCADE ;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS notify_link_change_trigger ON links ;
+DROP FUNCTION IF EXISTS notify_link_change ;
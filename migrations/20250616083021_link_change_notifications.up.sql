@@ -0,0 +1,3 @@
+-- Notify all instances when a link used for redirects changes, so that cached copies can be invalidated
+CREATE OR REPLACE FUNCTION notify_link_change () RETURNS TRIGGER AS $$ BEGIN PERFORM pg_notify('link_changes', OLD.id); RETURN NULL; END; $$ language 'plpgsql' ;
+CREATE TRIGGER notify_link_change_trigger AFTER UPDATE OF id, target_url, expires_at, track_conversions OR DELETE ON links FOR EACH ROW EXECUTE PROCEDURE notify_link_change () ;
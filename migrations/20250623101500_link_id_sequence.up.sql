@@ -0,0 +1,2 @@
+-- Sequence encoded into IDs by the sequential ID strategy
+CREATE SEQUENCE link_id_seq ;
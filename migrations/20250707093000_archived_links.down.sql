@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS links_expires_at_idx ;
+DROP TABLE IF EXISTS archived_links ;
@@ -0,0 +1,29 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS notify_link_change_trigger ON links ;
+
+ALTER TABLE links
+    ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at SET DEFAULT current_timestamp,
+    ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at SET DEFAULT current_timestamp,
+    ALTER COLUMN expires_at TYPE timestamp USING expires_at AT TIME ZONE 'UTC' ;
+
+CREATE TRIGGER notify_link_change_trigger AFTER UPDATE OF id, target_url, expires_at, track_conversions OR DELETE ON links FOR EACH ROW EXECUTE PROCEDURE notify_link_change () ;
+
+ALTER TABLE link_redirects_hourly
+    ALTER COLUMN bucket TYPE timestamp USING bucket AT TIME ZONE 'UTC' ;
+
+ALTER TABLE link_clicks
+    ALTER COLUMN clicked_at TYPE timestamp USING clicked_at AT TIME ZONE 'UTC',
+    ALTER COLUMN clicked_at SET DEFAULT current_timestamp ;
+
+ALTER TABLE conversions
+    ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at SET DEFAULT current_timestamp ;
+
+ALTER TABLE archived_links
+    ALTER COLUMN created_at TYPE timestamp USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE timestamp USING updated_at AT TIME ZONE 'UTC',
+    ALTER COLUMN expires_at TYPE timestamp USING expires_at AT TIME ZONE 'UTC',
+    ALTER COLUMN archived_at TYPE timestamp USING archived_at AT TIME ZONE 'UTC',
+    ALTER COLUMN archived_at SET DEFAULT now() ;
@@ -0,0 +1,33 @@
+-- Store times with their time zone. Existing times were written in UTC, so
+-- they are converted as such rather than in the session's time zone.
+--
+-- The change notification trigger depends on `expires_at`, so it is recreated
+-- around the change.
+DROP TRIGGER IF EXISTS notify_link_change_trigger ON links ;
+
+ALTER TABLE links
+    ALTER COLUMN created_at TYPE timestamptz USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at SET DEFAULT now(),
+    ALTER COLUMN updated_at TYPE timestamptz USING updated_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at SET DEFAULT now(),
+    ALTER COLUMN expires_at TYPE timestamptz USING expires_at AT TIME ZONE 'UTC' ;
+
+CREATE TRIGGER notify_link_change_trigger AFTER UPDATE OF id, target_url, expires_at, track_conversions OR DELETE ON links FOR EACH ROW EXECUTE PROCEDURE notify_link_change () ;
+
+ALTER TABLE link_redirects_hourly
+    ALTER COLUMN bucket TYPE timestamptz USING bucket AT TIME ZONE 'UTC' ;
+
+ALTER TABLE link_clicks
+    ALTER COLUMN clicked_at TYPE timestamptz USING clicked_at AT TIME ZONE 'UTC',
+    ALTER COLUMN clicked_at SET DEFAULT now() ;
+
+ALTER TABLE conversions
+    ALTER COLUMN created_at TYPE timestamptz USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at SET DEFAULT now() ;
+
+ALTER TABLE archived_links
+    ALTER COLUMN created_at TYPE timestamptz USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE timestamptz USING updated_at AT TIME ZONE 'UTC',
+    ALTER COLUMN expires_at TYPE timestamptz USING expires_at AT TIME ZONE 'UTC',
+    ALTER COLUMN archived_at TYPE timestamptz USING archived_at AT TIME ZONE 'UTC',
+    ALTER COLUMN archived_at SET DEFAULT now() ;
@@ -0,0 +1,5 @@
+DROP TRIGGER IF EXISTS notify_link_change_trigger ON links ;
+ALTER TABLE links DROP COLUMN IF EXISTS next_change_at CASCADE ;
+CREATE TRIGGER notify_link_change_trigger AFTER UPDATE OF id, target_url, expires_at, track_conversions OR DELETE ON links FOR EACH ROW EXECUTE PROCEDURE notify_link_change () ;
+
+DROP TABLE IF EXISTS scheduled_changes ;
@@ -0,0 +1,19 @@
+-- Changes to the target URLs of links, scheduled to be made at a later time
+create table if not exists scheduled_changes
+(
+    id bigserial primary key,
+    link_id text not null references links (id) on delete cascade,
+    target_url text not null,
+    scheduled_at timestamptz not null,
+    created_at timestamptz default now() not null
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_changes_link_id_idx ON scheduled_changes (link_id, scheduled_at) ;
+-- Find due changes to apply without scanning all scheduled changes
+CREATE INDEX IF NOT EXISTS scheduled_changes_scheduled_at_idx ON scheduled_changes (scheduled_at) ;
+
+-- Time of the earliest change scheduled for each link, so that cached copies are only used until then
+ALTER TABLE links ADD column IF NOT EXISTS next_change_at timestamptz ;
+
+DROP TRIGGER IF EXISTS notify_link_change_trigger ON links ;
+CREATE TRIGGER notify_link_change_trigger AFTER UPDATE OF id, target_url, expires_at, track_conversions, next_change_at OR DELETE ON links FOR EACH ROW EXECUTE PROCEDURE notify_link_change () ;
@@ -0,0 +1,2 @@
+ALTER TABLE links DROP COLUMN IF EXISTS legal_hold ;
+ALTER TABLE links DROP COLUMN IF EXISTS is_immutable ;
@@ -0,0 +1,9 @@
+-- Expired links which were purged, when purging deletes rather than archives them
+create table if not exists purged_links
+(
+    id text not null,
+    expires_at timestamptz not null,
+    purged_at timestamptz default now() not null
+);
+
+CREATE INDEX IF NOT EXISTS purged_links_id_idx ON purged_links (id) ;
@@ -0,0 +1,2 @@
+DELETE FROM link_revisions WHERE link_id NOT IN (SELECT id FROM links) ;
+ALTER TABLE link_revisions ADD CONSTRAINT link_revisions_link_id_fkey FOREIGN KEY (link_id) REFERENCES links (id) ON DELETE CASCADE ;
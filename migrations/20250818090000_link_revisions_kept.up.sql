@@ -0,0 +1,2 @@
+-- Revisions are kept when their link is deleted or purged, as a record of what it redirected to
+ALTER TABLE link_revisions DROP CONSTRAINT IF EXISTS link_revisions_link_id_fkey ;
@@ -0,0 +1,2 @@
+ALTER TABLE link_revisions DROP COLUMN IF EXISTS legal_hold ;
+ALTER TABLE link_revisions DROP COLUMN IF EXISTS is_immutable ;
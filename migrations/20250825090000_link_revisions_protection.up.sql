@@ -0,0 +1,10 @@
+-- Revisions also record whether the link was immutable or on legal hold, so that the history shows who changed them
+ALTER TABLE link_revisions ADD column IF NOT EXISTS is_immutable boolean DEFAULT false NOT NULL ;
+ALTER TABLE link_revisions ADD column IF NOT EXISTS legal_hold boolean DEFAULT false NOT NULL ;
+
+-- The latest revision of each link records its current protection
+UPDATE link_revisions r
+SET is_immutable = l.is_immutable, legal_hold = l.legal_hold
+FROM links l
+WHERE l.id = r.link_id
+  AND r.revision = (SELECT max(revision) FROM link_revisions WHERE link_id = r.link_id) ;
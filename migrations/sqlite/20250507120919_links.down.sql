@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER IF EXISTS update_updated_at_trigger ;
+DROP TABLE IF EXISTS links ;
@@ -0,0 +1,11 @@
+create table if not exists links
+(
+    id text not null primary key,
+    target_url text not null,
+    count_redirects integer default 0 not null,
+    created_at timestamp default current_timestamp not null,
+    updated_at timestamp default current_timestamp not null
+);
+
+-- Update `updated_at` automatically, unless it was set by the update itself
+CREATE TRIGGER update_updated_at_trigger AFTER UPDATE ON links FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at BEGIN UPDATE links SET updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = NEW.id ; END ;
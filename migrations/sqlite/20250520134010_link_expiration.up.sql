@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE links ADD column expires_at timestamp DEFAULT null ;
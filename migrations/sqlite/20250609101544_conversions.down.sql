@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS conversions ;
+DROP TABLE IF EXISTS link_clicks ;
+ALTER TABLE links DROP COLUMN track_conversions ;
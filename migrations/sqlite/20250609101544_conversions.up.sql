@@ -0,0 +1,23 @@
+-- Opt-in conversion tracking for links
+ALTER TABLE links ADD column track_conversions boolean DEFAULT false NOT NULL ;
+
+-- Individual redirects from links which track conversions
+create table if not exists link_clicks
+(
+    id blob not null primary key,
+    link_id text not null references links (id) on delete cascade,
+    clicked_at timestamp default current_timestamp not null
+);
+
+CREATE INDEX IF NOT EXISTS link_clicks_link_id_idx ON link_clicks (link_id) ;
+
+-- Goals reached following a click, reported by the target site
+create table if not exists conversions
+(
+    click_id blob not null references link_clicks (id) on delete cascade,
+    goal text not null,
+    created_at timestamp default current_timestamp not null,
+    primary key (click_id, goal)
+);
+
+CREATE INDEX IF NOT EXISTS conversions_goal_idx ON conversions (goal) ;
@@ -0,0 +1,3 @@
+-- Links are only cached by a single instance with SQLite, which invalidates
+-- its cache directly, so there is nothing to notify
+SELECT 1 ;
@@ -0,0 +1,7 @@
+-- Sequence encoded into IDs by the sequential ID strategy
+create table if not exists link_id_seq
+(
+    value integer not null
+);
+
+INSERT INTO link_id_seq (value) VALUES (0) ;
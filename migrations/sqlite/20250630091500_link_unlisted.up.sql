@@ -0,0 +1,2 @@
+-- Unlisted links have long, unguessable IDs and are left out of listings
+ALTER TABLE links ADD column is_unlisted boolean DEFAULT false NOT NULL ;
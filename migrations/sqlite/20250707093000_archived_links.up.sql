@@ -0,0 +1,18 @@
+-- Expired links which were purged, when purging archives rather than deletes them
+create table if not exists archived_links
+(
+    id text not null,
+    target_url text not null,
+    count_redirects integer not null,
+    created_at timestamp not null,
+    updated_at timestamp not null,
+    expires_at timestamp not null,
+    is_custom_id boolean not null,
+    track_conversions boolean not null,
+    is_unlisted boolean not null,
+    archived_at timestamp default current_timestamp not null
+);
+
+CREATE INDEX IF NOT EXISTS archived_links_id_idx ON archived_links (id) ;
+-- Find expired links to purge without scanning all links
+CREATE INDEX IF NOT EXISTS links_expires_at_idx ON links (expires_at) WHERE expires_at IS NOT NULL ;
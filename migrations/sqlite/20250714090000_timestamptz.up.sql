@@ -0,0 +1,2 @@
+-- SQLite has no time zone aware type, so times stay stored as text in UTC
+SELECT 1 ;
@@ -0,0 +1,17 @@
+-- History of the editable fields of links, recording each change and who made it
+create table if not exists link_revisions
+(
+    link_id text not null references links (id) on delete cascade,
+    revision integer not null,
+    target_url text not null,
+    expires_at timestamp,
+    track_conversions boolean not null,
+    changed_at timestamp default current_timestamp not null,
+    changed_by text not null,
+    restored_revision integer,
+    primary key (link_id, revision)
+);
+
+-- Existing links start their history with their current state, made by an unknown actor
+INSERT INTO link_revisions (link_id, revision, target_url, expires_at, track_conversions, changed_at, changed_by)
+SELECT id, 1, target_url, expires_at, track_conversions, updated_at, 'unknown' FROM links ;
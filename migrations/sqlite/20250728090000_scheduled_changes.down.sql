@@ -0,0 +1,2 @@
+ALTER TABLE links DROP COLUMN next_change_at ;
+DROP TABLE IF EXISTS scheduled_changes ;
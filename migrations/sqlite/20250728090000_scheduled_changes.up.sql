@@ -0,0 +1,16 @@
+-- Changes to the target URLs of links, scheduled to be made at a later time
+create table if not exists scheduled_changes
+(
+    id integer primary key,
+    link_id text not null references links (id) on delete cascade,
+    target_url text not null,
+    scheduled_at timestamp not null,
+    created_at timestamp default current_timestamp not null
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_changes_link_id_idx ON scheduled_changes (link_id, scheduled_at) ;
+-- Find due changes to apply without scanning all scheduled changes
+CREATE INDEX IF NOT EXISTS scheduled_changes_scheduled_at_idx ON scheduled_changes (scheduled_at) ;
+
+-- Time of the earliest change scheduled for each link, so that cached copies are only used until then
+ALTER TABLE links ADD column next_change_at timestamp ;
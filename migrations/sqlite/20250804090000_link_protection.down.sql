@@ -0,0 +1,2 @@
+ALTER TABLE links DROP COLUMN legal_hold ;
+ALTER TABLE links DROP COLUMN is_immutable ;
@@ -0,0 +1,3 @@
+-- Immutable links can never change target or expire, and links on legal hold can't be deleted or purged
+ALTER TABLE links ADD column is_immutable boolean DEFAULT false NOT NULL ;
+ALTER TABLE links ADD column legal_hold boolean DEFAULT false NOT NULL ;
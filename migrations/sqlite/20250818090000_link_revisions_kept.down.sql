@@ -0,0 +1,16 @@
+create table if not exists link_revisions_linked
+(
+    link_id text not null references links (id) on delete cascade,
+    revision integer not null,
+    target_url text not null,
+    expires_at timestamp,
+    track_conversions boolean not null,
+    changed_at timestamp default current_timestamp not null,
+    changed_by text not null,
+    restored_revision integer,
+    primary key (link_id, revision)
+);
+
+INSERT INTO link_revisions_linked SELECT * FROM link_revisions WHERE link_id IN (SELECT id FROM links) ;
+DROP TABLE link_revisions ;
+ALTER TABLE link_revisions_linked RENAME TO link_revisions ;
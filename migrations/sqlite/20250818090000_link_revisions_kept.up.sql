@@ -0,0 +1,17 @@
+-- Revisions are kept when their link is deleted or purged, as a record of what it redirected to
+create table if not exists link_revisions_kept
+(
+    link_id text not null,
+    revision integer not null,
+    target_url text not null,
+    expires_at timestamp,
+    track_conversions boolean not null,
+    changed_at timestamp default current_timestamp not null,
+    changed_by text not null,
+    restored_revision integer,
+    primary key (link_id, revision)
+);
+
+INSERT INTO link_revisions_kept SELECT * FROM link_revisions ;
+DROP TABLE link_revisions ;
+ALTER TABLE link_revisions_kept RENAME TO link_revisions ;
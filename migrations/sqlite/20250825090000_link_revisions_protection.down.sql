@@ -0,0 +1,2 @@
+ALTER TABLE link_revisions DROP COLUMN legal_hold ;
+ALTER TABLE link_revisions DROP COLUMN is_immutable ;
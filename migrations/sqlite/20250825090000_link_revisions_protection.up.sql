@@ -0,0 +1,10 @@
+-- Revisions also record whether the link was immutable or on legal hold, so that the history shows who changed them
+ALTER TABLE link_revisions ADD column is_immutable boolean DEFAULT false NOT NULL ;
+ALTER TABLE link_revisions ADD column legal_hold boolean DEFAULT false NOT NULL ;
+
+-- The latest revision of each link records its current protection
+UPDATE link_revisions
+SET is_immutable = (SELECT is_immutable FROM links WHERE id = link_revisions.link_id),
+    legal_hold = (SELECT legal_hold FROM links WHERE id = link_revisions.link_id)
+WHERE link_id IN (SELECT id FROM links)
+  AND revision = (SELECT max(revision) FROM link_revisions r WHERE r.link_id = link_revisions.link_id) ;
@@ -0,0 +1,363 @@
+//! Backups of all the data of an instance, as versioned NDJSON archives.
+//!
+//! An archive starts with a header carrying the schema version of the
+//! instance it was taken from, followed by one
+//! [`BackupRecord`](crate::database::BackupRecord) per line, and ends with a
+//! line counting the records, so that truncated archives are rejected.
+
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
+
+use crate::{database::{Actor, Backup, LinkRevision, LinkStore, schema_version}, error::{Error, Result}, time::deserialize_timestamp};
+
+/// Identifies backup archives, in their header.
+const ARCHIVE_FORMAT: &str = "curto-backup";
+/// Version of the layout of archives, rather than of the records within them.
+const ARCHIVE_VERSION: u32 = 1;
+/// Schema version of the first release with backups.
+///
+/// Archives from this version onwards are upgraded when restored by defaulting
+/// anything added to records since, so fields added to archived records must
+/// have defaults. Archives from newer versions are rejected.
+pub const MIN_BACKUP_SCHEMA_VERSION: i64 = 20250630091500;
+/// Schema version which added link revisions. Links in archives from before
+/// this version start their history with their state in the archive, as
+/// they do when migrating.
+const REVISIONS_SCHEMA_VERSION: i64 = 20250721090000;
+/// Maximum number of records in each chunk of a streamed archive.
+const CHUNK_RECORDS: usize = 500;
+
+/// Header of a backup archive.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveHeader {
+    pub format: String,
+    pub version: u32,
+    /// Version of the latest migration of the instance the backup was taken
+    /// from.
+    pub schema_version: i64,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Lines of an archive other than records.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ArchiveLine {
+    Header(ArchiveHeader),
+    End { records: u64 },
+}
+
+/// Number of rows of each table restored from a backup.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreSummary {
+    pub links: usize,
+    pub redirects: usize,
+    pub clicks: usize,
+    pub conversions: usize,
+    pub revisions: usize,
+    pub scheduled_changes: usize,
+}
+
+fn push_line(buf: &mut String, line: &impl Serialize) -> Result<()> {
+    buf.push_str(&serde_json::to_string(line).map_err(|e| Error::Internal(e.to_string()))?);
+    buf.push('\n');
+
+    Ok(())
+}
+
+/// Stream an archive of all the data in the store, in chunks.
+///
+/// The stream fails if the backup fails part of the way through, leaving the
+/// archive without its end line.
+pub fn backup_stream(store: Arc<dyn LinkStore>) -> impl Stream<Item = Result<Bytes>> {
+    let (sender, receiver) = mpsc::channel(CHUNK_RECORDS);
+    let backup = tokio::spawn(async move { store.backup(sender).await });
+
+    stream::try_unfold(Some((receiver, backup, None)), |state| async move {
+        let Some((mut receiver, backup, count)) = state else {
+            return Ok(None);
+        };
+
+        // The header is sent before the first record
+        let mut chunk = String::new();
+        if count.is_none() {
+            push_line(
+                &mut chunk,
+                &ArchiveLine::Header(ArchiveHeader {
+                    format: ARCHIVE_FORMAT.to_string(),
+                    version: ARCHIVE_VERSION,
+                    schema_version: schema_version(),
+                    created_at: Utc::now(),
+                }),
+            )?;
+        }
+
+        // Wait for the next record, then take any others which are ready
+        let mut n = 0;
+        while n < CHUNK_RECORDS {
+            let record = if n == 0 {
+                receiver.recv().await
+            } else {
+                receiver.try_recv().ok()
+            };
+            let Some(record) = record else {
+                break;
+            };
+            push_line(&mut chunk, &record)?;
+            n += 1;
+        }
+        let count = count.unwrap_or(0u64) + n as u64;
+        if n > 0 {
+            return Ok(Some((
+                Bytes::from(chunk),
+                Some((receiver, backup, Some(count))),
+            )));
+        }
+
+        // Every record was sent, so the backup has finished
+        backup
+            .await
+            .map_err(|e| Error::Internal(e.to_string()))?
+            .inspect_err(|e| tracing::error!("Failed to back up: {e}"))?;
+        push_line(&mut chunk, &ArchiveLine::End { records: count })?;
+
+        Ok(Some((Bytes::from(chunk), None)))
+    })
+}
+
+/// Check that an archive from the given schema version can be restored.
+fn check_schema_version(version: i64) -> Result<()> {
+    let current = schema_version();
+    if !(MIN_BACKUP_SCHEMA_VERSION..=current).contains(&version) {
+        return Err(Error::BackupSchemaNotSupported(version, current));
+    }
+
+    Ok(())
+}
+
+/// Parse a complete archive.
+pub fn parse_backup(data: &str) -> Result<Backup> {
+    let invalid = |line: usize, e: &dyn std::fmt::Display| {
+        Error::InvalidRequest(format!("invalid backup on line {line}: {e}"))
+    };
+    let mut lines = data
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    let header = match lines.next().map(|(_, line)| serde_json::from_str(line)) {
+        Some(Ok(ArchiveLine::Header(header))) if header.format == ARCHIVE_FORMAT => header,
+        _ => {
+            return Err(Error::InvalidRequest(
+                "not a backup, as it doesn't start with a backup header".into(),
+            ));
+        }
+    };
+    if header.version != ARCHIVE_VERSION {
+        return Err(Error::InvalidRequest(format!(
+            "unsupported backup format version {}",
+            header.version
+        )));
+    }
+    check_schema_version(header.schema_version)?;
+
+    let mut backup = Backup::default();
+    let mut records = 0;
+    let mut ended = false;
+    for (number, line) in lines {
+        if ended {
+            return Err(invalid(number, &"data after the end of the backup"));
+        }
+
+        let value: Value = serde_json::from_str(line).map_err(|e| invalid(number, &e))?;
+        match value.get("type").and_then(Value::as_str) {
+            Some("header") => return Err(invalid(number, &"unexpected header")),
+            Some("end") => {
+                let Ok(ArchiveLine::End { records: expected }) = serde_json::from_value(value)
+                else {
+                    return Err(invalid(number, &"malformed end of the backup"));
+                };
+                if expected != records {
+                    return Err(Error::InvalidRequest(format!(
+                        "incomplete backup, with {records} of {expected} records"
+                    )));
+                }
+                ended = true;
+            }
+            _ => {
+                backup.push(serde_json::from_value(value).map_err(|e| invalid(number, &e))?);
+                records += 1;
+            }
+        }
+    }
+
+    if !ended {
+        return Err(Error::InvalidRequest(
+            "incomplete backup, as it has no end".into(),
+        ));
+    }
+
+    if header.schema_version < REVISIONS_SCHEMA_VERSION {
+        backup.revisions = backup
+            .links
+            .iter()
+            .map(|link| LinkRevision::new(link, 1, Actor::Unknown, None))
+            .collect();
+    }
+
+    Ok(backup)
+}
+
+/// Replace all the data in the store with the data of an archive, all at
+/// once. Nothing is changed if the archive is invalid.
+pub async fn restore_backup(store: &dyn LinkStore, data: &str) -> Result<RestoreSummary> {
+    let backup = parse_backup(data)?;
+    store.restore(&backup).await?;
+
+    Ok(RestoreSummary {
+        links: backup.links.len(),
+        redirects: backup.redirects.len(),
+        clicks: backup.clicks.len(),
+        conversions: backup.conversions.len(),
+        revisions: backup.revisions.len(),
+        scheduled_changes: backup.scheduled_changes.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeDelta;
+    use futures_util::TryStreamExt;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::database::{Link, MemoryStore};
+
+    async fn archive(store: Arc<dyn LinkStore>) -> String {
+        let chunks: Vec<Bytes> = backup_stream(store).try_collect().await.unwrap();
+        String::from_utf8(chunks.concat()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backup_round_trip() {
+        let store: Arc<dyn LinkStore> = Arc::new(MemoryStore::default());
+        let link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        store.insert_link(&link, Actor::Admin).await.unwrap();
+        store.increment_link_redirect_count("abc").await.unwrap();
+        let click = store.record_click("abc").await.unwrap();
+        store.record_conversion(click, "signup").await.unwrap();
+        store
+            .schedule_change("abc", "https://docs.rs/", Utc::now() + TimeDelta::days(1))
+            .await
+            .unwrap();
+        store.next_sequence_number().await.unwrap();
+
+        let data = archive(store.clone()).await;
+        let lines: Vec<_> = data.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert!(lines[0].contains(r#""type":"header""#));
+        assert_eq!(lines[8], r#"{"type":"end","records":7}"#);
+
+        let restored: Arc<dyn LinkStore> = Arc::new(MemoryStore::default());
+        let summary = restore_backup(restored.as_ref(), &data).await.unwrap();
+        assert_eq!(
+            summary,
+            RestoreSummary {
+                links: 1,
+                redirects: 1,
+                clicks: 1,
+                conversions: 1,
+                revisions: 1,
+                scheduled_changes: 1,
+            }
+        );
+        assert_eq!(
+            archive(restored.clone())
+                .await
+                .lines()
+                .skip(1)
+                .collect::<Vec<_>>(),
+            lines[1..]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_backups() {
+        let header = |schema_version: i64| {
+            serde_json::to_string(&ArchiveLine::Header(ArchiveHeader {
+                format: ARCHIVE_FORMAT.into(),
+                version: ARCHIVE_VERSION,
+                schema_version,
+                created_at: Utc::now(),
+            }))
+            .unwrap()
+        };
+        let end = |records: u64| serde_json::to_string(&ArchiveLine::End { records }).unwrap();
+        let link = r#"{"type":"link","id":"abc","targetUrl":"https://crates.io/","countRedirects":0,"createdAt":"2025-01-01T00:00:00","updatedAt":"2025-01-01T00:00:00","expiresAt":null,"isCustomId":true,"trackConversions":false,"isUnlisted":false}"#;
+
+        let valid = format!("{}\n{link}\n{}\n", header(schema_version()), end(1));
+        let backup = parse_backup(&valid).unwrap();
+        assert_eq!(backup.links.len(), 1);
+        assert!(backup.revisions.is_empty());
+
+        // Links from before revisions were recorded start their history
+        let old = format!(
+            "{}\n{link}\n{}\n",
+            header(MIN_BACKUP_SCHEMA_VERSION),
+            end(1)
+        );
+        let revisions = parse_backup(&old).unwrap().revisions;
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].revision, 1);
+        assert_eq!(revisions[0].changed_by, Actor::Unknown);
+
+        for (data, error) in [
+            ("".to_string(), "doesn't start with a backup header"),
+            (link.to_string(), "doesn't start with a backup header"),
+            (
+                format!("{}\n{link}\n", header(schema_version())),
+                "has no end",
+            ),
+            (
+                format!("{}\n{link}\n{}\n", header(schema_version()), end(2)),
+                "with 1 of 2 records",
+            ),
+            (
+                format!(
+                    "{}\n{{\"type\":\"nope\"}}\n{}\n",
+                    header(schema_version()),
+                    end(1)
+                ),
+                "line 2",
+            ),
+            (
+                format!("{}\n{}\n{link}\n", header(schema_version()), end(0)),
+                "after the end",
+            ),
+        ] {
+            let result = parse_backup(&data);
+            assert!(
+                matches!(&result, Err(Error::InvalidRequest(e)) if e.contains(error)),
+                "{error}: {result:?}"
+            );
+        }
+
+        for schema_version in [MIN_BACKUP_SCHEMA_VERSION - 1, schema_version() + 1] {
+            let data = format!("{}\n{}\n", header(schema_version), end(0));
+            assert!(matches!(
+                parse_backup(&data),
+                Err(Error::BackupSchemaNotSupported(v, _)) if v == schema_version
+            ));
+        }
+    }
+}
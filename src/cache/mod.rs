@@ -0,0 +1,358 @@
+mod shared;
+
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use axum_prometheus::metrics::counter;
+use chrono::Utc;
+use moka::{future::Cache, notification::RemovalCause};
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+pub use self::shared::{SHARED_CACHE_SCHEMES, SharedCache};
+use crate::{config::CacheConfig, database::{CircuitBreaker, Link, LinkChange, LinkStore}, error::StartupError};
+
+/// Bounded in-process cache of links, used to serve redirects without querying
+/// the database.
+///
+/// Redirect counts for cached links are buffered, and periodically written to
+/// the database in a single batch.
+///
+/// The last known version of each link is kept for longer, to keep serving
+/// redirects while the database is unavailable.
+///
+/// Links missing from the in-process cache are looked up in the
+/// [`SharedCache`], if configured, before falling back to the database.
+/// Links which another instance changed are looked up in the database first,
+/// as the shared cache may not have the change yet.
+#[derive(Debug, Clone)]
+pub struct LinkCache {
+    links: Cache<String, Arc<Link>>,
+    last_known: Cache<String, Arc<Link>>,
+    /// IDs of links which changed since this instance last looked them up in
+    /// the database, kept for as long as the shared cache keeps links.
+    changed: Cache<String, ()>,
+    redirect_counts: Arc<Mutex<HashMap<String, i64>>>,
+    shared: Option<SharedCache>,
+}
+
+impl LinkCache {
+    /// Create a new cache, returning [`None`] if caching is disabled.
+    pub fn new(config: &CacheConfig) -> Result<Option<Self>, StartupError> {
+        let shared = SharedCache::new(config)?;
+        if config.capacity == 0 && shared.is_none() {
+            return Ok(None);
+        }
+
+        let links = Cache::builder()
+            .max_capacity(config.capacity)
+            .time_to_live(Duration::from_secs(config.ttlseconds))
+            .eviction_listener(|_, _, cause| match cause {
+                RemovalCause::Size => counter!("cache.evictions", "cause" => "size").increment(1),
+                RemovalCause::Expired => {
+                    counter!("cache.evictions", "cause" => "expired").increment(1)
+                }
+                RemovalCause::Explicit => counter!("cache.invalidations").increment(1),
+                RemovalCause::Replaced => {}
+            })
+            .build();
+
+        // Only bounded by size, and only invalidated when links change
+        let last_known = Cache::new(config.capacity);
+        let changed = Cache::builder()
+            .time_to_live(Duration::from_secs(config.redisttlseconds.max(1)))
+            .build();
+
+        Ok(Some(Self {
+            links,
+            last_known,
+            changed,
+            redirect_counts: Default::default(),
+            shared,
+        }))
+    }
+
+    /// Get a cached link which has not expired, and has no scheduled change
+    /// which is due.
+    pub async fn get(&self, link_id: &str) -> Option<Arc<Link>> {
+        let link = self
+            .links
+            .get(link_id)
+            .await
+            .filter(|l| l.valid_until().is_none_or(|t| t > Utc::now()));
+
+        if link.is_some() {
+            counter!("cache.hits").increment(1);
+            return link;
+        }
+        counter!("cache.misses").increment(1);
+
+        if self.changed.contains_key(link_id) {
+            return None;
+        }
+        let link = Arc::new(self.shared.as_ref()?.get(link_id).await?);
+        self.insert_local(link.clone()).await;
+        Some(link)
+    }
+
+    /// Get the last known version of a link which has not expired or been
+    /// changed by a scheduled change, even if it is no longer fresh enough to
+    /// be returned by [`LinkCache::get`].
+    pub async fn get_stale(&self, link_id: &str) -> Option<Arc<Link>> {
+        let link = self
+            .last_known
+            .get(link_id)
+            .await
+            .filter(|l| l.valid_until().is_none_or(|t| t > Utc::now()));
+
+        if link.is_some() {
+            counter!("cache.stale_hits").increment(1);
+        }
+
+        link
+    }
+
+    /// Cache a link from the database, writing it through to the shared
+    /// cache.
+    pub async fn insert(&self, link: Link) -> Arc<Link> {
+        let link = Arc::new(link);
+        if let Some(shared) = self.shared.as_ref() {
+            shared.set(&link).await;
+        }
+        self.changed.invalidate(&link.id).await;
+        self.insert_local(link.clone()).await;
+        link
+    }
+
+    async fn insert_local(&self, link: Arc<Link>) {
+        self.links.insert(link.id.clone(), link.clone()).await;
+        self.last_known.insert(link.id.clone(), link).await;
+    }
+
+    /// Remove a deleted link, including from the shared cache.
+    pub async fn remove(&self, link_id: &str) {
+        if let Some(shared) = self.shared.as_ref() {
+            shared.delete(link_id).await;
+        }
+        self.invalidate(link_id).await;
+    }
+
+    /// Invalidate a link in this instance's cache only.
+    ///
+    /// The shared cache is kept up to date by writing through changes instead,
+    /// as every instance invalidates links whenever they change.
+    pub async fn invalidate(&self, link_id: &str) {
+        self.links.invalidate(link_id).await;
+        self.last_known.invalidate(link_id).await;
+    }
+
+    /// Invalidate a link which was changed by any instance, so that it is next
+    /// looked up in the database rather than in the shared cache, which may
+    /// not have the change yet.
+    async fn link_changed(&self, link_id: &str) {
+        if self.shared.is_some() {
+            self.changed.insert(link_id.to_string(), ()).await;
+        }
+        self.invalidate(link_id).await;
+    }
+
+    /// Invalidate all fresh links, keeping the last known versions.
+    pub fn invalidate_all(&self) {
+        self.links.invalidate_all();
+    }
+
+    /// Remove all links, including their last known versions and from the
+    /// shared cache, e.g. after a backup is restored.
+    pub async fn clear(&self) {
+        self.links.invalidate_all();
+        self.last_known.invalidate_all();
+        if let Some(shared) = self.shared.as_ref() {
+            shared.clear().await;
+        }
+    }
+
+    /// Buffer a redirect from a cached link, to be written to the database on
+    /// the next flush.
+    pub fn add_redirect(&self, link_id: &str) {
+        *self
+            .redirect_counts
+            .lock()
+            .expect("redirect counts lock poisoned")
+            .entry(link_id.to_string())
+            .or_default() += 1;
+    }
+
+    /// Write buffered redirect counts to the database. If this fails, the
+    /// counts are kept for the next flush.
+    pub async fn flush_redirect_counts(&self, store: &dyn LinkStore, breaker: &CircuitBreaker) {
+        let counts = std::mem::take(
+            &mut *self
+                .redirect_counts
+                .lock()
+                .expect("redirect counts lock poisoned"),
+        );
+        if counts.is_empty() {
+            return;
+        }
+
+        if breaker
+            .call(store.add_link_redirect_counts(&counts))
+            .await
+            .is_err()
+        {
+            let mut buffered = self
+                .redirect_counts
+                .lock()
+                .expect("redirect counts lock poisoned");
+            for (id, n) in counts {
+                *buffered.entry(id).or_default() += n;
+            }
+        }
+    }
+
+    /// Spawn tasks which periodically flush buffered redirect counts, and
+    /// invalidate cached links whenever they are changed by any instance.
+    pub fn spawn_tasks(
+        &self,
+        store: Arc<dyn LinkStore>,
+        breaker: CircuitBreaker,
+        link_changes: Receiver<LinkChange>,
+        flush_interval: Duration,
+    ) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            while !store.is_closed() {
+                interval.tick().await;
+                cache.flush_redirect_counts(store.as_ref(), &breaker).await;
+            }
+
+            // Counts buffered since the last flush would otherwise be lost
+            cache.flush_redirect_counts(store.as_ref(), &breaker).await;
+        });
+
+        let cache = self.clone();
+        tokio::spawn(async move {
+            let mut link_changes = link_changes;
+            loop {
+                match link_changes.recv().await {
+                    Ok(LinkChange::Changed(id)) => cache.link_changed(&id).await,
+                    Ok(LinkChange::Unknown) | Err(RecvError::Lagged(_)) => cache.invalidate_all(),
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn get_cache() -> LinkCache {
+        LinkCache::new(&CacheConfig::default()).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_cache_disabled() {
+        assert!(
+            LinkCache::new(&CacheConfig {
+                capacity: 0,
+                ..Default::default()
+            })
+            .unwrap()
+            .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cache_get() {
+        let cache = get_cache();
+        assert!(cache.get("abc").await.is_none());
+
+        cache
+            .insert(Link::new(Some("abc".into()), "https://crates.io".into()))
+            .await;
+        assert_eq!(
+            cache.get("abc").await.unwrap().target_url,
+            "https://crates.io"
+        );
+
+        cache.invalidate("abc").await;
+        assert!(cache.get("abc").await.is_none());
+
+        cache
+            .insert(Link::new(Some("abc".into()), "https://crates.io".into()))
+            .await;
+        cache.invalidate_all();
+        assert!(cache.get("abc").await.is_none());
+
+        // The last known version is kept until the link itself changes
+        assert_eq!(
+            cache.get_stale("abc").await.unwrap().target_url,
+            "https://crates.io"
+        );
+        cache.invalidate("abc").await;
+        assert!(cache.get_stale("abc").await.is_none());
+
+        cache
+            .insert(Link::new(Some("abc".into()), "https://crates.io".into()))
+            .await;
+        cache.clear().await;
+        assert!(cache.get("abc").await.is_none());
+        assert!(cache.get_stale("abc").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_get_expired() {
+        let cache = get_cache();
+
+        cache
+            .insert(Link {
+                expires_at: Some(Utc::now() - chrono::Duration::seconds(1)),
+                ..Link::new(Some("abc".into()), "https://crates.io".into())
+            })
+            .await;
+        assert!(cache.get("abc").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cache_link_changed() {
+        // Nothing should be listening on this port
+        let cache = LinkCache::new(&CacheConfig {
+            redisurl: Some("redis://127.0.0.1:1".parse().unwrap()),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        cache
+            .insert(Link::new(Some("abc".into()), "https://crates.io".into()))
+            .await;
+
+        // Changed links are looked up in the database before the shared cache
+        cache.link_changed("abc").await;
+        assert!(cache.get("abc").await.is_none());
+        assert!(cache.changed.contains_key("abc"));
+        cache
+            .insert(Link::new(Some("abc".into()), "https://docs.rs".into()))
+            .await;
+        assert!(!cache.changed.contains_key("abc"));
+
+        // Without a shared cache, there is nothing to bypass
+        let cache = get_cache();
+        cache.link_changed("abc").await;
+        assert!(!cache.changed.contains_key("abc"));
+    }
+
+    #[test]
+    fn test_add_redirect() {
+        let cache = get_cache();
+        cache.add_redirect("abc");
+        cache.add_redirect("abc");
+        cache.add_redirect("xyz");
+
+        let counts = cache.redirect_counts.lock().unwrap();
+        assert_eq!(counts.get("abc"), Some(&2));
+        assert_eq!(counts.get("xyz"), Some(&1));
+    }
+}
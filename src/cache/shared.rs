@@ -0,0 +1,302 @@
+use std::{fmt, future::Future, sync::{Arc, LazyLock, Mutex}, time::{Duration, Instant}};
+
+use axum_prometheus::metrics::counter;
+use chrono::Utc;
+use redis::{AsyncCommands, Client, RedisResult, Script, aio::{ConnectionManager, ConnectionManagerConfig}};
+use tokio::sync::OnceCell;
+
+use crate::{config::CacheConfig, database::Link, error::StartupError};
+
+/// URL schemes of servers which the shared cache can connect to. TLS isn't
+/// supported.
+pub const SHARED_CACHE_SCHEMES: [&str; 5] =
+    ["redis", "valkey", "redis+unix", "valkey+unix", "unix"];
+/// Prefix of the keys which links are stored under.
+const KEY_PREFIX: &str = "curto:link:";
+/// Number of keys looked at by each step when clearing the shared cache.
+const CLEAR_BATCH_SIZE: usize = 1000;
+/// Store a link unless a newer version of it is already stored, comparing the
+/// versions which prefix the values.
+static SET_IF_NEWER: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r#"
+            local current = redis.call('GET', KEYS[1])
+            local version = current and tonumber(string.match(current, '^(%-?%d+):'))
+            if version and version > tonumber(ARGV[1]) then
+                return 0
+            end
+            redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+            return 1
+        "#,
+    )
+});
+
+/// Cache of links shared between all instances, stored in a server speaking
+/// the Redis protocol.
+///
+/// The shared cache is best-effort: if it is unavailable, it is bypassed for a
+/// while and links are looked up in the database instead.
+///
+/// Links are stored along with the time they were last updated, so that older
+/// versions, e.g. looked up just before a change, never replace newer ones.
+#[derive(Clone)]
+pub struct SharedCache {
+    client: Client,
+    /// Connected lazily, so that the shared cache being unavailable doesn't
+    /// prevent startup.
+    connection: Arc<OnceCell<ConnectionManager>>,
+    ttl: Duration,
+    timeout: Duration,
+    /// How long the shared cache is bypassed for after a failed request.
+    retry_interval: Duration,
+    unavailable_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl fmt::Debug for SharedCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCache")
+            .field("ttl", &self.ttl)
+            .field("timeout", &self.timeout)
+            .field("retry_interval", &self.retry_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedCache {
+    /// Create a new shared cache, returning [`None`] if it is not configured.
+    pub fn new(config: &CacheConfig) -> Result<Option<Self>, StartupError> {
+        let Some(url) = config.redisurl.as_ref() else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            client: Client::open(url.as_str()).map_err(StartupError::InvalidSharedCacheUrl)?,
+            connection: Default::default(),
+            ttl: Duration::from_secs(config.redisttlseconds.max(1)),
+            timeout: Duration::from_millis(config.redistimeoutms),
+            retry_interval: Duration::from_secs(config.redisretryseconds),
+            unavailable_until: Default::default(),
+        }))
+    }
+
+    /// Get a cached link which has not expired, and has no scheduled change
+    /// which is due.
+    pub async fn get(&self, link_id: &str) -> Option<Link> {
+        let value: Option<String> = self
+            .run(|mut conn| async move { conn.get(key(link_id)).await })
+            .await
+            .flatten();
+
+        let link = value
+            .as_deref()
+            .and_then(decode)
+            .filter(|l| l.valid_until().is_none_or(|t| t > Utc::now()));
+
+        if link.is_some() {
+            counter!("cache.shared_hits").increment(1);
+        } else {
+            counter!("cache.shared_misses").increment(1);
+        }
+
+        link
+    }
+
+    /// Store a link, for at most the configured TTL and never past its
+    /// expiration time or its next scheduled change, unless a newer version of
+    /// it is already stored.
+    pub async fn set(&self, link: &Link) {
+        let ttl = match link.valid_until() {
+            Some(valid_until) => match (valid_until - Utc::now()).to_std() {
+                Ok(remaining) => remaining.min(self.ttl),
+                // Already expired or changed
+                Err(_) => return,
+            },
+            None => self.ttl,
+        };
+
+        let value = match encode(link) {
+            Ok(value) => value,
+            Err(e) => return tracing::error!("Failed to serialise link {}: {e}", link.id),
+        };
+
+        self.run(|mut conn| async move {
+            SET_IF_NEWER
+                .key(key(&link.id))
+                .arg(version(link))
+                .arg(value)
+                .arg(ttl.as_secs().max(1))
+                .invoke_async::<()>(&mut conn)
+                .await
+        })
+        .await;
+    }
+
+    pub async fn delete(&self, link_id: &str) {
+        self.run(|mut conn| async move { conn.del::<_, ()>(key(link_id)).await })
+            .await;
+    }
+
+    /// Remove all links, e.g. after a backup is restored. Links cached by other
+    /// instances while this runs may be kept.
+    pub async fn clear(&self) {
+        let mut cursor = 0;
+        loop {
+            let Some((next, keys)) = self
+                .run(|mut conn| async move {
+                    redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(format!("{KEY_PREFIX}*"))
+                        .arg("COUNT")
+                        .arg(CLEAR_BATCH_SIZE)
+                        .query_async::<(u64, Vec<String>)>(&mut conn)
+                        .await
+                })
+                .await
+            else {
+                return tracing::error!("Failed to clear the shared cache");
+            };
+
+            if !keys.is_empty() {
+                self.run(|mut conn| async move { conn.del::<_, ()>(keys).await })
+                    .await;
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    }
+
+    /// Run a command, returning [`None`] if the shared cache is unavailable.
+    async fn run<T, F>(&self, command: impl FnOnce(ConnectionManager) -> F) -> Option<T>
+    where
+        F: Future<Output = RedisResult<T>>,
+    {
+        if self
+            .unavailable_until
+            .lock()
+            .expect("shared cache lock poisoned")
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return None;
+        }
+
+        let result = tokio::time::timeout(self.timeout, async {
+            let connection = self
+                .connection
+                .get_or_try_init(|| {
+                    ConnectionManager::new_with_config(
+                        self.client.clone(),
+                        ConnectionManagerConfig::new()
+                            .set_connection_timeout(self.timeout)
+                            .set_response_timeout(self.timeout)
+                            .set_number_of_retries(1),
+                    )
+                })
+                .await?;
+
+            command(connection.clone()).await
+        })
+        .await;
+
+        let error = match result {
+            Ok(Ok(value)) => return Some(value),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "request timed out".to_string(),
+        };
+
+        counter!("cache.shared_errors").increment(1);
+        tracing::warn!(
+            "Shared cache unavailable, bypassing it for {:?}: {error}",
+            self.retry_interval
+        );
+        *self
+            .unavailable_until
+            .lock()
+            .expect("shared cache lock poisoned") = Some(Instant::now() + self.retry_interval);
+
+        None
+    }
+}
+
+fn key(link_id: &str) -> String {
+    format!("{KEY_PREFIX}{link_id}")
+}
+
+/// Version of a link, increasing whenever the link changes.
+fn version(link: &Link) -> i64 {
+    link.updated_at.timestamp_micros()
+}
+
+/// Serialise a link, prefixed with its version.
+fn encode(link: &Link) -> serde_json::Result<String> {
+    Ok(format!(
+        "{}:{}",
+        version(link),
+        serde_json::to_string(link)?
+    ))
+}
+
+/// Deserialise a link stored by [`encode`]. Links stored without a version
+/// are ignored, so that they are replaced.
+fn decode(value: &str) -> Option<Link> {
+    let (version, json) = value.split_once(':')?;
+    version.parse::<i64>().ok()?;
+    serde_json::from_str(json)
+        .inspect_err(|e| tracing::error!("Malformed link in shared cache: {e}"))
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shared_cache_unavailable() {
+        // Nothing should be listening on this port
+        let cache = SharedCache::new(&CacheConfig {
+            redisurl: Some("redis://127.0.0.1:1".parse().unwrap()),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+
+        cache
+            .set(&Link::new(Some("abc".into()), "https://crates.io".into()))
+            .await;
+        assert!(cache.get("abc").await.is_none());
+
+        // Bypassed after the first failure
+        assert!(
+            cache
+                .unavailable_until
+                .lock()
+                .unwrap()
+                .is_some_and(|until| Instant::now() < until)
+        );
+    }
+
+    #[test]
+    fn test_shared_cache_encoding() {
+        let link = Link::new(Some("abc".into()), "https://crates.io".into());
+        let value = encode(&link).unwrap();
+        assert!(value.starts_with(&format!("{}:{{", link.updated_at.timestamp_micros())));
+        assert_eq!(decode(&value).unwrap().updated_at, link.updated_at);
+
+        // Links stored without a version are ignored
+        assert!(decode(&serde_json::to_string(&link).unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_shared_cache_invalid_url() {
+        let result = SharedCache::new(&CacheConfig {
+            redisurl: Some("http://127.0.0.1:6379".parse().unwrap()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            result,
+            Err(StartupError::InvalidSharedCacheUrl(_))
+        ));
+    }
+}
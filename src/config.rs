@@ -1,20 +1,73 @@
-use serde::{Deserialize, Deserializer, de::Error};
+use std::path::PathBuf;
+
+use axum::http::HeaderValue;
+use config::{ConfigError, Environment, File, FileFormat, Map};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
 use url::Url;
 
+use crate::{cache::SHARED_CACHE_SCHEMES, database::{IdStrategy, MEMORY_SCHEME, SQLITE_SCHEME}, purge::PurgeMode, redirect_map::RedirectMapFormat};
+
+/// Configuration file which is read if it exists, unless another file is
+/// given.
+const DEFAULT_CONFIG_FILE: &str = "curto.toml";
+/// Replaces secrets when printing the configuration.
+const REDACTED: &str = "redacted";
+
 /// Configuration options for the application.
-#[derive(Debug, Clone, serde::Deserialize)]
+///
+/// Options are read from a TOML configuration file, then environment
+/// variables, then command line flags, with later sources taking precedence.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
+    #[serde(default)]
     pub application: AppConfig,
     pub database: DbConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub redirectmap: RedirectMapConfig,
+    #[serde(default)]
+    pub purge: PurgeConfig,
+}
+
+/// Command line flags for configuring the application.
+#[derive(Debug, Default, Clone, clap::Args)]
+pub struct ConfigArgs {
+    /// Path to a TOML configuration file [default: curto.toml, if it exists]
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// The network host that the application should run on
+    #[arg(long)]
+    pub host: Option<String>,
+    /// The network port that the application should run on
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// The fully qualified URL used to connect to the PostgreSQL database
+    #[arg(long)]
+    pub database_url: Option<String>,
+    /// Set any configuration option, e.g. `--set database.maxconnections=50`
+    #[arg(short = 's', long = "set", value_name = "KEY=VALUE", value_parser = parse_override)]
+    pub overrides: Vec<(String, String)>,
+}
+
+fn parse_override(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{s}'"))
 }
 
 /// Configuration options specific to the main application.
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct AppConfig {
     /// The network host that the application should run on.
     ///
     /// The default is `[0,0,0,0]`
-    #[serde(deserialize_with = "deserialize_host")]
+    #[serde(
+        deserialize_with = "deserialize_host",
+        serialize_with = "serialize_host"
+    )]
     pub host: [u8; 4],
     /// The network port that the application should run on.
     ///
@@ -24,6 +77,92 @@ pub struct AppConfig {
     ///
     /// Rate-limiting should only be disabled for testing.
     pub shouldratelimit: bool,
+    /// Whether to identify clients by the `X-Real-IP` header, or otherwise
+    /// the rightmost `X-Forwarded-For` entry, rather than the address they
+    /// connect from. Only enable this behind a reverse proxy which sets these
+    /// headers, as clients can otherwise spoof them.
+    ///
+    /// The default is false.
+    pub trustproxyheaders: bool,
+    /// Token required as a bearer token in the `Authorization` header to
+    /// access admin-only routes.
+    ///
+    /// Admin-only routes are inaccessible if this is not set.
+    pub admintoken: Option<String>,
+    /// The number of requests each client can make in a burst, when
+    /// rate-limiting is enabled.
+    ///
+    /// The default is 10.
+    pub ratelimitburst: u32,
+    /// How often, in milliseconds, each client regains a request, when
+    /// rate-limiting is enabled.
+    ///
+    /// The default is 200.
+    pub ratelimitperiodms: u64,
+    /// The number of seconds after which requests time out.
+    ///
+    /// The default is 8.
+    pub requesttimeoutseconds: u64,
+    /// The maximum size of request bodies, in bytes.
+    ///
+    /// The default is 100000.
+    pub maxbodybytes: usize,
+    /// The number of seconds browsers may cache CORS preflight responses for.
+    ///
+    /// The default is 3600.
+    pub corsmaxageseconds: u64,
+    /// The `Cache-Control` header of redirects which don't carry a click ID.
+    ///
+    /// The default is `public, max-age=300, s-maxage=300,
+    /// stale-while-revalidate=300, stale-if-error=300`.
+    pub redirectcachecontrol: String,
+    /// How IDs of new links are generated, unless chosen when creating a link.
+    /// One of `random`, `sequential`, `hash` or `words`.
+    ///
+    /// The default is `random`.
+    pub idstrategy: IdStrategy,
+    /// Secret used to scramble generated link IDs, so that they can't be
+    /// mapped back to the numbers they encode. Must be the same for all
+    /// instances, and should not be changed once links are created.
+    ///
+    /// A random secret is used if this is not set, in which case sequential
+    /// IDs and IDs derived from the target URL differ between instances and
+    /// restarts.
+    pub idseed: Option<String>,
+    /// The number of redirects to links which could not be found that each
+    /// client can make within [`Self::enumerationwindowseconds`], before its
+    /// redirects are refused. Protects against clients guessing link IDs.
+    ///
+    /// The default is 20. Throttling is disabled if this is 0.
+    pub enumerationlimit: u32,
+    /// The number of seconds over which redirects to links which could not be
+    /// found are counted, and for which clients are throttled.
+    ///
+    /// The default is 60.
+    pub enumerationwindowseconds: u64,
+    /// How often, in seconds, the utilisation of the link ID keyspace is
+    /// refreshed, to grow generated IDs as it fills up.
+    ///
+    /// The default is 60.
+    pub keyspacerefreshseconds: u64,
+    /// The number of events buffered for subscribers which haven't received
+    /// them yet, and the number of redirect events waiting to be published,
+    /// beyond which events are dropped.
+    ///
+    /// The default is 1024.
+    pub eventbuffercapacity: usize,
+    /// How often, in milliseconds, queued redirect events are published to
+    /// all instances, together.
+    ///
+    /// The default is 100.
+    pub eventpublishintervalms: u64,
+    /// How often, in seconds, scheduled changes to links which are due are
+    /// made in the background. Redirects and lookups of links make due
+    /// changes themselves, so this only affects how soon changes show up
+    /// elsewhere, e.g. in listings of links.
+    ///
+    /// The default is 10.
+    pub scheduleintervalseconds: u64,
 }
 
 impl Default for AppConfig {
@@ -32,37 +171,511 @@ impl Default for AppConfig {
             host: [0, 0, 0, 0],
             port: 7229,
             shouldratelimit: true,
+            trustproxyheaders: false,
+            admintoken: None,
+            ratelimitburst: 10,
+            ratelimitperiodms: 200,
+            requesttimeoutseconds: 8,
+            maxbodybytes: 100_000,
+            corsmaxageseconds: 3600,
+            redirectcachecontrol:
+                "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300"
+                    .into(),
+            idstrategy: IdStrategy::default(),
+            idseed: None,
+            enumerationlimit: 20,
+            enumerationwindowseconds: 60,
+            keyspacerefreshseconds: 60,
+            eventbuffercapacity: 1024,
+            eventpublishintervalms: 100,
+            scheduleintervalseconds: 10,
         }
     }
 }
 
 /// Configuration options specific to the database.
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DbConfig {
     /// The fully qualified URL used to connect to the PostgreSQL database.
+    ///
+    /// Use a `sqlite://` URL (e.g. `sqlite://curto.db`) to store links in a
+    /// SQLite database file instead, or `memory://` to keep links in memory,
+    /// where they are lost when the application stops. Both are only suitable
+    /// for a single instance.
     pub url: Url,
     /// Option to require SSL mode for the database.
+    #[serde(default)]
     pub requiressl: bool,
+    /// The minimum number of connections kept open to the database.
+    ///
+    /// The default is 2.
+    #[serde(default = "default_min_connections")]
+    pub minconnections: u32,
+    /// The maximum number of connections opened to the database, and to each
+    /// read replica.
+    ///
+    /// The default is 20.
+    #[serde(default = "default_max_connections")]
+    pub maxconnections: u32,
+    /// The number of milliseconds after which database queries time out.
+    ///
+    /// The default is 400.
+    #[serde(default = "default_db_timeout_ms")]
+    pub timeoutms: u64,
+    /// The number of consecutive failed database requests after which
+    /// requests fail fast, instead of waiting on the database.
+    ///
+    /// The default is 5.
+    #[serde(default = "default_breaker_threshold")]
+    pub breakerthreshold: u32,
+    /// The number of seconds requests fail fast for, before the database is
+    /// tried again.
+    ///
+    /// The default is 10.
+    #[serde(default = "default_breaker_open_seconds")]
+    pub breakeropenseconds: u64,
+    /// URLs of read replicas of the database, used for read-only queries. Can
+    /// be provided as a comma-separated list.
+    ///
+    /// Only the primary database is used if this is empty.
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    pub replicaurls: Vec<Url>,
+    /// How often, in seconds, the health of each read replica is checked.
+    ///
+    /// The default is 5.
+    #[serde(default = "default_replica_health_check_seconds")]
+    pub replicahealthcheckseconds: u64,
+    /// The number of seconds to keep trying to connect to the database at
+    /// startup, after which the application exits.
+    ///
+    /// The default is 60.
+    #[serde(default = "default_startup_timeout_seconds")]
+    pub startuptimeoutseconds: u64,
+}
+
+fn default_min_connections() -> u32 {
+    2
+}
+
+fn default_max_connections() -> u32 {
+    20
+}
+
+fn default_db_timeout_ms() -> u64 {
+    400
+}
+
+fn default_breaker_threshold() -> u32 {
+    5
+}
+
+fn default_breaker_open_seconds() -> u64 {
+    10
+}
+
+fn default_replica_health_check_seconds() -> u64 {
+    5
+}
+
+fn default_startup_timeout_seconds() -> u64 {
+    60
+}
+
+/// Configuration options specific to the in-process cache of links used for
+/// redirects.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// The maximum number of links kept in the cache.
+    ///
+    /// The default is 10000. Caching is disabled if this is 0.
+    pub capacity: u64,
+    /// The number of seconds a link is kept in the cache for.
+    ///
+    /// The default is 60.
+    pub ttlseconds: u64,
+    /// How often, in milliseconds, the redirect counts of cached links are
+    /// written to the database.
+    ///
+    /// The default is 1000.
+    pub flushintervalms: u64,
+    /// URL of a Redis-compatible server, used as a cache shared between all
+    /// instances, e.g. `redis://localhost:6379`.
+    ///
+    /// The shared cache is disabled if this is not set.
+    pub redisurl: Option<Url>,
+    /// The number of seconds a link is kept in the shared cache for.
+    ///
+    /// The default is 3600.
+    pub redisttlseconds: u64,
+    /// How long, in milliseconds, to wait on the shared cache before falling
+    /// back to the database.
+    ///
+    /// The default is 100.
+    pub redistimeoutms: u64,
+    /// The number of seconds the shared cache is bypassed for after a failed
+    /// request.
+    ///
+    /// The default is 5.
+    pub redisretryseconds: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            ttlseconds: 60,
+            flushintervalms: 1000,
+            redisurl: None,
+            redisttlseconds: 3600,
+            redistimeoutms: 100,
+            redisretryseconds: 5,
+        }
+    }
+}
+
+/// Configuration options specific to the static redirect map, which is kept
+/// up to date so that redirects can be served without the application.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RedirectMapConfig {
+    /// Path to write the redirect map to, or the directory to write pages to
+    /// for the `html` format.
+    ///
+    /// The redirect map is not written if this is not set.
+    pub path: Option<PathBuf>,
+    /// Format of the redirect map, one of `nginx`, `apache`, `caddy`,
+    /// `netlify` or `html`.
+    ///
+    /// The default is `nginx`.
+    pub format: RedirectMapFormat,
+    /// How often, in seconds, the redirect map is regenerated. It is also
+    /// regenerated whenever a link in it expires.
+    ///
+    /// The default is 300.
+    pub intervalseconds: u64,
+}
+
+impl Default for RedirectMapConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: RedirectMapFormat::default(),
+            intervalseconds: 300,
+        }
+    }
+}
+
+/// Configuration options specific to purging expired links.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PurgeConfig {
+    /// What happens to links once they have been expired for longer than the
+    /// grace period, one of `off`, `archive` or `delete`.
+    ///
+    /// The default is `off`, keeping expired links forever. With PostgreSQL,
+    /// the instance purging holds a connection for as long as it purges, so
+    /// `database.maxconnections` must be at least 2.
+    pub mode: PurgeMode,
+    /// The number of seconds after a link expires before it is purged.
+    ///
+    /// The default is 2592000 (30 days).
+    pub graceperiodseconds: u64,
+    /// The maximum number of links purged at once, each in its own
+    /// transaction.
+    ///
+    /// The default is 500.
+    pub batchsize: u32,
+    /// How often, in seconds, expired links are purged. Only one instance
+    /// purges at a time.
+    ///
+    /// The default is 3600.
+    pub intervalseconds: u64,
+}
+
+impl Default for PurgeConfig {
+    fn default() -> Self {
+        Self {
+            mode: PurgeMode::default(),
+            graceperiodseconds: 30 * 24 * 3600,
+            batchsize: 500,
+            intervalseconds: 3600,
+        }
+    }
 }
 
 impl Config {
-    // Build configuration from env vars
-    pub fn get_config() -> Result<Self, config::ConfigError> {
+    // Build configuration from the default configuration file and env vars
+    pub fn get_config() -> Result<Self, ConfigError> {
+        Self::get_config_with_args(&ConfigArgs::default())
+    }
+
+    // Build configuration from a configuration file, env vars and command line
+    // flags
+    pub fn get_config_with_args(args: &ConfigArgs) -> Result<Self, ConfigError> {
         // Try to load env vars from a `.env` file, without overriding existing
         // variables
         let _ = dotenvy::dotenv();
 
-        config::Config::builder()
+        Self::build(args, None)
+    }
+
+    /// Build configuration, reading env vars from the given map instead of the
+    /// environment if provided.
+    fn build(args: &ConfigArgs, env: Option<Map<String, String>>) -> Result<Self, ConfigError> {
+        let file = match &args.config {
+            Some(path) => File::from(path.as_path()).required(true),
+            None => File::new(DEFAULT_CONFIG_FILE, FileFormat::Toml).required(false),
+        };
+
+        let mut builder = config::Config::builder()
+            .add_source(file.format(FileFormat::Toml))
             .add_source(
-                config::Environment::with_prefix("")
+                Environment::with_prefix("")
                     .prefix_separator("")
-                    .separator("_"),
-            )
-            .build()?
-            .try_deserialize::<Config>()
+                    .separator("_")
+                    .source(env),
+            );
+
+        for (key, value) in [
+            ("application.host", args.host.clone()),
+            ("application.port", args.port.map(|p| p.to_string())),
+            ("database.url", args.database_url.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| Some((k, v?)))
+        .chain(args.overrides.iter().map(|(k, v)| (k.as_str(), v.clone())))
+        {
+            builder = builder.set_override(key, value)?;
+        }
+
+        let config = builder.build()?.try_deserialize::<Config>()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check that options are within sensible ranges, so that mistakes are
+    /// caught at startup.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let app = &self.application;
+        let db = &self.database;
+        let cache = &self.cache;
+        let redirect_map = &self.redirectmap;
+        let purge = &self.purge;
+
+        let mut errors = Vec::new();
+        let mut check = |valid: bool, message: &str| {
+            if !valid {
+                errors.push(message.to_string());
+            }
+        };
+
+        check(
+            app.admintoken.as_ref().is_none_or(|t| !t.trim().is_empty()),
+            "application.admintoken must not be empty if set",
+        );
+        check(
+            app.idseed.as_ref().is_none_or(|s| !s.trim().is_empty()),
+            "application.idseed must not be empty if set",
+        );
+        check(
+            app.enumerationlimit == 0 || app.enumerationwindowseconds > 0,
+            "application.enumerationwindowseconds must be at least 1 when throttling is enabled",
+        );
+        check(
+            app.ratelimitburst > 0,
+            "application.ratelimitburst must be at least 1",
+        );
+        check(
+            app.ratelimitperiodms > 0,
+            "application.ratelimitperiodms must be at least 1",
+        );
+        check(
+            app.requesttimeoutseconds > 0,
+            "application.requesttimeoutseconds must be at least 1",
+        );
+        check(
+            app.maxbodybytes > 0,
+            "application.maxbodybytes must be at least 1",
+        );
+        check(
+            app.keyspacerefreshseconds > 0,
+            "application.keyspacerefreshseconds must be at least 1",
+        );
+        check(
+            app.eventbuffercapacity > 0,
+            "application.eventbuffercapacity must be at least 1",
+        );
+        check(
+            app.eventpublishintervalms > 0,
+            "application.eventpublishintervalms must be at least 1",
+        );
+        check(
+            app.scheduleintervalseconds > 0,
+            "application.scheduleintervalseconds must be at least 1",
+        );
+        check(
+            HeaderValue::from_str(&app.redirectcachecontrol).is_ok(),
+            "application.redirectcachecontrol must be a valid header value",
+        );
+        check(
+            db.maxconnections > 0,
+            "database.maxconnections must be at least 1",
+        );
+        check(
+            db.minconnections <= db.maxconnections,
+            "database.minconnections must not be greater than database.maxconnections",
+        );
+        check(db.timeoutms > 0, "database.timeoutms must be at least 1");
+        check(
+            ![MEMORY_SCHEME, SQLITE_SCHEME].contains(&db.url.scheme()) || db.replicaurls.is_empty(),
+            "database.replicaurls can only be used with PostgreSQL",
+        );
+        check(
+            db.breakerthreshold > 0,
+            "database.breakerthreshold must be at least 1",
+        );
+        check(
+            db.replicaurls.is_empty() || db.replicahealthcheckseconds > 0,
+            "database.replicahealthcheckseconds must be at least 1 when read replicas are used",
+        );
+        check(
+            db.startuptimeoutseconds > 0,
+            "database.startuptimeoutseconds must be at least 1",
+        );
+        check(
+            cache.capacity == 0 || cache.ttlseconds > 0,
+            "cache.ttlseconds must be at least 1 when caching is enabled",
+        );
+        check(
+            cache.flushintervalms > 0,
+            "cache.flushintervalms must be at least 1",
+        );
+        check(
+            cache
+                .redisurl
+                .as_ref()
+                .is_none_or(|u| SHARED_CACHE_SCHEMES.contains(&u.scheme())),
+            &format!(
+                "cache.redisurl must use one of the schemes: {}",
+                SHARED_CACHE_SCHEMES.join(", ")
+            ),
+        );
+        check(
+            cache.redisurl.is_none() || cache.redistimeoutms > 0,
+            "cache.redistimeoutms must be at least 1 when the shared cache is enabled",
+        );
+        check(
+            cache.redisurl.is_none() || cache.redisretryseconds > 0,
+            "cache.redisretryseconds must be at least 1 when the shared cache is enabled",
+        );
+        check(
+            redirect_map.path.is_none() || redirect_map.intervalseconds > 0,
+            "redirectmap.intervalseconds must be at least 1 when the redirect map is enabled",
+        );
+        check(
+            purge.mode == PurgeMode::Off || purge.batchsize > 0,
+            "purge.batchsize must be at least 1 when purging is enabled",
+        );
+        check(
+            purge.mode == PurgeMode::Off || purge.intervalseconds > 0,
+            "purge.intervalseconds must be at least 1 when purging is enabled",
+        );
+        check(
+            purge.mode == PurgeMode::Off
+                || [MEMORY_SCHEME, SQLITE_SCHEME].contains(&db.url.scheme())
+                || db.maxconnections > 1,
+            "database.maxconnections must be at least 2 when purging is enabled",
+        );
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(format!(
+                "Invalid configuration:\n- {}",
+                errors.join("\n- ")
+            )))
+        }
+    }
+
+    /// The configuration as TOML, with secrets redacted.
+    pub fn to_redacted_toml(&self) -> String {
+        let mut config = self.clone();
+
+        for secret in [
+            &mut config.application.admintoken,
+            &mut config.application.idseed,
+        ] {
+            if secret.is_some() {
+                *secret = Some(REDACTED.into());
+            }
+        }
+        for url in std::iter::once(&mut config.database.url)
+            .chain(config.database.replicaurls.iter_mut())
+            .chain(config.cache.redisurl.iter_mut())
+        {
+            redact_url(url);
+        }
+
+        toml::to_string(&config).expect("configuration should always be serialisable")
+    }
+}
+
+/// Redact the password of a URL, including when given as a query parameter.
+fn redact_url(url: &mut Url) {
+    if url.password().is_some() {
+        _ = url.set_password(Some(REDACTED));
+    }
+
+    if url.query_pairs().any(|(k, _)| k == "password") {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(k, v)| {
+                let v = if k == "password" {
+                    REDACTED.into()
+                } else {
+                    v.into_owned()
+                };
+                (k.into_owned(), v)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+}
+
+/// Custom de-serialiser for a list of URLs, accepting either a list or a
+/// comma-separated string
+fn deserialize_urls<'de, D>(deserializer: D) -> Result<Vec<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Urls {
+        List(Vec<Url>),
+        CommaSeparated(String),
+    }
+
+    match Urls::deserialize(deserializer)? {
+        Urls::List(urls) => Ok(urls),
+        Urls::CommaSeparated(s) => s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| Url::parse(s).map_err(|e| D::Error::custom(format!("Invalid URL '{s}': {e}"))))
+            .collect(),
     }
 }
 
+/// Custom serialiser for the host, converting `[u8; 4]` to a string value
+fn serialize_host<S>(host: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format!("{}.{}.{}.{}", host[0], host[1], host[2], host[3]))
+}
+
 /// Custom de-serialiser for the host, converting a string value to `[u8; 4]`
 fn deserialize_host<'de, D>(deserializer: D) -> Result<[u8; 4], D::Error>
 where
@@ -92,3 +705,220 @@ where
 
     Ok(res)
 }
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const DB_URL: &str = "postgresql://localhost:5432/curto-db?user=postgres&password=postgres";
+
+    fn env(vars: &[(&str, &str)]) -> Option<Map<String, String>> {
+        Some(
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    fn write_config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("curto-{}-{name}.toml", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let path = write_config_file(
+            "layers",
+            &format!(
+                r#"
+                    [application]
+                    port = 8000
+                    ratelimitburst = 20
+
+                    [database]
+                    url = "{DB_URL}"
+                    maxconnections = 50
+                    minconnections = 5
+                "#
+            ),
+        );
+
+        let args = ConfigArgs {
+            config: Some(path.clone()),
+            port: Some(9000),
+            overrides: vec![("database.minconnections".into(), "10".into())],
+            ..Default::default()
+        };
+        let config = Config::build(
+            &args,
+            env(&[
+                ("APPLICATION_PORT", "8500"),
+                ("DATABASE_MAXCONNECTIONS", "30"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // Command line flags take precedence over env vars, which take
+        // precedence over the file
+        assert_eq!(config.application.port, 9000);
+        assert_eq!(config.database.maxconnections, 30);
+        assert_eq!(config.database.minconnections, 10);
+        assert_eq!(config.application.ratelimitburst, 20);
+        // Defaults are used for anything not set
+        assert_eq!(config.application.ratelimitperiodms, 200);
+        assert_eq!(config.database.timeoutms, 400);
+        assert_eq!(config.cache.capacity, 10_000);
+    }
+
+    #[test]
+    fn test_config_missing_file() {
+        let args = ConfigArgs {
+            config: Some("does-not-exist.toml".into()),
+            ..Default::default()
+        };
+        assert!(Config::build(&args, env(&[("DATABASE_URL", DB_URL)])).is_err());
+
+        // The default file is optional
+        assert!(Config::build(&ConfigArgs::default(), env(&[("DATABASE_URL", DB_URL)])).is_ok());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let result = Config::build(
+            &ConfigArgs::default(),
+            env(&[
+                ("DATABASE_URL", DB_URL),
+                ("DATABASE_MINCONNECTIONS", "30"),
+                ("DATABASE_MAXCONNECTIONS", "20"),
+                ("APPLICATION_REDIRECTCACHECONTROL", "no\nstore"),
+                ("APPLICATION_IDSEED", " "),
+                ("REDIRECTMAP_PATH", "curto.map"),
+                ("REDIRECTMAP_INTERVALSECONDS", "0"),
+                ("PURGE_MODE", "delete"),
+                ("PURGE_BATCHSIZE", "0"),
+                ("CACHE_REDISURL", "https://localhost:6379"),
+                ("APPLICATION_EVENTBUFFERCAPACITY", "0"),
+                ("APPLICATION_EVENTPUBLISHINTERVALMS", "0"),
+                ("APPLICATION_SCHEDULEINTERVALSECONDS", "0"),
+            ]),
+        );
+
+        let Err(ConfigError::Message(message)) = result else {
+            panic!("expected a validation error, got {result:?}");
+        };
+        assert!(message.contains("database.minconnections"));
+        assert!(message.contains("application.redirectcachecontrol"));
+        assert!(message.contains("application.idseed"));
+        assert!(message.contains("redirectmap.intervalseconds"));
+        assert!(message.contains("purge.batchsize"));
+        assert!(message.contains("cache.redisurl"));
+        assert!(message.contains("application.eventbuffercapacity"));
+        assert!(message.contains("application.eventpublishintervalms"));
+        assert!(message.contains("application.scheduleintervalseconds"));
+        assert!(!message.contains("application.keyspacerefreshseconds"));
+        assert!(!message.contains("purge.intervalseconds"));
+        assert!(!message.contains("database.timeoutms"));
+
+        let result = Config::build(
+            &ConfigArgs::default(),
+            env(&[
+                ("DATABASE_URL", "memory://"),
+                ("DATABASE_REPLICAURLS", DB_URL),
+            ]),
+        );
+        let Err(ConfigError::Message(message)) = result else {
+            panic!("expected a validation error, got {result:?}");
+        };
+        assert!(message.contains("database.replicaurls"));
+
+        let result = Config::build(
+            &ConfigArgs::default(),
+            env(&[
+                ("DATABASE_URL", DB_URL),
+                ("DATABASE_MINCONNECTIONS", "1"),
+                ("DATABASE_MAXCONNECTIONS", "1"),
+                ("PURGE_MODE", "archive"),
+            ]),
+        );
+        let Err(ConfigError::Message(message)) = result else {
+            panic!("expected a validation error, got {result:?}");
+        };
+        assert!(message.contains("database.maxconnections must be at least 2"));
+
+        // Purging doesn't hold a separate connection without PostgreSQL
+        let result = Config::build(
+            &ConfigArgs::default(),
+            env(&[
+                ("DATABASE_URL", "memory://"),
+                ("DATABASE_MINCONNECTIONS", "1"),
+                ("DATABASE_MAXCONNECTIONS", "1"),
+                ("PURGE_MODE", "archive"),
+            ]),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_config_replica_urls() {
+        let config = Config::build(
+            &ConfigArgs::default(),
+            env(&[
+                ("DATABASE_URL", DB_URL),
+                (
+                    "DATABASE_REPLICAURLS",
+                    "postgresql://replica-1:5432/curto-db, postgresql://replica-2:5432/curto-db",
+                ),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config
+                .database
+                .replicaurls
+                .iter()
+                .map(|u| u.host_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["replica-1", "replica-2"]
+        );
+    }
+
+    #[test]
+    fn test_config_redacted_toml() {
+        let args = ConfigArgs {
+            overrides: vec![
+                ("application.admintoken".into(), "hunter2".into()),
+                ("application.idseed".into(), "hunter2".into()),
+                (
+                    "cache.redisurl".into(),
+                    "redis://:hunter2@localhost:6379".into(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let config = Config::build(&args, env(&[("DATABASE_URL", DB_URL)])).unwrap();
+
+        let printed = config.to_redacted_toml();
+        assert!(!printed.contains("hunter2"));
+        assert!(!printed.contains("password=postgres"));
+        assert!(printed.contains("user=postgres"));
+
+        // The printed configuration can be read back
+        let path = write_config_file("redacted", &printed);
+        let args = ConfigArgs {
+            config: Some(path.clone()),
+            ..Default::default()
+        };
+        let read_back = Config::build(&args, env(&[])).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read_back.application.host, config.application.host);
+        assert_eq!(
+            read_back.cache.redisurl.unwrap().host_str(),
+            Some("localhost")
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::time::Duration;
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use utoipa::ToSchema;
+
+use super::Link;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct TopLink {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub link: Link,
+    /// Count of redirects to the link within the requested time window.
+    pub window_redirects: i64,
+}
+
+/// Get the most redirected-to active [`Link`]s over the last `window_hours`
+/// hours, ordered by their count of redirects within that window. Unlisted
+/// links are left out.
+///
+/// Redirects are counted in hourly buckets, so the start of the window is
+/// rounded down to the nearest hour.
+pub async fn get_top_links(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    window_hours: i32,
+    limit: i64,
+) -> Result<Vec<TopLink>> {
+    let rows = tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                select
+                    l.id,
+                    l.target_url,
+                    l.count_redirects,
+                    l.created_at,
+                    l.updated_at,
+                    l.expires_at,
+                    l.is_custom_id,
+                    l.track_conversions,
+                    l.is_unlisted,
+                    l.next_change_at,
+                    l.is_immutable,
+                    l.legal_hold,
+                    sum(r.count_redirects)::bigint as "window_redirects!"
+                from link_redirects_hourly r
+                join links l on l.id = r.link_id
+                where r.bucket >= date_trunc('hour', now() - make_interval(hours => $1), 'UTC')
+                    and (l.expires_at is null or l.expires_at > now())
+                    and not l.is_unlisted
+                group by l.id
+                order by "window_redirects!" desc, l.id
+                limit $2
+            "#,
+            window_hours,
+            limit
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_top_links").increment(1))
+    .map_err(Error::from)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| TopLink {
+            link: Link {
+                id: r.id,
+                target_url: r.target_url,
+                count_redirects: r.count_redirects.into(),
+                created_at: r.created_at,
+                updated_at: r.updated_at,
+                expires_at: r.expires_at,
+                is_custom_id: r.is_custom_id,
+                track_conversions: r.track_conversions,
+                is_unlisted: r.is_unlisted,
+                next_change_at: r.next_change_at,
+                is_immutable: r.is_immutable,
+                legal_hold: r.legal_hold,
+            },
+            window_redirects: r.window_redirects,
+        })
+        .collect())
+}
+
+/// Summary statistics about all links stored by this instance.
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceStats {
+    /// Count of all stored links, including expired links.
+    pub total_links: i64,
+    /// Count of links which have not expired.
+    pub active_links: i64,
+    /// Count of links which have expired.
+    pub expired_links: i64,
+    /// Count of links with an ID provided by the user.
+    pub custom_id_links: i64,
+    /// Count of links with a generated ID.
+    pub generated_id_links: i64,
+    /// Ratio of links with a user-provided ID to all links, between 0 and 1.
+    pub custom_id_ratio: f64,
+    /// Count of all redirects, over the lifetime of all stored links.
+    pub total_redirects: i64,
+    /// Count of redirects within the last 24 hours.
+    pub redirects_last_day: i64,
+    /// Count of redirects within the last 7 days.
+    pub redirects_last_week: i64,
+    /// Count of links created on each of the requested days, oldest first.
+    pub links_created_per_day: Vec<DailyCount>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyCount {
+    pub date: NaiveDate,
+    pub count: i64,
+}
+
+/// Get [`InstanceStats`], with link creation counts for the last `days` days
+/// (including today).
+pub async fn get_instance_stats(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    days: i32,
+) -> Result<InstanceStats> {
+    let links = tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                select
+                    count(*) as "total!",
+                    count(*) filter (where expires_at is null or expires_at > now()) as "active!",
+                    count(*) filter (where is_custom_id) as "custom_id!",
+                    coalesce(sum(count_redirects), 0)::bigint as "redirects!"
+                from links
+            "#,
+        )
+        .fetch_one(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_stats").increment(1))?;
+
+    // Redirects are counted in hourly buckets, so the start of each window is
+    // rounded down to the nearest hour
+    let redirects = tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                select
+                    coalesce(sum(count_redirects) filter (
+                        where bucket >= date_trunc('hour', now() - interval '1 day', 'UTC')
+                    ), 0)::bigint as "last_day!",
+                    coalesce(sum(count_redirects), 0)::bigint as "last_week!"
+                from link_redirects_hourly
+                where bucket >= date_trunc('hour', now() - interval '7 days', 'UTC')
+            "#,
+        )
+        .fetch_one(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_stats").increment(1))?;
+
+    let links_created_per_day = tokio::time::timeout(
+        timeout,
+        sqlx::query_as!(
+            DailyCount,
+            r#"
+                select day::date as "date!", count(l.id) as "count!"
+                from generate_series(
+                    ((now() at time zone 'UTC')::date - ($1::int - 1))::timestamp,
+                    (now() at time zone 'UTC')::date::timestamp,
+                    interval '1 day'
+                ) as day
+                left join links l on (l.created_at at time zone 'UTC')::date = day::date
+                group by day
+                order by day
+            "#,
+            days
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_stats").increment(1))?;
+
+    let generated_id_links = links.total - links.custom_id;
+
+    Ok(InstanceStats {
+        total_links: links.total,
+        active_links: links.active,
+        expired_links: links.total - links.active,
+        custom_id_links: links.custom_id,
+        generated_id_links,
+        custom_id_ratio: if links.total == 0 {
+            0.0
+        } else {
+            links.custom_id as f64 / links.total as f64
+        },
+        total_redirects: links.redirects,
+        redirects_last_day: redirects.last_day,
+        redirects_last_week: redirects.last_week,
+        links_created_per_day,
+    })
+}
+
+/// Count of redirects to a [`Link`] within a single hour.
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct HourlyRedirects {
+    pub link_id: String,
+    pub target_url: String,
+    /// Start of the hour in which the redirects happened.
+    pub hour: DateTime<Utc>,
+    pub redirects: i64,
+}
+
+/// Filters for selecting [`HourlyRedirects`].
+#[derive(Debug, Default, Clone)]
+pub struct RedirectsFilter {
+    /// Only include redirects to links with these IDs, if provided.
+    /// Otherwise, unlisted links are left out.
+    pub link_ids: Option<Vec<String>>,
+    /// Only include redirects from this time onwards, if provided.
+    ///
+    /// Redirects are counted in hourly buckets, so this is rounded down to the
+    /// nearest hour.
+    pub from: Option<DateTime<Utc>>,
+    /// Only include redirects from before this time, if provided.
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Get a page of [`HourlyRedirects`] matching the given filter, ordered by
+/// link ID and then hour.
+///
+/// Pages are selected using a cursor, which should be the link ID and hour of
+/// the last entry of the previous page.
+pub async fn get_hourly_redirects(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    filter: &RedirectsFilter,
+    after: Option<(&str, DateTime<Utc>)>,
+    limit: i64,
+) -> Result<Vec<HourlyRedirects>> {
+    let (after_id, after_hour) = after.unzip();
+
+    tokio::time::timeout(
+        timeout,
+        sqlx::query_as!(
+            HourlyRedirects,
+            r#"
+                select
+                    r.link_id,
+                    l.target_url,
+                    r.bucket as hour,
+                    r.count_redirects as redirects
+                from link_redirects_hourly r
+                join links l on l.id = r.link_id
+                where ($1::text[] is null or r.link_id = any($1))
+                    and ($1::text[] is not null or not l.is_unlisted)
+                    and ($2::timestamptz is null or r.bucket >= date_trunc('hour', $2, 'UTC'))
+                    and ($3::timestamptz is null or r.bucket < $3)
+                    and ($4::text is null or (r.link_id, r.bucket) > ($4, $5))
+                order by r.link_id, r.bucket
+                limit $6
+            "#,
+            filter.link_ids.as_deref(),
+            filter.from,
+            filter.to,
+            after_id,
+            after_hour,
+            limit
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_redirects").increment(1))
+    .map_err(Error::from)
+}
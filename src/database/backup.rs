@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{Actor, Link, LinkRevision, ScheduledChange};
+use crate::{error::{Error, Result}, time::deserialize_timestamp};
+
+/// Redirects to a link within an hour, as stored in a backup.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupRedirects {
+    pub link_id: String,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub bucket: DateTime<Utc>,
+    pub count_redirects: i64,
+}
+
+/// A click on a link which tracks conversions, as stored in a backup.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupClick {
+    pub id: Uuid,
+    pub link_id: String,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub clicked_at: DateTime<Utc>,
+}
+
+/// A conversion following a click, as stored in a backup.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupConversion {
+    pub click_id: Uuid,
+    pub goal: String,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// A single row of the data of a store, as streamed into a backup.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BackupRecord {
+    Link(Link),
+    Redirects(BackupRedirects),
+    Click(BackupClick),
+    Conversion(BackupConversion),
+    Revision(LinkRevision),
+    ScheduledChange(ScheduledChange),
+    /// Last number handed out by the sequence encoded into sequential IDs, or
+    /// 0 if none were.
+    Sequence {
+        value: u64,
+    },
+}
+
+/// All the data of a store, to be restored from a backup.
+#[derive(Debug, Default, Clone)]
+pub struct Backup {
+    pub links: Vec<Link>,
+    pub redirects: Vec<BackupRedirects>,
+    pub clicks: Vec<BackupClick>,
+    pub conversions: Vec<BackupConversion>,
+    pub revisions: Vec<LinkRevision>,
+    pub scheduled_changes: Vec<ScheduledChange>,
+    pub sequence: u64,
+}
+
+impl Backup {
+    pub fn push(&mut self, record: BackupRecord) {
+        match record {
+            BackupRecord::Link(link) => self.links.push(link),
+            BackupRecord::Redirects(redirects) => self.redirects.push(redirects),
+            BackupRecord::Click(click) => self.clicks.push(click),
+            BackupRecord::Conversion(conversion) => self.conversions.push(conversion),
+            BackupRecord::Revision(revision) => self.revisions.push(revision),
+            BackupRecord::ScheduledChange(change) => self.scheduled_changes.push(change),
+            BackupRecord::Sequence { value } => self.sequence = value,
+        }
+    }
+
+    /// Check that restoring the backup wouldn't remove or change any of the
+    /// given links which are immutable or on legal hold.
+    pub fn check_protected_links<'a>(
+        &self,
+        protected: impl IntoIterator<Item = &'a Link>,
+    ) -> Result<()> {
+        let restored: HashMap<_, _> = self.links.iter().map(|l| (l.id.as_str(), l)).collect();
+        for link in protected {
+            link.check_restorable(restored.get(link.id.as_str()).copied())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Send every row of a query as a record, returning `false` if the receiver
+/// was dropped, in which case the backup should stop.
+pub(super) async fn send_rows<T>(
+    rows: impl Stream<Item = std::result::Result<T, sqlx::Error>>,
+    records: &mpsc::Sender<BackupRecord>,
+    to_record: impl Fn(T) -> BackupRecord,
+) -> Result<bool> {
+    let mut rows = std::pin::pin!(rows);
+    while let Some(row) = rows.next().await {
+        if records.send(to_record(row?)).await.is_err() {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Map errors caused by the contents of a backup being restored, such as rows
+/// referencing links which aren't in the backup, to [`Error::InvalidRequest`].
+pub(super) fn restore_error(e: sqlx::Error) -> Error {
+    match &e {
+        sqlx::Error::Database(db_err)
+            if matches!(
+                db_err.kind(),
+                sqlx::error::ErrorKind::ForeignKeyViolation
+                    | sqlx::error::ErrorKind::UniqueViolation
+            ) =>
+        {
+            Error::InvalidRequest(format!("inconsistent backup: {}", db_err.message()))
+        }
+        _ => e.into(),
+    }
+}
+
+/// Stream all data in the database as backup records, as of a single point in
+/// time.
+///
+/// This isn't limited by the default query timeout, as it may take a while
+/// for large databases.
+pub async fn backup(db: &Pool<Postgres>, records: mpsc::Sender<BackupRecord>) -> Result<()> {
+    backup_rows(db, records)
+        .await
+        .inspect_err(|_| counter!("db.failed_to_back_up").increment(1))
+}
+
+async fn backup_rows(db: &Pool<Postgres>, records: mpsc::Sender<BackupRecord>) -> Result<()> {
+    let mut tx = db.begin().await?;
+    sqlx::query!("set transaction isolation level repeatable read, read only")
+        .execute(&mut *tx)
+        .await?;
+
+    let rows = sqlx::query_as!(Link, "select * from links order by id").fetch(&mut *tx);
+    if !send_rows(rows, &records, BackupRecord::Link).await? {
+        return Ok(());
+    }
+
+    let rows = sqlx::query_as!(
+        BackupRedirects,
+        r#"
+            select link_id, bucket, count_redirects
+            from link_redirects_hourly
+            order by link_id, bucket
+        "#
+    )
+    .fetch(&mut *tx);
+    if !send_rows(rows, &records, BackupRecord::Redirects).await? {
+        return Ok(());
+    }
+
+    let rows = sqlx::query_as!(
+        BackupClick,
+        "select id, link_id, clicked_at from link_clicks order by id"
+    )
+    .fetch(&mut *tx);
+    if !send_rows(rows, &records, BackupRecord::Click).await? {
+        return Ok(());
+    }
+
+    let rows = sqlx::query_as!(
+        BackupConversion,
+        "select click_id, goal, created_at from conversions order by click_id, goal"
+    )
+    .fetch(&mut *tx);
+    if !send_rows(rows, &records, BackupRecord::Conversion).await? {
+        return Ok(());
+    }
+
+    let rows = sqlx::query_as!(
+        LinkRevision,
+        r#"
+            select
+                link_id, revision, target_url, expires_at, track_conversions, is_immutable,
+                legal_hold, changed_at, changed_by as "changed_by: Actor", restored_revision
+            from link_revisions
+            order by link_id, revision
+        "#
+    )
+    .fetch(&mut *tx);
+    if !send_rows(rows, &records, BackupRecord::Revision).await? {
+        return Ok(());
+    }
+
+    let rows = sqlx::query_as!(
+        ScheduledChange,
+        "select * from scheduled_changes order by id"
+    )
+    .fetch(&mut *tx);
+    if !send_rows(rows, &records, BackupRecord::ScheduledChange).await? {
+        return Ok(());
+    }
+
+    let sequence = sqlx::query!("select last_value, is_called from link_id_seq")
+        .fetch_one(&mut *tx)
+        .await?;
+    let value = if sequence.is_called {
+        sequence.last_value as u64
+    } else {
+        0
+    };
+    _ = records.send(BackupRecord::Sequence { value }).await;
+
+    Ok(())
+}
+
+/// Replace all data in the database with the data of a backup, in a single
+/// transaction.
+///
+/// This isn't limited by the default query timeout, as it may take a while
+/// for large backups.
+pub async fn restore(db: &Pool<Postgres>, backup: &Backup) -> Result<()> {
+    restore_rows(db, backup)
+        .await
+        .inspect_err(|_| counter!("db.failed_to_restore").increment(1))
+}
+
+async fn restore_rows(db: &Pool<Postgres>, backup: &Backup) -> Result<()> {
+    let mut tx = db.begin().await?;
+
+    // Links which are immutable or on legal hold must survive the restore
+    let protected = sqlx::query_as!(
+        Link,
+        "select * from links where is_immutable or legal_hold for update"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    backup.check_protected_links(&protected)?;
+
+    // Redirects, clicks, conversions and scheduled changes are deleted along
+    // with their links, but revisions are kept for deleted links
+    sqlx::query!("delete from links").execute(&mut *tx).await?;
+    sqlx::query!("delete from link_revisions")
+        .execute(&mut *tx)
+        .await?;
+
+    for link in &backup.links {
+        sqlx::query!(
+            r#"
+                insert into links(
+                    id, target_url, count_redirects, created_at, updated_at, expires_at,
+                    is_custom_id, track_conversions, is_unlisted, next_change_at, is_immutable,
+                    legal_hold
+                )
+                values ($1, $2, $3::bigint, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            "#,
+            link.id,
+            link.target_url,
+            link.count_redirects,
+            link.created_at,
+            link.updated_at,
+            link.expires_at,
+            link.is_custom_id,
+            link.track_conversions,
+            link.is_unlisted,
+            link.next_change_at,
+            link.is_immutable,
+            link.legal_hold
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(restore_error)?;
+    }
+
+    for redirects in &backup.redirects {
+        sqlx::query!(
+            r#"
+                insert into link_redirects_hourly (link_id, bucket, count_redirects)
+                values ($1, $2, $3)
+            "#,
+            redirects.link_id,
+            redirects.bucket,
+            redirects.count_redirects
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(restore_error)?;
+    }
+
+    for click in &backup.clicks {
+        sqlx::query!(
+            "insert into link_clicks (id, link_id, clicked_at) values ($1, $2, $3)",
+            click.id,
+            click.link_id,
+            click.clicked_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(restore_error)?;
+    }
+
+    for conversion in &backup.conversions {
+        sqlx::query!(
+            "insert into conversions (click_id, goal, created_at) values ($1, $2, $3)",
+            conversion.click_id,
+            conversion.goal,
+            conversion.created_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(restore_error)?;
+    }
+
+    for revision in &backup.revisions {
+        sqlx::query!(
+            r#"
+                insert into link_revisions (
+                    link_id, revision, target_url, expires_at, track_conversions, is_immutable,
+                    legal_hold, changed_at, changed_by, restored_revision
+                )
+                values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+            revision.link_id,
+            revision.revision,
+            revision.target_url,
+            revision.expires_at,
+            revision.track_conversions,
+            revision.is_immutable,
+            revision.legal_hold,
+            revision.changed_at,
+            revision.changed_by as Actor,
+            revision.restored_revision
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(restore_error)?;
+    }
+
+    for change in &backup.scheduled_changes {
+        sqlx::query!(
+            r#"
+                insert into scheduled_changes (id, link_id, target_url, scheduled_at, created_at)
+                values ($1, $2, $3, $4, $5)
+            "#,
+            change.id,
+            change.link_id,
+            change.target_url,
+            change.scheduled_at,
+            change.created_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(restore_error)?;
+    }
+    // Carry on numbering scheduled changes after the restored ones
+    sqlx::query!(
+        r#"
+            select setval(
+                pg_get_serial_sequence('scheduled_changes', 'id'),
+                coalesce(max(id), 0) + 1,
+                false
+            )
+            from scheduled_changes
+        "#
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // The sequence starts at 1, so a value of 0 means it hasn't been used
+    sqlx::query!(
+        "select setval('link_id_seq', greatest($1, 1), $1 > 0)",
+        backup.sequence as i64
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
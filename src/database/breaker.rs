@@ -0,0 +1,202 @@
+use std::{future::Future, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use axum_prometheus::metrics::{counter, gauge};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests reach the database.
+    Closed { failures: u32 },
+    /// Requests fail fast, until the given time.
+    Open { until: Instant },
+    /// A single trial request, started at the given time, is allowed through
+    /// to check if the database has recovered.
+    HalfOpen { since: Instant },
+}
+
+/// Circuit breaker around database requests.
+///
+/// After enough consecutive failures, requests fail fast with
+/// [`Error::DatabaseUnavailable`] for a while instead of waiting on timeouts,
+/// giving the database time to recover.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<State>>,
+    failure_threshold: u32,
+    open_duration: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+        }
+    }
+
+    /// Run a database request, unless the circuit is open.
+    ///
+    /// Only [`Error::Internal`] errors count as failures, as other errors
+    /// (e.g. a taken link ID) mean the database is responding.
+    pub async fn call<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        self.acquire()?;
+
+        let result = request.await;
+        match &result {
+            Err(Error::Internal(_)) => self.record_failure(),
+            _ => self.record_success(),
+        }
+
+        result
+    }
+
+    /// Whether requests are currently failing fast.
+    pub fn is_open(&self) -> bool {
+        matches!(*self.lock(), State::Open { until } if Instant::now() < until)
+    }
+
+    fn acquire(&self) -> Result<()> {
+        let mut state = self.lock();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if Instant::now() >= until => {
+                *state = State::HalfOpen {
+                    since: Instant::now(),
+                };
+                Ok(())
+            }
+            // The trial request never finished, e.g. because it was cancelled
+            State::HalfOpen { since } if since.elapsed() >= self.open_duration => {
+                *state = State::HalfOpen {
+                    since: Instant::now(),
+                };
+                Ok(())
+            }
+            State::Open { until } => {
+                counter!("db.circuit_breaker_rejections").increment(1);
+                Err(Error::DatabaseUnavailable(retry_after(until)))
+            }
+            State::HalfOpen { .. } => {
+                counter!("db.circuit_breaker_rejections").increment(1);
+                Err(Error::DatabaseUnavailable(1))
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.lock();
+        if *state != (State::Closed { failures: 0 }) {
+            if !matches!(*state, State::Closed { .. }) {
+                tracing::info!("Database recovered, closing circuit breaker");
+                gauge!("db.circuit_breaker_open").set(0);
+            }
+            *state = State::Closed { failures: 0 };
+        }
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.lock();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // The trial request failed
+            State::HalfOpen { .. } => self.failure_threshold,
+            // Requests which started before the circuit opened
+            State::Open { .. } => return,
+        };
+
+        *state = if failures >= self.failure_threshold {
+            tracing::warn!(
+                "Database unavailable, opening circuit breaker for {:?}",
+                self.open_duration
+            );
+            gauge!("db.circuit_breaker_open").set(1);
+            State::Open {
+                until: Instant::now() + self.open_duration,
+            }
+        } else {
+            State::Closed { failures }
+        };
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("circuit breaker lock poisoned")
+    }
+}
+
+/// Seconds until the given time, rounded up and at least 1.
+fn retry_after(until: Instant) -> u64 {
+    let remaining = until.saturating_duration_since(Instant::now());
+    (remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)).max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<()> {
+        breaker
+            .call(async { Err::<(), _>(Error::Internal("timeout".into())) })
+            .await
+    }
+
+    async fn succeed(breaker: &CircuitBreaker) -> Result<()> {
+        breaker.call(async { Ok(()) }).await
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_opens() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+
+        // Failures must be consecutive
+        assert!(fail(&breaker).await.is_err());
+        assert!(fail(&breaker).await.is_err());
+        assert!(succeed(&breaker).await.is_ok());
+        assert!(fail(&breaker).await.is_err());
+        assert!(fail(&breaker).await.is_err());
+        assert!(!breaker.is_open());
+
+        // Errors other than internal errors don't count as failures
+        assert!(
+            breaker
+                .call(async { Err::<(), _>(Error::LinkIdNotUnique("abc".into())) })
+                .await
+                .is_err()
+        );
+        assert!(!breaker.is_open());
+
+        assert!(fail(&breaker).await.is_err());
+        assert!(fail(&breaker).await.is_err());
+        assert!(fail(&breaker).await.is_err());
+        assert!(breaker.is_open());
+
+        // Fails fast while open
+        match succeed(&breaker).await {
+            Err(Error::DatabaseUnavailable(retry_after)) => assert_eq!(retry_after, 30),
+            other => panic!("expected the circuit to be open, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_recovers() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+
+        assert!(fail(&breaker).await.is_err());
+        assert!(breaker.is_open());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // A failed trial request re-opens the circuit
+        assert!(!breaker.is_open());
+        assert!(matches!(fail(&breaker).await, Err(Error::Internal(_))));
+        assert!(breaker.is_open());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        // A successful trial request closes the circuit
+        assert!(succeed(&breaker).await.is_ok());
+        assert!(!breaker.is_open());
+        assert!(succeed(&breaker).await.is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use axum_prometheus::metrics::counter;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+
+/// Maximum length of a conversion goal name.
+const MAX_GOAL_LENGTH: usize = 64;
+
+/// Conversions for a single goal.
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GoalConversions {
+    /// Name of the goal, as reported by the target site.
+    pub goal: String,
+    /// Count of tracked clicks which led to the goal being reached.
+    pub conversions: i64,
+    /// Ratio of conversions to tracked clicks, between 0 and 1.
+    pub conversion_rate: f64,
+}
+
+/// Conversions following tracked clicks, for each goal.
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionStats {
+    /// Count of redirects which carried a click ID.
+    pub tracked_clicks: i64,
+    /// Conversions for each goal, ordered by the number of conversions.
+    pub goals: Vec<GoalConversions>,
+}
+
+/// Check that a goal name is non-empty, not too long, and only contains
+/// alphanumeric characters, '-', '_' or '.'.
+pub fn validate_goal(goal: &str) -> bool {
+    !goal.is_empty()
+        && goal.len() <= MAX_GOAL_LENGTH
+        && goal
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Record a click on a link which tracks conversions, returning the new click
+/// ID.
+pub async fn record_click(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: impl AsRef<str>,
+) -> Result<Uuid> {
+    let click_id = Uuid::new_v4();
+
+    tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            "insert into link_clicks (id, link_id) values ($1, $2)",
+            click_id,
+            link_id.as_ref()
+        )
+        .execute(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_record_click").increment(1))?;
+
+    Ok(click_id)
+}
+
+/// Record that a click led to the given goal being reached, returning `false`
+/// if this conversion had already been recorded.
+pub async fn record_conversion(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    click_id: Uuid,
+    goal: &str,
+) -> Result<bool> {
+    if !validate_goal(goal) {
+        return Err(Error::ConversionGoalNotValid(goal.to_string()));
+    }
+
+    let result = tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                insert into conversions (click_id, goal) values ($1, $2)
+                on conflict (click_id, goal) do nothing
+            "#,
+            click_id,
+            goal
+        )
+        .execute(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .map_err(|e| {
+        // Click ID does not exist
+        if let sqlx::Error::Database(db_err) = &e
+            && db_err.kind() == sqlx::error::ErrorKind::ForeignKeyViolation
+        {
+            return Error::ClickNotFound(click_id.to_string());
+        }
+
+        counter!("db.failed_to_record_conversion").increment(1);
+        e.into()
+    })?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get [`ConversionStats`] for the link with the given ID, or for all links if
+/// no ID is given.
+pub async fn get_conversion_stats(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: Option<&str>,
+) -> Result<ConversionStats> {
+    let tracked_clicks = tokio::time::timeout(
+        timeout,
+        sqlx::query_scalar!(
+            r#"select count(*) as "count!" from link_clicks where $1::text is null or link_id = $1"#,
+            link_id
+        )
+        .fetch_one(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_conversions").increment(1))?;
+
+    let goals = tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                select c.goal, count(*) as "conversions!"
+                from conversions c
+                join link_clicks l on l.id = c.click_id
+                where $1::text is null or l.link_id = $1
+                group by c.goal
+                order by "conversions!" desc, c.goal
+            "#,
+            link_id
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_conversions").increment(1))?;
+
+    Ok(ConversionStats {
+        tracked_clicks,
+        goals: goals
+            .into_iter()
+            .map(|r| GoalConversions {
+                goal: r.goal,
+                conversions: r.conversions,
+                conversion_rate: if tracked_clicks == 0 {
+                    0.0
+                } else {
+                    r.conversions as f64 / tracked_clicks as f64
+                },
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_goal() {
+        assert!(!validate_goal(""));
+        assert!(!validate_goal("sign up"));
+        assert!(!validate_goal("signup!"));
+        assert!(!validate_goal("😥"));
+        assert!(!validate_goal(&"a".repeat(MAX_GOAL_LENGTH + 1)));
+
+        assert!(validate_goal("signup"));
+        assert!(validate_goal("sign-up_v2.final"));
+        assert!(validate_goal(&"a".repeat(MAX_GOAL_LENGTH)));
+    }
+}
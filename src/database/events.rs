@@ -0,0 +1,354 @@
+use std::{sync::{Arc, OnceLock}, time::Duration};
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, postgres::PgListener};
+use tokio::{sync::{broadcast, mpsc}, time::MissedTickBehavior};
+use url::Url;
+use utoipa::ToSchema;
+
+use super::LinkStore;
+use crate::error::{Error, Result};
+
+/// PostgreSQL `NOTIFY` channel used to fan out redirect events to all
+/// instances.
+const CHANNEL_REDIRECT_EVENTS: &str = "redirect_events";
+/// PostgreSQL `NOTIFY` channel used by a trigger to notify all instances when
+/// a link changes.
+const CHANNEL_LINK_CHANGES: &str = "link_changes";
+/// Largest `NOTIFY` payload, which must be shorter than 8000 bytes.
+const MAX_NOTIFY_PAYLOAD_BYTES: usize = 7999;
+
+/// Coarse classification of the client which followed a shortened link.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum UserAgentClass {
+    Desktop,
+    Mobile,
+    Bot,
+    Cli,
+    #[default]
+    Unknown,
+}
+
+impl UserAgentClass {
+    /// Classify a client based on its `User-Agent` header.
+    pub fn from_user_agent(user_agent: Option<&str>) -> Self {
+        let Some(ua) = user_agent.map(str::to_lowercase) else {
+            return Self::Unknown;
+        };
+
+        if ["bot", "crawler", "spider", "preview"]
+            .iter()
+            .any(|s| ua.contains(s))
+        {
+            Self::Bot
+        } else if [
+            "curl/",
+            "wget/",
+            "httpie/",
+            "python-requests/",
+            "go-http-client/",
+        ]
+        .iter()
+        .any(|s| ua.contains(s))
+        {
+            Self::Cli
+        } else if ["mobile", "android", "iphone", "ipad"]
+            .iter()
+            .any(|s| ua.contains(s))
+        {
+            Self::Mobile
+        } else if ua.starts_with("mozilla/") {
+            Self::Desktop
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// A single redirect from a shortened link, without any identifying
+/// information about the client.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectEvent {
+    /// ID of the shortened link which was followed.
+    pub link_id: String,
+    /// Time of the redirect (UTC).
+    pub timestamp: DateTime<Utc>,
+    /// Coarse classification of the client.
+    pub user_agent: UserAgentClass,
+    /// Domain of the page which linked to the shortened link, if known.
+    pub referrer_domain: Option<String>,
+}
+
+impl RedirectEvent {
+    pub fn new(link_id: String, user_agent: Option<&str>, referrer: Option<&str>) -> Self {
+        Self {
+            link_id,
+            timestamp: Utc::now(),
+            user_agent: UserAgentClass::from_user_agent(user_agent),
+            referrer_domain: referrer
+                .and_then(|r| Url::parse(r).ok())
+                .and_then(|u| u.host_str().map(String::from)),
+        }
+    }
+}
+
+/// Split [`RedirectEvent`]s into JSON arrays which each fit in a single
+/// `NOTIFY` payload. Events which are too large on their own are dropped.
+fn redirect_event_payloads(events: &[RedirectEvent]) -> Result<Vec<String>> {
+    let mut payloads = Vec::new();
+    let mut payload = String::new();
+    for event in events {
+        let json = serde_json::to_string(event).map_err(|e| Error::Internal(e.to_string()))?;
+        // Room is left for the brackets around the array
+        if json.len() + 2 > MAX_NOTIFY_PAYLOAD_BYTES {
+            counter!("events.redirect_events_dropped").increment(1);
+            continue;
+        }
+        if !payload.is_empty() && payload.len() + json.len() + 2 > MAX_NOTIFY_PAYLOAD_BYTES {
+            payload.push(']');
+            payloads.push(std::mem::take(&mut payload));
+        }
+        payload.push(if payload.is_empty() { '[' } else { ',' });
+        payload.push_str(&json);
+    }
+    if !payload.is_empty() {
+        payload.push(']');
+        payloads.push(payload);
+    }
+
+    Ok(payloads)
+}
+
+/// Publish [`RedirectEvent`]s to all instances listening for them, with as
+/// few notifications as possible, in a single query.
+pub async fn publish_redirect_events(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    events: &[RedirectEvent],
+) -> Result<()> {
+    let payloads = redirect_event_payloads(events)?;
+    if payloads.is_empty() {
+        return Ok(());
+    }
+
+    tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            "select pg_notify($1, payload) from unnest($2::text[]) as payload",
+            CHANNEL_REDIRECT_EVENTS,
+            &payloads
+        )
+        .execute(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_publish_redirect_event").increment(1))?;
+
+    Ok(())
+}
+
+/// Publish the [`RedirectEvent`]s queued by redirects together, at most once
+/// per interval, until the queue is closed.
+///
+/// Publishing doesn't go through a circuit breaker, so that failing to
+/// publish events never fails requests.
+pub fn spawn_redirect_publisher(
+    store: Arc<dyn LinkStore>,
+    interval: Duration,
+    mut queue: mpsc::Receiver<RedirectEvent>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut events = Vec::new();
+        loop {
+            ticker.tick().await;
+            // Wait for an event, then take all the others queued with it
+            if queue.recv_many(&mut events, queue.max_capacity()).await == 0 {
+                break;
+            }
+            if let Err(e) = store.publish_redirect_events(&events).await {
+                counter!("events.redirect_events_dropped").increment(events.len() as u64);
+                tracing::error!("Failed to publish {} redirect events: {e}", events.len());
+            }
+            events.clear();
+        }
+    });
+}
+
+/// Change to a link which may be cached by any instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkChange {
+    /// The link with the given ID was changed or deleted.
+    Changed(String),
+    /// Notifications may have been missed, so any link could have changed.
+    Unknown,
+}
+
+/// Senders for events published by any instance.
+#[derive(Debug, Clone)]
+pub struct EventSenders {
+    pub redirects: broadcast::Sender<RedirectEvent>,
+    pub link_changes: broadcast::Sender<LinkChange>,
+}
+
+/// Sends events directly to local subscribers, for stores which are only used
+/// by a single instance. Events are dropped until listening starts.
+#[derive(Debug, Default)]
+pub(super) struct LocalEvents {
+    senders: OnceLock<EventSenders>,
+}
+
+impl LocalEvents {
+    /// Start sending events to the given channels.
+    pub fn listen(&self, senders: EventSenders) {
+        _ = self.senders.set(senders);
+    }
+
+    pub fn publish_redirect(&self, event: &RedirectEvent) {
+        // Sending only fails when there are no subscribers, which is fine
+        if let Some(senders) = self.senders.get() {
+            _ = senders.redirects.send(event.clone());
+        }
+    }
+
+    pub fn link_changed(&self, link_id: &str) {
+        if let Some(senders) = self.senders.get() {
+            _ = senders
+                .link_changes
+                .send(LinkChange::Changed(link_id.to_string()));
+        }
+    }
+}
+
+/// Listen for [`RedirectEvent`]s and [`LinkChange`]s published by any
+/// instance, forwarding them to the given channels.
+///
+/// This runs until the database pool is closed, reconnecting to the database
+/// whenever the connection is lost.
+pub async fn listen_events(db: Pool<Postgres>, senders: EventSenders) {
+    let mut listener = loop {
+        match PgListener::connect_with(&db).await {
+            Ok(mut listener) => match listener
+                .listen_all([CHANNEL_REDIRECT_EVENTS, CHANNEL_LINK_CHANGES])
+                .await
+            {
+                Ok(()) => break listener,
+                Err(e) => tracing::error!("Failed to listen for events: {e}"),
+            },
+            Err(sqlx::Error::PoolClosed) => return,
+            Err(e) => tracing::error!("Failed to connect event listener: {e}"),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    };
+
+    // Sending only fails when there are no subscribers, which is fine
+    loop {
+        match listener.recv().await {
+            Ok(notification) if notification.channel() == CHANNEL_LINK_CHANGES => {
+                _ = senders
+                    .link_changes
+                    .send(LinkChange::Changed(notification.payload().to_string()));
+            }
+            Ok(notification) => {
+                match serde_json::from_str::<Vec<RedirectEvent>>(notification.payload()) {
+                    Ok(events) => {
+                        for event in events {
+                            _ = senders.redirects.send(event);
+                        }
+                    }
+                    Err(e) => tracing::error!("Received malformed redirect events: {e}"),
+                }
+            }
+            Err(sqlx::Error::PoolClosed) => return,
+            Err(e) => {
+                // The listener reconnects on the next call to `recv`, but any
+                // notifications sent in the meantime are lost
+                tracing::error!("Lost connection while listening for events: {e}");
+                _ = senders.link_changes.send(LinkChange::Unknown);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_user_agent_class() {
+        for (ua, class) in [
+            (None, UserAgentClass::Unknown),
+            (Some(""), UserAgentClass::Unknown),
+            (Some("something"), UserAgentClass::Unknown),
+            (
+                Some("Mozilla/5.0 (X11; Linux x86_64; rv:139.0) Gecko/20100101 Firefox/139.0"),
+                UserAgentClass::Desktop,
+            ),
+            (
+                Some(
+                    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                ),
+                UserAgentClass::Mobile,
+            ),
+            (
+                Some("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"),
+                UserAgentClass::Bot,
+            ),
+            (Some("curl/8.7.1"), UserAgentClass::Cli),
+            (Some("python-requests/2.32.3"), UserAgentClass::Cli),
+        ] {
+            assert_eq!(UserAgentClass::from_user_agent(ua), class, "{ua:?}");
+        }
+    }
+
+    #[test]
+    fn test_redirect_event_referrer_domain() {
+        let event = RedirectEvent::new(
+            "abc".into(),
+            None,
+            Some("https://news.ycombinator.com/item?id=1"),
+        );
+        assert_eq!(
+            event.referrer_domain.as_deref(),
+            Some("news.ycombinator.com")
+        );
+
+        let event = RedirectEvent::new("abc".into(), None, Some("not a url"));
+        assert_eq!(event.referrer_domain, None);
+
+        let event = RedirectEvent::new("abc".into(), None, None);
+        assert_eq!(event.referrer_domain, None);
+    }
+
+    #[test]
+    fn test_redirect_event_payloads() {
+        assert!(redirect_event_payloads(&[]).unwrap().is_empty());
+
+        let event = RedirectEvent::new("abc".into(), Some("curl/8.7.1"), None);
+        let events = vec![event.clone(); 500];
+        let payloads = redirect_event_payloads(&events).unwrap();
+        assert!(payloads.len() > 1);
+        let mut published = Vec::new();
+        for payload in payloads {
+            assert!(payload.len() <= MAX_NOTIFY_PAYLOAD_BYTES);
+            published.extend(serde_json::from_str::<Vec<RedirectEvent>>(&payload).unwrap());
+        }
+        assert_eq!(published, events);
+
+        // Events which can't fit in a payload are dropped
+        let huge = RedirectEvent::new("a".repeat(MAX_NOTIFY_PAYLOAD_BYTES), None, None);
+        let payloads = redirect_event_payloads(&[huge, event.clone()]).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<RedirectEvent>>(&payloads[0]).unwrap(),
+            vec![event]
+        );
+    }
+}
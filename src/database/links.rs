@@ -1,17 +1,59 @@
-use axum_prometheus::metrics::counter;
+use std::{collections::HashMap, sync::{Arc, atomic::{AtomicU8, Ordering}}, time::Duration};
+
+use axum_prometheus::metrics::{counter, gauge};
 use block_id::{Alphabet, BlockId};
-use chrono::{NaiveDateTime, Utc};
+use chrono::{DateTime, SubsecRound, Utc};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, Transaction};
 use strum::IntoEnumIterator;
 use utoipa::ToSchema;
 
-use crate::{error::{Error, Result}, routes::Route, utils::get_default_db_timeout};
+use super::{Actor, LinkStore, add_revision, now};
+use crate::{error::{Error, Result}, routes::Route, time::{deserialize_optional_timestamp, deserialize_timestamp}};
 
 const CHARS: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
 
-#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+/// Length of generated IDs while the keyspace is mostly empty.
+const MIN_ID_LENGTH: u8 = 5;
+/// Length at which the keyspace still fits in a `u64`.
+const MAX_ID_LENGTH: u8 = 10;
+/// Share of the keyspace of a length which can be used before longer IDs are
+/// generated, which is also the chance of a generated ID colliding.
+const MAX_KEYSPACE_UTILISATION: f64 = 0.01;
+/// Number of collisions when creating a link after which longer IDs are
+/// generated.
+const COLLISIONS_BEFORE_GROWING: u32 = 3;
+/// Number of attempts to create a link with a generated ID before giving up.
+const MAX_ID_ATTEMPTS: u32 = 10;
+/// Length of IDs derived from the target URL.
+const HASH_ID_LENGTH: u8 = 7;
+/// Number of digits at the end of word IDs while there are few collisions.
+const MIN_WORD_ID_DIGITS: u32 = 2;
+/// Length of IDs of unlisted links, giving over 128 bits of entropy so that
+/// they can't be found by guessing.
+pub const UNLISTED_ID_LENGTH: usize = 22;
+
+/// Strategy used to generate link IDs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum IdStrategy {
+    /// Short random alphanumeric IDs, e.g. `aZ3k9`, which grow longer as the
+    /// keyspace fills up.
+    #[default]
+    Random,
+    /// Alphanumeric IDs encoding an increasing sequence number, e.g. `8fKq2`,
+    /// which grow longer as more links are created.
+    Sequential,
+    /// IDs derived from the target URL, e.g. `Qm4x0Ta`, so that shortening the
+    /// same URL again with the same options returns the existing link.
+    Hash,
+    /// Readable IDs made of words and a number, e.g. `BraveOtter42`.
+    Words,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, ToSchema, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct Link {
     /// ID of the shortened link.
@@ -21,17 +63,42 @@ pub struct Link {
     /// Count of successful redirects to [`Self::target_url`].
     pub count_redirects: i64,
     /// Shortened link creation time.
-    pub created_at: NaiveDateTime,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub created_at: DateTime<Utc>,
     /// Shortened link last modification time.
-    pub updated_at: NaiveDateTime,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub updated_at: DateTime<Utc>,
     /// Shortened link (optional) expiration time
-    pub expires_at: Option<NaiveDateTime>,
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the ID of the shortened link was provided by the user, rather
+    /// than generated.
+    pub is_custom_id: bool,
+    /// Whether redirects from the shortened link carry a click ID, which the
+    /// target site can use to report conversions.
+    pub track_conversions: bool,
+    /// Whether the shortened link has a long, unguessable ID and is left out
+    /// of listings of links.
+    pub is_unlisted: bool,
+    /// Time at which the next change scheduled for the shortened link is
+    /// made, if any.
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub next_change_at: Option<DateTime<Utc>>,
+    /// Whether the target URL and expiration time of the shortened link are
+    /// fixed for good, and it can't be deleted.
+    #[serde(default)]
+    pub is_immutable: bool,
+    /// Whether the shortened link is kept for an investigation, so that it
+    /// can't be deleted or purged. Only shown to admins, while it is set.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub legal_hold: bool,
 }
 
 impl Link {
     pub fn new(id: Option<String>, target_url: String) -> Self {
-        let id = id.unwrap_or_else(Link::generate_id);
-        let now = Utc::now().naive_utc();
+        let is_custom_id = id.is_some();
+        let id = id.unwrap_or_else(|| Link::generate_id(MIN_ID_LENGTH, rand::random()));
+        let now = Utc::now();
 
         Link {
             id,
@@ -40,50 +107,360 @@ impl Link {
             created_at: now,
             updated_at: now,
             expires_at: None,
+            is_custom_id,
+            track_conversions: false,
+            is_unlisted: false,
+            next_change_at: None,
+            is_immutable: false,
+            legal_hold: false,
+        }
+    }
+
+    /// Time until which this version of the link can be used for redirects,
+    /// which is when it expires or its next scheduled change is made,
+    /// whichever is first.
+    pub fn valid_until(&self) -> Option<DateTime<Utc>> {
+        self.expires_at.into_iter().chain(self.next_change_at).min()
+    }
+
+    /// Check that the link can be deleted, which it can't be while it is
+    /// immutable or on legal hold.
+    pub fn check_deletable(&self) -> Result<()> {
+        if self.is_immutable {
+            return Err(Error::LinkImmutable(self.id.clone()));
+        }
+        if self.legal_hold {
+            return Err(Error::LinkOnLegalHold(self.id.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// The link as shown to anyone other than admins, without whether it is
+    /// on legal hold.
+    pub fn for_public(self) -> Self {
+        Self {
+            legal_hold: false,
+            ..self
         }
     }
 
-    fn generate_id() -> String {
-        // TODO: investigate whether this is safe to do - is there any issue with being
-        // able to work out the random number from the link ID?
-        const SEED: u128 = 1234;
-        const LENGTH: u8 = 5;
+    /// Check that the link can be replaced by the given version of it from a
+    /// backup, or removed if the backup doesn't have it. Links on legal hold
+    /// must stay on hold, and immutable links must stay immutable with the
+    /// same target URL and expiration time.
+    pub fn check_restorable(&self, restored: Option<&Link>) -> Result<()> {
+        if self.is_immutable
+            && restored.is_none_or(|r| {
+                !r.is_immutable
+                    || r.target_url != self.target_url
+                    || r.expires_at != self.expires_at
+            })
+        {
+            return Err(Error::LinkImmutable(self.id.clone()));
+        }
+        if self.legal_hold && restored.is_none_or(|r| !r.legal_hold) {
+            return Err(Error::LinkOnLegalHold(self.id.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Generate a random ID of the given length.
+    ///
+    /// The seed scrambles how numbers are encoded, and must be kept secret so
+    /// that IDs can't be mapped back to the numbers they encode.
+    fn generate_id(length: u8, seed: u128) -> String {
+        let length = length.clamp(MIN_ID_LENGTH, MAX_ID_LENGTH);
+        let block_id = BlockId::new(Alphabet::alphanumeric(), seed, length);
+
+        // Numbers within the keyspace are always encoded with exactly `length`
+        // characters. Candidates are only very rarely rejected, e.g. if they
+        // match a route.
+        loop {
+            let random_number = rand::rng().random_range(0..keyspace_size(length));
+            let id = block_id
+                .encode_string(random_number)
+                .expect("could not encode random number as the short link ID");
+
+            if Link::validate_id(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Generate an ID encoding the given sequence number.
+    fn sequential_id(n: u64, seed: u128) -> Option<String> {
+        BlockId::new(Alphabet::alphanumeric(), seed, MIN_ID_LENGTH)
+            .encode_string(n)
+            .filter(|id| Link::validate_id(id))
+    }
+
+    /// Generate an ID derived from the target URL. Different salts give
+    /// different IDs for the same URL, in case of collisions.
+    ///
+    /// The seed is hashed along with the URL, so that IDs can't be computed
+    /// for URLs without knowing it.
+    fn hash_id(target_url: &str, salt: u32, seed: u128) -> String {
+        let block_id = BlockId::new(Alphabet::alphanumeric(), seed, HASH_ID_LENGTH);
+        (salt..)
+            .map(|salt| {
+                let mut hasher = Sha256::new();
+                hasher.update(seed.to_be_bytes());
+                hasher.update(target_url.as_bytes());
+                if salt > 0 {
+                    hasher.update(salt.to_be_bytes());
+                }
+                let digest: [u8; 8] = hasher.finalize()[..8]
+                    .try_into()
+                    .expect("digest should be longer than 8 bytes");
+
+                block_id
+                    .encode_string(u64::from_be_bytes(digest) % keyspace_size(HASH_ID_LENGTH))
+                    .expect("could not encode hash as the short link ID")
+            })
+            .find(|id| Link::validate_id(id))
+            .expect("some salt should give a valid ID")
+    }
+
+    /// Generate a readable ID from capitalised words and a number with the
+    /// given number of digits. The words aren't separated, so that the ID is
+    /// valid like any other.
+    fn words_id(digits: u32) -> String {
+        let mut rng = rand::rng();
+        loop {
+            let id = format!(
+                "{}{}{}",
+                capitalise(ID_ADJECTIVES[rng.random_range(0..ID_ADJECTIVES.len())]),
+                capitalise(ID_NOUNS[rng.random_range(0..ID_NOUNS.len())]),
+                rng.random_range(0..10u64.pow(digits.min(18)))
+            );
+
+            if Link::validate_id(&id) {
+                return id;
+            }
+        }
+    }
 
-        let random_number = u64::from(rand::rng().random_range(0..u32::MAX));
-        let id = BlockId::new(Alphabet::alphanumeric(), SEED, LENGTH)
-            .encode_string(random_number)
-            .expect("could not encode random number as the short link ID");
+    /// Generate a long random ID for an unlisted link.
+    fn unlisted_id() -> String {
+        let mut rng = rand::rng();
+        loop {
+            let id: String = (0..UNLISTED_ID_LENGTH)
+                .map(|_| char::from(CHARS.as_bytes()[rng.random_range(0..CHARS.len())]))
+                .collect();
 
-        for _ in 0..5 {
             if Link::validate_id(&id) {
                 return id;
             }
         }
-        unreachable!("something wrong with ID generation");
     }
 
-    fn validate_id(id: &str) -> bool {
+    /// Whether the ID can be used for a link.
+    pub fn validate_id(id: &str) -> bool {
         !id.is_empty()
         // Does not contain non-alphanumeric characters
         && !id.chars().any(|c| !CHARS.chars().any(|cc| c == cc))
-        // Does not match any existing routes
-        && !Route::iter().any(|r| {
-                let mut r = r.as_str().trim_start_matches("/");
-                if r.contains("/") {
-                    r = r.split_once("/").expect("should contain /").0;
-                };
-
-                id.to_lowercase() == r.to_lowercase()
+        && !Link::is_reserved_id(id)
+    }
+
+    /// Whether the ID matches any existing routes, either as the first
+    /// segment of a path, or in place of the ID in `/links/{link_id}`.
+    fn is_reserved_id(id: &str) -> bool {
+        let id = id.to_lowercase();
+        Route::iter().any(|r| {
+            let mut segments = r.as_str().trim_start_matches("/").split("/");
+            let first = segments.next().unwrap_or_default();
+            let second = segments.next().filter(|s| !s.starts_with("{"));
+
+            id == first.to_lowercase()
+                || (first == "links" && second.is_some_and(|s| id == s.to_lowercase()))
         })
     }
 }
 
+/// Uppercase the first letter of a word.
+fn capitalise(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Number of possible IDs with the given length.
+fn keyspace_size(length: u8) -> u64 {
+    (CHARS.len() as u64).pow(length.into())
+}
+
+/// Shortest ID length whose keyspace is not too full, given the number of
+/// existing links with IDs of each length.
+fn id_length_for(counts: &HashMap<u8, i64>) -> u8 {
+    (MIN_ID_LENGTH..MAX_ID_LENGTH)
+        .find(|length| keyspace_utilisation(*length, counts) < MAX_KEYSPACE_UTILISATION)
+        .unwrap_or(MAX_ID_LENGTH)
+}
+
+fn keyspace_utilisation(length: u8, counts: &HashMap<u8, i64>) -> f64 {
+    counts.get(&length).copied().unwrap_or_default() as f64 / keyspace_size(length) as f64
+}
+
+/// Generates link IDs using an [`IdStrategy`], shared between all requests.
+///
+/// The length of random IDs grows as the keyspace fills up, keeping
+/// collisions between generated IDs rare.
+#[derive(Debug, Clone)]
+pub struct IdGenerator {
+    strategy: IdStrategy,
+    seed: u128,
+    length: Arc<AtomicU8>,
+}
+
+impl Default for IdGenerator {
+    fn default() -> Self {
+        Self::new(IdStrategy::default(), None)
+    }
+}
+
+impl IdGenerator {
+    /// Create a generator using the given strategy, unless another is chosen
+    /// when creating a link.
+    ///
+    /// IDs are scrambled using a seed derived from the given secret. A random
+    /// seed is used if there is no secret, in which case sequential IDs and
+    /// IDs derived from the target URL differ between instances and restarts.
+    pub fn new(strategy: IdStrategy, secret: Option<&str>) -> Self {
+        let seed = match secret {
+            Some(secret) => {
+                let digest: [u8; 16] = Sha256::digest(secret.as_bytes())[..16]
+                    .try_into()
+                    .expect("digest should be longer than 16 bytes");
+                u128::from_be_bytes(digest)
+            }
+            None => rand::random(),
+        };
+
+        Self {
+            strategy,
+            seed,
+            length: Arc::new(AtomicU8::new(MIN_ID_LENGTH)),
+        }
+    }
+
+    /// Generate a candidate ID for a link. `collisions` is the number of
+    /// candidates for the same link which were already taken.
+    async fn generate(
+        &self,
+        store: &dyn LinkStore,
+        strategy: IdStrategy,
+        target_url: &str,
+        length: u8,
+        collisions: u32,
+    ) -> Result<String> {
+        Ok(match strategy {
+            IdStrategy::Random => Link::generate_id(length, self.seed),
+            IdStrategy::Sequential => loop {
+                let n = store.next_sequence_number().await?;
+                if let Some(id) = Link::sequential_id(n, self.seed) {
+                    break id;
+                }
+            },
+            IdStrategy::Hash => Link::hash_id(target_url, collisions, self.seed),
+            IdStrategy::Words => {
+                Link::words_id(MIN_WORD_ID_DIGITS + collisions / COLLISIONS_BEFORE_GROWING)
+            }
+        })
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length.load(Ordering::Relaxed)
+    }
+
+    /// Generate longer IDs from now on, after collisions with IDs of the
+    /// given length.
+    fn grow(&self, from_length: u8) -> u8 {
+        let length = (from_length + 1).min(MAX_ID_LENGTH);
+        self.length.fetch_max(length, Ordering::Relaxed);
+        tracing::info!("Generating link IDs with length {length} after repeated collisions");
+        length
+    }
+
+    /// Recompute the utilisation of the keyspace from the store, and the
+    /// length of generated IDs from it. IDs never get shorter, so growth after
+    /// collisions is kept.
+    pub async fn refresh(&self, store: &dyn LinkStore) -> Result<()> {
+        let counts = store.count_link_ids(MIN_ID_LENGTH, MAX_ID_LENGTH).await?;
+
+        let length = id_length_for(&counts);
+        let length = self.length.fetch_max(length, Ordering::Relaxed).max(length);
+        gauge!("links.id_length").set(length);
+        gauge!("links.id_keyspace_utilisation").set(keyspace_utilisation(length, &counts));
+
+        Ok(())
+    }
+
+    /// Spawn a task which refreshes the keyspace utilisation at the given
+    /// interval, until the store is closed.
+    pub fn spawn_refresh(&self, store: Arc<dyn LinkStore>, interval: Duration) {
+        let generator = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            while !store.is_closed() {
+                interval.tick().await;
+                if let Err(e) = generator.refresh(store.as_ref()).await {
+                    tracing::error!("Failed to refresh link ID keyspace utilisation: {e}");
+                }
+            }
+        });
+    }
+}
+
+/// A link to be created with [`create_link`].
+#[derive(Debug, Default, Clone)]
+pub struct NewLink {
+    pub target_url: String,
+    /// ID provided by the user, which is generated if not provided.
+    pub custom_id: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub track_conversions: bool,
+    /// Strategy used to generate the ID, instead of the generator's default.
+    pub id_strategy: Option<IdStrategy>,
+    /// Whether the link gets a long, unguessable ID and is left out of
+    /// listings. Any ID strategy is ignored for unlisted links.
+    pub unlisted: bool,
+    /// Creation time to keep for a link imported from elsewhere, instead of
+    /// the current time.
+    pub created_at: Option<DateTime<Utc>>,
+    /// Number of redirects to keep for a link imported from elsewhere.
+    pub count_redirects: i64,
+    /// Who is creating the link, as recorded in its first revision.
+    pub created_by: Actor,
+}
+
+/// Create a new link, with an ID generated using the given strategy (or the
+/// generator's default) unless one is provided.
+///
+/// Generated IDs which are already taken are retried with new IDs. With
+/// [`IdStrategy::Hash`], an existing link with the same target URL, expiration
+/// time and conversion tracking is returned instead.
 pub async fn create_link(
-    db: &Pool<Postgres>,
-    link_target: String,
-    link_id: Option<String>,
-    expiration_time: Option<NaiveDateTime>,
+    store: &dyn LinkStore,
+    id_generator: &IdGenerator,
+    new_link: NewLink,
 ) -> Result<Link> {
+    let NewLink {
+        target_url: link_target,
+        custom_id: link_id,
+        expires_at: expiration_time,
+        track_conversions,
+        id_strategy,
+        unlisted,
+        created_at,
+        count_redirects,
+        created_by,
+    } = new_link;
+    let strategy = id_strategy.unwrap_or(id_generator.strategy);
+
     // User provided invalid link ID
     if let Some(id) = link_id.as_ref()
         && !Link::validate_id(id)
@@ -91,50 +468,260 @@ pub async fn create_link(
         return Err(Error::LinkIdNotValid(id.clone()));
     };
 
+    // Custom IDs would defeat the point of unlisted links
+    if unlisted && link_id.is_some() {
+        return Err(Error::InvalidRequest(
+            "unlisted links can't have a custom ID".into(),
+        ));
+    }
+
     // User provided invalid expiration time
     if let Some(exp) = expiration_time.as_ref() {
-        let now = Utc::now().naive_utc();
+        let now = Utc::now();
         if now >= *exp {
             return Err(Error::LinkExpirationTimeNotValid(*exp));
         }
     }
 
-    tokio::time::timeout(
-        get_default_db_timeout(),
-        sqlx::query_as!(
+    let mut length = id_generator.length();
+    let mut collisions = 0;
+    for _ in 0..MAX_ID_ATTEMPTS {
+        let id = match link_id.as_ref() {
+            Some(id) => id.clone(),
+            None if unlisted => Link::unlisted_id(),
+            None => {
+                id_generator
+                    .generate(store, strategy, &link_target, length, collisions)
+                    .await?
+            }
+        };
+
+        // Stored with the precision of the database, so the link matches
+        // what is read back later
+        let now = now();
+        let link = Link {
+            id,
+            target_url: link_target.clone(),
+            count_redirects,
+            created_at: created_at.unwrap_or(now),
+            updated_at: now,
+            expires_at: expiration_time,
+            is_custom_id: link_id.is_some(),
+            track_conversions,
+            is_unlisted: unlisted,
+            next_change_at: None,
+            is_immutable: false,
+            legal_hold: false,
+        };
+
+        let inserted = store
+            .insert_link(&link, created_by)
+            .await
+            .inspect_err(|_| counter!("db.saving_link_impossible").increment(1))?;
+        if let Some(link) = inserted {
+            return Ok(link);
+        }
+
+        // Provided custom ID already exists in the database
+        if let Some(link_id) = link_id {
+            counter!("db.user_provided_taken_id").increment(1);
+            return Err(Error::LinkIdNotUnique(link_id));
+        }
+
+        // The same URL was already shortened with the same options, otherwise
+        // the link is treated as a collision
+        if strategy == IdStrategy::Hash
+            && !unlisted
+            && let Some(existing) = store.get_link(&link.id).await?
+            && existing.target_url == link_target
+            && existing.expires_at == expiration_time.map(|e| e.trunc_subsecs(6))
+            && existing.track_conversions == track_conversions
+            && existing.expires_at.is_none_or(|e| e > Utc::now())
+        {
+            return Ok(existing);
+        }
+
+        // Generated ID already exists, so try again with a new one
+        counter!("db.generated_id_collisions").increment(1);
+        collisions += 1;
+        if strategy == IdStrategy::Random
+            && !unlisted
+            && collisions % COLLISIONS_BEFORE_GROWING == 0
+        {
+            length = id_generator.grow(length);
+        }
+    }
+
+    counter!("db.saving_link_impossible").increment(1);
+    Err(Error::Internal(format!(
+        "Could not generate a unique link ID after {MAX_ID_ATTEMPTS} attempts"
+    )))
+}
+
+/// Insert a new [`Link`] into the database along with its first revision,
+/// returning [`None`] if its ID is already taken.
+pub async fn insert_link(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link: &Link,
+    created_by: Actor,
+) -> Result<Option<Link>> {
+    let result = tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        let link = sqlx::query_as!(
             Link,
             r#"
-                insert into links(target_url, id, expires_at)
-                values ($1, $2, $3)
+                insert into links(
+                    id, target_url, count_redirects, created_at, updated_at, expires_at,
+                    is_custom_id, track_conversions, is_unlisted
+                )
+                values ($1, $2, $3::bigint, $4, $5, $6, $7, $8, $9)
                 returning *
             "#,
-            link_target,
-            link_id.clone().unwrap_or_else(Link::generate_id),
-            expiration_time
+            link.id,
+            link.target_url,
+            link.count_redirects,
+            link.created_at,
+            link.updated_at,
+            link.expires_at,
+            link.is_custom_id,
+            link.track_conversions,
+            link.is_unlisted
         )
-        .fetch_one(db),
-    )
+        .fetch_one(&mut *tx)
+        .await?;
+        add_revision(&mut tx, &link, created_by, None).await?;
+
+        tx.commit().await?;
+
+        Ok::<_, sqlx::Error>(link)
+    })
     .await
-    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
-    .map_err(|e| {
-        // Provided custom ID already exists in the database
-        if let sqlx::Error::Database(db_err) = &e
-            && let Some(link_id) = link_id
-            && db_err.kind() == sqlx::error::ErrorKind::UniqueViolation
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?;
+
+    match result {
+        Ok(link) => Ok(Some(link)),
+        Err(sqlx::Error::Database(db_err))
+            if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
         {
-            counter!("db.user_provided_taken_id").increment(1);
-            return Error::LinkIdNotUnique(link_id);
+            Ok(None)
         }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Insert a [`Link`] into the database, replacing any existing link with the
+/// same ID unless it is immutable, and record it as the link's next revision.
+///
+/// Whether the existing link is immutable or on legal hold is kept.
+pub async fn replace_link(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link: &Link,
+    changed_by: Actor,
+) -> Result<Link> {
+    tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        let link = sqlx::query_as!(
+            Link,
+            r#"
+                insert into links(
+                    id, target_url, count_redirects, created_at, updated_at, expires_at,
+                    is_custom_id, track_conversions, is_unlisted
+                )
+                values ($1, $2, $3::bigint, $4, $5, $6, $7, $8, $9)
+                on conflict (id) do update
+                set target_url = excluded.target_url,
+                    count_redirects = excluded.count_redirects,
+                    created_at = excluded.created_at,
+                    expires_at = excluded.expires_at,
+                    is_custom_id = excluded.is_custom_id,
+                    track_conversions = excluded.track_conversions,
+                    is_unlisted = excluded.is_unlisted
+                where not links.is_immutable
+                returning *
+            "#,
+            link.id,
+            link.target_url,
+            link.count_redirects,
+            link.created_at,
+            link.updated_at,
+            link.expires_at,
+            link.is_custom_id,
+            link.track_conversions,
+            link.is_unlisted
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::LinkImmutable(link.id.clone()))?;
+        add_revision(&mut tx, &link, changed_by, None).await?;
+
+        tx.commit().await?;
 
-        counter!("db.saving_link_impossible").increment(1);
-        e.into()
+        Ok::<_, Error>(link)
+    })
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|e| {
+        if matches!(e, Error::Internal(_)) {
+            counter!("db.failed_to_replace_link").increment(1);
+        }
     })
 }
 
+/// Get the next number of the sequence encoded into sequential IDs.
+pub async fn next_link_sequence_number(db: &Pool<Postgres>, timeout: Duration) -> Result<u64> {
+    let n = tokio::time::timeout(
+        timeout,
+        sqlx::query_scalar!(r#"select nextval('link_id_seq') as "n!""#).fetch_one(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_generate_sequential_id").increment(1))?;
+
+    Ok(n as u64)
+}
+
+/// Count links in the database with IDs of each length between the given
+/// lengths (inclusive).
+pub async fn count_link_ids(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    min_length: u8,
+    max_length: u8,
+) -> Result<HashMap<u8, i64>> {
+    Ok(tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                select length(id) as "length!", count(*) as "count!"
+                from links
+                where length(id) between $1 and $2
+                group by 1
+            "#,
+            i32::from(min_length),
+            i32::from(max_length)
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_count_link_ids").increment(1))?
+    .into_iter()
+    .map(|r| (r.length as u8, r.count))
+    .collect())
+}
+
 /// Find an existing [`Link`] in the database with the given ID.
-pub async fn get_link(db: &Pool<Postgres>, link_id: impl AsRef<str>) -> Result<Option<Link>> {
+pub async fn get_link(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: impl AsRef<str>,
+) -> Result<Option<Link>> {
     tokio::time::timeout(
-        get_default_db_timeout(),
+        timeout,
         sqlx::query_as!(
             Link,
             r#"select * from links where id = $1"#,
@@ -148,13 +735,17 @@ pub async fn get_link(db: &Pool<Postgres>, link_id: impl AsRef<str>) -> Result<O
     .map_err(Error::from)
 }
 
-/// Get all existing [`Link`]s in the database.
-pub async fn get_links(db: &Pool<Postgres>) -> Result<Vec<Link>> {
+/// Get all existing [`Link`]s in the database, except unlisted links.
+pub async fn get_links(db: &Pool<Postgres>, timeout: Duration) -> Result<Vec<Link>> {
     tokio::time::timeout(
-        get_default_db_timeout(),
+        timeout,
         sqlx::query_as!(
             Link,
-            r#"select * from links where expires_at is null or expires_at > now()"#,
+            r#"
+                select * from links
+                where (expires_at is null or expires_at > now()) and not is_unlisted
+                order by created_at, id
+            "#,
         )
         .fetch_all(db),
     )
@@ -164,20 +755,237 @@ pub async fn get_links(db: &Pool<Postgres>) -> Result<Vec<Link>> {
     .map_err(Error::from)
 }
 
+/// Get all active [`Link`]s in the database, including unlisted links.
+pub async fn get_active_links(db: &Pool<Postgres>, timeout: Duration) -> Result<Vec<Link>> {
+    tokio::time::timeout(
+        timeout,
+        sqlx::query_as!(
+            Link,
+            r#"
+                select * from links
+                where expires_at is null or expires_at > now()
+                order by id
+            "#,
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_link").increment(1))
+    .map_err(Error::from)
+}
+
+/// Changes to the user-editable fields of a [`Link`]. Fields which are
+/// [`None`] are left unchanged.
+#[derive(Debug, Default, Clone)]
+pub struct LinkUpdate {
+    pub target_url: Option<String>,
+    /// New expiration time, where `Some(None)` removes the expiration.
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub track_conversions: Option<bool>,
+    /// Whether the link is immutable. Immutable links can't be made mutable
+    /// again.
+    pub immutable: Option<bool>,
+    pub legal_hold: Option<bool>,
+    /// Who is making the change, as recorded in the link's history.
+    pub changed_by: Actor,
+    /// Revision being restored, if the change is a rollback.
+    pub restored_revision: Option<i32>,
+}
+
+impl LinkUpdate {
+    /// Check that the new expiration time, if any, is in the future.
+    pub fn validate(&self) -> Result<()> {
+        match self.expires_at {
+            Some(Some(exp)) if Utc::now() >= exp => Err(Error::LinkExpirationTimeNotValid(exp)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the update can be made to the given link, which is
+    /// rejected if it would change the target URL or expiration time of an
+    /// immutable link.
+    ///
+    /// Links can only be made immutable if they don't expire and have no
+    /// scheduled changes, so that they are guaranteed to never change.
+    pub fn check(&self, link: &Link) -> Result<()> {
+        if link.is_immutable {
+            let changes_target = self
+                .target_url
+                .as_ref()
+                .is_some_and(|url| *url != link.target_url);
+            let changes_expiry = self
+                .expires_at
+                .is_some_and(|exp| exp.map(|e| e.trunc_subsecs(6)) != link.expires_at);
+            if changes_target || changes_expiry || self.immutable == Some(false) {
+                return Err(Error::LinkImmutable(link.id.clone()));
+            }
+        } else if self.immutable == Some(true) {
+            if self.expires_at.unwrap_or(link.expires_at).is_some() {
+                return Err(Error::InvalidRequest("immutable links can't expire".into()));
+            }
+            if link.next_change_at.is_some() {
+                return Err(Error::InvalidRequest(
+                    "immutable links can't have scheduled changes".into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.target_url.is_none()
+            && self.expires_at.is_none()
+            && self.track_conversions.is_none()
+            && self.immutable.is_none()
+            && self.legal_hold.is_none()
+    }
+}
+
+/// Update the link with the given ID, if it passes the precondition.
+///
+/// The link is locked while the precondition is checked, so that concurrent
+/// changes can't be lost.
+pub async fn update_link(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: &str,
+    update: LinkUpdate,
+    precondition: impl FnOnce(&Link) -> Result<()>,
+) -> Result<Link> {
+    update.validate()?;
+
+    tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        let link = lock_link(&mut tx, link_id).await?;
+        precondition(&link)?;
+        update.check(&link)?;
+        if update.is_empty() {
+            return Ok(link);
+        }
+
+        let link = sqlx::query_as!(
+            Link,
+            r#"
+                update links set
+                    target_url = coalesce($2, target_url),
+                    expires_at = case when $3 then $4 else expires_at end,
+                    track_conversions = coalesce($5, track_conversions),
+                    is_immutable = coalesce($6, is_immutable),
+                    legal_hold = coalesce($7, legal_hold)
+                where id = $1
+                returning *
+            "#,
+            link_id,
+            update.target_url,
+            update.expires_at.is_some(),
+            update.expires_at.flatten(),
+            update.track_conversions,
+            update.immutable,
+            update.legal_hold
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        add_revision(&mut tx, &link, update.changed_by, update.restored_revision).await?;
+
+        tx.commit().await?;
+
+        Ok::<_, Error>(link)
+    })
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|e| {
+        if matches!(e, Error::Internal(_)) {
+            counter!("db.failed_to_update_link").increment(1);
+        }
+    })
+}
+
+/// Delete the link with the given ID, if it passes the precondition.
+pub async fn delete_link(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: &str,
+    precondition: impl FnOnce(&Link) -> Result<()>,
+) -> Result<()> {
+    tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        let link = lock_link(&mut tx, link_id).await?;
+        precondition(&link)?;
+        link.check_deletable()?;
+
+        sqlx::query!("delete from links where id = $1", link_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok::<_, Error>(())
+    })
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|e| {
+        if matches!(e, Error::Internal(_)) {
+            counter!("db.failed_to_delete_link").increment(1);
+        }
+    })
+}
+
+/// Find the link with the given ID, locking it until the end of the
+/// transaction.
+pub(super) async fn lock_link(tx: &mut Transaction<'_, Postgres>, link_id: &str) -> Result<Link> {
+    sqlx::query_as!(
+        Link,
+        r#"select * from links where id = $1 for update"#,
+        link_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .ok_or_else(|| Error::LinkNotFound(link_id.to_string()))
+}
+
 /// Increment [`Link::count_redirects`], returning [`None`] if no link with the
 /// given ID was found.
+///
+/// The redirect is also recorded in the hourly roll-up table, which is used
+/// for ranking links over a time window.
 pub async fn increment_link_redirect_count(
     db: &Pool<Postgres>,
+    timeout: Duration,
     link_id: impl AsRef<str>,
 ) -> Result<Option<Link>> {
     let link = tokio::time::timeout(
-        get_default_db_timeout(),
+        timeout,
         sqlx::query_as!(
             Link,
             r#"
-                update links set count_redirects = count_redirects + 1
-                where id = $1 and (expires_at is null or expires_at > now())
-                returning *
+                with link as (
+                    update links set count_redirects = count_redirects + 1
+                    where id = $1 and (expires_at is null or expires_at > now())
+                    returning *
+                ), rollup as (
+                    insert into link_redirects_hourly (link_id, bucket, count_redirects)
+                    select id, date_trunc('hour', now(), 'UTC'), 1 from link
+                    on conflict (link_id, bucket) do update
+                    set count_redirects = link_redirects_hourly.count_redirects + 1
+                )
+                select
+                    id as "id!",
+                    target_url as "target_url!",
+                    count_redirects as "count_redirects!",
+                    created_at as "created_at!",
+                    updated_at as "updated_at!",
+                    expires_at,
+                    is_custom_id as "is_custom_id!",
+                    track_conversions as "track_conversions!",
+                    is_unlisted as "is_unlisted!",
+                    next_change_at,
+                    is_immutable as "is_immutable!",
+                    legal_hold as "legal_hold!"
+                from link
             "#,
             link_id.as_ref()
         )
@@ -209,9 +1017,85 @@ pub async fn increment_link_redirect_count(
     Ok(link)
 }
 
+/// Add the given number of redirects to the [`Link::count_redirects`] of each
+/// link, for redirects which were served without querying the database.
+///
+/// Redirects are recorded in the hourly roll-up table under the current hour.
+pub async fn add_link_redirect_counts(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    counts: &HashMap<String, i64>,
+) -> Result<()> {
+    let (ids, counts): (Vec<_>, Vec<_>) = counts.iter().map(|(id, n)| (id.clone(), *n)).unzip();
+
+    tokio::time::timeout(
+        timeout,
+        sqlx::query!(
+            r#"
+                with counts as (
+                    select * from unnest($1::text[], $2::bigint[]) as c(id, n)
+                ), link as (
+                    update links l set count_redirects = l.count_redirects + c.n
+                    from counts c
+                    where l.id = c.id
+                    returning l.id
+                )
+                insert into link_redirects_hourly (link_id, bucket, count_redirects)
+                select c.id, date_trunc('hour', now(), 'UTC'), c.n
+                from counts c join link l on l.id = c.id
+                on conflict (link_id, bucket) do update
+                set count_redirects = link_redirects_hourly.count_redirects + excluded.count_redirects
+            "#,
+            &ids,
+            &counts
+        )
+        .execute(db),
+    )
+    .await
+    .inspect_err(|e| {
+        counter!("db.failed_to_increment_link").increment(1);
+        tracing::error!("Adding link redirect counts resulted in a timeout: {e}");
+    })?
+    .inspect_err(|e| {
+        counter!("db.failed_to_increment_link").increment(1);
+        tracing::error!("Adding link redirect counts resulted in the following error: {e}");
+    })?;
+
+    tracing::debug!("Added redirect counts for {} links", ids.len());
+
+    Ok(())
+}
+
+/// Adjectives used in word IDs.
+const ID_ADJECTIVES: &[&str] = &[
+    "able", "bold", "brave", "bright", "calm", "clever", "cosy", "crisp", "curious", "daring",
+    "eager", "early", "fair", "fancy", "fast", "fierce", "fond", "free", "fresh", "gentle", "glad",
+    "golden", "grand", "happy", "hardy", "humble", "jolly", "keen", "kind", "lively", "loyal",
+    "lucky", "merry", "mighty", "modest", "neat", "nimble", "noble", "polite", "proud", "quick",
+    "quiet", "rapid", "ready", "rosy", "royal", "shiny", "silent", "silly", "sleek", "smart",
+    "snug", "steady", "sunny", "swift", "tidy", "tiny", "vivid", "warm", "wise", "witty", "young",
+    "zany", "zesty",
+];
+
+/// Nouns used in word IDs.
+const ID_NOUNS: &[&str] = &[
+    "badger", "bear", "beaver", "bison", "camel", "cat", "cobra", "crane", "crow", "deer", "dingo",
+    "dolphin", "dove", "eagle", "falcon", "ferret", "finch", "fox", "frog", "gecko", "goat",
+    "goose", "hare", "hawk", "heron", "horse", "ibis", "koala", "lark", "lemur", "lion", "llama",
+    "lynx", "marten", "mole", "moose", "newt", "otter", "owl", "panda", "parrot", "pelican",
+    "penguin", "puffin", "quail", "rabbit", "raven", "robin", "salmon", "seal", "shark", "sloth",
+    "sparrow", "swan", "tiger", "toad", "trout", "turtle", "walrus", "whale", "wolf", "wombat",
+    "yak", "zebra",
+];
+
 #[cfg(test)]
 mod test {
+    use std::collections::HashSet;
+
+    use pretty_assertions::assert_eq;
+
     use super::*;
+    use crate::database::MemoryStore;
 
     #[test]
     fn test_validate_link_id() {
@@ -226,19 +1110,273 @@ mod test {
         assert!(!Link::validate_id("Health"));
         assert!(!Link::validate_id("links"));
         assert!(!Link::validate_id("lInKs"));
+        // Links with these IDs couldn't be reached under /links/{link_id}
+        for id in ["top", "export", "import", "redirect-map", "Top"] {
+            assert!(Link::is_reserved_id(id), "{id}");
+        }
 
         assert!(Link::validate_id("abc"));
         assert!(Link::validate_id("alkw13"));
         assert!(Link::validate_id("BAD"));
     }
 
+    const SEED: u128 = 1234;
+
     #[test]
     fn test_generate_link_id() {
-        for _ in 0..1000 {
-            let id = Link::generate_id();
-            assert!(!id.is_empty());
-            assert!(!id.len() >= 4);
+        for length in [MIN_ID_LENGTH, 7, MAX_ID_LENGTH] {
+            for _ in 0..1000 {
+                let id = Link::generate_id(length, SEED);
+                assert_eq!(id.len(), usize::from(length));
+                assert!(Link::validate_id(&id));
+            }
+        }
+
+        // Generates new candidates rather than repeating the same ID
+        let ids: HashSet<_> = (0..100)
+            .map(|_| Link::generate_id(MIN_ID_LENGTH, SEED))
+            .collect();
+        assert!(ids.len() > 1);
+    }
+
+    #[test]
+    fn test_unlisted_link_id() {
+        let ids: HashSet<_> = (0..1000).map(|_| Link::unlisted_id()).collect();
+        assert_eq!(ids.len(), 1000);
+        for id in ids {
+            assert_eq!(id.len(), UNLISTED_ID_LENGTH);
             assert!(Link::validate_id(&id));
         }
     }
+
+    #[test]
+    fn test_link_id_seed() {
+        // The same secret always gives the same seed
+        let generator = IdGenerator::new(IdStrategy::Hash, Some("secret"));
+        assert_eq!(
+            IdGenerator::new(IdStrategy::Random, Some("secret")).seed,
+            generator.seed
+        );
+        assert_ne!(
+            IdGenerator::new(IdStrategy::Hash, Some("other")).seed,
+            generator.seed
+        );
+        assert_ne!(
+            IdGenerator::new(IdStrategy::Hash, None).seed,
+            generator.seed
+        );
+
+        // Different seeds encode numbers differently
+        assert_ne!(Link::sequential_id(1, 1), Link::sequential_id(1, 2));
+        assert_ne!(
+            Link::hash_id("https://crates.io/", 0, 1),
+            Link::hash_id("https://crates.io/", 0, 2)
+        );
+    }
+
+    #[test]
+    fn test_sequential_link_id() {
+        let ids: HashSet<_> = (0..1000)
+            .filter_map(|n| Link::sequential_id(n, SEED))
+            .collect();
+        assert!(ids.len() >= 999);
+        for id in ids {
+            assert_eq!(id.len(), usize::from(MIN_ID_LENGTH));
+            assert!(Link::validate_id(&id));
+        }
+
+        // Grows longer as the sequence number grows
+        assert!(Link::sequential_id(u64::MAX, SEED).unwrap().len() > usize::from(MIN_ID_LENGTH));
+    }
+
+    #[test]
+    fn test_hash_link_id() {
+        let id = Link::hash_id("https://crates.io/", 0, SEED);
+        assert_eq!(id.len(), usize::from(HASH_ID_LENGTH));
+        assert!(Link::validate_id(&id));
+
+        // Deterministic for the same URL and salt
+        assert_eq!(Link::hash_id("https://crates.io/", 0, SEED), id);
+        assert_ne!(Link::hash_id("https://crates.io/", 1, SEED), id);
+        assert_ne!(Link::hash_id("https://docs.rs/", 0, SEED), id);
+    }
+
+    #[test]
+    fn test_words_link_id() {
+        for _ in 0..1000 {
+            let id = Link::words_id(MIN_WORD_ID_DIGITS);
+            let words = id.trim_end_matches(|c: char| c.is_ascii_digit());
+            let (adjective, noun) = words
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| c.is_ascii_uppercase())
+                .map(|(i, _)| words.split_at(i))
+                .unwrap();
+            assert!(
+                ID_ADJECTIVES.contains(&adjective.to_lowercase().as_str()),
+                "{id}"
+            );
+            assert!(ID_NOUNS.contains(&noun.to_lowercase().as_str()), "{id}");
+            assert!(id[words.len()..].parse::<u32>().unwrap() < 100);
+            assert!(Link::validate_id(&id), "{id}");
+        }
+    }
+
+    #[test]
+    fn test_id_length_grows_with_keyspace_utilisation() {
+        let mut counts = HashMap::new();
+        assert_eq!(id_length_for(&counts), MIN_ID_LENGTH);
+
+        let full = (keyspace_size(MIN_ID_LENGTH) as f64 * MAX_KEYSPACE_UTILISATION) as i64;
+        counts.insert(MIN_ID_LENGTH, full - 1);
+        assert_eq!(id_length_for(&counts), MIN_ID_LENGTH);
+
+        counts.insert(MIN_ID_LENGTH, full + 1);
+        assert_eq!(id_length_for(&counts), MIN_ID_LENGTH + 1);
+
+        // Never longer than the maximum length
+        for length in MIN_ID_LENGTH..=MAX_ID_LENGTH {
+            counts.insert(length, i64::MAX);
+        }
+        assert_eq!(id_length_for(&counts), MAX_ID_LENGTH);
+    }
+
+    #[test]
+    fn test_id_generator_grow() {
+        let generator = IdGenerator::default();
+        assert_eq!(generator.length(), MIN_ID_LENGTH);
+
+        assert_eq!(generator.grow(MIN_ID_LENGTH), MIN_ID_LENGTH + 1);
+        assert_eq!(generator.length(), MIN_ID_LENGTH + 1);
+
+        // Concurrent requests growing from an older length don't shrink it
+        generator.grow(MIN_ID_LENGTH + 1);
+        generator.grow(MIN_ID_LENGTH);
+        assert_eq!(generator.length(), MIN_ID_LENGTH + 2);
+
+        assert_eq!(generator.grow(MAX_ID_LENGTH), MAX_ID_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn test_id_generator_refresh_keeps_growth() {
+        let generator = IdGenerator::default();
+        generator.grow(MIN_ID_LENGTH);
+
+        // The keyspace of the shortest IDs is barely used, but IDs stay longer
+        generator.refresh(&MemoryStore::default()).await.unwrap();
+        assert_eq!(generator.length(), MIN_ID_LENGTH + 1);
+    }
+
+    #[test]
+    fn test_immutable_link_update() {
+        let mut link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        let immutable = LinkUpdate {
+            immutable: Some(true),
+            ..Default::default()
+        };
+
+        // Links which expire or will change can't be made immutable
+        link.expires_at = Some(Utc::now() + chrono::TimeDelta::days(1));
+        assert!(matches!(
+            immutable.check(&link),
+            Err(Error::InvalidRequest(_))
+        ));
+        let never_expires = LinkUpdate {
+            expires_at: Some(None),
+            ..immutable.clone()
+        };
+        assert!(never_expires.check(&link).is_ok());
+        link.expires_at = None;
+        link.next_change_at = Some(Utc::now() + chrono::TimeDelta::days(1));
+        assert!(matches!(
+            immutable.check(&link),
+            Err(Error::InvalidRequest(_))
+        ));
+        link.next_change_at = None;
+        assert!(immutable.check(&link).is_ok());
+
+        // Only changes to the target URL and expiration time are rejected
+        link.is_immutable = true;
+        for update in [
+            LinkUpdate {
+                target_url: Some("https://docs.rs/".into()),
+                ..Default::default()
+            },
+            LinkUpdate {
+                expires_at: Some(Some(Utc::now() + chrono::TimeDelta::days(1))),
+                ..Default::default()
+            },
+            LinkUpdate {
+                immutable: Some(false),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(update.check(&link), Err(Error::LinkImmutable(_))));
+        }
+        for update in [
+            LinkUpdate {
+                target_url: Some("https://crates.io/".into()),
+                expires_at: Some(None),
+                ..Default::default()
+            },
+            LinkUpdate {
+                track_conversions: Some(true),
+                legal_hold: Some(true),
+                ..Default::default()
+            },
+            immutable,
+        ] {
+            assert!(update.check(&link).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_check_deletable() {
+        let mut link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        assert!(link.check_deletable().is_ok());
+
+        link.legal_hold = true;
+        assert!(matches!(
+            link.check_deletable(),
+            Err(Error::LinkOnLegalHold(_))
+        ));
+        link.is_immutable = true;
+        assert!(matches!(
+            link.check_deletable(),
+            Err(Error::LinkImmutable(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_restorable() {
+        let mut link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        assert!(link.check_restorable(None).is_ok());
+
+        // Links on legal hold must be restored with the hold
+        link.legal_hold = true;
+        assert!(link.check_restorable(Some(&link)).is_ok());
+        let mut restored = link.clone();
+        restored.legal_hold = false;
+        for restored in [None, Some(&restored)] {
+            assert!(matches!(
+                link.check_restorable(restored),
+                Err(Error::LinkOnLegalHold(_))
+            ));
+        }
+
+        // Immutable links must be restored unchanged, other than their
+        // analytics
+        link.legal_hold = false;
+        link.is_immutable = true;
+        let mut restored = link.clone();
+        restored.count_redirects = 10;
+        assert!(link.check_restorable(Some(&restored)).is_ok());
+        restored.target_url = "https://docs.rs/".into();
+        for restored in [None, Some(&restored)] {
+            assert!(matches!(
+                link.check_restorable(restored),
+                Err(Error::LinkImmutable(_))
+            ));
+        }
+    }
 }
@@ -0,0 +1,1014 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::{Mutex, MutexGuard}, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SubsecRound, TimeDelta, Utc};
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::*;
+use crate::error::{Error, Result, StartupError};
+
+/// [`LinkStore`] which keeps everything in memory, for tests and small
+/// deployments with a single instance.
+///
+/// Nothing is persisted, so all links are lost when the application stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    data: Mutex<MemoryData>,
+    events: LocalEvents,
+}
+
+#[derive(Debug, Default)]
+struct MemoryData {
+    links: HashMap<String, Link>,
+    /// Redirect counts for each link and hour.
+    redirects_hourly: BTreeMap<(String, DateTime<Utc>), i64>,
+    /// Link ID and time of each click.
+    clicks: HashMap<Uuid, (String, DateTime<Utc>)>,
+    /// Time of each conversion, by click ID and goal.
+    conversions: HashMap<(Uuid, String), DateTime<Utc>>,
+    sequence: u64,
+    /// Purged links which were archived, with the time they were archived.
+    archived_links: Vec<(Link, DateTime<Utc>)>,
+    /// IDs of purged links which were deleted, with the time they expired and
+    /// the time they were purged.
+    purged_links: Vec<(String, DateTime<Utc>, DateTime<Utc>)>,
+    /// Revisions of each link, oldest first.
+    revisions: HashMap<String, Vec<LinkRevision>>,
+    /// Changes which haven't been made yet, by ID.
+    scheduled_changes: BTreeMap<i64, ScheduledChange>,
+    /// Last ID given to a scheduled change.
+    scheduled_change_id: i64,
+}
+
+impl MemoryData {
+    /// Record redirects to a link in the current hour.
+    fn add_redirects(&mut self, link_id: &str, n: i64) {
+        *self
+            .redirects_hourly
+            .entry((link_id.to_string(), start_of_hour(now())))
+            .or_default() += n;
+    }
+
+    /// Record the current state of a link as its next revision.
+    fn add_revision(&mut self, link: &Link, changed_by: Actor, restored: Option<i32>) {
+        let revisions = self.revisions.entry(link.id.clone()).or_default();
+        let revision = revisions.last().map_or(1, |r| r.revision + 1);
+        revisions.push(LinkRevision::new(link, revision, changed_by, restored));
+    }
+
+    /// Set [`Link::next_change_at`] to the time of the earliest change
+    /// scheduled for the link with the given ID, if it isn't already.
+    fn update_next_change(&mut self, link_id: &str) {
+        let next = self
+            .scheduled_changes
+            .values()
+            .filter(|c| c.link_id == link_id)
+            .map(|c| c.scheduled_at)
+            .min();
+        if let Some(link) = self.links.get_mut(link_id)
+            && link.next_change_at != next
+        {
+            link.next_change_at = next;
+            link.updated_at = now();
+        }
+    }
+
+    /// Remove a link along with its redirects, clicks, conversions and
+    /// scheduled changes, keeping its revisions.
+    fn remove_link(&mut self, link_id: &str) -> Option<Link> {
+        let link = self.links.remove(link_id)?;
+        self.scheduled_changes.retain(|_, c| c.link_id != link_id);
+        self.redirects_hourly.retain(|(id, _), _| id != link_id);
+        let clicks: HashSet<_> = self
+            .clicks
+            .iter()
+            .filter(|(_, (id, _))| id == link_id)
+            .map(|(click, _)| *click)
+            .collect();
+        self.clicks.retain(|click, _| !clicks.contains(click));
+        self.conversions
+            .retain(|(click, _), _| !clicks.contains(click));
+
+        Some(link)
+    }
+}
+
+impl MemoryStore {
+    fn data(&self) -> MutexGuard<'_, MemoryData> {
+        self.data.lock().expect("memory store lock poisoned")
+    }
+}
+
+fn is_active(link: &Link, now: DateTime<Utc>) -> bool {
+    link.expires_at.is_none_or(|e| e > now)
+}
+
+#[async_trait]
+impl LinkStore for MemoryStore {
+    async fn connect(&self, _timeout: Duration) -> std::result::Result<(), StartupError> {
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    async fn insert_link(&self, link: &Link, created_by: Actor) -> Result<Option<Link>> {
+        let mut data = self.data();
+        if data.links.contains_key(&link.id) {
+            return Ok(None);
+        }
+
+        let link = Link {
+            created_at: link.created_at.trunc_subsecs(6),
+            updated_at: link.updated_at.trunc_subsecs(6),
+            expires_at: link.expires_at.map(|e| e.trunc_subsecs(6)),
+            ..link.clone()
+        };
+        data.links.insert(link.id.clone(), link.clone());
+        data.add_revision(&link, created_by, None);
+
+        Ok(Some(link))
+    }
+
+    async fn replace_link(&self, link: &Link, changed_by: Actor) -> Result<Link> {
+        let link = {
+            let mut data = self.data();
+            let existing = data.links.get(&link.id);
+            if existing.is_some_and(|l| l.is_immutable) {
+                return Err(Error::LinkImmutable(link.id.clone()));
+            }
+
+            let link = Link {
+                created_at: link.created_at.trunc_subsecs(6),
+                updated_at: link.updated_at.trunc_subsecs(6),
+                expires_at: link.expires_at.map(|e| e.trunc_subsecs(6)),
+                next_change_at: existing.and_then(|l| l.next_change_at),
+                is_immutable: false,
+                legal_hold: existing.is_some_and(|l| l.legal_hold),
+                ..link.clone()
+            };
+            data.links.insert(link.id.clone(), link.clone());
+            data.add_revision(&link, changed_by, None);
+            link
+        };
+
+        self.events.link_changed(&link.id);
+
+        Ok(link)
+    }
+
+    async fn next_sequence_number(&self) -> Result<u64> {
+        let mut data = self.data();
+        data.sequence += 1;
+        Ok(data.sequence)
+    }
+
+    async fn count_link_ids(&self, min_length: u8, max_length: u8) -> Result<HashMap<u8, i64>> {
+        let mut counts = HashMap::new();
+        for id in self.data().links.keys() {
+            if let Ok(length) = u8::try_from(id.chars().count())
+                && (min_length..=max_length).contains(&length)
+            {
+                *counts.entry(length).or_default() += 1;
+            }
+        }
+
+        Ok(counts)
+    }
+
+    async fn get_link(&self, link_id: &str) -> Result<Option<Link>> {
+        Ok(self.data().links.get(link_id).cloned())
+    }
+
+    async fn get_links(&self) -> Result<Vec<Link>> {
+        let now = now();
+        let mut links: Vec<_> = self
+            .data()
+            .links
+            .values()
+            .filter(|l| is_active(l, now) && !l.is_unlisted)
+            .cloned()
+            .collect();
+        links.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+
+        Ok(links)
+    }
+
+    async fn get_active_links(&self) -> Result<Vec<Link>> {
+        let now = now();
+        let mut links: Vec<_> = self
+            .data()
+            .links
+            .values()
+            .filter(|l| is_active(l, now))
+            .cloned()
+            .collect();
+        links.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Ok(links)
+    }
+
+    async fn update_link(
+        &self,
+        link_id: &str,
+        update: LinkUpdate,
+        precondition: Precondition<'_>,
+    ) -> Result<Link> {
+        update.validate()?;
+
+        let link = {
+            let mut data = self.data();
+            let link = data
+                .links
+                .get_mut(link_id)
+                .ok_or_else(|| Error::LinkNotFound(link_id.to_string()))?;
+            precondition(link)?;
+            update.check(link)?;
+            if update.is_empty() {
+                return Ok(link.clone());
+            }
+
+            if let Some(target_url) = update.target_url {
+                link.target_url = target_url;
+            }
+            if let Some(expires_at) = update.expires_at {
+                link.expires_at = expires_at.map(|e| e.trunc_subsecs(6));
+            }
+            if let Some(track_conversions) = update.track_conversions {
+                link.track_conversions = track_conversions;
+            }
+            if let Some(immutable) = update.immutable {
+                link.is_immutable = immutable;
+            }
+            if let Some(legal_hold) = update.legal_hold {
+                link.legal_hold = legal_hold;
+            }
+            link.updated_at = now();
+
+            let link = link.clone();
+            data.add_revision(&link, update.changed_by, update.restored_revision);
+            link
+        };
+
+        self.events.link_changed(link_id);
+
+        Ok(link)
+    }
+
+    async fn delete_link(&self, link_id: &str, precondition: Precondition<'_>) -> Result<()> {
+        {
+            let mut data = self.data();
+            let link = data
+                .links
+                .get(link_id)
+                .ok_or_else(|| Error::LinkNotFound(link_id.to_string()))?;
+            precondition(link)?;
+            link.check_deletable()?;
+
+            data.remove_link(link_id);
+        }
+
+        self.events.link_changed(link_id);
+
+        Ok(())
+    }
+
+    async fn get_link_revisions(&self, link_id: &str) -> Result<Vec<LinkRevision>> {
+        Ok(self
+            .data()
+            .revisions
+            .get(link_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn schedule_change(
+        &self,
+        link_id: &str,
+        target_url: &str,
+        scheduled_at: DateTime<Utc>,
+    ) -> Result<ScheduledChange> {
+        let change = {
+            let mut data = self.data();
+            let link = data
+                .links
+                .get(link_id)
+                .ok_or_else(|| Error::LinkNotFound(link_id.to_string()))?;
+            if link.is_immutable {
+                return Err(Error::LinkImmutable(link_id.to_string()));
+            }
+
+            data.scheduled_change_id += 1;
+            let change = ScheduledChange {
+                id: data.scheduled_change_id,
+                link_id: link_id.to_string(),
+                target_url: target_url.to_string(),
+                scheduled_at: scheduled_at.trunc_subsecs(6),
+                created_at: now(),
+            };
+            data.scheduled_changes.insert(change.id, change.clone());
+            data.update_next_change(link_id);
+            change
+        };
+
+        self.events.link_changed(link_id);
+
+        Ok(change)
+    }
+
+    async fn get_scheduled_changes(&self, link_id: &str) -> Result<Vec<ScheduledChange>> {
+        let mut changes: Vec<_> = self
+            .data()
+            .scheduled_changes
+            .values()
+            .filter(|c| c.link_id == link_id)
+            .cloned()
+            .collect();
+        changes.sort_by_key(|c| (c.scheduled_at, c.id));
+
+        Ok(changes)
+    }
+
+    async fn cancel_scheduled_change(&self, link_id: &str, change_id: i64) -> Result<()> {
+        {
+            let mut data = self.data();
+            if !data.links.contains_key(link_id) {
+                return Err(Error::LinkNotFound(link_id.to_string()));
+            }
+            if data
+                .scheduled_changes
+                .remove(&change_id)
+                .is_none_or(|c| c.link_id != link_id)
+            {
+                return Err(Error::ScheduledChangeNotFound(
+                    link_id.to_string(),
+                    change_id,
+                ));
+            }
+            data.update_next_change(link_id);
+        }
+
+        self.events.link_changed(link_id);
+
+        Ok(())
+    }
+
+    async fn apply_scheduled_changes(&self, link_id: Option<&str>) -> Result<Vec<Link>> {
+        let links = {
+            let now = now();
+            let mut data = self.data();
+            let mut due: Vec<_> = data
+                .scheduled_changes
+                .values()
+                .filter(|c| c.scheduled_at <= now && link_id.is_none_or(|id| c.link_id == id))
+                .cloned()
+                .collect();
+            due.sort_by_key(|c| (c.scheduled_at, c.id));
+
+            // Only the latest due change to each link is made
+            let mut latest = BTreeMap::new();
+            for change in due {
+                data.scheduled_changes.remove(&change.id);
+                latest.insert(change.link_id.clone(), change);
+            }
+
+            let mut links = Vec::with_capacity(latest.len());
+            for (id, change) in latest {
+                data.update_next_change(&id);
+                let Some(link) = data.links.get_mut(&id) else {
+                    continue;
+                };
+                link.target_url = change.target_url;
+                link.updated_at = now;
+
+                let link = link.clone();
+                data.add_revision(&link, Actor::Schedule, None);
+                links.push(link);
+            }
+            links
+        };
+
+        for link in &links {
+            self.events.link_changed(&link.id);
+        }
+
+        Ok(links)
+    }
+
+    async fn increment_link_redirect_count(&self, link_id: &str) -> Result<Option<Link>> {
+        let now = now();
+        let mut data = self.data();
+        let Some(link) = data.links.get_mut(link_id).filter(|l| is_active(l, now)) else {
+            return Ok(None);
+        };
+
+        link.count_redirects += 1;
+        link.updated_at = now;
+        let link = link.clone();
+        data.add_redirects(link_id, 1);
+
+        Ok(Some(link))
+    }
+
+    async fn add_link_redirect_counts(&self, counts: &HashMap<String, i64>) -> Result<()> {
+        let now = now();
+        let mut data = self.data();
+        for (id, n) in counts {
+            if let Some(link) = data.links.get_mut(id) {
+                link.count_redirects += n;
+                link.updated_at = now;
+                data.add_redirects(id, *n);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_top_links(&self, window_hours: i32, limit: i64) -> Result<Vec<TopLink>> {
+        let now = now();
+        let start = start_of_hour(now - TimeDelta::hours(window_hours.into()));
+        let data = self.data();
+
+        let mut window_redirects: HashMap<&str, i64> = HashMap::new();
+        for ((id, bucket), n) in &data.redirects_hourly {
+            if *bucket >= start {
+                *window_redirects.entry(id).or_default() += n;
+            }
+        }
+
+        let mut links: Vec<_> = window_redirects
+            .into_iter()
+            .filter_map(|(id, n)| {
+                data.links
+                    .get(id)
+                    .filter(|l| is_active(l, now) && !l.is_unlisted)
+                    .map(|link| TopLink {
+                        link: link.clone(),
+                        window_redirects: n,
+                    })
+            })
+            .collect();
+        links.sort_by(|a, b| {
+            b.window_redirects
+                .cmp(&a.window_redirects)
+                .then_with(|| a.link.id.cmp(&b.link.id))
+        });
+        links.truncate(limit.try_into().unwrap_or_default());
+
+        Ok(links)
+    }
+
+    async fn get_instance_stats(&self, days: i32) -> Result<InstanceStats> {
+        let now = now();
+        let data = self.data();
+
+        let total_links = data.links.len() as i64;
+        let active_links = data.links.values().filter(|l| is_active(l, now)).count() as i64;
+        let custom_id_links = data.links.values().filter(|l| l.is_custom_id).count() as i64;
+
+        // Redirects are counted in hourly buckets, so the start of each window
+        // is rounded down to the nearest hour
+        let last_day = start_of_hour(now - TimeDelta::days(1));
+        let last_week = start_of_hour(now - TimeDelta::days(7));
+        let redirects_since = |start| {
+            data.redirects_hourly
+                .iter()
+                .filter(|((_, bucket), _)| *bucket >= start)
+                .map(|(_, n)| n)
+                .sum()
+        };
+
+        let today = now.date_naive();
+        let links_created_per_day = (0..days.into())
+            .rev()
+            .map(|ago| {
+                let date = today - TimeDelta::days(ago);
+                DailyCount {
+                    date,
+                    count: data
+                        .links
+                        .values()
+                        .filter(|l| l.created_at.date_naive() == date)
+                        .count() as i64,
+                }
+            })
+            .collect();
+
+        Ok(InstanceStats {
+            total_links,
+            active_links,
+            expired_links: total_links - active_links,
+            custom_id_links,
+            generated_id_links: total_links - custom_id_links,
+            custom_id_ratio: if total_links == 0 {
+                0.0
+            } else {
+                custom_id_links as f64 / total_links as f64
+            },
+            total_redirects: data.links.values().map(|l| l.count_redirects).sum(),
+            redirects_last_day: redirects_since(last_day),
+            redirects_last_week: redirects_since(last_week),
+            links_created_per_day,
+        })
+    }
+
+    async fn get_hourly_redirects(
+        &self,
+        filter: &RedirectsFilter,
+        after: Option<(&str, DateTime<Utc>)>,
+        limit: i64,
+    ) -> Result<Vec<HourlyRedirects>> {
+        let data = self.data();
+
+        Ok(data
+            .redirects_hourly
+            .iter()
+            .filter(|((id, bucket), _)| {
+                after.is_none_or(|(after_id, after_hour)| {
+                    (id.as_str(), *bucket) > (after_id, after_hour)
+                }) && filter
+                    .from
+                    .is_none_or(|from| *bucket >= start_of_hour(from))
+                    && filter.to.is_none_or(|to| *bucket < to)
+            })
+            .filter_map(|((id, bucket), n)| {
+                let link = data.links.get(id)?;
+                let included = match &filter.link_ids {
+                    Some(ids) => ids.contains(id),
+                    None => !link.is_unlisted,
+                };
+
+                included.then(|| HourlyRedirects {
+                    link_id: id.clone(),
+                    target_url: link.target_url.clone(),
+                    hour: *bucket,
+                    redirects: *n,
+                })
+            })
+            .take(limit.try_into().unwrap_or_default())
+            .collect())
+    }
+
+    async fn record_click(&self, link_id: &str) -> Result<Uuid> {
+        let mut data = self.data();
+        if !data.links.contains_key(link_id) {
+            return Err(Error::LinkNotFound(link_id.to_string()));
+        }
+
+        let click_id = Uuid::new_v4();
+        data.clicks.insert(click_id, (link_id.to_string(), now()));
+
+        Ok(click_id)
+    }
+
+    async fn record_conversion(&self, click_id: Uuid, goal: &str) -> Result<bool> {
+        if !validate_goal(goal) {
+            return Err(Error::ConversionGoalNotValid(goal.to_string()));
+        }
+
+        let mut data = self.data();
+        if !data.clicks.contains_key(&click_id) {
+            return Err(Error::ClickNotFound(click_id.to_string()));
+        }
+
+        let key = (click_id, goal.to_string());
+        if data.conversions.contains_key(&key) {
+            return Ok(false);
+        }
+        data.conversions.insert(key, now());
+
+        Ok(true)
+    }
+
+    async fn get_conversion_stats(&self, link_id: Option<&str>) -> Result<ConversionStats> {
+        let data = self.data();
+        let is_included = |click_id: &Uuid| {
+            data.clicks
+                .get(click_id)
+                .is_some_and(|(id, _)| link_id.is_none_or(|link_id| id == link_id))
+        };
+
+        let tracked_clicks = data.clicks.keys().filter(|c| is_included(c)).count() as i64;
+
+        let mut conversions: HashMap<&str, i64> = HashMap::new();
+        for (click_id, goal) in data.conversions.keys() {
+            if is_included(click_id) {
+                *conversions.entry(goal).or_default() += 1;
+            }
+        }
+
+        let mut goals: Vec<_> = conversions
+            .into_iter()
+            .map(|(goal, conversions)| GoalConversions {
+                goal: goal.to_string(),
+                conversions,
+                conversion_rate: if tracked_clicks == 0 {
+                    0.0
+                } else {
+                    conversions as f64 / tracked_clicks as f64
+                },
+            })
+            .collect();
+        goals.sort_by(|a, b| {
+            b.conversions
+                .cmp(&a.conversions)
+                .then_with(|| a.goal.cmp(&b.goal))
+        });
+
+        Ok(ConversionStats {
+            tracked_clicks,
+            goals,
+        })
+    }
+
+    async fn backup(&self, records: mpsc::Sender<BackupRecord>) -> Result<()> {
+        // Copy everything first, as the lock can't be held while sending
+        let snapshot = {
+            let data = self.data();
+            let mut links: Vec<_> = data.links.values().cloned().collect();
+            links.sort_by(|a, b| a.id.cmp(&b.id));
+
+            let mut clicks: Vec<_> = data
+                .clicks
+                .iter()
+                .map(|(id, (link_id, clicked_at))| BackupClick {
+                    id: *id,
+                    link_id: link_id.clone(),
+                    clicked_at: *clicked_at,
+                })
+                .collect();
+            clicks.sort_by_key(|c| c.id);
+
+            let mut conversions: Vec<_> = data
+                .conversions
+                .iter()
+                .map(|((click_id, goal), created_at)| BackupConversion {
+                    click_id: *click_id,
+                    goal: goal.clone(),
+                    created_at: *created_at,
+                })
+                .collect();
+            conversions.sort_by(|a, b| (a.click_id, &a.goal).cmp(&(b.click_id, &b.goal)));
+
+            let mut revisions: Vec<_> = data.revisions.values().flatten().cloned().collect();
+            revisions.sort_by(|a, b| (&a.link_id, a.revision).cmp(&(&b.link_id, b.revision)));
+            let scheduled_changes: Vec<_> = data.scheduled_changes.values().cloned().collect();
+
+            links
+                .into_iter()
+                .map(BackupRecord::Link)
+                .chain(data.redirects_hourly.iter().map(|((link_id, bucket), n)| {
+                    BackupRecord::Redirects(BackupRedirects {
+                        link_id: link_id.clone(),
+                        bucket: *bucket,
+                        count_redirects: *n,
+                    })
+                }))
+                .chain(clicks.into_iter().map(BackupRecord::Click))
+                .chain(conversions.into_iter().map(BackupRecord::Conversion))
+                .chain(revisions.into_iter().map(BackupRecord::Revision))
+                .chain(
+                    scheduled_changes
+                        .into_iter()
+                        .map(BackupRecord::ScheduledChange),
+                )
+                .chain([BackupRecord::Sequence {
+                    value: data.sequence,
+                }])
+                .collect::<Vec<_>>()
+        };
+
+        for record in snapshot {
+            if records.send(record).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn restore(&self, backup: &Backup) -> Result<()> {
+        let mut restored = MemoryData {
+            sequence: backup.sequence,
+            ..Default::default()
+        };
+        for link in &backup.links {
+            if restored
+                .links
+                .insert(link.id.clone(), link.clone())
+                .is_some()
+            {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: duplicate link {}",
+                    link.id
+                )));
+            }
+        }
+        for r in &backup.redirects {
+            if !restored.links.contains_key(&r.link_id) {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: redirects for missing link {}",
+                    r.link_id
+                )));
+            }
+            restored
+                .redirects_hourly
+                .insert((r.link_id.clone(), r.bucket), r.count_redirects);
+        }
+        for c in &backup.clicks {
+            if !restored.links.contains_key(&c.link_id) {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: click for missing link {}",
+                    c.link_id
+                )));
+            }
+            restored
+                .clicks
+                .insert(c.id, (c.link_id.clone(), c.clicked_at));
+        }
+        for c in &backup.conversions {
+            if !restored.clicks.contains_key(&c.click_id) {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: conversion for missing click {}",
+                    c.click_id
+                )));
+            }
+            restored
+                .conversions
+                .insert((c.click_id, c.goal.clone()), c.created_at);
+        }
+        // Revisions are kept for deleted links, so they needn't have a link
+        for r in &backup.revisions {
+            let revisions = restored.revisions.entry(r.link_id.clone()).or_default();
+            if revisions
+                .iter()
+                .any(|existing| existing.revision == r.revision)
+            {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: duplicate revision {} of link {}",
+                    r.revision, r.link_id
+                )));
+            }
+            revisions.push(r.clone());
+            revisions.sort_by_key(|r| r.revision);
+        }
+        for c in &backup.scheduled_changes {
+            if !restored.links.contains_key(&c.link_id) {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: scheduled change for missing link {}",
+                    c.link_id
+                )));
+            }
+            if restored.scheduled_changes.insert(c.id, c.clone()).is_some() {
+                return Err(Error::InvalidRequest(format!(
+                    "inconsistent backup: duplicate scheduled change {}",
+                    c.id
+                )));
+            }
+            restored.scheduled_change_id = restored.scheduled_change_id.max(c.id);
+        }
+
+        // Links which are immutable or on legal hold must survive the restore
+        let mut data = self.data();
+        backup.check_protected_links(
+            data.links
+                .values()
+                .filter(|l| l.is_immutable || l.legal_hold),
+        )?;
+
+        // Archived and purged links aren't part of backups, so they are kept
+        restored.archived_links = std::mem::take(&mut data.archived_links);
+        restored.purged_links = std::mem::take(&mut data.purged_links);
+        let previous = std::mem::replace(&mut *data, restored);
+        drop(data);
+        for link_id in previous.links.keys() {
+            self.events.link_changed(link_id);
+        }
+
+        Ok(())
+    }
+
+    async fn purge_expired_links(
+        &self,
+        expired_before: DateTime<Utc>,
+        limit: i64,
+        archive: bool,
+    ) -> Result<Vec<String>> {
+        let ids = {
+            let mut data = self.data();
+            let mut expired: Vec<_> = data
+                .links
+                .values()
+                .filter(|l| !l.legal_hold)
+                .filter_map(|l| {
+                    l.expires_at
+                        .filter(|e| *e < expired_before)
+                        .map(|e| (e, l.id.clone()))
+                })
+                .collect();
+            expired.sort();
+            expired.truncate(limit.max(0) as usize);
+
+            let now = now();
+            let mut ids = Vec::with_capacity(expired.len());
+            for (expires_at, id) in expired {
+                let link = data.remove_link(&id).expect("expired link should exist");
+                if archive {
+                    data.archived_links.push((link, now));
+                } else {
+                    data.purged_links.push((id.clone(), expires_at, now));
+                }
+                ids.push(id);
+            }
+            ids
+        };
+
+        for id in &ids {
+            self.events.link_changed(id);
+        }
+
+        Ok(ids)
+    }
+
+    async fn run_exclusively(
+        &self,
+        _lock_key: i64,
+        job: BoxFuture<'_, Result<()>>,
+    ) -> Result<bool> {
+        // There is only a single instance
+        job.await.map(|_| true)
+    }
+
+    async fn publish_redirect_events(&self, events: &[RedirectEvent]) -> Result<()> {
+        for event in events {
+            self.events.publish_redirect(event);
+        }
+        Ok(())
+    }
+
+    async fn listen_events(&self, senders: EventSenders) {
+        self.events.listen(senders);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn no_precondition<'a>() -> Precondition<'a> {
+        Box::new(|_| Ok(()))
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_links() {
+        let store = MemoryStore::default();
+        let link = Link::new(Some("abc".into()), "https://crates.io/".into());
+
+        assert!(
+            store
+                .insert_link(&link, Actor::Admin)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            store
+                .insert_link(&link, Actor::Admin)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            store.get_link("abc").await.unwrap().unwrap().target_url,
+            "https://crates.io/"
+        );
+        assert_eq!(store.get_links().await.unwrap().len(), 1);
+
+        // Redirects are counted in total and per hour
+        let link = store
+            .increment_link_redirect_count("abc")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(link.count_redirects, 1);
+        store
+            .add_link_redirect_counts(&HashMap::from([("abc".into(), 2)]))
+            .await
+            .unwrap();
+        let rows = store
+            .get_hourly_redirects(&RedirectsFilter::default(), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].redirects, 3);
+        let top = store.get_top_links(24, 10).await.unwrap();
+        assert_eq!(top[0].window_redirects, 3);
+
+        // Preconditions are checked before changes are made
+        let result = store
+            .update_link(
+                "abc",
+                LinkUpdate {
+                    target_url: Some("https://docs.rs/".into()),
+                    ..Default::default()
+                },
+                Box::new(|_| Err(Error::LinkChanged)),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::LinkChanged)));
+        let link = store
+            .update_link(
+                "abc",
+                LinkUpdate {
+                    target_url: Some("https://docs.rs/".into()),
+                    ..Default::default()
+                },
+                no_precondition(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(link.target_url, "https://docs.rs/");
+        assert_eq!(link.count_redirects, 3);
+
+        store.delete_link("abc", no_precondition()).await.unwrap();
+        assert!(store.get_link("abc").await.unwrap().is_none());
+        assert!(matches!(
+            store.delete_link("abc", no_precondition()).await,
+            Err(Error::LinkNotFound(_))
+        ));
+        assert!(
+            store
+                .get_hourly_redirects(&RedirectsFilter::default(), None, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_conversions() {
+        let store = MemoryStore::default();
+        let link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        store.insert_link(&link, Actor::Admin).await.unwrap();
+
+        let click = store.record_click("abc").await.unwrap();
+        assert!(store.record_conversion(click, "signup").await.unwrap());
+        assert!(!store.record_conversion(click, "signup").await.unwrap());
+        assert!(matches!(
+            store.record_conversion(Uuid::new_v4(), "signup").await,
+            Err(Error::ClickNotFound(_))
+        ));
+
+        let stats = store.get_conversion_stats(Some("abc")).await.unwrap();
+        assert_eq!(stats.tracked_clicks, 1);
+        assert_eq!(stats.goals[0].goal, "signup");
+        assert_eq!(stats.goals[0].conversion_rate, 1.0);
+        assert_eq!(
+            store
+                .get_conversion_stats(Some("other"))
+                .await
+                .unwrap()
+                .tracked_clicks,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_purged_links() {
+        let store = MemoryStore::default();
+        let expires_at = now() - TimeDelta::days(1);
+        for id in ["deleted", "archived"] {
+            let mut link = Link::new(Some(id.into()), "https://crates.io/".into());
+            link.expires_at = Some(expires_at);
+            store.insert_link(&link, Actor::Admin).await.unwrap();
+        }
+
+        // Deleted links are recorded by ID, and archived links in full
+        assert_eq!(
+            store
+                .purge_expired_links(now(), 1, false)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            store
+                .purge_expired_links(now(), 1, true)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        let data = store.data();
+        assert_eq!(data.purged_links.len(), 1);
+        assert_eq!(data.purged_links[0].1, expires_at);
+        assert_eq!(data.archived_links.len(), 1);
+        assert_ne!(data.purged_links[0].0, data.archived_links[0].0.id);
+    }
+}
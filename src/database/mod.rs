@@ -1,27 +1,120 @@
+mod analytics;
+mod backup;
+mod breaker;
+mod conversions;
+mod events;
 mod links;
-use std::str::FromStr;
+mod memory;
+mod postgres;
+mod purge;
+mod replicas;
+mod revisions;
+mod schedule;
+mod sqlite;
+mod store;
+use std::{str::FromStr, time::Duration};
 
-use sqlx::{PgPool, postgres::{PgConnectOptions, PgPoolOptions, PgSslMode}};
+use sqlx::{PgPool, migrate::Migrator, postgres::{PgConnectOptions, PgPoolOptions, PgSslMode}};
+use tokio::time::Instant;
+use url::Url;
 
-pub use self::links::*;
-use crate::config::DbConfig;
+pub use self::{analytics::*, backup::*, breaker::*, conversions::*, events::*, links::*, memory::*, postgres::*, purge::*, replicas::*, revisions::*, schedule::*, sqlite::*, store::*};
+use crate::{config::DbConfig, error::StartupError};
 
-pub async fn init_db(config: &DbConfig) -> Result<PgPool, sqlx::Error> {
-    let options = PgConnectOptions::from_str(config.url.as_str())
-        .expect("Invalid Database URL")
-        .ssl_mode(if config.requiressl {
+/// Delay before the first retry when connecting to the database at startup,
+/// which doubles with each attempt.
+const INITIAL_CONNECT_BACKOFF: Duration = Duration::from_millis(100);
+/// Maximum delay between attempts to connect to the database at startup.
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(5);
+/// How long a single attempt to connect to the database may take.
+const CONNECT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Migrations of the PostgreSQL schema, which the SQLite schema mirrors.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Version of the latest migration of the schema, which is the same for all
+/// stores.
+pub fn schema_version() -> i64 {
+    MIGRATOR
+        .iter()
+        .map(|m| m.version)
+        .max()
+        .expect("there should be at least one migration")
+}
+
+fn connect_options(url: &Url, require_ssl: bool) -> Result<PgConnectOptions, sqlx::Error> {
+    Ok(
+        PgConnectOptions::from_str(url.as_str())?.ssl_mode(if require_ssl {
             PgSslMode::Require
         } else {
             PgSslMode::Prefer
-        });
+        }),
+    )
+}
+
+/// Create the pool for the primary database, without connecting to it yet.
+/// Use [`wait_for_db`] before using the pool.
+pub fn init_db(config: &DbConfig) -> Result<PgPool, StartupError> {
+    let options = connect_options(&config.url, config.requiressl)
+        .map_err(StartupError::InvalidDatabaseUrl)?;
+
+    Ok(PgPoolOptions::new()
+        .min_connections(config.minconnections)
+        .max_connections(config.maxconnections)
+        .connect_lazy_with(options))
+}
+
+/// Wait until the database can be connected to, retrying with exponential
+/// backoff until the timeout, then run any pending migrations.
+pub async fn wait_for_db(db: &PgPool, timeout: Duration) -> Result<(), StartupError> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = INITIAL_CONNECT_BACKOFF;
+
+    loop {
+        let attempt = tokio::time::timeout(
+            CONNECT_ATTEMPT_TIMEOUT.min(deadline.saturating_duration_since(Instant::now())),
+            sqlx::query("select 1").execute(db),
+        )
+        .await;
+
+        let error = match attempt {
+            Ok(Ok(_)) => break,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        if Instant::now() + backoff >= deadline {
+            return Err(StartupError::DatabaseUnavailable(timeout.as_secs(), error));
+        }
+
+        tracing::warn!("Could not connect to the database, retrying in {backoff:?}: {error}");
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+    }
+
+    MIGRATOR.run(db).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    let pool = PgPoolOptions::new()
-        .min_connections(2)
-        .max_connections(20)
-        .connect_with(options)
-        .await?;
+    #[tokio::test]
+    async fn test_wait_for_unavailable_db() {
+        let url = Url::parse("postgresql://127.0.0.1:1/curto-db").unwrap();
+        let db = PgPoolOptions::new().connect_lazy_with(connect_options(&url, false).unwrap());
 
-    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        let start = Instant::now();
+        let result = wait_for_db(&db, Duration::from_secs(1)).await;
+        assert!(matches!(
+            result,
+            Err(StartupError::DatabaseUnavailable(1, _))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(2));
 
-    Ok(pool)
+        let url = Url::parse("postgresql://localhost/curto-db?sslmode=sometimes").unwrap();
+        assert!(connect_options(&url, false).is_err());
+    }
 }
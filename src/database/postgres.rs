@@ -0,0 +1,198 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::*;
+use crate::{config::DbConfig, error::{Result, StartupError}};
+
+/// [`LinkStore`] backed by PostgreSQL, which is shared by all instances.
+#[derive(Debug, Clone)]
+pub struct PgStore {
+    db: PgPool,
+    /// Pools used for read-only queries instead of [`Self::db`], if any.
+    replicas: Option<ReadReplicas>,
+    /// Timeout for each query.
+    timeout: Duration,
+}
+
+impl PgStore {
+    /// Create a store for the primary database, without connecting to it yet.
+    pub fn new(config: &DbConfig) -> std::result::Result<Self, StartupError> {
+        Ok(Self {
+            db: init_db(config)?,
+            replicas: None,
+            timeout: Duration::from_millis(config.timeoutms),
+        })
+    }
+
+    /// Create a store whose read-only queries are spread across the configured
+    /// replicas, which may lag behind this store. Writes still go to the
+    /// primary.
+    pub fn with_replicas(&self, config: &DbConfig) -> std::result::Result<Self, StartupError> {
+        let replicas = ReadReplicas::new(config, self.db.clone())?;
+        replicas.spawn_health_checks();
+
+        Ok(Self {
+            db: self.db.clone(),
+            replicas: Some(replicas),
+            timeout: self.timeout,
+        })
+    }
+
+    /// Get the pool to run the next read-only query on. Queries which write
+    /// always run on [`Self::db`] instead, even in stores for replicas.
+    fn read_pool(&self) -> &PgPool {
+        match &self.replicas {
+            Some(replicas) => replicas.pool(),
+            None => &self.db,
+        }
+    }
+}
+
+#[async_trait]
+impl LinkStore for PgStore {
+    async fn connect(&self, timeout: Duration) -> std::result::Result<(), StartupError> {
+        wait_for_db(&self.db, timeout).await
+    }
+
+    fn is_closed(&self) -> bool {
+        self.db.is_closed()
+    }
+
+    async fn insert_link(&self, link: &Link, created_by: Actor) -> Result<Option<Link>> {
+        insert_link(&self.db, self.timeout, link, created_by).await
+    }
+
+    async fn replace_link(&self, link: &Link, changed_by: Actor) -> Result<Link> {
+        replace_link(&self.db, self.timeout, link, changed_by).await
+    }
+
+    async fn next_sequence_number(&self) -> Result<u64> {
+        next_link_sequence_number(&self.db, self.timeout).await
+    }
+
+    async fn count_link_ids(&self, min_length: u8, max_length: u8) -> Result<HashMap<u8, i64>> {
+        count_link_ids(self.read_pool(), self.timeout, min_length, max_length).await
+    }
+
+    async fn get_link(&self, link_id: &str) -> Result<Option<Link>> {
+        get_link(self.read_pool(), self.timeout, link_id).await
+    }
+
+    async fn get_links(&self) -> Result<Vec<Link>> {
+        get_links(self.read_pool(), self.timeout).await
+    }
+
+    async fn get_active_links(&self) -> Result<Vec<Link>> {
+        get_active_links(self.read_pool(), self.timeout).await
+    }
+
+    async fn update_link(
+        &self,
+        link_id: &str,
+        update: LinkUpdate,
+        precondition: Precondition<'_>,
+    ) -> Result<Link> {
+        update_link(&self.db, self.timeout, link_id, update, precondition).await
+    }
+
+    async fn delete_link(&self, link_id: &str, precondition: Precondition<'_>) -> Result<()> {
+        delete_link(&self.db, self.timeout, link_id, precondition).await
+    }
+
+    async fn get_link_revisions(&self, link_id: &str) -> Result<Vec<LinkRevision>> {
+        get_link_revisions(self.read_pool(), self.timeout, link_id).await
+    }
+
+    async fn schedule_change(
+        &self,
+        link_id: &str,
+        target_url: &str,
+        scheduled_at: DateTime<Utc>,
+    ) -> Result<ScheduledChange> {
+        schedule_change(&self.db, self.timeout, link_id, target_url, scheduled_at).await
+    }
+
+    async fn get_scheduled_changes(&self, link_id: &str) -> Result<Vec<ScheduledChange>> {
+        get_scheduled_changes(self.read_pool(), self.timeout, link_id).await
+    }
+
+    async fn cancel_scheduled_change(&self, link_id: &str, change_id: i64) -> Result<()> {
+        cancel_scheduled_change(&self.db, self.timeout, link_id, change_id).await
+    }
+
+    async fn apply_scheduled_changes(&self, link_id: Option<&str>) -> Result<Vec<Link>> {
+        apply_scheduled_changes(&self.db, self.timeout, link_id).await
+    }
+
+    async fn increment_link_redirect_count(&self, link_id: &str) -> Result<Option<Link>> {
+        increment_link_redirect_count(&self.db, self.timeout, link_id).await
+    }
+
+    async fn add_link_redirect_counts(&self, counts: &HashMap<String, i64>) -> Result<()> {
+        add_link_redirect_counts(&self.db, self.timeout, counts).await
+    }
+
+    async fn get_top_links(&self, window_hours: i32, limit: i64) -> Result<Vec<TopLink>> {
+        get_top_links(self.read_pool(), self.timeout, window_hours, limit).await
+    }
+
+    async fn get_instance_stats(&self, days: i32) -> Result<InstanceStats> {
+        get_instance_stats(self.read_pool(), self.timeout, days).await
+    }
+
+    async fn get_hourly_redirects(
+        &self,
+        filter: &RedirectsFilter,
+        after: Option<(&str, DateTime<Utc>)>,
+        limit: i64,
+    ) -> Result<Vec<HourlyRedirects>> {
+        get_hourly_redirects(self.read_pool(), self.timeout, filter, after, limit).await
+    }
+
+    async fn record_click(&self, link_id: &str) -> Result<Uuid> {
+        record_click(&self.db, self.timeout, link_id).await
+    }
+
+    async fn record_conversion(&self, click_id: Uuid, goal: &str) -> Result<bool> {
+        record_conversion(&self.db, self.timeout, click_id, goal).await
+    }
+
+    async fn get_conversion_stats(&self, link_id: Option<&str>) -> Result<ConversionStats> {
+        get_conversion_stats(self.read_pool(), self.timeout, link_id).await
+    }
+
+    async fn backup(&self, records: mpsc::Sender<BackupRecord>) -> Result<()> {
+        backup(&self.db, records).await
+    }
+
+    async fn restore(&self, backup: &Backup) -> Result<()> {
+        restore(&self.db, backup).await
+    }
+
+    async fn purge_expired_links(
+        &self,
+        expired_before: DateTime<Utc>,
+        limit: i64,
+        archive: bool,
+    ) -> Result<Vec<String>> {
+        purge_expired_links(&self.db, self.timeout, expired_before, limit, archive).await
+    }
+
+    async fn run_exclusively(&self, lock_key: i64, job: BoxFuture<'_, Result<()>>) -> Result<bool> {
+        run_exclusively(&self.db, lock_key, job).await
+    }
+
+    async fn publish_redirect_events(&self, events: &[RedirectEvent]) -> Result<()> {
+        publish_redirect_events(&self.db, self.timeout, events).await
+    }
+
+    async fn listen_events(&self, senders: EventSenders) {
+        listen_events(self.db.clone(), senders).await
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use sqlx::{Pool, Postgres};
+
+use crate::error::Result;
+
+/// Delete, and either archive or record, up to `limit` links which expired
+/// before the given time, returning their IDs.
+///
+/// Links on legal hold are never purged, and links locked by other
+/// transactions are skipped, so that purging never waits on requests.
+pub async fn purge_expired_links(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    expired_before: DateTime<Utc>,
+    limit: i64,
+    archive: bool,
+) -> Result<Vec<String>> {
+    let ids = tokio::time::timeout(
+        timeout,
+        sqlx::query_scalar!(
+            r#"
+                with purged as (
+                    delete from links
+                    where id in (
+                        select id from links
+                        where expires_at < $1 and not legal_hold
+                        order by expires_at
+                        limit $2
+                        for update skip locked
+                    )
+                    returning *
+                ), archived as (
+                    insert into archived_links (
+                        id, target_url, count_redirects, created_at, updated_at, expires_at,
+                        is_custom_id, track_conversions, is_unlisted
+                    )
+                    select
+                        id, target_url, count_redirects, created_at, updated_at, expires_at,
+                        is_custom_id, track_conversions, is_unlisted
+                    from purged
+                    where $3
+                ), recorded as (
+                    insert into purged_links (id, expires_at)
+                    select id, expires_at from purged
+                    where not $3
+                )
+                select id as "id!" from purged
+            "#,
+            expired_before,
+            limit,
+            archive
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_purge_links").increment(1))?;
+
+    Ok(ids)
+}
+
+/// Run a job while holding a session-level advisory lock with the given key,
+/// unless another session holds it, returning whether the job was run.
+///
+/// The lock is held by a connection set aside for the duration of the job,
+/// and is released if that connection is lost.
+pub async fn run_exclusively(
+    db: &Pool<Postgres>,
+    lock_key: i64,
+    job: BoxFuture<'_, Result<()>>,
+) -> Result<bool> {
+    let mut conn = db.acquire().await?;
+    let locked = sqlx::query_scalar!(r#"select pg_try_advisory_lock($1) as "locked!""#, lock_key)
+        .fetch_one(&mut *conn)
+        .await?;
+    if !locked {
+        return Ok(false);
+    }
+
+    let result = job.await;
+
+    let unlocked = sqlx::query_scalar!("select pg_advisory_unlock($1)", lock_key)
+        .fetch_one(&mut *conn)
+        .await;
+    if !matches!(unlocked, Ok(Some(true))) {
+        // Closing the connection releases the lock instead
+        tracing::warn!("Failed to release advisory lock {lock_key}, closing its connection");
+        drop(conn.detach());
+    }
+
+    result.map(|_| true)
+}
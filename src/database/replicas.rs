@@ -0,0 +1,170 @@
+use std::{sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::Duration};
+
+use axum_prometheus::metrics::{counter, gauge};
+use sqlx::{PgPool, postgres::PgPoolOptions};
+
+use super::connect_options;
+use crate::{config::DbConfig, error::StartupError};
+
+#[derive(Debug)]
+struct Replica {
+    pool: PgPool,
+    /// Replicas are unhealthy until they pass their first health check.
+    healthy: AtomicBool,
+}
+
+/// Database pools used for read-only queries.
+///
+/// Queries are spread across healthy read replicas, falling back to the
+/// primary if there are none. As replicas may lag behind the primary, they
+/// must not be used for queries which need to see the latest writes.
+#[derive(Debug, Clone)]
+pub struct ReadReplicas {
+    primary: PgPool,
+    replicas: Arc<[Replica]>,
+    next: Arc<AtomicUsize>,
+    /// Timeout for each health check.
+    timeout: Duration,
+    /// How often the health of each replica is checked.
+    health_check_interval: Duration,
+}
+
+impl ReadReplicas {
+    /// Create pools for the configured replicas, without connecting to them
+    /// yet, so that unavailable replicas don't prevent startup.
+    pub fn new(config: &DbConfig, primary: PgPool) -> Result<Self, StartupError> {
+        let timeout = Duration::from_millis(config.timeoutms);
+        let replicas = config
+            .replicaurls
+            .iter()
+            .map(|url| {
+                Ok(Replica {
+                    pool: PgPoolOptions::new()
+                        .max_connections(config.maxconnections)
+                        .acquire_timeout(timeout)
+                        .connect_lazy_with(
+                            connect_options(url, config.requiressl)
+                                .map_err(StartupError::InvalidDatabaseUrl)?,
+                        ),
+                    healthy: AtomicBool::new(false),
+                })
+            })
+            .collect::<Result<_, StartupError>>()?;
+
+        Ok(Self {
+            primary,
+            replicas,
+            next: Default::default(),
+            timeout,
+            health_check_interval: Duration::from_secs(config.replicahealthcheckseconds),
+        })
+    }
+
+    /// Get the pool to run the next read-only query on.
+    pub fn pool(&self) -> &PgPool {
+        match self.next_healthy() {
+            Some(i) => &self.replicas[i].pool,
+            None => {
+                if !self.replicas.is_empty() {
+                    counter!("db.replica_fallbacks").increment(1);
+                }
+                &self.primary
+            }
+        }
+    }
+
+    /// Index of the next healthy replica, in round-robin order.
+    fn next_healthy(&self) -> Option<usize> {
+        let healthy: Vec<_> = (0..self.replicas.len())
+            .filter(|&i| self.replicas[i].healthy.load(Ordering::Relaxed))
+            .collect();
+        if healthy.is_empty() {
+            return None;
+        }
+
+        Some(healthy[self.next.fetch_add(1, Ordering::Relaxed) % healthy.len()])
+    }
+
+    /// Spawn a task which periodically checks the health of each replica,
+    /// until the primary pool is closed.
+    pub fn spawn_health_checks(&self) {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        let replicas = self.replicas.clone();
+        let primary = self.primary.clone();
+        let (timeout, health_check_interval) = (self.timeout, self.health_check_interval);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(health_check_interval);
+            while !primary.is_closed() {
+                interval.tick().await;
+
+                let mut healthy_count = 0;
+                for (i, replica) in replicas.iter().enumerate() {
+                    let healthy = matches!(
+                        tokio::time::timeout(
+                            timeout,
+                            sqlx::query("select 1").execute(&replica.pool)
+                        )
+                        .await,
+                        Ok(Ok(_))
+                    );
+
+                    if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                        if healthy {
+                            tracing::info!("Read replica {i} is healthy");
+                        } else {
+                            tracing::warn!("Read replica {i} failed its health check");
+                        }
+                    }
+                    healthy_count += u32::from(healthy);
+                }
+
+                gauge!("db.healthy_replicas").set(healthy_count);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use url::Url;
+
+    use super::*;
+
+    fn get_replicas(count: usize) -> ReadReplicas {
+        let url = Url::parse("postgresql://127.0.0.1:1/curto-db").unwrap();
+        let config = DbConfig {
+            url: url.clone(),
+            requiressl: false,
+            breakerthreshold: 5,
+            breakeropenseconds: 10,
+            minconnections: 0,
+            maxconnections: 1,
+            timeoutms: 400,
+            replicaurls: vec![url.clone(); count],
+            replicahealthcheckseconds: 5,
+            startuptimeoutseconds: 60,
+        };
+        let primary = PgPoolOptions::new().connect_lazy_with(connect_options(&url, false).unwrap());
+
+        ReadReplicas::new(&config, primary).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_next_healthy_replica() {
+        let replicas = get_replicas(3);
+
+        // Falls back to the primary when no replicas are healthy
+        assert_eq!(replicas.next_healthy(), None);
+
+        replicas.replicas[0].healthy.store(true, Ordering::Relaxed);
+        replicas.replicas[2].healthy.store(true, Ordering::Relaxed);
+        let picked: Vec<_> = (0..4).filter_map(|_| replicas.next_healthy()).collect();
+        assert_eq!(picked, vec![0, 2, 0, 2]);
+
+        assert_eq!(get_replicas(0).next_healthy(), None);
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use super::Link;
+use crate::error::{Error, Result};
+
+/// Who made a change recorded in a [`LinkRevision`].
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Actor {
+    /// Anyone creating a link through the public API.
+    Anonymous,
+    /// A holder of the admin token.
+    Admin,
+    /// An import of links from elsewhere, through the API or the CLI.
+    Import,
+    /// A change scheduled in advance, made at its scheduled time.
+    Schedule,
+    /// Not known, as the change was made before revisions were recorded.
+    #[default]
+    Unknown,
+}
+
+/// State of the editable fields of a [`Link`], and of its protection, after a
+/// change to them.
+///
+/// Every link has at least one revision, recorded when it was created.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkRevision {
+    pub link_id: String,
+    /// Number of the revision, counting up from 1 for each link.
+    pub revision: i32,
+    pub target_url: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub track_conversions: bool,
+    /// Whether the link was immutable after the change.
+    #[serde(default)]
+    pub is_immutable: bool,
+    /// Whether the link was on legal hold after the change.
+    #[serde(default)]
+    pub legal_hold: bool,
+    /// Time of the change.
+    pub changed_at: DateTime<Utc>,
+    pub changed_by: Actor,
+    /// Revision which was restored by this change, if it was a rollback.
+    pub restored_revision: Option<i32>,
+}
+
+impl LinkRevision {
+    /// Revision recording the current state of a link, made at the time the
+    /// link was last updated.
+    pub fn new(link: &Link, revision: i32, changed_by: Actor, restored: Option<i32>) -> Self {
+        Self {
+            link_id: link.id.clone(),
+            revision,
+            target_url: link.target_url.clone(),
+            expires_at: link.expires_at,
+            track_conversions: link.track_conversions,
+            is_immutable: link.is_immutable,
+            legal_hold: link.legal_hold,
+            changed_at: link.updated_at,
+            changed_by,
+            restored_revision: restored,
+        }
+    }
+}
+
+/// Record the current state of a link as its next revision.
+pub(super) async fn add_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    link: &Link,
+    changed_by: Actor,
+    restored_revision: Option<i32>,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            insert into link_revisions (
+                link_id, revision, target_url, expires_at, track_conversions, is_immutable,
+                legal_hold, changed_at, changed_by, restored_revision
+            )
+            select $1, coalesce(max(revision), 0) + 1, $2, $3, $4, $5, $6, $7, $8, $9
+            from link_revisions
+            where link_id = $1
+        "#,
+        link.id,
+        link.target_url,
+        link.expires_at,
+        link.track_conversions,
+        link.is_immutable,
+        link.legal_hold,
+        link.updated_at,
+        changed_by as Actor,
+        restored_revision
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Get all revisions of the link with the given ID, oldest first.
+pub async fn get_link_revisions(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: &str,
+) -> Result<Vec<LinkRevision>> {
+    tokio::time::timeout(
+        timeout,
+        sqlx::query_as!(
+            LinkRevision,
+            r#"
+                select
+                    link_id, revision, target_url, expires_at, track_conversions, is_immutable,
+                    legal_hold, changed_at, changed_by as "changed_by: Actor", restored_revision
+                from link_revisions
+                where link_id = $1
+                order by revision
+            "#,
+            link_id
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_revisions").increment(1))
+    .map_err(Error::from)
+}
@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use utoipa::ToSchema;
+
+use super::{Actor, Link, add_revision, lock_link};
+use crate::{error::{Error, Result}, time::deserialize_timestamp};
+
+/// A change to the target URL of a [`Link`], scheduled to be made at a later
+/// time.
+///
+/// Once it is due, the change is made and recorded as a
+/// [`super::LinkRevision`], and the scheduled change is removed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledChange {
+    /// ID of the scheduled change, used to cancel it.
+    pub id: i64,
+    pub link_id: String,
+    /// URL that the shortened link will redirect to from the scheduled time.
+    pub target_url: String,
+    /// Time at which the change is made.
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub scheduled_at: DateTime<Utc>,
+    /// Time the change was scheduled.
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Set [`Link::next_change_at`] to the time of the earliest change scheduled
+/// for the link with the given ID, if it isn't already.
+async fn update_next_change(
+    tx: &mut Transaction<'_, Postgres>,
+    link_id: &str,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+            update links set next_change_at = next.scheduled_at
+            from (select min(scheduled_at) as scheduled_at from scheduled_changes where link_id = $1) next
+            where id = $1 and next_change_at is distinct from next.scheduled_at
+        "#,
+        link_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Schedule a change to the target URL of the link with the given ID.
+pub async fn schedule_change(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: &str,
+    target_url: &str,
+    scheduled_at: DateTime<Utc>,
+) -> Result<ScheduledChange> {
+    tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        let link = lock_link(&mut tx, link_id).await?;
+        if link.is_immutable {
+            return Err(Error::LinkImmutable(link.id));
+        }
+        let change = sqlx::query_as!(
+            ScheduledChange,
+            r#"
+                insert into scheduled_changes (link_id, target_url, scheduled_at)
+                values ($1, $2, $3)
+                returning *
+            "#,
+            link_id,
+            target_url,
+            scheduled_at
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        update_next_change(&mut tx, link_id).await?;
+
+        tx.commit().await?;
+
+        Ok::<_, Error>(change)
+    })
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|e| {
+        if matches!(e, Error::Internal(_)) {
+            counter!("db.failed_to_schedule_change").increment(1);
+        }
+    })
+}
+
+/// Get all changes scheduled for the link with the given ID which haven't
+/// been made yet, earliest first.
+pub async fn get_scheduled_changes(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: &str,
+) -> Result<Vec<ScheduledChange>> {
+    tokio::time::timeout(
+        timeout,
+        sqlx::query_as!(
+            ScheduledChange,
+            r#"
+                select * from scheduled_changes
+                where link_id = $1
+                order by scheduled_at, id
+            "#,
+            link_id
+        )
+        .fetch_all(db),
+    )
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|_| counter!("db.failed_to_lookup_scheduled_changes").increment(1))
+    .map_err(Error::from)
+}
+
+/// Cancel a change scheduled for the link with the given ID, before it is
+/// made.
+pub async fn cancel_scheduled_change(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: &str,
+    change_id: i64,
+) -> Result<()> {
+    tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        lock_link(&mut tx, link_id).await?;
+        let deleted = sqlx::query!(
+            "delete from scheduled_changes where id = $1 and link_id = $2",
+            change_id,
+            link_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(Error::ScheduledChangeNotFound(
+                link_id.to_string(),
+                change_id,
+            ));
+        }
+        update_next_change(&mut tx, link_id).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    })
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|e| {
+        if matches!(e, Error::Internal(_)) {
+            counter!("db.failed_to_cancel_scheduled_change").increment(1);
+        }
+    })
+}
+
+/// Make the changes which are due, for the link with the given ID or for all
+/// links, returning the links which were changed.
+///
+/// If several changes to a link are due, the latest of them is made. Links
+/// are locked before their changes, as when changes are scheduled or
+/// cancelled, so that each change is only made once.
+pub async fn apply_scheduled_changes(
+    db: &Pool<Postgres>,
+    timeout: Duration,
+    link_id: Option<&str>,
+) -> Result<Vec<Link>> {
+    tokio::time::timeout(timeout, async {
+        let mut tx = db.begin().await?;
+
+        let link_ids = sqlx::query_scalar!(
+            r#"
+                select id from links
+                where id in (
+                    select link_id from scheduled_changes
+                    where scheduled_at <= now() and ($1::text is null or link_id = $1)
+                )
+                order by id
+                for update
+            "#,
+            link_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        if link_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let due = sqlx::query!(
+            r#"
+                with due as (
+                    delete from scheduled_changes
+                    where link_id = any($1) and scheduled_at <= now()
+                    returning *
+                )
+                select distinct on (link_id) link_id, target_url
+                from due
+                order by link_id, scheduled_at desc, id desc
+            "#,
+            &link_ids
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut links = Vec::with_capacity(due.len());
+        for change in due {
+            let link = sqlx::query_as!(
+                Link,
+                r#"
+                    update links set
+                        target_url = $2,
+                        next_change_at = (
+                            select min(scheduled_at) from scheduled_changes where link_id = $1
+                        )
+                    where id = $1
+                    returning *
+                "#,
+                change.link_id,
+                change.target_url
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            add_revision(&mut tx, &link, Actor::Schedule, None).await?;
+            links.push(link);
+        }
+
+        tx.commit().await?;
+
+        Ok::<_, Error>(links)
+    })
+    .await
+    .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+    .inspect_err(|e| {
+        if matches!(e, Error::Internal(_)) {
+            counter!("db.failed_to_apply_scheduled_changes").increment(1);
+        }
+    })
+}
@@ -0,0 +1,1248 @@
+use std::{collections::{BTreeMap, HashMap}, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use futures_util::future::BoxFuture;
+use sqlx::{SqlitePool, Transaction, sqlite::{Sqlite, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions}};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::*;
+use crate::{config::DbConfig, error::{Error, Result, StartupError}};
+
+/// URL scheme selecting the [`SqliteStore`].
+pub const SQLITE_SCHEME: &str = "sqlite";
+
+/// How long to wait for other connections to finish writing before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// [`LinkStore`] backed by a SQLite database, for small deployments with a
+/// single instance.
+///
+/// Queries mirror those of [`PgStore`], but are only checked at runtime.
+/// Times are computed by the application rather than the database, so that
+/// they are stored in a consistent format. SQLite has no time zone aware type,
+/// so they are bound as UTC without an offset, which also keeps them sorting
+/// correctly as text.
+#[derive(Debug)]
+pub struct SqliteStore {
+    db: SqlitePool,
+    events: LocalEvents,
+    /// Timeout for each query.
+    timeout: Duration,
+}
+
+impl SqliteStore {
+    /// Create a store for the configured database file, which is created if
+    /// it doesn't exist yet.
+    pub fn new(config: &DbConfig) -> std::result::Result<Self, StartupError> {
+        let options = SqliteConnectOptions::from_str(config.url.as_str())
+            .map_err(StartupError::InvalidDatabaseUrl)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT)
+            .foreign_keys(true);
+
+        Ok(Self {
+            db: SqlitePoolOptions::new()
+                .min_connections(config.minconnections)
+                .max_connections(config.maxconnections)
+                .connect_lazy_with(options),
+            events: LocalEvents::default(),
+            timeout: Duration::from_millis(config.timeoutms),
+        })
+    }
+
+    /// Begin a transaction which holds the write lock from the start, so that
+    /// links read within it can't change until it ends.
+    async fn begin_write(&self) -> std::result::Result<Transaction<'static, Sqlite>, sqlx::Error> {
+        self.db.begin_with("BEGIN IMMEDIATE").await
+    }
+
+    /// Run a query with the configured timeout, counting failures with the
+    /// given metric.
+    async fn timed<T>(
+        &self,
+        failure_metric: &'static str,
+        query: impl Future<Output = std::result::Result<T, sqlx::Error>>,
+    ) -> Result<T> {
+        tokio::time::timeout(self.timeout, query)
+            .await
+            .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+            .inspect_err(|_| counter!(failure_metric).increment(1))
+            .map_err(Error::from)
+    }
+}
+
+/// Find the link with the given ID within a write transaction.
+async fn find_link(tx: &mut Transaction<'_, Sqlite>, link_id: &str) -> Result<Link> {
+    sqlx::query_as::<_, Link>("select * from links where id = ?1")
+        .bind(link_id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or_else(|| Error::LinkNotFound(link_id.to_string()))
+}
+
+/// Record redirects to a link in the hourly roll-up table.
+async fn add_hourly_redirects(
+    tx: &mut Transaction<'_, Sqlite>,
+    link_id: &str,
+    bucket: DateTime<Utc>,
+    n: i64,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+            insert into link_redirects_hourly (link_id, bucket, count_redirects)
+            values (?1, ?2, ?3)
+            on conflict (link_id, bucket) do update
+            set count_redirects = count_redirects + excluded.count_redirects
+        "#,
+    )
+    .bind(link_id)
+    .bind(bucket.naive_utc())
+    .bind(n)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Record the current state of a link as its next revision.
+async fn add_revision(
+    tx: &mut Transaction<'_, Sqlite>,
+    link: &Link,
+    changed_by: Actor,
+    restored_revision: Option<i32>,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+            insert into link_revisions (
+                link_id, revision, target_url, expires_at, track_conversions, is_immutable,
+                legal_hold, changed_at, changed_by, restored_revision
+            )
+            select ?1, coalesce(max(revision), 0) + 1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9
+            from link_revisions
+            where link_id = ?1
+        "#,
+    )
+    .bind(&link.id)
+    .bind(&link.target_url)
+    .bind(link.expires_at.map(|t| t.naive_utc()))
+    .bind(link.track_conversions)
+    .bind(link.is_immutable)
+    .bind(link.legal_hold)
+    .bind(link.updated_at.naive_utc())
+    .bind(changed_by)
+    .bind(restored_revision)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Set [`Link::next_change_at`] to the time of the earliest change scheduled
+/// for the link with the given ID, if it isn't already.
+async fn update_next_change(
+    tx: &mut Transaction<'_, Sqlite>,
+    link_id: &str,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+            update links set
+                next_change_at = (select min(scheduled_at) from scheduled_changes where link_id = ?1),
+                updated_at = ?2
+            where id = ?1 and next_change_at is not (
+                select min(scheduled_at) from scheduled_changes where link_id = ?1
+            )
+        "#,
+    )
+    .bind(link_id)
+    .bind(now().naive_utc())
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl LinkStore for SqliteStore {
+    async fn connect(&self, timeout: Duration) -> std::result::Result<(), StartupError> {
+        tokio::time::timeout(timeout, sqlx::migrate!("./migrations/sqlite").run(&self.db))
+            .await
+            .map_err(|e| StartupError::DatabaseUnavailable(timeout.as_secs(), e.to_string()))??;
+
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.db.is_closed()
+    }
+
+    async fn insert_link(&self, link: &Link, created_by: Actor) -> Result<Option<Link>> {
+        let result = tokio::time::timeout(self.timeout, async {
+            let mut tx = self.begin_write().await?;
+
+            let link = sqlx::query_as::<_, Link>(
+                r#"
+                    insert into links(
+                        id, target_url, count_redirects, created_at, updated_at, expires_at,
+                        is_custom_id, track_conversions, is_unlisted
+                    )
+                    values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                    returning *
+                "#,
+            )
+            .bind(&link.id)
+            .bind(&link.target_url)
+            .bind(link.count_redirects)
+            .bind(link.created_at.naive_utc())
+            .bind(link.updated_at.naive_utc())
+            .bind(link.expires_at.map(|t| t.naive_utc()))
+            .bind(link.is_custom_id)
+            .bind(link.track_conversions)
+            .bind(link.is_unlisted)
+            .fetch_one(&mut *tx)
+            .await?;
+            add_revision(&mut tx, &link, created_by, None).await?;
+
+            tx.commit().await?;
+
+            Ok::<_, sqlx::Error>(link)
+        })
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?;
+
+        match result {
+            Ok(link) => Ok(Some(link)),
+            Err(sqlx::Error::Database(db_err))
+                if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn replace_link(&self, link: &Link, changed_by: Actor) -> Result<Link> {
+        let link = tokio::time::timeout(self.timeout, async {
+            let mut tx = self.begin_write().await?;
+
+            let link = sqlx::query_as::<_, Link>(
+                r#"
+                    insert into links(
+                        id, target_url, count_redirects, created_at, updated_at, expires_at,
+                        is_custom_id, track_conversions, is_unlisted
+                    )
+                    values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                    on conflict (id) do update
+                    set target_url = excluded.target_url,
+                        count_redirects = excluded.count_redirects,
+                        created_at = excluded.created_at,
+                        updated_at = excluded.updated_at,
+                        expires_at = excluded.expires_at,
+                        is_custom_id = excluded.is_custom_id,
+                        track_conversions = excluded.track_conversions,
+                        is_unlisted = excluded.is_unlisted
+                    where not links.is_immutable
+                    returning *
+                "#,
+            )
+            .bind(&link.id)
+            .bind(&link.target_url)
+            .bind(link.count_redirects)
+            .bind(link.created_at.naive_utc())
+            .bind(link.updated_at.naive_utc())
+            .bind(link.expires_at.map(|t| t.naive_utc()))
+            .bind(link.is_custom_id)
+            .bind(link.track_conversions)
+            .bind(link.is_unlisted)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| Error::LinkImmutable(link.id.clone()))?;
+            add_revision(&mut tx, &link, changed_by, None).await?;
+
+            tx.commit().await?;
+
+            Ok::<_, Error>(link)
+        })
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+        .inspect_err(|e| {
+            if matches!(e, Error::Internal(_)) {
+                counter!("db.failed_to_replace_link").increment(1);
+            }
+        })?;
+
+        self.events.link_changed(&link.id);
+
+        Ok(link)
+    }
+
+    async fn next_sequence_number(&self) -> Result<u64> {
+        let n: i64 = self
+            .timed(
+                "db.failed_to_generate_sequential_id",
+                sqlx::query_scalar("update link_id_seq set value = value + 1 returning value")
+                    .fetch_one(&self.db),
+            )
+            .await?;
+
+        Ok(n as u64)
+    }
+
+    async fn count_link_ids(&self, min_length: u8, max_length: u8) -> Result<HashMap<u8, i64>> {
+        let rows: Vec<(i64, i64)> = self
+            .timed(
+                "db.failed_to_count_link_ids",
+                sqlx::query_as(
+                    r#"
+                    select length(id), count(*)
+                    from links
+                    where length(id) between ?1 and ?2
+                    group by 1
+                "#,
+                )
+                .bind(min_length)
+                .bind(max_length)
+                .fetch_all(&self.db),
+            )
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(length, count)| (length as u8, count))
+            .collect())
+    }
+
+    async fn get_link(&self, link_id: &str) -> Result<Option<Link>> {
+        self.timed(
+            "db.failed_to_lookup_link",
+            sqlx::query_as("select * from links where id = ?1")
+                .bind(link_id)
+                .fetch_optional(&self.db),
+        )
+        .await
+    }
+
+    async fn get_links(&self) -> Result<Vec<Link>> {
+        self.timed(
+            "db.failed_to_lookup_link",
+            sqlx::query_as(
+                r#"
+                    select * from links
+                    where (expires_at is null or expires_at > ?1) and not is_unlisted
+                    order by created_at, id
+                "#,
+            )
+            .bind(now().naive_utc())
+            .fetch_all(&self.db),
+        )
+        .await
+    }
+
+    async fn get_active_links(&self) -> Result<Vec<Link>> {
+        self.timed(
+            "db.failed_to_lookup_link",
+            sqlx::query_as(
+                "select * from links where expires_at is null or expires_at > ?1 order by id",
+            )
+            .bind(now().naive_utc())
+            .fetch_all(&self.db),
+        )
+        .await
+    }
+
+    async fn update_link(
+        &self,
+        link_id: &str,
+        update: LinkUpdate,
+        precondition: Precondition<'_>,
+    ) -> Result<Link> {
+        update.validate()?;
+
+        let link = tokio::time::timeout(self.timeout, async {
+            let mut tx = self.begin_write().await?;
+
+            let link = find_link(&mut tx, link_id).await?;
+            precondition(&link)?;
+            update.check(&link)?;
+            if update.is_empty() {
+                return Ok(link);
+            }
+
+            let link = sqlx::query_as::<_, Link>(
+                r#"
+                    update links set
+                        target_url = coalesce(?2, target_url),
+                        expires_at = case when ?3 then ?4 else expires_at end,
+                        track_conversions = coalesce(?5, track_conversions),
+                        is_immutable = coalesce(?6, is_immutable),
+                        legal_hold = coalesce(?7, legal_hold),
+                        updated_at = ?8
+                    where id = ?1
+                    returning *
+                "#,
+            )
+            .bind(link_id)
+            .bind(update.target_url)
+            .bind(update.expires_at.is_some())
+            .bind(update.expires_at.flatten().map(|t| t.naive_utc()))
+            .bind(update.track_conversions)
+            .bind(update.immutable)
+            .bind(update.legal_hold)
+            .bind(now().naive_utc())
+            .fetch_one(&mut *tx)
+            .await?;
+            add_revision(&mut tx, &link, update.changed_by, update.restored_revision).await?;
+
+            tx.commit().await?;
+
+            Ok::<_, Error>(link)
+        })
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+        .inspect_err(|e| {
+            if matches!(e, Error::Internal(_)) {
+                counter!("db.failed_to_update_link").increment(1);
+            }
+        })?;
+
+        self.events.link_changed(link_id);
+
+        Ok(link)
+    }
+
+    async fn delete_link(&self, link_id: &str, precondition: Precondition<'_>) -> Result<()> {
+        tokio::time::timeout(self.timeout, async {
+            let mut tx = self.begin_write().await?;
+
+            let link = find_link(&mut tx, link_id).await?;
+            precondition(&link)?;
+            link.check_deletable()?;
+
+            sqlx::query("delete from links where id = ?1")
+                .bind(link_id)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+        .inspect_err(|e| {
+            if matches!(e, Error::Internal(_)) {
+                counter!("db.failed_to_delete_link").increment(1);
+            }
+        })?;
+
+        self.events.link_changed(link_id);
+
+        Ok(())
+    }
+
+    async fn get_link_revisions(&self, link_id: &str) -> Result<Vec<LinkRevision>> {
+        self.timed(
+            "db.failed_to_lookup_revisions",
+            sqlx::query_as("select * from link_revisions where link_id = ?1 order by revision")
+                .bind(link_id)
+                .fetch_all(&self.db),
+        )
+        .await
+    }
+
+    async fn schedule_change(
+        &self,
+        link_id: &str,
+        target_url: &str,
+        scheduled_at: DateTime<Utc>,
+    ) -> Result<ScheduledChange> {
+        let change = tokio::time::timeout(self.timeout, async {
+            let mut tx = self.begin_write().await?;
+
+            let link = find_link(&mut tx, link_id).await?;
+            if link.is_immutable {
+                return Err(Error::LinkImmutable(link.id));
+            }
+            let change = sqlx::query_as::<_, ScheduledChange>(
+                r#"
+                    insert into scheduled_changes (link_id, target_url, scheduled_at, created_at)
+                    values (?1, ?2, ?3, ?4)
+                    returning *
+                "#,
+            )
+            .bind(link_id)
+            .bind(target_url)
+            .bind(scheduled_at.naive_utc())
+            .bind(now().naive_utc())
+            .fetch_one(&mut *tx)
+            .await?;
+            update_next_change(&mut tx, link_id).await?;
+
+            tx.commit().await?;
+
+            Ok::<_, Error>(change)
+        })
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+        .inspect_err(|e| {
+            if matches!(e, Error::Internal(_)) {
+                counter!("db.failed_to_schedule_change").increment(1);
+            }
+        })?;
+
+        self.events.link_changed(link_id);
+
+        Ok(change)
+    }
+
+    async fn get_scheduled_changes(&self, link_id: &str) -> Result<Vec<ScheduledChange>> {
+        self.timed(
+            "db.failed_to_lookup_scheduled_changes",
+            sqlx::query_as(
+                "select * from scheduled_changes where link_id = ?1 order by scheduled_at, id",
+            )
+            .bind(link_id)
+            .fetch_all(&self.db),
+        )
+        .await
+    }
+
+    async fn cancel_scheduled_change(&self, link_id: &str, change_id: i64) -> Result<()> {
+        tokio::time::timeout(self.timeout, async {
+            let mut tx = self.begin_write().await?;
+
+            find_link(&mut tx, link_id).await?;
+            let deleted =
+                sqlx::query("delete from scheduled_changes where id = ?1 and link_id = ?2")
+                    .bind(change_id)
+                    .bind(link_id)
+                    .execute(&mut *tx)
+                    .await?;
+            if deleted.rows_affected() == 0 {
+                return Err(Error::ScheduledChangeNotFound(
+                    link_id.to_string(),
+                    change_id,
+                ));
+            }
+            update_next_change(&mut tx, link_id).await?;
+
+            tx.commit().await?;
+
+            Ok(())
+        })
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+        .inspect_err(|e| {
+            if matches!(e, Error::Internal(_)) {
+                counter!("db.failed_to_cancel_scheduled_change").increment(1);
+            }
+        })?;
+
+        self.events.link_changed(link_id);
+
+        Ok(())
+    }
+
+    async fn apply_scheduled_changes(&self, link_id: Option<&str>) -> Result<Vec<Link>> {
+        let now = now();
+        let links = self
+            .timed("db.failed_to_apply_scheduled_changes", async {
+                let mut tx = self.begin_write().await?;
+
+                let mut due: Vec<ScheduledChange> = sqlx::query_as(
+                    r#"
+                    delete from scheduled_changes
+                    where scheduled_at <= ?1 and (?2 is null or link_id = ?2)
+                    returning *
+                "#,
+                )
+                .bind(now.naive_utc())
+                .bind(link_id)
+                .fetch_all(&mut *tx)
+                .await?;
+                due.sort_by_key(|c| (c.scheduled_at, c.id));
+
+                // Only the latest due change to each link is made
+                let latest: BTreeMap<_, _> =
+                    due.into_iter().map(|c| (c.link_id, c.target_url)).collect();
+
+                let mut links = Vec::with_capacity(latest.len());
+                for (link_id, target_url) in latest {
+                    let link = sqlx::query_as::<_, Link>(
+                        r#"
+                        update links set
+                            target_url = ?2,
+                            next_change_at = (
+                                select min(scheduled_at) from scheduled_changes where link_id = ?1
+                            ),
+                            updated_at = ?3
+                        where id = ?1
+                        returning *
+                    "#,
+                    )
+                    .bind(&link_id)
+                    .bind(target_url)
+                    .bind(now.naive_utc())
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    add_revision(&mut tx, &link, Actor::Schedule, None).await?;
+                    links.push(link);
+                }
+
+                tx.commit().await?;
+
+                Ok(links)
+            })
+            .await?;
+
+        for link in &links {
+            self.events.link_changed(&link.id);
+        }
+
+        Ok(links)
+    }
+
+    async fn increment_link_redirect_count(&self, link_id: &str) -> Result<Option<Link>> {
+        let now = now();
+        let link = self
+            .timed("db.failed_to_increment_link", async {
+                let mut tx = self.begin_write().await?;
+
+                let link = sqlx::query_as::<_, Link>(
+                    r#"
+                    update links set count_redirects = count_redirects + 1, updated_at = ?2
+                    where id = ?1 and (expires_at is null or expires_at > ?2)
+                    returning *
+                "#,
+                )
+                .bind(link_id)
+                .bind(now.naive_utc())
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                if link.is_some() {
+                    add_hourly_redirects(&mut tx, link_id, start_of_hour(now), 1).await?;
+                }
+                tx.commit().await?;
+
+                Ok(link)
+            })
+            .await
+            .inspect_err(|e| {
+                tracing::error!(
+                    "Incrementing link redirect count resulted in the following error: {e}"
+                )
+            })?;
+
+        if link.is_some() {
+            tracing::debug!("Incremented redirect count for link with ID {link_id}");
+        }
+
+        Ok(link)
+    }
+
+    async fn add_link_redirect_counts(&self, counts: &HashMap<String, i64>) -> Result<()> {
+        let now = now();
+        self.timed("db.failed_to_increment_link", async {
+            let mut tx = self.begin_write().await?;
+
+            for (id, n) in counts {
+                let updated = sqlx::query(
+                    r#"
+                        update links set count_redirects = count_redirects + ?2, updated_at = ?3
+                        where id = ?1
+                    "#,
+                )
+                .bind(id)
+                .bind(n)
+                .bind(now.naive_utc())
+                .execute(&mut *tx)
+                .await?;
+
+                if updated.rows_affected() > 0 {
+                    add_hourly_redirects(&mut tx, id, start_of_hour(now), *n).await?;
+                }
+            }
+
+            tx.commit().await
+        })
+        .await
+        .inspect_err(|e| {
+            tracing::error!("Adding link redirect counts resulted in the following error: {e}")
+        })?;
+
+        tracing::debug!("Added redirect counts for {} links", counts.len());
+
+        Ok(())
+    }
+
+    async fn get_top_links(&self, window_hours: i32, limit: i64) -> Result<Vec<TopLink>> {
+        let now = now();
+        self.timed(
+            "db.failed_to_lookup_top_links",
+            sqlx::query_as(
+                r#"
+                    select l.*, sum(r.count_redirects) as window_redirects
+                    from link_redirects_hourly r
+                    join links l on l.id = r.link_id
+                    where r.bucket >= ?1
+                        and (l.expires_at is null or l.expires_at > ?2)
+                        and not l.is_unlisted
+                    group by l.id
+                    order by window_redirects desc, l.id
+                    limit ?3
+                "#,
+            )
+            .bind(start_of_hour(now - TimeDelta::hours(window_hours.into())).naive_utc())
+            .bind(now.naive_utc())
+            .bind(limit)
+            .fetch_all(&self.db),
+        )
+        .await
+    }
+
+    async fn get_instance_stats(&self, days: i32) -> Result<InstanceStats> {
+        let now = now();
+
+        let (total, active, custom_id, total_redirects): (i64, i64, i64, i64) = self
+            .timed(
+                "db.failed_to_lookup_stats",
+                sqlx::query_as(
+                    r#"
+                    select
+                        count(*),
+                        coalesce(sum(expires_at is null or expires_at > ?1), 0),
+                        coalesce(sum(is_custom_id), 0),
+                        coalesce(sum(count_redirects), 0)
+                    from links
+                "#,
+                )
+                .bind(now.naive_utc())
+                .fetch_one(&self.db),
+            )
+            .await?;
+
+        // Redirects are counted in hourly buckets, so the start of each window
+        // is rounded down to the nearest hour
+        let (redirects_last_day, redirects_last_week): (i64, i64) = self
+            .timed(
+                "db.failed_to_lookup_stats",
+                sqlx::query_as(
+                    r#"
+                    select
+                        coalesce(sum(case when bucket >= ?1 then count_redirects end), 0),
+                        coalesce(sum(count_redirects), 0)
+                    from link_redirects_hourly
+                    where bucket >= ?2
+                "#,
+                )
+                .bind(start_of_hour(now - TimeDelta::days(1)).naive_utc())
+                .bind(start_of_hour(now - TimeDelta::days(7)).naive_utc())
+                .fetch_one(&self.db),
+            )
+            .await?;
+
+        let first_day = now.date_naive() - TimeDelta::days(i64::from(days) - 1);
+        let created: HashMap<NaiveDate, i64> = self
+            .timed(
+                "db.failed_to_lookup_stats",
+                sqlx::query_as(
+                    r#"
+                    select date(created_at), count(*)
+                    from links
+                    where date(created_at) >= ?1
+                    group by 1
+                "#,
+                )
+                .bind(first_day)
+                .fetch_all(&self.db),
+            )
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok(InstanceStats {
+            total_links: total,
+            active_links: active,
+            expired_links: total - active,
+            custom_id_links: custom_id,
+            generated_id_links: total - custom_id,
+            custom_id_ratio: if total == 0 {
+                0.0
+            } else {
+                custom_id as f64 / total as f64
+            },
+            total_redirects,
+            redirects_last_day,
+            redirects_last_week,
+            links_created_per_day: first_day
+                .iter_days()
+                .take_while(|date| *date <= now.date_naive())
+                .map(|date| DailyCount {
+                    date,
+                    count: created.get(&date).copied().unwrap_or_default(),
+                })
+                .collect(),
+        })
+    }
+
+    async fn get_hourly_redirects(
+        &self,
+        filter: &RedirectsFilter,
+        after: Option<(&str, DateTime<Utc>)>,
+        limit: i64,
+    ) -> Result<Vec<HourlyRedirects>> {
+        let (after_id, after_hour) = after.unzip();
+        let link_ids = filter
+            .link_ids
+            .as_ref()
+            .map(|ids| serde_json::to_string(ids).expect("link IDs should be serialisable"));
+
+        self.timed(
+            "db.failed_to_lookup_redirects",
+            sqlx::query_as(
+                r#"
+                    select
+                        r.link_id,
+                        l.target_url,
+                        r.bucket as hour,
+                        r.count_redirects as redirects
+                    from link_redirects_hourly r
+                    join links l on l.id = r.link_id
+                    where (?1 is null or r.link_id in (select value from json_each(?1)))
+                        and (?1 is not null or not l.is_unlisted)
+                        and (?2 is null or r.bucket >= ?2)
+                        and (?3 is null or r.bucket < ?3)
+                        and (?4 is null or (r.link_id, r.bucket) > (?4, ?5))
+                    order by r.link_id, r.bucket
+                    limit ?6
+                "#,
+            )
+            .bind(link_ids)
+            .bind(filter.from.map(start_of_hour).map(|t| t.naive_utc()))
+            .bind(filter.to.map(|t| t.naive_utc()))
+            .bind(after_id)
+            .bind(after_hour.map(|t| t.naive_utc()))
+            .bind(limit)
+            .fetch_all(&self.db),
+        )
+        .await
+    }
+
+    async fn record_click(&self, link_id: &str) -> Result<Uuid> {
+        let click_id = Uuid::new_v4();
+
+        self.timed(
+            "db.failed_to_record_click",
+            sqlx::query("insert into link_clicks (id, link_id, clicked_at) values (?1, ?2, ?3)")
+                .bind(click_id)
+                .bind(link_id)
+                .bind(now().naive_utc())
+                .execute(&self.db),
+        )
+        .await?;
+
+        Ok(click_id)
+    }
+
+    async fn record_conversion(&self, click_id: Uuid, goal: &str) -> Result<bool> {
+        if !validate_goal(goal) {
+            return Err(Error::ConversionGoalNotValid(goal.to_string()));
+        }
+
+        let result = tokio::time::timeout(
+            self.timeout,
+            sqlx::query(
+                r#"
+                    insert into conversions (click_id, goal, created_at) values (?1, ?2, ?3)
+                    on conflict (click_id, goal) do nothing
+                "#,
+            )
+            .bind(click_id)
+            .bind(goal)
+            .bind(now().naive_utc())
+            .execute(&self.db),
+        )
+        .await
+        .inspect_err(|_| counter!("db.connection_timeout").increment(1))?
+        .map_err(|e| {
+            // Click ID does not exist
+            if let sqlx::Error::Database(db_err) = &e
+                && db_err.kind() == sqlx::error::ErrorKind::ForeignKeyViolation
+            {
+                return Error::ClickNotFound(click_id.to_string());
+            }
+
+            counter!("db.failed_to_record_conversion").increment(1);
+            e.into()
+        })?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_conversion_stats(&self, link_id: Option<&str>) -> Result<ConversionStats> {
+        let tracked_clicks: i64 = self
+            .timed(
+                "db.failed_to_lookup_conversions",
+                sqlx::query_scalar(
+                    "select count(*) from link_clicks where ?1 is null or link_id = ?1",
+                )
+                .bind(link_id)
+                .fetch_one(&self.db),
+            )
+            .await?;
+
+        let goals: Vec<(String, i64)> = self
+            .timed(
+                "db.failed_to_lookup_conversions",
+                sqlx::query_as(
+                    r#"
+                    select c.goal, count(*) as conversions
+                    from conversions c
+                    join link_clicks l on l.id = c.click_id
+                    where ?1 is null or l.link_id = ?1
+                    group by c.goal
+                    order by conversions desc, c.goal
+                "#,
+                )
+                .bind(link_id)
+                .fetch_all(&self.db),
+            )
+            .await?;
+
+        Ok(ConversionStats {
+            tracked_clicks,
+            goals: goals
+                .into_iter()
+                .map(|(goal, conversions)| GoalConversions {
+                    goal,
+                    conversions,
+                    conversion_rate: if tracked_clicks == 0 {
+                        0.0
+                    } else {
+                        conversions as f64 / tracked_clicks as f64
+                    },
+                })
+                .collect(),
+        })
+    }
+
+    async fn backup(&self, records: mpsc::Sender<BackupRecord>) -> Result<()> {
+        // Reads within a transaction see a single snapshot of the database
+        async {
+            let mut tx = self.db.begin().await?;
+
+            let rows = sqlx::query_as("select * from links order by id").fetch(&mut *tx);
+            if !send_rows(rows, &records, BackupRecord::Link).await? {
+                return Ok(());
+            }
+
+            let rows = sqlx::query_as(
+                r#"
+                    select link_id, bucket, count_redirects
+                    from link_redirects_hourly
+                    order by link_id, bucket
+                "#,
+            )
+            .fetch(&mut *tx);
+            if !send_rows(rows, &records, BackupRecord::Redirects).await? {
+                return Ok(());
+            }
+
+            let rows =
+                sqlx::query_as("select id, link_id, clicked_at from link_clicks order by id")
+                    .fetch(&mut *tx);
+            if !send_rows(rows, &records, BackupRecord::Click).await? {
+                return Ok(());
+            }
+
+            let rows = sqlx::query_as(
+                "select click_id, goal, created_at from conversions order by click_id, goal",
+            )
+            .fetch(&mut *tx);
+            if !send_rows(rows, &records, BackupRecord::Conversion).await? {
+                return Ok(());
+            }
+
+            let rows = sqlx::query_as("select * from link_revisions order by link_id, revision")
+                .fetch(&mut *tx);
+            if !send_rows(rows, &records, BackupRecord::Revision).await? {
+                return Ok(());
+            }
+
+            let rows =
+                sqlx::query_as("select * from scheduled_changes order by id").fetch(&mut *tx);
+            if !send_rows(rows, &records, BackupRecord::ScheduledChange).await? {
+                return Ok(());
+            }
+
+            let value: i64 = sqlx::query_scalar("select value from link_id_seq")
+                .fetch_one(&mut *tx)
+                .await?;
+            _ = records
+                .send(BackupRecord::Sequence {
+                    value: value as u64,
+                })
+                .await;
+
+            Ok(())
+        }
+        .await
+        .inspect_err(|_: &Error| counter!("db.failed_to_back_up").increment(1))
+    }
+
+    async fn restore(&self, backup: &Backup) -> Result<()> {
+        let previous_ids = async {
+            let mut tx = self.begin_write().await?;
+
+            // Links which are immutable or on legal hold must survive the
+            // restore
+            let protected: Vec<Link> =
+                sqlx::query_as("select * from links where is_immutable or legal_hold")
+                    .fetch_all(&mut *tx)
+                    .await?;
+            backup.check_protected_links(&protected)?;
+
+            // Redirects, clicks, conversions and scheduled changes are deleted
+            // along with their links, but revisions are kept for deleted links
+            let previous_ids: Vec<String> = sqlx::query_scalar("delete from links returning id")
+                .fetch_all(&mut *tx)
+                .await?;
+            sqlx::query("delete from link_revisions")
+                .execute(&mut *tx)
+                .await?;
+
+            for link in &backup.links {
+                sqlx::query(
+                    r#"
+                        insert into links(
+                            id, target_url, count_redirects, created_at, updated_at, expires_at,
+                                is_custom_id, track_conversions, is_unlisted, next_change_at,
+                            is_immutable, legal_hold
+                        )
+                        values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                    "#,
+                )
+                .bind(&link.id)
+                .bind(&link.target_url)
+                .bind(link.count_redirects)
+                .bind(link.created_at.naive_utc())
+                .bind(link.updated_at.naive_utc())
+                .bind(link.expires_at.map(|t| t.naive_utc()))
+                .bind(link.is_custom_id)
+                .bind(link.track_conversions)
+                .bind(link.is_unlisted)
+                .bind(link.next_change_at.map(|t| t.naive_utc()))
+                .bind(link.is_immutable)
+                .bind(link.legal_hold)
+                .execute(&mut *tx)
+                .await
+                .map_err(restore_error)?;
+            }
+
+            for redirects in &backup.redirects {
+                sqlx::query(
+                    r#"
+                        insert into link_redirects_hourly (link_id, bucket, count_redirects)
+                        values (?1, ?2, ?3)
+                    "#,
+                )
+                .bind(&redirects.link_id)
+                .bind(redirects.bucket.naive_utc())
+                .bind(redirects.count_redirects)
+                .execute(&mut *tx)
+                .await
+                .map_err(restore_error)?;
+            }
+
+            for click in &backup.clicks {
+                sqlx::query(
+                    "insert into link_clicks (id, link_id, clicked_at) values (?1, ?2, ?3)",
+                )
+                .bind(click.id)
+                .bind(&click.link_id)
+                .bind(click.clicked_at.naive_utc())
+                .execute(&mut *tx)
+                .await
+                .map_err(restore_error)?;
+            }
+
+            for conversion in &backup.conversions {
+                sqlx::query(
+                    "insert into conversions (click_id, goal, created_at) values (?1, ?2, ?3)",
+                )
+                .bind(conversion.click_id)
+                .bind(&conversion.goal)
+                .bind(conversion.created_at.naive_utc())
+                .execute(&mut *tx)
+                .await
+                .map_err(restore_error)?;
+            }
+
+            for revision in &backup.revisions {
+                sqlx::query(
+                    r#"
+                        insert into link_revisions (
+                            link_id, revision, target_url, expires_at, track_conversions,
+                            is_immutable, legal_hold, changed_at, changed_by, restored_revision
+                        )
+                        values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                    "#,
+                )
+                .bind(&revision.link_id)
+                .bind(revision.revision)
+                .bind(&revision.target_url)
+                .bind(revision.expires_at.map(|t| t.naive_utc()))
+                .bind(revision.track_conversions)
+                .bind(revision.is_immutable)
+                .bind(revision.legal_hold)
+                .bind(revision.changed_at.naive_utc())
+                .bind(revision.changed_by)
+                .bind(revision.restored_revision)
+                .execute(&mut *tx)
+                .await
+                .map_err(restore_error)?;
+            }
+
+            for change in &backup.scheduled_changes {
+                sqlx::query(
+                    r#"
+                        insert into scheduled_changes (
+                            id, link_id, target_url, scheduled_at, created_at
+                        )
+                        values (?1, ?2, ?3, ?4, ?5)
+                    "#,
+                )
+                .bind(change.id)
+                .bind(&change.link_id)
+                .bind(&change.target_url)
+                .bind(change.scheduled_at.naive_utc())
+                .bind(change.created_at.naive_utc())
+                .execute(&mut *tx)
+                .await
+                .map_err(restore_error)?;
+            }
+
+            sqlx::query("update link_id_seq set value = ?1")
+                .bind(backup.sequence as i64)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+
+            Ok(previous_ids)
+        }
+        .await
+        .inspect_err(|_: &Error| counter!("db.failed_to_restore").increment(1))?;
+
+        for link_id in previous_ids {
+            self.events.link_changed(&link_id);
+        }
+
+        Ok(())
+    }
+
+    async fn purge_expired_links(
+        &self,
+        expired_before: DateTime<Utc>,
+        limit: i64,
+        archive: bool,
+    ) -> Result<Vec<String>> {
+        let links = self
+            .timed("db.failed_to_purge_links", async {
+                let mut tx = self.begin_write().await?;
+
+                let links: Vec<Link> = sqlx::query_as(
+                    r#"
+                    delete from links
+                    where id in (
+                        select id from links
+                        where expires_at < ?1 and not legal_hold
+                        order by expires_at
+                        limit ?2
+                    )
+                    returning *
+                "#,
+                )
+                .bind(expired_before.naive_utc())
+                .bind(limit)
+                .fetch_all(&mut *tx)
+                .await?;
+
+                if archive {
+                    let archived_at = now();
+                    for link in &links {
+                        sqlx::query(
+                            r#"
+                            insert into archived_links (
+                                id, target_url, count_redirects, created_at, updated_at,
+                                expires_at, is_custom_id, track_conversions, is_unlisted,
+                                archived_at
+                            )
+                            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                        "#,
+                        )
+                        .bind(&link.id)
+                        .bind(&link.target_url)
+                        .bind(link.count_redirects)
+                        .bind(link.created_at.naive_utc())
+                        .bind(link.updated_at.naive_utc())
+                        .bind(link.expires_at.map(|t| t.naive_utc()))
+                        .bind(link.is_custom_id)
+                        .bind(link.track_conversions)
+                        .bind(link.is_unlisted)
+                        .bind(archived_at.naive_utc())
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                } else {
+                    let purged_at = now();
+                    for link in &links {
+                        sqlx::query(
+                            "insert into purged_links (id, expires_at, purged_at) values (?1, ?2, ?3)",
+                        )
+                        .bind(&link.id)
+                        .bind(link.expires_at.map(|t| t.naive_utc()))
+                        .bind(purged_at.naive_utc())
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                }
+
+                tx.commit().await?;
+
+                Ok(links)
+            })
+            .await?;
+
+        let ids: Vec<_> = links.into_iter().map(|l| l.id).collect();
+        for id in &ids {
+            self.events.link_changed(id);
+        }
+
+        Ok(ids)
+    }
+
+    async fn run_exclusively(
+        &self,
+        _lock_key: i64,
+        job: BoxFuture<'_, Result<()>>,
+    ) -> Result<bool> {
+        // Only a single instance can use the database file
+        job.await.map(|_| true)
+    }
+
+    async fn publish_redirect_events(&self, events: &[RedirectEvent]) -> Result<()> {
+        for event in events {
+            self.events.publish_redirect(event);
+        }
+        Ok(())
+    }
+
+    async fn listen_events(&self, senders: EventSenders) {
+        self.events.listen(senders);
+    }
+}
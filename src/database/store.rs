@@ -0,0 +1,213 @@
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, SubsecRound, TimeDelta, Utc};
+use futures_util::future::BoxFuture;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::{Actor, Backup, BackupRecord, ConversionStats, EventSenders, HourlyRedirects, InstanceStats, Link, LinkRevision, LinkUpdate, MemoryStore, PgStore, RedirectEvent, RedirectsFilter, SQLITE_SCHEME, ScheduledChange, SqliteStore, TopLink};
+use crate::{config::DbConfig, error::{Result, StartupError}};
+
+/// URL scheme selecting the [`MemoryStore`].
+pub const MEMORY_SCHEME: &str = "memory";
+
+/// Check run on a link while it is locked, before it is changed. The change is
+/// not made if this fails.
+pub type Precondition<'a> = Box<dyn FnOnce(&Link) -> Result<()> + Send + 'a>;
+
+/// Current time, with the same precision as PostgreSQL timestamps.
+pub(crate) fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(6)
+}
+
+/// Start of the hourly bucket which redirects at the given time are counted in.
+pub(super) fn start_of_hour(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(TimeDelta::hours(1))
+        .expect("times should be truncatable to the hour")
+}
+
+/// Storage of links, along with their analytics and conversions.
+///
+/// Validation which doesn't depend on stored data, such as of link IDs, is
+/// done before calling the store.
+#[async_trait]
+pub trait LinkStore: Debug + Send + Sync {
+    /// Wait until the store is usable, retrying until the timeout, and prepare
+    /// it for use (e.g. by running migrations).
+    async fn connect(&self, timeout: Duration) -> std::result::Result<(), StartupError>;
+
+    /// Whether the store has been closed, after which background tasks using
+    /// it should stop.
+    fn is_closed(&self) -> bool;
+
+    /// Insert a new link along with its first [`LinkRevision`], returning
+    /// [`None`] if its ID is already taken.
+    async fn insert_link(&self, link: &Link, created_by: Actor) -> Result<Option<Link>>;
+
+    /// Insert a link, replacing any existing link with the same ID, and record
+    /// it as the link's next [`LinkRevision`].
+    async fn replace_link(&self, link: &Link, changed_by: Actor) -> Result<Link>;
+
+    /// Get the next number of the sequence encoded into sequential IDs.
+    async fn next_sequence_number(&self) -> Result<u64>;
+
+    /// Count links with IDs of each length between the given lengths
+    /// (inclusive).
+    async fn count_link_ids(&self, min_length: u8, max_length: u8) -> Result<HashMap<u8, i64>>;
+
+    /// Find an existing [`Link`] with the given ID.
+    async fn get_link(&self, link_id: &str) -> Result<Option<Link>>;
+
+    /// Get all active [`Link`]s, except unlisted links, ordered by creation
+    /// time.
+    async fn get_links(&self) -> Result<Vec<Link>>;
+
+    /// Get all active [`Link`]s, including unlisted links, ordered by ID.
+    async fn get_active_links(&self) -> Result<Vec<Link>>;
+
+    /// Update the link with the given ID, if it passes the precondition, and
+    /// record the result as the link's next [`LinkRevision`].
+    async fn update_link(
+        &self,
+        link_id: &str,
+        update: LinkUpdate,
+        precondition: Precondition<'_>,
+    ) -> Result<Link>;
+
+    /// Delete the link with the given ID, if it passes the precondition,
+    /// keeping its revisions.
+    async fn delete_link(&self, link_id: &str, precondition: Precondition<'_>) -> Result<()>;
+
+    /// Get all [`LinkRevision`]s of the link with the given ID, oldest first.
+    /// Revisions are kept after the link is deleted, so only links which never
+    /// existed have no revisions.
+    async fn get_link_revisions(&self, link_id: &str) -> Result<Vec<LinkRevision>>;
+
+    /// Schedule a change to the target URL of the link with the given ID, to
+    /// be made at the given time.
+    async fn schedule_change(
+        &self,
+        link_id: &str,
+        target_url: &str,
+        scheduled_at: DateTime<Utc>,
+    ) -> Result<ScheduledChange>;
+
+    /// Get all [`ScheduledChange`]s of the link with the given ID which
+    /// haven't been made yet, earliest first.
+    async fn get_scheduled_changes(&self, link_id: &str) -> Result<Vec<ScheduledChange>>;
+
+    /// Cancel a change scheduled for the link with the given ID.
+    async fn cancel_scheduled_change(&self, link_id: &str, change_id: i64) -> Result<()>;
+
+    /// Make the scheduled changes which are due, for the link with the given
+    /// ID or for all links, recording each as a [`LinkRevision`]. Returns the
+    /// links which were changed.
+    async fn apply_scheduled_changes(&self, link_id: Option<&str>) -> Result<Vec<Link>>;
+
+    /// Increment [`Link::count_redirects`] of an active link, and record the
+    /// redirect in the current hour. Returns [`None`] if no active link with
+    /// the given ID was found.
+    async fn increment_link_redirect_count(&self, link_id: &str) -> Result<Option<Link>>;
+
+    /// Add the given number of redirects to each link, recording them in the
+    /// current hour.
+    async fn add_link_redirect_counts(&self, counts: &HashMap<String, i64>) -> Result<()>;
+
+    /// Get the most redirected-to active links over the last `window_hours`
+    /// hours. Unlisted links are left out.
+    async fn get_top_links(&self, window_hours: i32, limit: i64) -> Result<Vec<TopLink>>;
+
+    /// Get [`InstanceStats`], with link creation counts for the last `days`
+    /// days (including today).
+    async fn get_instance_stats(&self, days: i32) -> Result<InstanceStats>;
+
+    /// Get a page of [`HourlyRedirects`] matching the filter, ordered by link
+    /// ID and then hour, after the given cursor.
+    async fn get_hourly_redirects(
+        &self,
+        filter: &RedirectsFilter,
+        after: Option<(&str, DateTime<Utc>)>,
+        limit: i64,
+    ) -> Result<Vec<HourlyRedirects>>;
+
+    /// Record a click on a link, returning the new click ID.
+    async fn record_click(&self, link_id: &str) -> Result<Uuid>;
+
+    /// Record that a click led to the goal being reached, returning `false` if
+    /// this conversion had already been recorded.
+    async fn record_conversion(&self, click_id: Uuid, goal: &str) -> Result<bool>;
+
+    /// Get [`ConversionStats`] for the link with the given ID, or for all links
+    /// if no ID is given.
+    async fn get_conversion_stats(&self, link_id: Option<&str>) -> Result<ConversionStats>;
+
+    /// Stream all data as backup records, as of a single point in time.
+    /// Stops early if the receiver is dropped.
+    async fn backup(&self, records: mpsc::Sender<BackupRecord>) -> Result<()>;
+
+    /// Replace all data with the data of a backup, all at once. Nothing is
+    /// restored if it would remove or change a link which is immutable or on
+    /// legal hold.
+    async fn restore(&self, backup: &Backup) -> Result<()>;
+
+    /// Delete up to `limit` links which expired before the given time, along
+    /// with their analytics, returning their IDs. Deleted links are moved to
+    /// the archive if `archive` is set, and otherwise only their IDs are
+    /// recorded as purged.
+    async fn purge_expired_links(
+        &self,
+        expired_before: DateTime<Utc>,
+        limit: i64,
+        archive: bool,
+    ) -> Result<Vec<String>>;
+
+    /// Run a job unless another instance is running a job with the same lock
+    /// key, returning whether it was run.
+    async fn run_exclusively(&self, lock_key: i64, job: BoxFuture<'_, Result<()>>) -> Result<bool>;
+
+    /// Publish [`RedirectEvent`]s to all instances listening for them.
+    async fn publish_redirect_events(&self, events: &[RedirectEvent]) -> Result<()>;
+
+    /// Forward events published by any instance, and changes to links, to the
+    /// given channels, until the store is closed.
+    async fn listen_events(&self, senders: EventSenders);
+}
+
+/// Stores used by the application, selected by the scheme of the database URL.
+pub struct Stores {
+    /// Store used for all queries which write, or need to see the latest
+    /// writes.
+    pub store: Arc<dyn LinkStore>,
+    /// Store used for read-only queries when read replicas are configured,
+    /// which may lag behind [`Self::store`].
+    pub read_store: Option<Arc<dyn LinkStore>>,
+}
+
+/// Create the [`Stores`] for the configured database URL, without connecting
+/// to the database yet.
+pub fn init_stores(config: &DbConfig) -> std::result::Result<Stores, StartupError> {
+    // Only PostgreSQL supports read replicas
+    let store: Arc<dyn LinkStore> = match config.url.scheme() {
+        MEMORY_SCHEME => Arc::new(MemoryStore::default()),
+        SQLITE_SCHEME => Arc::new(SqliteStore::new(config)?),
+        _ => {
+            let store = PgStore::new(config)?;
+            let read_store = if config.replicaurls.is_empty() {
+                None
+            } else {
+                Some(Arc::new(store.with_replicas(config)?) as Arc<dyn LinkStore>)
+            };
+
+            return Ok(Stores {
+                store: Arc::new(store),
+                read_store,
+            });
+        }
+    };
+
+    Ok(Stores {
+        store,
+        read_store: None,
+    })
+}
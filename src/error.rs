@@ -1,7 +1,7 @@
 use std::fmt::Display;
 
-use axum::{extract::rejection::{JsonRejection, PathRejection, QueryRejection}, http::StatusCode, response::{IntoResponse, Response}};
-use chrono::NaiveDateTime;
+use axum::{extract::rejection::{JsonRejection, PathRejection, QueryRejection}, http::{StatusCode, header::RETRY_AFTER}, response::{IntoResponse, Response}};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use tokio::time::error::Elapsed;
 use url::ParseError;
@@ -17,6 +17,8 @@ pub enum Error {
     // Short link redirection
     #[error("A link with the provided ID '{0}' could not be found")]
     LinkNotFound(String),
+    #[error("Too many links could not be found, please retry after {0} seconds")]
+    TooManyNotFound(u64),
 
     // Short link generation
     #[error("The provided custom link ID is already in use: {0}")]
@@ -24,7 +26,7 @@ pub enum Error {
     #[error("The provided custom link ID is not valid: {0}")]
     LinkIdNotValid(String),
     #[error("The provided expiration time is not valid: {0}")]
-    LinkExpirationTimeNotValid(NaiveDateTime),
+    LinkExpirationTimeNotValid(DateTime<Utc>),
     #[error("Malformed URL: {0}")]
     MalformedURL(String),
     #[error("Only URLs with valid hosts are accepted: {0}")]
@@ -32,16 +34,63 @@ pub enum Error {
     #[error("URLs with the same host as this service are forbidden: {0}")]
     URLWithMatchingHosts(String),
 
+    // Short link modification
+    #[error("The link has changed since it was fetched, as it no longer matches the given ETag")]
+    LinkChanged,
+    #[error(
+        "Link '{0}' is immutable, so its target URL and expiration time can't be changed and it can't be deleted"
+    )]
+    LinkImmutable(String),
+    #[error("Link '{0}' is on legal hold, so it can't be deleted or purged")]
+    LinkOnLegalHold(String),
+    #[error("Link '{0}' has no revision {1}")]
+    LinkRevisionNotFound(String, i32),
+    #[error("Link '{0}' has no scheduled change {1}")]
+    ScheduledChangeNotFound(String, i64),
+    #[error("The provided scheduled change time is not valid: {0}")]
+    ScheduledChangeTimeNotValid(DateTime<Utc>),
+
+    // Conversion tracking
+    #[error("A click with the provided ID '{0}' could not be found")]
+    ClickNotFound(String),
+    #[error("The provided conversion goal is not valid: {0}")]
+    ConversionGoalNotValid(String),
+
+    // Backups
+    #[error(
+        "The backup has schema version {0}, which can't be restored by this instance (schema version {1})"
+    )]
+    BackupSchemaNotSupported(i64, i64),
+
     // Other errors
     #[error("Route not found")]
     RouteNotFound,
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
+    #[error("The service is temporarily unavailable, please retry after {0} seconds")]
+    DatabaseUnavailable(u64),
+    #[error("Missing or invalid admin credentials")]
+    Unauthorized,
     // Avoid exposing details about internal server errors to the client
     #[error("Something went wrong")]
     Internal(String),
 }
 
+/// Errors which prevent the application from starting
+#[derive(thiserror::Error, Debug)]
+pub enum StartupError {
+    #[error("Invalid database URL: {0}")]
+    InvalidDatabaseUrl(sqlx::Error),
+    #[error("The database could not be reached within {0} seconds: {1}")]
+    DatabaseUnavailable(u64, String),
+    #[error("Invalid shared cache URL: {0}")]
+    InvalidSharedCacheUrl(redis::RedisError),
+    #[error("Failed to migrate the database: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("Startup failed unexpectedly: {0}")]
+    Internal(String),
+}
+
 /// For serialising error response into a specific format
 #[derive(Serialize, Debug, ToSchema)]
 pub struct ErrorResponse {
@@ -60,6 +109,7 @@ impl IntoResponse for Error {
         let status = match &self {
             // Redirection
             Self::LinkNotFound(_) => StatusCode::NOT_FOUND,
+            Self::TooManyNotFound(_) => StatusCode::TOO_MANY_REQUESTS,
 
             // Creation
             Self::LinkIdNotUnique(_) => StatusCode::UNPROCESSABLE_ENTITY,
@@ -69,8 +119,25 @@ impl IntoResponse for Error {
             Self::LinkIdNotValid(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::LinkExpirationTimeNotValid(_) => StatusCode::UNPROCESSABLE_ENTITY,
 
+            // Modification
+            Self::LinkChanged => StatusCode::PRECONDITION_FAILED,
+            Self::LinkImmutable(_) => StatusCode::CONFLICT,
+            Self::LinkOnLegalHold(_) => StatusCode::CONFLICT,
+            Self::LinkRevisionNotFound(..) => StatusCode::NOT_FOUND,
+            Self::ScheduledChangeNotFound(..) => StatusCode::NOT_FOUND,
+            Self::ScheduledChangeTimeNotValid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+
+            // Conversion tracking
+            Self::ClickNotFound(_) => StatusCode::NOT_FOUND,
+            Self::ConversionGoalNotValid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+
+            // Backups
+            Self::BackupSchemaNotSupported(..) => StatusCode::UNPROCESSABLE_ENTITY,
+
             Self::RouteNotFound => StatusCode::NOT_FOUND,
             Self::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::DatabaseUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
 
             Self::Internal(s) => {
                 tracing::error!("Internal server error: {s}");
@@ -78,7 +145,17 @@ impl IntoResponse for Error {
             }
         };
 
-        (status, Json(ErrorResponse::from(self))).into_response()
+        let retry_after = match &self {
+            Self::DatabaseUnavailable(seconds) | Self::TooManyNotFound(seconds) => Some(*seconds),
+            _ => None,
+        };
+
+        let mut response = (status, Json(ErrorResponse::from(self))).into_response();
+        if let Some(seconds) = retry_after {
+            response.headers_mut().insert(RETRY_AFTER, seconds.into());
+        }
+
+        response
     }
 }
 
@@ -1,7 +1,10 @@
-use axum::{extract::{FromRequest, FromRequestParts}, response::IntoResponse};
+use std::{convert::Infallible, net::{IpAddr, SocketAddr}};
+
+use axum::{extract::{ConnectInfo, FromRequest, FromRequestParts, OptionalFromRequestParts}, http::{HeaderMap, header::{AUTHORIZATION, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH}, request::Parts}, response::IntoResponse};
+use chrono::{DateTime, SubsecRound, Utc};
 use serde::Serialize;
 
-use crate::error::Error;
+use crate::{AppState, error::Error};
 
 // MAIN JSON EXTRACTOR
 // ----------------------------------------------------------------------------
@@ -30,7 +33,260 @@ pub struct Query<T>(pub T);
 #[from_request(via(axum::extract::Path), rejection(Error))]
 pub struct Path<T>(pub T);
 
+// ADMIN AUTHORISATION
+// ------------------------------------------------------------------------------
+/// Only extracted successfully if the request provides the configured admin
+/// token as a bearer token in the `Authorization` header.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+
+        match (state.admin_token.as_deref(), provided) {
+            (Some(expected), Some(provided)) if tokens_match(expected, provided) => Ok(Admin),
+            _ => Err(Error::Unauthorized),
+        }
+    }
+}
+
+/// Extracted as [`None`] unless the request provides the admin token, for
+/// routes which anyone can use but which show admins more.
+impl OptionalFromRequestParts<AppState> for Admin {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(
+            <Admin as FromRequestParts<AppState>>::from_request_parts(parts, state)
+                .await
+                .ok(),
+        )
+    }
+}
+
+/// Compare tokens in constant time (for tokens of equal length), to avoid
+/// leaking information about the expected token through response timings.
+fn tokens_match(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+// CLIENT IP
+// ------------------------------------------------------------------------------
+/// IP address of the client, taken from the `X-Real-IP` or `X-Forwarded-For`
+/// headers when configured to trust them behind a proxy, or otherwise the
+/// address it connects from. [`None`] if it can't be determined.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded = state
+            .trust_proxy_headers
+            .then(|| forwarded_ip(&parts.headers))
+            .flatten();
+
+        Ok(ClientIp(forwarded.or_else(|| {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })))
+    }
+}
+
+/// IP address of the client according to proxy headers, if any.
+///
+/// `X-Real-IP` is preferred when present. Otherwise the rightmost
+/// `X-Forwarded-For` entry is used, as that is the one added by the proxy in
+/// front of the server, while entries to its left are provided by the client.
+fn forwarded_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    headers
+        .get("x-real-ip")
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| {
+            headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .next_back()
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit(',').next())
+        })
+        .and_then(|v| v.trim().parse().ok())
+}
+
+// CONDITIONAL REQUESTS
+// ------------------------------------------------------------------------------
+/// Conditional request headers, used to avoid sending unchanged resources
+/// again, and to prevent concurrent changes from overwriting each other.
+#[derive(Debug, Default, Clone)]
+pub struct Conditions {
+    if_match: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Conditions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+
+        Ok(Conditions {
+            if_match: header(IF_MATCH),
+            if_none_match: header(IF_NONE_MATCH),
+            // Invalid dates are ignored
+            if_modified_since: header(IF_MODIFIED_SINCE)
+                .and_then(|v| DateTime::parse_from_rfc2822(&v).ok())
+                .map(|d| d.to_utc()),
+        })
+    }
+}
+
+impl Conditions {
+    /// Whether the client already has the current version of a resource with
+    /// the given ETag and modification time.
+    ///
+    /// `If-Modified-Since` is only used if `If-None-Match` is absent.
+    pub fn is_not_modified(&self, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+        match (&self.if_none_match, self.if_modified_since, last_modified) {
+            // ETags are compared weakly
+            (Some(tags), _, _) => entity_tags(tags).any(|t| t == "*" || weak(t) == weak(etag)),
+            (None, Some(since), Some(modified)) => modified.trunc_subsecs(0) <= since,
+            _ => false,
+        }
+    }
+
+    /// Check that the ETag of the current version of a resource is matched by
+    /// `If-Match`, if provided, using the given comparison.
+    ///
+    /// Weak ETags never match.
+    pub fn check_match(&self, matches: impl Fn(&str) -> bool) -> Result<(), Error> {
+        let Some(tags) = &self.if_match else {
+            return Ok(());
+        };
+
+        if entity_tags(tags).any(|t| t == "*" || (!t.starts_with("W/") && matches(t))) {
+            Ok(())
+        } else {
+            Err(Error::LinkChanged)
+        }
+    }
+}
+
+/// Entity tags in a comma-separated header value.
+fn entity_tags(header: &str) -> impl Iterator<Item = &str> {
+    header.split(',').map(str::trim).filter(|t| !t.is_empty())
+}
+
+/// Entity tag without the weakness indicator.
+fn weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
 // HOST
 // #[derive(FromRequestParts)]
 // #[from_request(via(axum_extra::extract::Host), rejection(Error))]
 // pub struct Host(String);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("token", "token"));
+        assert!(tokens_match("", ""));
+
+        assert!(!tokens_match("token", "tokem"));
+        assert!(!tokens_match("token", "token "));
+        assert!(!tokens_match("token", "Token"));
+        assert!(!tokens_match("token", ""));
+    }
+
+    #[test]
+    fn test_conditions() {
+        let conditions = Conditions::default();
+        assert!(!conditions.is_not_modified("\"a\"", None));
+        assert!(conditions.check_match(|_| false).is_ok());
+
+        let modified = DateTime::parse_from_rfc2822("Sun, 06 Nov 1994 08:49:37 GMT")
+            .unwrap()
+            .to_utc();
+        let conditions = Conditions {
+            if_none_match: Some("\"a\", W/\"b\"".into()),
+            if_match: Some("W/\"a\", \"b\"".into()),
+            if_modified_since: Some(modified),
+        };
+        assert!(conditions.is_not_modified("\"a\"", None));
+        assert!(conditions.is_not_modified("\"b\"", None));
+        // If-Modified-Since is ignored with If-None-Match
+        assert!(!conditions.is_not_modified("\"c\"", Some(modified)));
+        assert!(conditions.check_match(|t| t == "\"b\"").is_ok());
+        assert!(conditions.check_match(|t| t == "\"a\"").is_err());
+
+        let conditions = Conditions {
+            if_modified_since: Some(modified),
+            ..Default::default()
+        };
+        assert!(conditions.is_not_modified("\"c\"", Some(modified)));
+        assert!(
+            !conditions.is_not_modified("\"c\"", Some(modified + chrono::Duration::seconds(1)))
+        );
+
+        let conditions = Conditions {
+            if_none_match: Some("*".into()),
+            if_match: Some("*".into()),
+            ..Default::default()
+        };
+        assert!(conditions.is_not_modified("\"a\"", None));
+        assert!(conditions.check_match(|_| false).is_ok());
+    }
+
+    #[test]
+    fn test_forwarded_ip() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(forwarded_ip(&headers), None);
+
+        // The last address is the one added by the proxy, earlier ones can be
+        // spoofed by clients
+        headers.insert("x-forwarded-for", "10.0.0.1, 10.0.0.3".parse().unwrap());
+        assert_eq!(forwarded_ip(&headers), "10.0.0.3".parse().ok());
+        headers.append("x-forwarded-for", "10.0.0.4".parse().unwrap());
+        assert_eq!(forwarded_ip(&headers), "10.0.0.4".parse().ok());
+
+        headers.insert("x-forwarded-for", "10.0.0.1, not-an-ip".parse().unwrap());
+        assert_eq!(forwarded_ip(&headers), None);
+
+        headers.insert("x-real-ip", "10.0.0.2".parse().unwrap());
+        assert_eq!(forwarded_ip(&headers), "10.0.0.2".parse().ok());
+    }
+}
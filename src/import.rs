@@ -0,0 +1,626 @@
+//! Importing links from other link shorteners and from bookmark files.
+
+use std::collections::HashMap;
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{database::{Actor, IdGenerator, Link, LinkStore, NewLink, create_link, now}, error::{Error, Result}, routes::api::links::create::{parse_target_url, validate_target_url}};
+
+/// Format of links being imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// CSV export of the YOURLS `yourls_url` table, with `keyword`, `url`,
+    /// `timestamp` and `clicks` columns.
+    Yourls,
+    /// JSON response of the Shlink short URLs API, either in full or just the
+    /// list of short URLs.
+    Shlink,
+    /// CSV export of Bitly links, with columns such as `bitlink`, `long_url`,
+    /// `created_at` and `clicks`.
+    Bitly,
+    /// Netscape bookmark HTML file, as exported by browsers. Bookmarks don't
+    /// have IDs, so these are always generated.
+    Bookmarks,
+}
+
+/// How to handle imported links whose ID is already in use.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Keep the existing link, and don't import the new one.
+    #[default]
+    Skip,
+    /// Import the new link with a generated ID instead.
+    Rename,
+    /// Replace the existing link with the new one.
+    Overwrite,
+}
+
+/// A link parsed from an import, before it is stored.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportedLink {
+    /// ID of the link in the system it was exported from, if any.
+    pub id: Option<String>,
+    pub target_url: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub count_redirects: i64,
+}
+
+/// What happened to a single link of an import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ImportOutcome {
+    /// The link was imported, keeping its original ID if it had one.
+    Imported,
+    /// The link was imported with a generated ID, as its original ID was
+    /// invalid or already in use.
+    Renamed,
+    /// The link replaced an existing link with the same ID.
+    Overwritten,
+    /// The link wasn't imported, as its ID was already in use.
+    Skipped,
+    /// The link couldn't be parsed or stored.
+    Failed,
+}
+
+/// Result of importing a single link.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEntry {
+    /// Position of the link in the import, starting from 1.
+    pub position: usize,
+    /// ID of the link in the system it was exported from, if any.
+    pub original_id: Option<String>,
+    /// ID of the stored link, unless it was skipped or failed.
+    pub id: Option<String>,
+    /// Target URL of the link, if it could be parsed.
+    pub target_url: Option<String>,
+    pub outcome: ImportOutcome,
+    /// Why the link was renamed, skipped or failed.
+    pub reason: Option<String>,
+}
+
+/// Report of an import, with the result for every link.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub imported: usize,
+    pub renamed: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub entries: Vec<ImportEntry>,
+}
+
+impl ImportReport {
+    fn push(&mut self, entry: ImportEntry) {
+        match entry.outcome {
+            ImportOutcome::Imported => self.imported += 1,
+            ImportOutcome::Renamed => self.renamed += 1,
+            ImportOutcome::Overwritten => self.overwritten += 1,
+            ImportOutcome::Skipped => self.skipped += 1,
+            ImportOutcome::Failed => self.failed += 1,
+        }
+        counter!("links.imported", "outcome" => format!("{:?}", entry.outcome).to_lowercase())
+            .increment(1);
+        self.entries.push(entry);
+    }
+}
+
+/// Links parsed from an import, or why each couldn't be parsed.
+pub type ParsedLinks = Vec<std::result::Result<ImportedLink, String>>;
+
+/// Parse links from an export in the given format. Fails if the export as a
+/// whole is malformed, while links which can't be parsed are reported
+/// individually.
+pub fn parse_links(format: ImportFormat, data: &str) -> Result<ParsedLinks> {
+    match format {
+        ImportFormat::Yourls => parse_csv(
+            data,
+            &CsvColumns {
+                id: &["keyword"],
+                target_url: &["url"],
+                created_at: &["timestamp"],
+                count_redirects: &["clicks"],
+            },
+        ),
+        ImportFormat::Bitly => parse_csv(
+            data,
+            &CsvColumns {
+                id: &["bitlink", "link", "short link", "short_url", "short url"],
+                target_url: &["long_url", "long url", "destination url", "original url"],
+                created_at: &["created_at", "created", "date created", "creation date"],
+                count_redirects: &["clicks", "total clicks", "engagements", "total engagements"],
+            },
+        ),
+        ImportFormat::Shlink => parse_shlink(data),
+        ImportFormat::Bookmarks => Ok(parse_bookmarks(data)),
+    }
+}
+
+/// Names of the columns (in lowercase) holding each field of a CSV export,
+/// with the first name found in the header being used.
+struct CsvColumns {
+    id: &'static [&'static str],
+    target_url: &'static [&'static str],
+    created_at: &'static [&'static str],
+    count_redirects: &'static [&'static str],
+}
+
+fn parse_csv(data: &str, columns: &CsvColumns) -> Result<ParsedLinks> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data.as_bytes());
+
+    let header: HashMap<String, usize> = reader
+        .headers()
+        .map_err(|e| Error::InvalidRequest(format!("malformed CSV header: {e}")))?
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.to_lowercase(), i))
+        .collect();
+    let find = |names: &[&str]| names.iter().find_map(|n| header.get(*n).copied());
+
+    let id_column = find(columns.id);
+    let created_at_column = find(columns.created_at);
+    let count_column = find(columns.count_redirects);
+    let Some(url_column) = find(columns.target_url) else {
+        return Err(Error::InvalidRequest(format!(
+            "CSV header has no target URL column (expected one of: {})",
+            columns.target_url.join(", ")
+        )));
+    };
+
+    Ok(reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|e| format!("malformed CSV record: {e}"))?;
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|c| record.get(c))
+                    .filter(|value| !value.is_empty())
+            };
+
+            Ok(ImportedLink {
+                // Short links may be given as URLs, with the ID as their path
+                id: field(id_column).map(|id| {
+                    id.trim_end_matches('/')
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or(id)
+                        .to_string()
+                }),
+                target_url: field(Some(url_column))
+                    .ok_or("missing target URL")?
+                    .to_string(),
+                created_at: field(created_at_column).map(parse_time).transpose()?,
+                expires_at: None,
+                count_redirects: field(count_column)
+                    .map(|n| n.parse().map_err(|_| format!("invalid click count: {n}")))
+                    .transpose()?
+                    .unwrap_or_default(),
+            })
+        })
+        .collect())
+}
+
+fn parse_shlink(data: &str) -> Result<ParsedLinks> {
+    let json: Value = serde_json::from_str(data)
+        .map_err(|e| Error::InvalidRequest(format!("malformed JSON: {e}")))?;
+
+    // Either the full API response, or just the list of short URLs
+    let short_urls = json
+        .pointer("/shortUrls/data")
+        .unwrap_or(&json)
+        .as_array()
+        .ok_or_else(|| Error::InvalidRequest("expected a list of short URLs".into()))?;
+
+    Ok(short_urls
+        .iter()
+        .map(|short_url| {
+            let string = |pointer: &str| {
+                short_url
+                    .pointer(pointer)
+                    .and_then(Value::as_str)
+                    .filter(|value| !value.is_empty())
+            };
+
+            Ok(ImportedLink {
+                id: string("/shortCode").map(String::from),
+                target_url: string("/longUrl").ok_or("missing longUrl")?.to_string(),
+                created_at: string("/dateCreated").map(parse_time).transpose()?,
+                expires_at: string("/meta/validUntil").map(parse_time).transpose()?,
+                // Older versions of Shlink only have a count of visits
+                count_redirects: short_url
+                    .pointer("/visitsSummary/total")
+                    .or_else(|| short_url.pointer("/visitsCount"))
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default(),
+            })
+        })
+        .collect())
+}
+
+/// Parse the links of a Netscape bookmark file, which are `<A>` elements with
+/// `HREF` and `ADD_DATE` attributes. Any other markup is ignored.
+fn parse_bookmarks(data: &str) -> ParsedLinks {
+    let lowercase = data.to_ascii_lowercase();
+
+    lowercase
+        .match_indices("<a ")
+        .map(|(start, _)| {
+            let end = lowercase[start..]
+                .find('>')
+                .map_or(data.len(), |end| start + end);
+            let attributes = parse_attributes(&data[start + 3..end]);
+
+            Ok(ImportedLink {
+                id: None,
+                target_url: attributes
+                    .get("href")
+                    .filter(|href| !href.is_empty())
+                    .ok_or("bookmark has no HREF")?
+                    .clone(),
+                created_at: attributes
+                    .get("add_date")
+                    .map(|date| parse_time(date))
+                    .transpose()?,
+                expires_at: None,
+                count_redirects: 0,
+            })
+        })
+        .collect()
+}
+
+/// Parse the attributes of an HTML tag, with names in lowercase and values
+/// unescaped.
+fn parse_attributes(tag: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = tag.trim_start();
+
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().to_ascii_lowercase();
+        let value_start = rest[eq + 1..].trim_start();
+
+        let (value, remaining) = match value_start.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value_start[1..];
+                match value.find(quote) {
+                    Some(end) => (&value[..end], &value[end + 1..]),
+                    None => (value, ""),
+                }
+            }
+            _ => {
+                let end = value_start
+                    .find(char::is_whitespace)
+                    .unwrap_or(value_start.len());
+                (&value_start[..end], &value_start[end..])
+            }
+        };
+
+        // Attributes without values come before the name of the next one
+        let name = name.rsplit(char::is_whitespace).next().unwrap_or_default();
+        attributes.insert(name.to_string(), unescape_html(value));
+        rest = remaining.trim_start();
+    }
+
+    attributes
+}
+
+/// Unescape the HTML entities which commonly appear in attribute values.
+fn unescape_html(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Parse a time in any of the formats used by supported exports, converting
+/// it to UTC if it has an offset. Unix timestamps are given in seconds.
+fn parse_time(value: &str) -> std::result::Result<DateTime<Utc>, String> {
+    let time = if let Ok(seconds) = value.parse::<i64>() {
+        DateTime::from_timestamp(seconds, 0)
+    } else if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        Some(time.to_utc())
+    } else if let Ok(time) = DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%z") {
+        Some(time.to_utc())
+    } else if let Ok(time) = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z") {
+        Some(time.to_utc())
+    } else if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+        Some(time.and_utc())
+    } else if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+        Some(time.and_utc())
+    } else {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc())
+    };
+
+    time.map(|t| t.trunc_subsecs(6))
+        .ok_or_else(|| format!("invalid time: {value}"))
+}
+
+/// Store parsed links, resolving conflicts with existing links using the
+/// given policy.
+///
+/// Target URLs are checked against the host of this service if it is known.
+/// Original IDs are kept where they are valid, and otherwise generated. The
+/// import stops at the first failure of the store, keeping any links stored
+/// before it.
+pub async fn import_links(
+    store: &dyn LinkStore,
+    id_generator: &IdGenerator,
+    links: ParsedLinks,
+    policy: ConflictPolicy,
+    host: Option<&str>,
+) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+
+    for (i, parsed) in links.into_iter().enumerate() {
+        let position = i + 1;
+        let link = match parsed {
+            Ok(link) => link,
+            Err(reason) => {
+                report.push(ImportEntry {
+                    position,
+                    original_id: None,
+                    id: None,
+                    target_url: None,
+                    outcome: ImportOutcome::Failed,
+                    reason: Some(reason),
+                });
+                continue;
+            }
+        };
+
+        let mut entry = ImportEntry {
+            position,
+            original_id: link.id.clone(),
+            id: None,
+            target_url: Some(link.target_url.clone()),
+            outcome: ImportOutcome::Imported,
+            reason: None,
+        };
+        match import_link(store, id_generator, link, policy, host).await {
+            Ok((id, outcome, reason)) => {
+                entry.id = id;
+                entry.outcome = outcome;
+                entry.reason = reason;
+            }
+            Err(e @ (Error::Internal(_) | Error::DatabaseUnavailable(_))) => return Err(e),
+            Err(e) => {
+                entry.outcome = ImportOutcome::Failed;
+                entry.reason = Some(e.to_string());
+            }
+        }
+        report.push(entry);
+    }
+
+    Ok(report)
+}
+
+/// Store a single parsed link, returning its ID (if stored), what happened to
+/// it and why.
+async fn import_link(
+    store: &dyn LinkStore,
+    id_generator: &IdGenerator,
+    link: ImportedLink,
+    policy: ConflictPolicy,
+    host: Option<&str>,
+) -> Result<(Option<String>, ImportOutcome, Option<String>)> {
+    let target_url = match host {
+        Some(host) => validate_target_url(host, &link.target_url)?,
+        None => parse_target_url(&link.target_url)?,
+    }
+    .to_string();
+
+    let new_link = NewLink {
+        target_url,
+        custom_id: link.id.clone().filter(|id| Link::validate_id(id)),
+        expires_at: link.expires_at,
+        created_at: link.created_at,
+        count_redirects: link.count_redirects,
+        created_by: Actor::Import,
+        ..Default::default()
+    };
+
+    if let Some(id) = link.id.as_ref()
+        && new_link.custom_id.is_none()
+    {
+        let created = create_link(store, id_generator, new_link).await?;
+        return Ok((
+            Some(created.id),
+            ImportOutcome::Renamed,
+            Some(Error::LinkIdNotValid(id.clone()).to_string()),
+        ));
+    }
+
+    match create_link(store, id_generator, new_link.clone()).await {
+        Ok(created) => Ok((Some(created.id), ImportOutcome::Imported, None)),
+        Err(e @ Error::LinkIdNotUnique(_)) => match policy {
+            ConflictPolicy::Skip => Ok((None, ImportOutcome::Skipped, Some(e.to_string()))),
+            ConflictPolicy::Rename => {
+                let created = create_link(
+                    store,
+                    id_generator,
+                    NewLink {
+                        custom_id: None,
+                        ..new_link
+                    },
+                )
+                .await?;
+                Ok((
+                    Some(created.id),
+                    ImportOutcome::Renamed,
+                    Some(e.to_string()),
+                ))
+            }
+            ConflictPolicy::Overwrite => {
+                let now = now();
+                let replaced = store
+                    .replace_link(
+                        &Link {
+                            id: new_link.custom_id.unwrap_or_default(),
+                            target_url: new_link.target_url,
+                            count_redirects: new_link.count_redirects,
+                            created_at: new_link.created_at.unwrap_or(now),
+                            updated_at: now,
+                            expires_at: new_link.expires_at,
+                            is_custom_id: true,
+                            ..Default::default()
+                        },
+                        Actor::Import,
+                    )
+                    .await?;
+                Ok((Some(replaced.id), ImportOutcome::Overwritten, None))
+            }
+        },
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn time(value: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn test_parse_yourls() {
+        let data = "keyword,url,title,timestamp,ip,clicks\n\
+            abc,https://crates.io/,Crates,2024-01-02 03:04:05,127.0.0.1,42\n\
+            def,,Empty,2024-01-02 03:04:05,127.0.0.1,0\n";
+
+        let links = parse_links(ImportFormat::Yourls, data).unwrap();
+        assert_eq!(
+            links,
+            vec![
+                Ok(ImportedLink {
+                    id: Some("abc".into()),
+                    target_url: "https://crates.io/".into(),
+                    created_at: Some(time("2024-01-02 03:04:05")),
+                    expires_at: None,
+                    count_redirects: 42,
+                }),
+                Err("missing target URL".into()),
+            ]
+        );
+
+        assert!(parse_links(ImportFormat::Yourls, "keyword,title\nabc,Crates\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_bitly() {
+        let data = "Title,Bitlink,Long URL,Created,Total Clicks\n\
+            \"Docs, Rust\",https://bit.ly/3xYz,https://docs.rs/,2024-01-02T03:04:05+0100,7\n";
+
+        let links = parse_links(ImportFormat::Bitly, data).unwrap();
+        assert_eq!(
+            links,
+            vec![Ok(ImportedLink {
+                id: Some("3xYz".into()),
+                target_url: "https://docs.rs/".into(),
+                created_at: Some(time("2024-01-02 02:04:05")),
+                expires_at: None,
+                count_redirects: 7,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_parse_shlink() {
+        let short_url = r#"{
+            "shortCode": "abc12",
+            "longUrl": "https://crates.io/",
+            "dateCreated": "2024-01-02T03:04:05+02:00",
+            "visitsSummary": { "total": 3, "nonBots": 2, "bots": 1 },
+            "meta": { "validSince": null, "validUntil": "2099-01-01T00:00:00+00:00" }
+        }"#;
+        let expected = vec![Ok(ImportedLink {
+            id: Some("abc12".into()),
+            target_url: "https://crates.io/".into(),
+            created_at: Some(time("2024-01-02 01:04:05")),
+            expires_at: Some(time("2099-01-01 00:00:00")),
+            count_redirects: 3,
+        })];
+
+        let response = format!(r#"{{ "shortUrls": {{ "data": [{short_url}] }} }}"#);
+        assert_eq!(
+            parse_links(ImportFormat::Shlink, &response).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_links(ImportFormat::Shlink, &format!("[{short_url}]")).unwrap(),
+            expected
+        );
+
+        assert!(parse_links(ImportFormat::Shlink, "not json").is_err());
+        assert!(parse_links(ImportFormat::Shlink, r#"{"shortUrls": {}}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_bookmarks() {
+        let data = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+            <TITLE>Bookmarks</TITLE>
+            <DL><p>
+                <DT><H3 ADD_DATE="1700000000">Folder</H3>
+                <DL><p>
+                    <DT><A HREF="https://crates.io/search?q=a&amp;page=2" ADD_DATE="1704164645" ICON="data:">Crates</A>
+                    <DT><a href='https://docs.rs/' private>Docs</a>
+                    <DT><A NAME="no-href">Nothing</A>
+                </DL><p>
+            </DL><p>"#;
+
+        assert_eq!(
+            parse_links(ImportFormat::Bookmarks, data).unwrap(),
+            vec![
+                Ok(ImportedLink {
+                    target_url: "https://crates.io/search?q=a&page=2".into(),
+                    created_at: Some(time("2024-01-02 03:04:05")),
+                    ..Default::default()
+                }),
+                Ok(ImportedLink {
+                    target_url: "https://docs.rs/".into(),
+                    ..Default::default()
+                }),
+                Err("bookmark has no HREF".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_time() {
+        for (value, expected) in [
+            ("1704164645", "2024-01-02 03:04:05"),
+            ("2024-01-02T03:04:05Z", "2024-01-02 03:04:05"),
+            ("2024-01-02T05:04:05+02:00", "2024-01-02 03:04:05"),
+            ("2024-01-02T03:04:05+0000", "2024-01-02 03:04:05"),
+            ("2024-01-02 03:04:05 +0000", "2024-01-02 03:04:05"),
+            ("2024-01-02 03:04:05", "2024-01-02 03:04:05"),
+            ("2024-01-02T03:04:05", "2024-01-02 03:04:05"),
+            ("2024-01-02", "2024-01-02 00:00:00"),
+        ] {
+            assert_eq!(parse_time(value), Ok(time(expected)), "{value}");
+        }
+
+        assert!(parse_time("yesterday").is_err());
+    }
+}
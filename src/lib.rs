@@ -1,20 +1,32 @@
 #![forbid(unsafe_code)]
 
+pub mod backup;
+pub mod cache;
 pub mod config;
 pub mod database;
 pub mod error;
 pub mod extractors;
+pub mod import;
+pub mod purge;
+pub mod redirect_map;
 pub mod routes;
+pub mod schedule;
+pub mod throttle;
+pub mod time;
 pub mod utils;
 
-use std::{sync::{Arc, OnceLock}, time::Duration};
+use std::{sync::{Arc, OnceLock, atomic::{AtomicBool, Ordering}}, time::Duration};
 
-use axum::{Router, http::Method};
-use axum_prometheus::{GenericMetricLayer, Handle, PrometheusMetricLayer, metrics_exporter_prometheus::PrometheusHandle};
+use axum::{Router, extract::{Request, State}, http::{HeaderValue, Method}, middleware::{self, Next}, response::{IntoResponse, Response}};
+use axum_prometheus::{GenericMetricLayer, Handle, PrometheusMetricLayer, metrics::counter, metrics_exporter_prometheus::PrometheusHandle};
+use cache::LinkCache;
 use config::Config;
-use database::init_db;
-use routes::{Route, api::{links, misc}};
-use sqlx::{Pool, Postgres};
+use database::{CircuitBreaker, EventSenders, IdGenerator, LinkStore, RedirectEvent, Stores, init_stores, spawn_redirect_publisher};
+use error::{Error, StartupError};
+use futures_util::future::BoxFuture;
+use routes::{Route, api::{self, conversions, links, misc}};
+use throttle::NotFoundThrottle;
+use tokio::{sync::{broadcast, mpsc}, task::JoinHandle};
 use tower_governor::{GovernorLayer, governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor};
 use tower_http::{compression::CompressionLayer, cors::{Any, CorsLayer}, limit::RequestBodyLimitLayer, timeout::TimeoutLayer, trace::TraceLayer};
 use utoipa::OpenApi;
@@ -32,21 +44,200 @@ static PROMETHEUS: OnceLock<(
 #[derive(Debug, Clone)]
 pub struct AppState {
     metric_handle: PrometheusHandle,
-    db: Pool<Postgres>,
+    store: Arc<dyn LinkStore>,
+    /// Store used for read-only queries when read replicas are configured,
+    /// which may lag behind [`Self::store`].
+    read_store: Option<Arc<dyn LinkStore>>,
+    breaker: CircuitBreaker,
+    /// Fails fast while the read replicas are unavailable, separately from
+    /// the primary database.
+    replica_breaker: CircuitBreaker,
+    id_generator: IdGenerator,
+    admin_token: Option<String>,
+    /// Whether clients are identified by proxy headers.
+    trust_proxy_headers: bool,
+    redirect_cache_control: HeaderValue,
+    redirect_events: broadcast::Sender<RedirectEvent>,
+    /// Queue of redirect events waiting to be published to all instances.
+    redirect_event_queue: mpsc::Sender<RedirectEvent>,
+    cache: Option<LinkCache>,
+    not_found_throttle: Option<NotFoundThrottle>,
+    /// Whether the database is usable, after startup.
+    ready: Arc<AtomicBool>,
 }
 
-pub async fn get_app(config: Config) -> Router {
-    // Setup database connection
-    let db = init_db(&config.database)
-        .await
-        .expect("could not setup database connection");
+impl AppState {
+    /// Run a read-only query on the read replicas, if configured, or
+    /// otherwise on the primary database. Queries which fail on the replicas
+    /// are retried on the primary database.
+    async fn read<'a, T>(
+        &'a self,
+        query: impl Fn(&'a dyn LinkStore) -> BoxFuture<'a, error::Result<T>>,
+    ) -> error::Result<T> {
+        let Some(read_store) = self.read_store.as_ref() else {
+            return self.breaker.call(query(self.store.as_ref())).await;
+        };
+
+        match self.replica_breaker.call(query(read_store.as_ref())).await {
+            Err(e @ (Error::Internal(_) | Error::DatabaseUnavailable(_))) => {
+                counter!("db.replica_read_retries").increment(1);
+                tracing::warn!(
+                    "Retrying read on the primary database after it failed on a replica: {e}"
+                );
+                self.breaker.call(query(self.store.as_ref())).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// Seconds after which clients should retry requests made before the
+/// application is ready.
+const NOT_READY_RETRY_AFTER_SECONDS: u64 = 5;
+
+/// The application router, which can serve requests while the database is
+/// still being connected to at startup.
+pub struct App {
+    pub router: Router,
+    startup: Option<JoinHandle<Result<(), StartupError>>>,
+    state: AppState,
+}
+
+impl App {
+    /// Wait until the database is usable. Fails if the database isn't usable
+    /// within the configured startup timeout, in which case the application
+    /// should exit.
+    pub async fn ready(&mut self) -> Result<(), StartupError> {
+        if let Some(startup) = self.startup.as_mut() {
+            let result = startup
+                .await
+                .map_err(|e| StartupError::Internal(e.to_string()));
+            self.startup = None;
+            result??;
+        }
+
+        Ok(())
+    }
+
+    /// Wait until the database is usable, returning the router, as with
+    /// [`App::ready`].
+    pub async fn wait_until_ready(mut self) -> Result<Router, StartupError> {
+        self.ready().await?;
+
+        Ok(self.router)
+    }
+
+    /// Write redirect counts buffered by the cache to the database, once the
+    /// server has stopped serving requests.
+    pub async fn shutdown(&self) {
+        if let Some(cache) = self.state.cache.as_ref() {
+            cache
+                .flush_redirect_counts(self.state.store.as_ref(), &self.state.breaker)
+                .await;
+        }
+    }
+}
+
+/// Build the application. Requests which need the database are refused until
+/// it is usable, which happens in the background.
+pub fn get_app(config: Config) -> Result<App, StartupError> {
+    // Setup storage, spreading read-only queries across any replicas
+    let Stores { store, read_store } = init_stores(&config.database)?;
+    let ready = Arc::new(AtomicBool::new(false));
+
+    // Grow generated link IDs as the keyspace fills up
+    if config.application.idseed.is_none() {
+        tracing::warn!(
+            "No application.idseed is set, so a random one is used. Sequential IDs and IDs \
+            derived from target URLs will differ between instances and restarts"
+        );
+    }
+    let id_generator = IdGenerator::new(
+        config.application.idstrategy,
+        config.application.idseed.as_deref(),
+    );
+
+    // Fail fast while the database is unavailable
+    let breaker = CircuitBreaker::new(
+        config.database.breakerthreshold,
+        Duration::from_secs(config.database.breakeropenseconds),
+    );
+    let replica_breaker = CircuitBreaker::new(
+        config.database.breakerthreshold,
+        Duration::from_secs(config.database.breakeropenseconds),
+    );
+
+    // Fan out events from all instances to local subscribers
+    let events = EventSenders {
+        redirects: broadcast::channel(config.application.eventbuffercapacity).0,
+        link_changes: broadcast::channel(config.application.eventbuffercapacity).0,
+    };
+    let (redirect_event_queue, redirect_event_receiver) =
+        mpsc::channel(config.application.eventbuffercapacity);
+
+    // Cache of links used for redirects
+    let cache = LinkCache::new(&config.cache)?;
+
+    // Connect to the database, and start background tasks which need it
+    let startup = tokio::spawn({
+        let (store, ready, id_generator, breaker, events, cache) = (
+            store.clone(),
+            ready.clone(),
+            id_generator.clone(),
+            breaker.clone(),
+            events.clone(),
+            cache.clone(),
+        );
+        let timeout = Duration::from_secs(config.database.startuptimeoutseconds);
+        let flush_interval = Duration::from_millis(config.cache.flushintervalms);
+        let keyspace_refresh_interval =
+            Duration::from_secs(config.application.keyspacerefreshseconds);
+        let event_publish_interval =
+            Duration::from_millis(config.application.eventpublishintervalms);
+        let schedule_interval = Duration::from_secs(config.application.scheduleintervalseconds);
+        let redirect_map = config.redirectmap.clone();
+        let purge = config.purge.clone();
+
+        async move {
+            store.connect(timeout).await?;
+
+            id_generator.spawn_refresh(store.clone(), keyspace_refresh_interval);
+            spawn_redirect_publisher(
+                store.clone(),
+                event_publish_interval,
+                redirect_event_receiver,
+            );
+            if let Some(cache) = cache.as_ref() {
+                cache.spawn_tasks(
+                    store.clone(),
+                    breaker,
+                    events.link_changes.subscribe(),
+                    flush_interval,
+                );
+            }
+            redirect_map::spawn_redirect_map_writer(store.clone(), redirect_map);
+            purge::spawn_purge_job(store.clone(), purge);
+            schedule::spawn_schedule_job(store.clone(), schedule_interval);
+            tokio::spawn(async move { store.listen_events(events).await });
+
+            ready.store(true, Ordering::Relaxed);
+            tracing::info!("Connected to the database, ready to serve requests");
+
+            Ok(())
+        }
+    });
+
+    // Throttle clients which appear to be guessing link IDs
+    let not_found_throttle = NotFoundThrottle::new(&config.application);
 
     // Governor configuration for rate-limiting
     let governor_conf = Arc::new({
         let mut builder = GovernorConfigBuilder::default().key_extractor(SmartIpKeyExtractor);
 
         if config.application.shouldratelimit {
-            builder.burst_size(10).per_millisecond(200);
+            builder
+                .burst_size(config.application.ratelimitburst)
+                .per_millisecond(config.application.ratelimitperiodms);
         } else {
             builder.burst_size(u32::MAX).per_millisecond(1);
         };
@@ -60,7 +251,7 @@ pub async fn get_app(config: Config) -> Router {
     let cors = CorsLayer::default()
         .allow_methods([Method::GET])
         .allow_origin(Any)
-        .max_age(Duration::from_secs(3600));
+        .max_age(Duration::from_secs(config.application.corsmaxageseconds));
 
     // Metrics
     let (prometheus_layer, metric_handle) =
@@ -69,16 +260,36 @@ pub async fn get_app(config: Config) -> Router {
     // Compresses response bodies
     let compression_layer = CompressionLayer::new();
 
-    // Limit the size of request bodies to 100KB.
-    let request_size_layer = RequestBodyLimitLayer::new(1000 * 100);
+    // Limit the size of request bodies
+    let request_size_layer = RequestBodyLimitLayer::new(config.application.maxbodybytes);
 
     // Application state
-    let state = AppState { db, metric_handle };
+    let state = AppState {
+        store,
+        read_store,
+        breaker,
+        replica_breaker,
+        id_generator,
+        metric_handle,
+        admin_token: config.application.admintoken,
+        trust_proxy_headers: config.application.trustproxyheaders,
+        redirect_cache_control: HeaderValue::from_str(&config.application.redirectcachecontrol)
+            .expect("redirect Cache-Control header should be validated with the config"),
+        redirect_events: events.redirects,
+        redirect_event_queue,
+        cache,
+        not_found_throttle,
+        ready: ready.clone(),
+    };
 
     let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .merge(links::routes())
+        .merge(conversions::routes())
+        .merge(api::backup::routes())
         .merge(misc::routes())
         .fallback(async || error::Error::RouteNotFound)
+        // Readiness
+        .layer(middleware::from_fn_with_state(ready, require_ready))
         // Rate-limiting
         .layer(GovernorLayer {
             config: governor_conf,
@@ -94,11 +305,36 @@ pub async fn get_app(config: Config) -> Router {
         // Logging
         .layer(TraceLayer::new_for_http())
         // Timeout
-        .layer(TimeoutLayer::new(Duration::from_secs(8)))
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            config.application.requesttimeoutseconds,
+        )))
         // STATE
-        .with_state(state)
+        .with_state(state.clone())
         .split_for_parts();
 
     // Add API doc routes separately
-    router.nest(Route::Docs.into(), docs::routes(api))
+    Ok(App {
+        router: router.nest(Route::Docs.into(), docs::routes(api)),
+        startup: Some(startup),
+        state,
+    })
+}
+
+/// Refuse requests until the application is ready, except for health checks
+/// and metrics.
+async fn require_ready(
+    State(ready): State<Arc<AtomicBool>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if ready.load(Ordering::Relaxed)
+        || [Route::Health, Route::Ready, Route::Metrics]
+            .iter()
+            .any(|r| r.as_str() == path)
+    {
+        return next.run(request).await;
+    }
+
+    Error::DatabaseUnavailable(NOT_READY_RETRY_AFTER_SECONDS).into_response()
 }
@@ -1,10 +1,67 @@
-use std::net::SocketAddr;
+use std::{fs::File, future::IntoFuture, io::{BufWriter, Write}, net::SocketAddr, path::{Path, PathBuf}, pin::pin, sync::Arc, time::Duration};
 
-use curto::{config::Config, get_app, routes::Route, utils::shutdown_signal};
+use clap::{Parser, Subcommand};
+use curto::{backup::{backup_stream, restore_backup}, cache::SharedCache, config::{Config, ConfigArgs}, database::{IdGenerator, LinkStore, Stores, init_stores}, get_app, import::{ConflictPolicy, ImportFormat, import_links, parse_links}, redirect_map::{RedirectMapFormat, generate_redirect_map, write_redirect_map}, routes::Route, utils::shutdown_signal};
+use futures_util::TryStreamExt;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+/// Self-hostable link shortener.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Print the configuration, with secrets redacted, and exit
+    #[arg(long)]
+    print_config: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Import links from a file exported from another link shortener or a
+    /// browser, printing a report as JSON
+    Import {
+        /// Format of the file
+        #[arg(long, value_enum)]
+        format: ImportFormat,
+        /// How to handle links whose ID is already in use
+        #[arg(long, value_enum, default_value_t)]
+        policy: ConflictPolicy,
+        /// File to import links from
+        file: PathBuf,
+    },
+    /// Write a backup of all links, with their analytics, conversions and
+    /// history, as newline-delimited JSON
+    Backup {
+        /// File to write the backup to, instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Restore a backup, replacing all links, analytics, conversions and
+    /// history
+    Restore {
+        /// File to restore the backup from
+        file: PathBuf,
+    },
+    /// Write a static redirect map of all active links, to serve redirects
+    /// without this service
+    RedirectMap {
+        /// Format of the redirect map
+        #[arg(long, value_enum, default_value_t)]
+        format: RedirectMapFormat,
+        /// File to write the redirect map to, instead of standard output, or
+        /// the directory to write pages to for the html format
+        #[arg(short, long, required_if_eq("format", "html"))]
+        output: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -14,10 +71,44 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let config = Config::get_config().expect("Failed to read configuration");
+    let config = Config::get_config_with_args(&cli.config).unwrap_or_else(|e| {
+        tracing::error!("Failed to read configuration: {e}");
+        std::process::exit(1);
+    });
 
-    let app = get_app(config.clone()).await;
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml());
+        return;
+    }
 
+    if let Some(command) = cli.command {
+        let (result, action) = match command {
+            Command::Import {
+                format,
+                policy,
+                file,
+            } => (import(&config, format, policy, &file).await, "import links"),
+            Command::Backup { output } => (backup(&config, output.as_deref()).await, "back up"),
+            Command::Restore { file } => (restore(&config, &file).await, "restore backup"),
+            Command::RedirectMap { format, output } => (
+                redirect_map(&config, format, output.as_deref()).await,
+                "write redirect map",
+            ),
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to {action}: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut app = get_app(config.clone()).unwrap_or_else(|e| {
+        tracing::error!("Failed to start: {e}");
+        std::process::exit(1);
+    });
+
+    // Start listening straight away, reporting readiness once the database is
+    // usable
     let addr = SocketAddr::from((config.application.host, config.application.port));
     let listener = tokio::net::TcpListener::bind(addr)
         .await
@@ -30,11 +121,134 @@ async fn main() {
         Route::Docs.as_str()
     );
 
-    axum::serve(
-        listener,
-        app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .with_graceful_shutdown(shutdown_signal())
-    .await
-    .expect("Failed to start server");
+    let mut server = pin!(
+        axum::serve(
+            listener,
+            app.router
+                .clone()
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .into_future()
+    );
+
+    // Exit if the database doesn't become usable, unless shut down first
+    tokio::select! {
+        result = &mut server => result.expect("Failed to start server"),
+        result = app.ready() => {
+            if let Err(e) = result {
+                tracing::error!("Failed to start: {e}");
+                std::process::exit(1);
+            }
+            server.await.expect("Failed to start server");
+        }
+    }
+
+    // Write anything buffered once requests are no longer served
+    app.shutdown().await;
+}
+
+/// Connect to the configured store, without starting the server.
+async fn connect(config: &Config) -> Result<Arc<dyn LinkStore>, Box<dyn std::error::Error>> {
+    let Stores { store, .. } = init_stores(&config.database)?;
+    store
+        .connect(Duration::from_secs(config.database.startuptimeoutseconds))
+        .await?;
+
+    Ok(store)
+}
+
+/// Import links from a file directly into the configured store, without
+/// starting the server.
+async fn import(
+    config: &Config,
+    format: ImportFormat,
+    policy: ConflictPolicy,
+    file: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(file)?;
+    let links = parse_links(format, &data)?;
+
+    let store = connect(config).await?;
+
+    // Generate IDs for renamed links in the same way as the server
+    let id_generator = IdGenerator::new(
+        config.application.idstrategy,
+        config.application.idseed.as_deref(),
+    );
+    id_generator.refresh(store.as_ref()).await?;
+
+    // The host of this service isn't known, so target URLs are only checked
+    // for a host
+    let report = import_links(store.as_ref(), &id_generator, links, policy, None).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    eprintln!(
+        "Imported {}, renamed {}, overwritten {}, skipped {}, failed {}",
+        report.imported, report.renamed, report.overwritten, report.skipped, report.failed
+    );
+
+    Ok(())
+}
+
+/// Write a backup of the configured store to a file, or to standard output.
+async fn backup(config: &Config, output: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let store = connect(config).await?;
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+    let mut chunks = pin!(backup_stream(store));
+    while let Some(chunk) = chunks.try_next().await? {
+        writer.write_all(&chunk)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Restore a backup from a file directly into the configured store, printing
+/// a summary as JSON.
+async fn restore(config: &Config, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read_to_string(file)?;
+    let store = connect(config).await?;
+
+    let summary = restore_backup(store.as_ref(), &data).await?;
+
+    // Links cached by running instances may have changed, or no longer exist
+    if let Some(shared) = SharedCache::new(&config.cache)? {
+        shared.clear().await;
+    }
+
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+
+    Ok(())
+}
+
+/// Write a static redirect map of the configured store to a file, or to
+/// standard output.
+async fn redirect_map(
+    config: &Config,
+    format: RedirectMapFormat,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store = connect(config).await?;
+
+    match output {
+        Some(path) => {
+            let summary = write_redirect_map(store.as_ref(), format, path).await?;
+            eprintln!(
+                "Wrote {} links to {}{}",
+                summary.links,
+                path.display(),
+                summary
+                    .next_expiry
+                    .map(|e| format!(", which should be regenerated by {e}"))
+                    .unwrap_or_default()
+            );
+        }
+        None => print!("{}", generate_redirect_map(store.as_ref(), format).await?),
+    }
+
+    Ok(())
 }
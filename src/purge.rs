@@ -0,0 +1,208 @@
+//! Background purge of links which have been expired for longer than a grace
+//! period, so that they don't stay in the database forever.
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use axum_prometheus::metrics::{counter, histogram};
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::PurgeConfig, database::{LinkStore, now}, error::Result};
+
+/// Key of the advisory lock held by the instance running the purge.
+const PURGE_LOCK_KEY: i64 = 0x6375_7274_6f00_0001;
+
+/// What happens to links when they are purged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PurgeMode {
+    /// Expired links are kept.
+    #[default]
+    Off,
+    /// Expired links are moved to the `archived_links` table, without their
+    /// analytics.
+    Archive,
+    /// Expired links are deleted, along with their analytics, and only their
+    /// IDs are recorded in the `purged_links` table.
+    Delete,
+}
+
+impl PurgeMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Archive => "archive",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Result of a single run of the purge.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PurgeSummary {
+    /// Number of links purged.
+    pub links: usize,
+    /// Number of batches the links were purged in.
+    pub batches: usize,
+}
+
+/// Purge links which expired more than the grace period ago, in batches.
+///
+/// Returns [`None`] without purging anything if another instance is already
+/// purging.
+pub async fn purge_expired_links(
+    store: &dyn LinkStore,
+    config: &PurgeConfig,
+) -> Result<Option<PurgeSummary>> {
+    let archive = match config.mode {
+        PurgeMode::Off => return Ok(Some(PurgeSummary::default())),
+        PurgeMode::Archive => true,
+        PurgeMode::Delete => false,
+    };
+    // Nothing can have expired before times which can't be represented
+    let Some(expired_before) = i64::try_from(config.graceperiodseconds)
+        .ok()
+        .and_then(TimeDelta::try_seconds)
+        .and_then(|grace| now().checked_sub_signed(grace))
+    else {
+        return Ok(Some(PurgeSummary::default()));
+    };
+    let batch_size = i64::from(config.batchsize);
+
+    let mut summary = PurgeSummary::default();
+    let ran = store
+        .run_exclusively(
+            PURGE_LOCK_KEY,
+            Box::pin(async {
+                loop {
+                    let ids = store
+                        .purge_expired_links(expired_before, batch_size, archive)
+                        .await?;
+                    if ids.is_empty() {
+                        return Ok(());
+                    }
+
+                    counter!("purge.links_purged", "mode" => config.mode.as_str())
+                        .increment(ids.len() as u64);
+                    tracing::info!(
+                        "Purged {} links which expired before {expired_before} ({}): {}",
+                        ids.len(),
+                        config.mode.as_str(),
+                        ids.join(", ")
+                    );
+                    summary.links += ids.len();
+                    summary.batches += 1;
+
+                    if (ids.len() as i64) < batch_size {
+                        return Ok(());
+                    }
+                }
+            }),
+        )
+        .await?;
+
+    Ok(ran.then_some(summary))
+}
+
+/// Purge expired links periodically, if enabled.
+pub fn spawn_purge_job(store: Arc<dyn LinkStore>, config: PurgeConfig) {
+    if config.mode == PurgeMode::Off {
+        return;
+    }
+    let interval = Duration::from_secs(config.intervalseconds);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if store.is_closed() {
+                break;
+            }
+
+            let start = Instant::now();
+            match purge_expired_links(store.as_ref(), &config).await {
+                Ok(Some(summary)) => {
+                    histogram!("purge.duration_seconds").record(start.elapsed().as_secs_f64());
+                    tracing::debug!(
+                        "Purged {} expired links in {} batches",
+                        summary.links,
+                        summary.batches
+                    );
+                }
+                Ok(None) => {
+                    counter!("purge.skipped").increment(1);
+                    tracing::debug!(
+                        "Skipped purging expired links, as another instance is purging them"
+                    );
+                }
+                Err(e) => {
+                    counter!("purge.failed").increment(1);
+                    tracing::error!("Failed to purge expired links: {e}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::database::{Actor, Link, MemoryStore};
+
+    #[tokio::test]
+    async fn test_purge_expired_links() {
+        let store = MemoryStore::default();
+        let now = now();
+        for (id, expired_for) in [
+            ("old1", Some(TimeDelta::days(3))),
+            ("old2", Some(TimeDelta::days(2))),
+            ("old3", Some(TimeDelta::days(2))),
+            ("recent", Some(TimeDelta::minutes(5))),
+            ("later", Some(-TimeDelta::days(1))),
+            ("forever", None),
+        ] {
+            let mut link = Link::new(Some(id.into()), "https://crates.io/".into());
+            link.expires_at = expired_for.map(|d| now - d);
+            store.insert_link(&link, Actor::Admin).await.unwrap();
+        }
+
+        let mut config = PurgeConfig {
+            mode: PurgeMode::Off,
+            graceperiodseconds: 3600,
+            batchsize: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            purge_expired_links(&store, &config).await.unwrap(),
+            Some(PurgeSummary::default())
+        );
+        assert!(store.get_link("old1").await.unwrap().is_some());
+
+        config.mode = PurgeMode::Delete;
+        assert_eq!(
+            purge_expired_links(&store, &config).await.unwrap(),
+            Some(PurgeSummary {
+                links: 3,
+                batches: 2
+            })
+        );
+        for (id, kept) in [
+            ("old1", false),
+            ("old2", false),
+            ("old3", false),
+            ("recent", true),
+            ("later", true),
+            ("forever", true),
+        ] {
+            assert_eq!(store.get_link(id).await.unwrap().is_some(), kept, "{id}");
+        }
+
+        // Nothing is left to purge
+        assert_eq!(
+            purge_expired_links(&store, &config).await.unwrap(),
+            Some(PurgeSummary::default())
+        );
+    }
+}
@@ -0,0 +1,452 @@
+//! Static redirect maps, which let web servers and hosting platforms serve
+//! redirects for all active links without this service, e.g. for disaster
+//! recovery.
+//!
+//! Maps only contain links which are active when they are generated, so they
+//! should be regenerated whenever a link in them expires, which is done by
+//! [`spawn_redirect_map_writer`].
+
+use std::{collections::HashSet, fmt::Write as _, path::{Path, PathBuf}, sync::Arc, time::Duration};
+
+use axum_prometheus::metrics::counter;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{config::RedirectMapConfig, database::{Link, LinkStore, now}, error::{Error, Result}};
+
+/// Extension of the pages of [`RedirectMapFormat::Html`] maps.
+const PAGE_EXTENSION: &str = "html";
+
+/// Format of a static redirect map.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectMapFormat {
+    /// nginx `map` block, setting `$curto_redirect` from the request URI.
+    #[default]
+    Nginx,
+    /// Apache `RewriteMap` text file, from IDs to target URLs.
+    Apache,
+    /// Caddyfile snippet with a `redir` directive for each link.
+    Caddy,
+    /// Netlify `_redirects` file.
+    Netlify,
+    /// Directory with an HTML page for each link, which redirects using a
+    /// meta refresh.
+    Html,
+}
+
+impl RedirectMapFormat {
+    /// Whether maps of this format are a directory of files, rather than a
+    /// single file.
+    pub fn is_directory(&self) -> bool {
+        matches!(self, Self::Html)
+    }
+
+    /// Conventional file name for maps of this format.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Nginx => "curto.map",
+            Self::Apache => "curto.txt",
+            Self::Caddy => "curto.caddy",
+            Self::Netlify => "_redirects",
+            Self::Html => "curto",
+        }
+    }
+}
+
+/// Result of writing a redirect map.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectMapSummary {
+    /// Number of links in the map.
+    pub links: usize,
+    /// When the first link in the map expires or has a scheduled change made,
+    /// after which the map should be regenerated.
+    pub next_expiry: Option<DateTime<Utc>>,
+}
+
+/// Links which are active at the given time, along with when the first of
+/// them expires or changes.
+fn active_links(links: Vec<Link>, now: DateTime<Utc>) -> (Vec<Link>, Option<DateTime<Utc>>) {
+    let links: Vec<_> = links
+        .into_iter()
+        .filter(|l| l.expires_at.is_none_or(|e| e > now))
+        .collect();
+    let next_expiry = links.iter().filter_map(|l| l.valid_until()).min();
+
+    (links, next_expiry)
+}
+
+/// Percent-encode whitespace, control characters and the given characters
+/// in a URL, which would otherwise break the syntax of a map.
+fn encode_url(url: &str, special: &[char]) -> String {
+    let mut encoded = String::with_capacity(url.len());
+    for c in url.chars() {
+        if c.is_whitespace() || c.is_control() || special.contains(&c) {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                _ = write!(encoded, "%{byte:02X}");
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+
+    encoded
+}
+
+/// Escape text for use in HTML content and attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Render a single file map of the given links.
+///
+/// # Panics
+///
+/// If the format is [`RedirectMapFormat::Html`], which isn't a single file.
+pub fn render_map(
+    format: RedirectMapFormat,
+    links: &[Link],
+    generated_at: DateTime<Utc>,
+) -> String {
+    let mut map = format!(
+        "# Generated by curto at {}, with {} links\n",
+        generated_at.format("%Y-%m-%dT%H:%M:%S"),
+        links.len()
+    );
+
+    match format {
+        RedirectMapFormat::Nginx => {
+            map.push_str(concat!(
+                "# Include this in the http block, then redirect from a server block with:\n",
+                "#     if ($curto_redirect) { return 307 $curto_redirect; }\n",
+                "map $uri $curto_redirect {\n",
+                "    default \"\";\n",
+            ));
+            for link in links {
+                // Variables are expanded in values, so dollar signs are encoded
+                let url = encode_url(&link.target_url, &['"', '\\', '$', '{', '}']);
+                _ = writeln!(map, "    /{} \"{url}\";", link.id);
+            }
+            map.push_str("}\n");
+        }
+        RedirectMapFormat::Apache => {
+            map.push_str(concat!(
+                "# Use this from a virtual host with:\n",
+                "#     RewriteMap curto \"txt:/path/to/curto.txt\"\n",
+                "#     RewriteCond ${curto:$1} !=\"\"\n",
+                "#     RewriteRule ^/([^/]+)$ ${curto:$1} [R=307,L]\n",
+            ));
+            for link in links {
+                _ = writeln!(map, "{} {}", link.id, encode_url(&link.target_url, &[]));
+            }
+        }
+        RedirectMapFormat::Caddy => {
+            map.push_str("# Use this from a site block with `import curto`\n(curto) {\n");
+            for link in links {
+                // Braces would be read as placeholders
+                let url = encode_url(&link.target_url, &['"', '\\', '{', '}']);
+                _ = writeln!(map, "\tredir /{} \"{url}\" 307", link.id);
+            }
+            map.push_str("}\n");
+        }
+        RedirectMapFormat::Netlify => {
+            for link in links {
+                _ = writeln!(
+                    map,
+                    "/{} {} 302",
+                    link.id,
+                    encode_url(&link.target_url, &[])
+                );
+            }
+        }
+        RedirectMapFormat::Html => panic!("HTML redirect maps are a directory of pages"),
+    }
+
+    map
+}
+
+/// Render the HTML page which redirects to the target of a link.
+pub fn render_page(link: &Link) -> String {
+    let url = escape_html(&link.target_url);
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="robots" content="noindex">
+<meta http-equiv="refresh" content="0; url={url}">
+<link rel="canonical" href="{url}">
+<title>Redirecting</title>
+</head>
+<body>
+<p>Redirecting to <a href="{url}">{url}</a>.</p>
+</body>
+</html>
+"#
+    )
+}
+
+/// Render a map of all active links in the store.
+///
+/// Fails for [`RedirectMapFormat::Html`], which can only be written to a
+/// directory.
+pub async fn generate_redirect_map(
+    store: &dyn LinkStore,
+    format: RedirectMapFormat,
+) -> Result<String> {
+    if format.is_directory() {
+        return Err(Error::InvalidRequest(
+            "HTML redirect maps are a directory of pages, which can only be written to a \
+             directory"
+                .into(),
+        ));
+    }
+
+    store.apply_scheduled_changes(None).await?;
+    let now = now();
+    let (links, _) = active_links(store.get_active_links().await?, now);
+
+    Ok(render_map(format, &links, now))
+}
+
+/// Write a map of all active links in the store to the given path, replacing
+/// any previous map there.
+///
+/// Single file maps are replaced atomically. For directories of pages, pages
+/// of links which are no longer active are removed.
+pub async fn write_redirect_map(
+    store: &dyn LinkStore,
+    format: RedirectMapFormat,
+    path: &Path,
+) -> Result<RedirectMapSummary> {
+    store.apply_scheduled_changes(None).await?;
+    let now = now();
+    let (links, next_expiry) = active_links(store.get_active_links().await?, now);
+
+    let summary = RedirectMapSummary {
+        links: links.len(),
+        next_expiry,
+    };
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        if format.is_directory() {
+            write_pages(&links, &path)
+        } else {
+            write_file(&render_map(format, &links, now), &path)
+        }
+    })
+    .await
+    .map_err(|e| Error::Internal(e.to_string()))?
+    .map_err(|e| Error::Internal(format!("failed to write redirect map: {e}")))?;
+
+    Ok(summary)
+}
+
+/// Write a file by renaming a temporary file over it, so that it's never read
+/// partially written.
+fn write_file(contents: &str, path: &Path) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+
+    std::fs::write(&temp, contents)?;
+    std::fs::rename(&temp, path)
+}
+
+fn write_pages(links: &[Link], dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+
+    let mut pages = HashSet::with_capacity(links.len());
+    for link in links {
+        let name = format!("{}.{PAGE_EXTENSION}", link.id);
+        write_file(&render_page(link), &dir.join(&name))?;
+        pages.insert(name);
+    }
+
+    // Remove pages of links which have expired or been deleted
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_stale = path.extension().is_some_and(|e| e == PAGE_EXTENSION)
+            && path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| !pages.contains(n));
+        if is_stale {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Regenerate the configured redirect map periodically, and whenever a link
+/// in it expires or changes as scheduled.
+pub fn spawn_redirect_map_writer(store: Arc<dyn LinkStore>, config: RedirectMapConfig) {
+    let Some(path) = config.path else {
+        return;
+    };
+    let interval = Duration::from_secs(config.intervalseconds);
+
+    tokio::spawn(async move {
+        loop {
+            let delay = match write_redirect_map(store.as_ref(), config.format, &path).await {
+                Ok(summary) => {
+                    tracing::debug!(
+                        "Wrote redirect map with {} links to {}",
+                        summary.links,
+                        path.display()
+                    );
+                    // Wake up just after the next link expires or changes
+                    summary
+                        .next_expiry
+                        .and_then(|e| (e - now()).to_std().ok())
+                        .map_or(interval, |d| d.min(interval) + Duration::from_millis(1))
+                }
+                Err(e) => {
+                    counter!("redirect_map.failed_to_write").increment(1);
+                    tracing::error!("Failed to write redirect map: {e}");
+                    interval
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeDelta;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::database::Actor;
+
+    fn link(id: &str, target_url: &str) -> Link {
+        Link::new(Some(id.into()), target_url.into())
+    }
+
+    #[test]
+    fn test_render_maps() {
+        let links = [
+            link("abc", "https://crates.io/"),
+            link("def", "https://docs.rs/search?q=a b&x=${y}"),
+        ];
+        let generated_at = DateTime::<Utc>::default();
+        let lines = |format| {
+            render_map(format, &links, generated_at)
+                .lines()
+                .filter(|l| !l.starts_with('#'))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lines(RedirectMapFormat::Nginx),
+            vec![
+                "map $uri $curto_redirect {",
+                "    default \"\";",
+                "    /abc \"https://crates.io/\";",
+                "    /def \"https://docs.rs/search?q=a%20b&x=%24%7By%7D\";",
+                "}",
+            ]
+        );
+        assert_eq!(
+            lines(RedirectMapFormat::Apache),
+            vec![
+                "abc https://crates.io/",
+                "def https://docs.rs/search?q=a%20b&x=${y}",
+            ]
+        );
+        assert_eq!(
+            lines(RedirectMapFormat::Caddy),
+            vec![
+                "(curto) {",
+                "\tredir /abc \"https://crates.io/\" 307",
+                "\tredir /def \"https://docs.rs/search?q=a%20b&x=$%7By%7D\" 307",
+                "}",
+            ]
+        );
+        assert_eq!(
+            lines(RedirectMapFormat::Netlify),
+            vec![
+                "/abc https://crates.io/ 302",
+                "/def https://docs.rs/search?q=a%20b&x=${y} 302",
+            ]
+        );
+
+        let page = render_page(&link("abc", "https://example.com/?a=1&b=\"2\""));
+        assert!(page.contains(
+            r#"<meta http-equiv="refresh" content="0; url=https://example.com/?a=1&amp;b=&quot;2&quot;">"#
+        ));
+    }
+
+    #[test]
+    fn test_active_links() {
+        let now = now();
+        let mut expired = link("old", "https://crates.io/");
+        expired.expires_at = Some(now - TimeDelta::seconds(1));
+        let mut expiring = link("soon", "https://crates.io/");
+        expiring.expires_at = Some(now + TimeDelta::hours(1));
+        let mut later = link("later", "https://crates.io/");
+        later.expires_at = Some(now + TimeDelta::days(1));
+
+        let (links, next_expiry) = active_links(
+            vec![
+                expired,
+                expiring,
+                later,
+                link("forever", "https://crates.io/"),
+            ],
+            now,
+        );
+        assert_eq!(
+            links.iter().map(|l| l.id.as_str()).collect::<Vec<_>>(),
+            vec!["soon", "later", "forever"]
+        );
+        assert_eq!(next_expiry, Some(now + TimeDelta::hours(1)));
+    }
+
+    #[tokio::test]
+    async fn test_write_pages() {
+        let dir = std::env::temp_dir().join(format!("curto-pages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("gone.html"), "").unwrap();
+        std::fs::write(dir.join("keep.txt"), "").unwrap();
+
+        let store = crate::database::MemoryStore::default();
+        store
+            .insert_link(&link("abc", "https://crates.io/"), Actor::Admin)
+            .await
+            .unwrap();
+        let summary = write_redirect_map(&store, RedirectMapFormat::Html, &dir)
+            .await
+            .unwrap();
+        assert_eq!(summary.links, 1);
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, vec!["abc.html", "keep.txt"]);
+    }
+}
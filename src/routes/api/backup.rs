@@ -0,0 +1,128 @@
+use axum::{body::Body, extract::State, http::{StatusCode, header::{CONTENT_DISPOSITION, CONTENT_TYPE}}, response::Response};
+use chrono::Utc;
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{AppState, backup::{self, RestoreSummary}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Json}, routes::Route};
+
+const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(handle_backup, handle_restore))
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "backup" ],
+    path = Route::Backup.as_str(),
+    description = "Stream a backup of all links, with their analytics and conversions, as \
+        newline-delimited JSON (admin only). The backup is taken as of a single point in time, \
+        and carries the schema version of this instance so that it can be restored by other \
+        instances.",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Streaming backup", content(
+            ("application/x-ndjson", example = json!(concat!(
+                r#"{"type":"header","format":"curto-backup","version":1,"schemaVersion":20250630091500,"createdAt":"2025-07-01T12:00:00Z"}"#, "\n",
+                r#"{"type":"link","id":"bmdkw","targetUrl":"https://crates.io/","countRedirects":42,"createdAt":"2025-06-01T10:00:00Z","updatedAt":"2025-06-01T10:00:00Z","expiresAt":null,"isCustomId":false,"trackConversions":false,"isUnlisted":false}"#, "\n",
+                r#"{"type":"sequence","value":0}"#, "\n",
+                r#"{"type":"end","records":2}"#, "\n",
+            ))),
+        ), headers(
+            ("Content-Disposition"),
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+    )
+)]
+async fn handle_backup(_: Admin, State(state): State<AppState>) -> Response {
+    tracing::debug!("Streaming backup");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, CONTENT_TYPE_NDJSON)
+        .header(
+            CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"curto-backup-{}.ndjson\"",
+                Utc::now().format("%Y%m%dT%H%M%S")
+            ),
+        )
+        .body(Body::from_stream(backup::backup_stream(state.store)))
+        .expect("This response should always be constructable")
+}
+
+#[utoipa::path(
+    post,
+    tags = [ "backup" ],
+    path = Route::Backup.as_str(),
+    description = "Restore a backup, replacing all links, analytics and conversions (admin only). \
+        Nothing is changed unless the whole backup is restored. Backups which would remove or \
+        change a link which is immutable or on legal hold are rejected. Backups from older \
+        versions are upgraded, while backups from newer versions are rejected. Backups larger than \
+        the request body limit can be restored with `curto restore` instead.",
+    request_body(content = String, content_type = "application/x-ndjson", description = "Backup to restore"),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Backup restored", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Backup restored", value = json!(
+                    RestoreSummary { links: 120, redirects: 3400, clicks: 50, conversions: 7, revisions: 135, scheduled_changes: 2 }
+                )))
+            )),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Incomplete backup" = (summary="User provided a backup which was cut short",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("incomplete backup, as it has no end".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 409, description = "Backup would remove or change a protected link", content(
+            ("application/json", examples(
+                ("Link immutable" = (summary="The backup doesn't have an immutable link, or has a different version of it",
+                    value=json!(ErrorResponse::from(Error::LinkImmutable("bmdkw".to_string()))))),
+                ("Link on legal hold" = (summary="The backup doesn't have a link on legal hold, or has it without the hold",
+                    value=json!(ErrorResponse::from(Error::LinkOnLegalHold("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 422, description = "Backup can't be restored by this instance", content(
+            ("application/json", examples(
+                ("Unsupported schema version" = (summary="User provided a backup from a newer version",
+                    value=json!(ErrorResponse::from(Error::BackupSchemaNotSupported(20990101000000, 20250630091500)))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+async fn handle_restore(
+    _: Admin,
+    State(state): State<AppState>,
+    body: String,
+) -> Result<Json<RestoreSummary>> {
+    let summary = state
+        .breaker
+        .call(backup::restore_backup(state.store.as_ref(), &body))
+        .await?;
+
+    tracing::info!("Restored backup with {} links", summary.links);
+
+    // Every link may have changed, or no longer exist. Other instances are
+    // notified of changes by the store.
+    if let Some(cache) = state.cache.as_ref() {
+        cache.clear().await;
+    }
+
+    Ok(Json(summary))
+}
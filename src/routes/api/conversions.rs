@@ -0,0 +1,225 @@
+use axum::{body::Body, extract::State, http::{StatusCode, header::{CACHE_CONTROL, CONTENT_TYPE}}, response::Response};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+use uuid::Uuid;
+
+use crate::{AppState, database::{ConversionStats, GoalConversions}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Json, Path, Query}, routes::Route};
+
+/// Transparent 1x1 GIF, returned by the conversion pixel.
+const PIXEL_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+pub fn routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(create_conversion, list_conversions))
+        .routes(routes!(conversion_pixel))
+        .routes(routes!(link_conversions))
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionRequest {
+    /// The click ID which was appended to the target URL, in the
+    /// `curto_click_id` query parameter.
+    pub click_id: Uuid,
+    /// Name of the goal which was reached, e.g. "signup". Can only contain
+    /// alphanumeric characters, '-', '_' or '.', and be at most 64 characters
+    /// long.
+    pub goal: String,
+}
+
+#[utoipa::path(
+    post,
+    tags = [ "conversions" ],
+    path = Route::Conversions.as_str(),
+    description = "Report that a click on a shortened link led to a goal being reached",
+    request_body = ConversionRequest,
+    responses(
+        (status = 201, description = "Conversion recorded", body = ConversionRequest),
+        (status = 200, description = "Conversion had already been recorded", body = ConversionRequest),
+        (status = 404, description = "Click ID not found", content(
+            ("application/json", examples(
+                ("Click not found" = (summary="No click matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::ClickNotFound(Uuid::nil().to_string())))))
+            ))
+        )),
+        (status = 422, description = "Request parameter(s) invalid", content(
+            ("application/json", examples(
+                ("Goal not valid" = (summary="User provided an invalid goal name",
+                    value=json!(ErrorResponse::from(Error::ConversionGoalNotValid("sign up".to_string())))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn create_conversion(
+    State(state): State<AppState>,
+    Json(conversion): Json<ConversionRequest>,
+) -> Result<(StatusCode, Json<ConversionRequest>)> {
+    let status = if state
+        .breaker
+        .call(
+            state
+                .store
+                .record_conversion(conversion.click_id, &conversion.goal),
+        )
+        .await?
+    {
+        tracing::debug!(
+            "Recorded conversion for goal {} from click {}",
+            conversion.goal,
+            conversion.click_id
+        );
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((status, Json(conversion)))
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "conversions" ],
+    path = Route::ConversionPixel.as_str(),
+    description = "Report that a click on a shortened link led to a goal being reached, \
+        for embedding as an image on the target site",
+    params(ConversionRequest),
+    responses(
+        (status = 200, description = "Conversion recorded", content_type = "image/gif"),
+        (status = 404, description = "Click ID not found", content(
+            ("application/json", examples(
+                ("Click not found" = (summary="No click matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::ClickNotFound(Uuid::nil().to_string())))))
+            ))
+        )),
+        (status = 422, description = "Request parameter(s) invalid", content(
+            ("application/json", examples(
+                ("Goal not valid" = (summary="User provided an invalid goal name",
+                    value=json!(ErrorResponse::from(Error::ConversionGoalNotValid("sign up".to_string())))))
+            ))
+        )),
+    )
+)]
+pub async fn conversion_pixel(
+    State(state): State<AppState>,
+    Query(conversion): Query<ConversionRequest>,
+) -> Result<Response> {
+    state
+        .breaker
+        .call(
+            state
+                .store
+                .record_conversion(conversion.click_id, &conversion.goal),
+        )
+        .await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "image/gif")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(PIXEL_GIF))
+        .expect("This response should always be constructable"))
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "conversions" ],
+    path = Route::Conversions.as_str(),
+    description = "Get conversion rates for each goal, across all links (admin only)",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Conversion rates found", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Conversion rates found", value = json!(
+                    ConversionStats {
+                        tracked_clicks: 200,
+                        goals: vec![GoalConversions {
+                            goal: "signup".into(),
+                            conversions: 10,
+                            conversion_rate: 0.05,
+                        }],
+                    }
+                )))
+            )),
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn list_conversions(
+    _: Admin,
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<ConversionStats>)> {
+    let stats = state.read(|s| s.get_conversion_stats(None)).await?;
+
+    Ok((StatusCode::OK, Json(stats)))
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "conversions" ],
+    path = Route::LinkConversions.as_str(),
+    description = "Get conversion rates for each goal, for a specific link",
+    responses(
+        (status = 200, description = "Conversion rates found", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Conversion rates found", value = json!(
+                    ConversionStats {
+                        tracked_clicks: 20,
+                        goals: vec![GoalConversions {
+                            goal: "signup".into(),
+                            conversions: 5,
+                            conversion_rate: 0.25,
+                        }],
+                    }
+                )))
+            )),
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn link_conversions(
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<(StatusCode, Json<ConversionStats>)> {
+    state
+        .read(|s| s.get_link(&link_id))
+        .await?
+        .ok_or_else(|| Error::LinkNotFound(link_id.clone()))?;
+
+    let stats = state
+        .read(|s| s.get_conversion_stats(Some(&link_id)))
+        .await?;
+
+    Ok((StatusCode::OK, Json(stats)))
+}
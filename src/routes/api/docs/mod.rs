@@ -1,19 +1,37 @@
 use axum::Router;
-use utoipa::{OpenApi, openapi};
+use utoipa::{Modify, OpenApi, openapi::{self, security::{Http, HttpAuthScheme, SecurityScheme}}};
 use utoipa_scalar::{Scalar, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
 pub const ROUTE_SWAGGER_UI: &str = "/swagger/";
 pub const ROUTE_API_FILE: &str = "/api.json";
+/// Name of the security scheme used by admin-only routes.
+pub const SECURITY_ADMIN: &str = "admin_token";
 
 #[derive(OpenApi)]
-#[openapi(info(
-    title = "Curto API",
-    description = "Easy-to-use URL shortener",
-    license(name = "AGPLv3"),
-))]
+#[openapi(
+    info(
+        title = "Curto API",
+        description = "Easy-to-use URL shortener",
+        license(name = "AGPLv3"),
+    ),
+    modifiers(&SecurityAddon),
+)]
 pub struct ApiDoc;
 
+/// Registers the security schemes referenced by routes.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            SECURITY_ADMIN,
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
 pub fn routes(api: openapi::OpenApi) -> Router {
     Router::new()
         // TODO: figure out how to make the swagger UI work with the API file in this nested route
@@ -0,0 +1,107 @@
+use axum::{http::{HeaderValue, StatusCode, header::{ETAG, LAST_MODIFIED}}, response::{IntoResponse, Response}};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{database::Link, extractors::{Conditions, Json}};
+
+/// Hex-encoded hash of the JSON representation of a value.
+fn content_hash(value: &impl Serialize) -> String {
+    let json = serde_json::to_vec(value).expect("links should always be serialisable");
+    format!("{:x}", Sha256::digest(json))[..32].to_string()
+}
+
+/// Version of a link, which changes whenever the link is edited, but not when
+/// it is redirected from.
+fn link_version(link: &Link) -> String {
+    content_hash(&Link {
+        count_redirects: 0,
+        updated_at: DateTime::<Utc>::default(),
+        ..link.clone()
+    })
+}
+
+/// ETag of a link, made of its version and redirect count.
+pub fn link_etag(link: &Link) -> String {
+    format!("\"{}-{}\"", link_version(link), link.count_redirects)
+}
+
+/// Whether the ETag refers to the current version of the link, ignoring its
+/// redirect count, so that redirects don't cause conflicts while the link is
+/// being edited.
+pub fn matches_link_version(link: &Link, etag: &str) -> bool {
+    etag.trim_matches('"')
+        .split_once('-')
+        .is_some_and(|(version, _)| version == link_version(link))
+}
+
+/// ETag of a list of links.
+pub fn links_etag(links: &[Link]) -> String {
+    let etags: Vec<_> = links.iter().map(link_etag).collect();
+    format!("\"{}\"", content_hash(&etags))
+}
+
+/// Respond with the body as JSON, or with `304 Not Modified` if the client
+/// already has the current version.
+pub fn conditional_json(
+    conditions: &Conditions,
+    etag: String,
+    last_modified: Option<DateTime<Utc>>,
+    body: impl Serialize,
+) -> Response {
+    let mut response = if conditions.is_not_modified(&etag, last_modified) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        (StatusCode::OK, Json(body)).into_response()
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        ETAG,
+        HeaderValue::from_str(&etag).expect("ETags should be valid header values"),
+    );
+    if let Some(modified) = last_modified {
+        headers.insert(LAST_MODIFIED, http_date(modified));
+    }
+
+    response
+}
+
+/// Format a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(time: DateTime<Utc>) -> HeaderValue {
+    HeaderValue::from_str(&time.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+        .expect("HTTP dates should be valid header values")
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_link_etag() {
+        let mut link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        let etag = link_etag(&link);
+        assert!(matches_link_version(&link, &etag));
+
+        // Redirects change the ETag, but not the version
+        link.count_redirects += 1;
+        link.updated_at += chrono::Duration::seconds(1);
+        assert_ne!(link_etag(&link), etag);
+        assert!(matches_link_version(&link, &etag));
+
+        // Edits change the version
+        link.target_url = "https://docs.rs/".into();
+        assert!(!matches_link_version(&link, &etag));
+        assert!(!matches_link_version(&link, "\"not-an-etag\""));
+    }
+
+    #[test]
+    fn test_http_date() {
+        let time = chrono::DateTime::parse_from_rfc3339("1994-11-06T08:49:37Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+}
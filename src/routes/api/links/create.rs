@@ -1,13 +1,12 @@
 use axum::{extract::State, http::StatusCode};
 use axum_extra::extract::Host;
-use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use url::Url;
 use utoipa::ToSchema;
 
-use crate::{AppState, database::{Link, create_link}, error::{Error, ErrorResponse, Result}, extractors::Json, routes::Route};
+use crate::{AppState, database::{Actor, IdStrategy, Link, NewLink, create_link, now}, error::{Error, ErrorResponse, Result}, extractors::Json, routes::Route, time::Expiry};
 
-#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateLinkRequest {
     /// The target URL which the new shortened link should redirect to
@@ -16,16 +15,32 @@ pub struct CreateLinkRequest {
     ///
     /// This value will be randomly generated if omitted
     pub custom_id: Option<String>,
-    /// An optional expiration time for the new shortened link, given in the
-    /// form "yyyy-mm-ddTHH:MM:ss.SSS" (without a timezone).
-    pub custom_expires_at: Option<NaiveDateTime>,
+    /// An optional expiration time for the new shortened link, given either
+    /// as an RFC 3339 timestamp like "2025-07-01T12:00:00+02:00", or as a
+    /// duration from now like "7d" or "PT12H". Timestamps without an offset
+    /// are taken to be in UTC.
+    pub custom_expires_at: Option<Expiry>,
+    /// Whether redirects from the new shortened link should carry a click ID
+    /// in the `curto_click_id` query parameter, which the target site can use
+    /// to report conversions. Defaults to false.
+    #[serde(default)]
+    pub track_conversions: bool,
+    /// How the ID of the new shortened link should be generated, if no custom
+    /// ID is provided. Defaults to the strategy configured for this service.
+    pub id_strategy: Option<IdStrategy>,
+    /// Whether the new shortened link should be unlisted, with a long,
+    /// unguessable ID, and left out of listings of links. Unlisted links can't
+    /// have a custom ID. Defaults to false.
+    #[serde(default)]
+    pub unlisted: bool,
 }
 
 #[utoipa::path(
     post,
     path = Route::Links.as_str(),
     tags = [ "links" ],
-    description = "Create new shortened links",
+    description = "Create new shortened links. When generating IDs derived from the target URL, \
+        an existing shortened link with the same target URL is returned instead.",
     request_body = CreateLinkRequest,
     responses(
         (status = 201, description = "Shortened link created successfully", content(
@@ -38,7 +53,9 @@ pub struct CreateLinkRequest {
         (status = 400, description = "Bad request", content(
             ("application/json", examples(
                 ("Malformed URL" = (summary="User provided a malformed URL",
-                    value=json!(ErrorResponse::from(Error::MalformedURL("hppts://googlecom".to_string())))))
+                    value=json!(ErrorResponse::from(Error::MalformedURL("hppts://googlecom".to_string()))))),
+                ("Unlisted link with custom ID" = (summary="User provided a custom ID for an unlisted link",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("unlisted links can't have a custom ID".to_string())))))
             ))
         )),
         (status = 422, description = "Request parameter(s) invalid", content(
@@ -66,32 +83,64 @@ pub async fn create_new_link(
     Host(host): Host,
     Json(new_link): Json<CreateLinkRequest>,
 ) -> Result<(StatusCode, Json<Link>)> {
-    let url = Url::parse(&new_link.target_url)?;
+    let url = validate_target_url(&host, &new_link.target_url)?;
 
-    // Deny URLs without a defined host
-    let target_host = url
-        .host()
-        .ok_or_else(|| Error::URLWithoutHost(url.to_string()))?
-        .to_string();
+    // Create a new link
+    let new_link = state
+        .breaker
+        .call(create_link(
+            state.store.as_ref(),
+            &state.id_generator,
+            NewLink {
+                target_url: url.to_string(),
+                custom_id: new_link.custom_id,
+                expires_at: new_link.custom_expires_at.map(|e| e.resolve(now())),
+                track_conversions: new_link.track_conversions,
+                id_strategy: new_link.id_strategy,
+                unlisted: new_link.unlisted,
+                created_by: Actor::Anonymous,
+                ..Default::default()
+            },
+        ))
+        .await?;
+
+    tracing::debug!("Created new link with id {} targeting {}", new_link.id, url);
+
+    // Write through to the cache, so that other instances don't have to look
+    // up the new link in the database
+    if let Some(cache) = state.cache.as_ref() {
+        cache.insert(new_link.clone()).await;
+    }
+
+    // The link may already exist, e.g. when derived from the target URL
+    Ok((StatusCode::CREATED, Json(new_link.for_public())))
+}
+
+/// Parse a target URL for a link, denying URLs which can't be redirected to
+/// from this service.
+pub(crate) fn validate_target_url(host: &str, target_url: &str) -> Result<Url> {
+    let url = parse_target_url(target_url)?;
+    let target_host = url.host_str().unwrap_or_default();
 
     // Attempt to deny URLs with a host that matches this service, to prevent a
     // circular redirect.
-    if hosts_match(&host, &target_host) {
-        return Err(Error::URLWithMatchingHosts(host));
+    if hosts_match(host, target_host) {
+        return Err(Error::URLWithMatchingHosts(host.to_string()));
     };
 
-    // Create a new link
-    let new_link = create_link(
-        &state.db,
-        url.to_string(),
-        new_link.custom_id,
-        new_link.custom_expires_at,
-    )
-    .await?;
+    Ok(url)
+}
 
-    tracing::debug!("Created new link with id {} targeting {}", new_link.id, url);
+/// Parse a target URL for a link, denying URLs without a host.
+pub(crate) fn parse_target_url(target_url: &str) -> Result<Url> {
+    let url = Url::parse(target_url)?;
+
+    // Deny URLs without a defined host
+    if url.host().is_none() {
+        return Err(Error::URLWithoutHost(url.to_string()));
+    }
 
-    Ok((StatusCode::CREATED, Json(new_link)))
+    Ok(url)
 }
 
 /// Utility function used to check if the request and target hosts match
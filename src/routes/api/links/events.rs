@@ -0,0 +1,108 @@
+use std::convert::Infallible;
+
+use axum::{extract::State, response::{Sse, sse::{Event, KeepAlive}}};
+use futures_util::{Stream, stream};
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+use crate::{AppState, database::RedirectEvent, error::{Error, ErrorResponse, Result}, extractors::{Admin, Path}, routes::Route};
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Stream redirects from a specific link in real time, as Server-Sent Events",
+    path = Route::LinkEvents.as_str(),
+    responses(
+        (status = 200, description = "Stream of redirect events", content(
+            (RedirectEvent = "text/event-stream"),
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn link_events(
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = core::result::Result<Event, Infallible>>>> {
+    // Subscribe before checking the link exists, so no events are missed
+    let receiver = state.redirect_events.subscribe();
+
+    state
+        .read(|s| s.get_link(&link_id))
+        .await?
+        .ok_or_else(|| Error::LinkNotFound(link_id.clone()))?;
+
+    tracing::debug!("Streaming redirect events for link with ID {}", link_id);
+
+    Ok(Sse::new(event_stream(receiver, Some(link_id))).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Stream redirects from all links in real time, as Server-Sent Events (admin only)",
+    path = Route::Events.as_str(),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Stream of redirect events", content(
+            (RedirectEvent = "text/event-stream"),
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+    )
+)]
+pub async fn all_events(
+    _: Admin,
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = core::result::Result<Event, Infallible>>> {
+    tracing::debug!("Streaming redirect events for all links");
+
+    Sse::new(event_stream(state.redirect_events.subscribe(), None)).keep_alive(KeepAlive::default())
+}
+
+/// Convert received [`RedirectEvent`]s into SSE messages, optionally only for
+/// the link with the given ID.
+fn event_stream(
+    receiver: Receiver<RedirectEvent>,
+    link_id: Option<String>,
+) -> impl Stream<Item = core::result::Result<Event, Infallible>> {
+    stream::unfold(receiver, move |mut receiver| {
+        let link_id = link_id.clone();
+
+        async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Redirect event stream lagged, skipping {skipped} events");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+
+                if link_id.as_ref().is_some_and(|id| *id != event.link_id) {
+                    continue;
+                }
+
+                let message = Event::default()
+                    .event("redirect")
+                    .json_data(&event)
+                    .expect("redirect events should always be serialisable");
+
+                return Some((Ok(message), receiver));
+            }
+        }
+    })
+}
@@ -0,0 +1,291 @@
+use axum::{body::{Body, Bytes}, extract::State, http::{HeaderMap, StatusCode, header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE}}, response::Response};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppState, database::{HourlyRedirects, RedirectsFilter}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Query}, routes::Route, time::deserialize_optional_timestamp};
+
+/// Number of rows fetched from the database for each chunk of the export.
+const EXPORT_CHUNK_SIZE: i64 = 1000;
+const CONTENT_TYPE_CSV: &str = "text/csv; charset=utf-8";
+const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
+
+/// Format of exported link analytics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// Choose a format based on the `Accept` header, falling back to CSV.
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let accepts_ndjson = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.contains("application/x-ndjson") || v.contains("application/ndjson"));
+
+        if accepts_ndjson {
+            Self::Ndjson
+        } else {
+            Self::Csv
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => CONTENT_TYPE_CSV,
+            Self::Ndjson => CONTENT_TYPE_NDJSON,
+        }
+    }
+
+    fn file_extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportQuery {
+    /// Comma-separated IDs of the links to export redirects for. All links
+    /// except unlisted links are included if omitted.
+    pub ids: Option<String>,
+    /// Only export redirects from this time onwards, given in RFC 3339 like
+    /// "2025-07-01T12:00:00+02:00" (with the `+` percent-encoded), or in UTC
+    /// if without an offset. Rounded down to the hour.
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub from: Option<DateTime<Utc>>,
+    /// Only export redirects from before this time, given in RFC 3339 like
+    /// "2025-07-01T12:00:00+02:00", or in UTC if without an offset.
+    #[serde(default, deserialize_with = "deserialize_optional_timestamp")]
+    pub to: Option<DateTime<Utc>>,
+    /// Format of the export. If omitted, this is chosen based on the `Accept`
+    /// header, defaulting to CSV.
+    #[param(inline)]
+    pub format: Option<ExportFormat>,
+}
+
+impl ExportQuery {
+    fn into_filter(self) -> Result<RedirectsFilter> {
+        if let (Some(from), Some(to)) = (self.from, self.to)
+            && from >= to
+        {
+            return Err(Error::InvalidRequest(
+                "from must be earlier than to".to_string(),
+            ));
+        }
+
+        Ok(RedirectsFilter {
+            link_ids: self.ids.map(|ids| {
+                ids.split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(String::from)
+                    .collect()
+            }),
+            from: self.from,
+            to: self.to,
+        })
+    }
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Export hourly redirect counts for links as CSV or newline-delimited JSON \
+        (admin only). The export is streamed in chunks, so it is suitable for large time ranges.",
+    path = Route::LinksExport.as_str(),
+    security(("admin_token" = [])),
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "Streaming export of hourly redirect counts", content(
+            (String = "text/csv", example = json!(
+                "link_id,target_url,hour,redirects\nbmdkw,https://crates.io/,2025-06-01T10:00:00Z,42\n"
+            )),
+            (HourlyRedirects = "application/x-ndjson"),
+        ), headers(
+            ("Content-Disposition"),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Invalid time range" = (summary="User provided a start time which is not before the end time",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("from must be earlier than to".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+    )
+)]
+pub async fn export_links(
+    _: Admin,
+    State(state): State<AppState>,
+    Query(query): Query<ExportQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let format = query
+        .format
+        .unwrap_or_else(|| ExportFormat::from_headers(&headers));
+    let filter = query.into_filter()?;
+
+    tracing::debug!("Exporting link analytics as {:?}", format);
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, format.content_type())
+        .header(
+            CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"curto-analytics.{}\"",
+                format.file_extension()
+            ),
+        )
+        .body(Body::from_stream(export_stream(state, filter, format)))
+        .expect("This response should always be constructable"))
+}
+
+/// State of an in-progress export.
+enum ExportState {
+    Start,
+    /// Cursor of the last exported row.
+    After(String, DateTime<Utc>),
+    Done,
+}
+
+/// Stream of serialised chunks of the export, fetching each chunk from the
+/// database only once the previous chunk has been consumed.
+fn export_stream(
+    app: AppState,
+    filter: RedirectsFilter,
+    format: ExportFormat,
+) -> impl futures_util::Stream<Item = Result<Bytes>> {
+    stream::try_unfold(ExportState::Start, move |state| {
+        let app = app.clone();
+        let filter = filter.clone();
+
+        async move {
+            let after = match &state {
+                ExportState::Start => None,
+                ExportState::After(id, hour) => Some((id.as_str(), *hour)),
+                ExportState::Done => return Ok(None),
+            };
+
+            let rows = app
+                .read(|s| s.get_hourly_redirects(&filter, after, EXPORT_CHUNK_SIZE))
+                .await
+                .inspect_err(|e| tracing::error!("Failed to export link analytics: {e}"))?;
+
+            let chunk = serialise_chunk(&rows, format, matches!(state, ExportState::Start))?;
+
+            let next = match rows.last() {
+                Some(last) if rows.len() as i64 == EXPORT_CHUNK_SIZE => {
+                    ExportState::After(last.link_id.clone(), last.hour)
+                }
+                _ => ExportState::Done,
+            };
+
+            Ok(Some((chunk, next)))
+        }
+    })
+}
+
+/// Serialise a chunk of rows of the export, starting with the CSV header if
+/// it is the first chunk.
+fn serialise_chunk(rows: &[HourlyRedirects], format: ExportFormat, first: bool) -> Result<Bytes> {
+    let chunk = match format {
+        ExportFormat::Csv => csv_chunk(rows, first)
+            .map_err(|e| Error::Internal(format!("failed to write CSV: {e}")))?,
+        ExportFormat::Ndjson => {
+            let mut buf = Vec::new();
+            for row in rows {
+                serde_json::to_writer(&mut buf, row).map_err(|e| Error::Internal(e.to_string()))?;
+                buf.push(b'\n');
+            }
+            buf
+        }
+    };
+
+    Ok(Bytes::from(chunk))
+}
+
+fn csv_chunk(rows: &[HourlyRedirects], first: bool) -> csv::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if first {
+        writer.write_record(["link_id", "target_url", "hour", "redirects"])?;
+    }
+    for row in rows {
+        writer.write_record([
+            row.link_id.as_str(),
+            row.target_url.as_str(),
+            &row.hour.to_rfc3339_opts(SecondsFormat::Secs, true),
+            &row.redirects.to_string(),
+        ])?;
+    }
+
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::HeaderValue;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_serialise_chunk() {
+        let row = |target_url: &str| HourlyRedirects {
+            link_id: "abc".into(),
+            target_url: target_url.into(),
+            hour: DateTime::<Utc>::default(),
+            redirects: 2,
+        };
+        let rows = [
+            row("https://crates.io/"),
+            row("https://crates.io/?a,b"),
+            row("https://crates.io/?\"a\""),
+        ];
+
+        let csv = serialise_chunk(&rows, ExportFormat::Csv, true).unwrap();
+        assert_eq!(
+            csv,
+            "link_id,target_url,hour,redirects\n\
+             abc,https://crates.io/,1970-01-01T00:00:00Z,2\n\
+             abc,\"https://crates.io/?a,b\",1970-01-01T00:00:00Z,2\n\
+             abc,\"https://crates.io/?\"\"a\"\"\",1970-01-01T00:00:00Z,2\n"
+        );
+        let csv = serialise_chunk(&rows[..1], ExportFormat::Csv, false).unwrap();
+        assert_eq!(csv, "abc,https://crates.io/,1970-01-01T00:00:00Z,2\n");
+
+        let ndjson = serialise_chunk(&rows[..1], ExportFormat::Ndjson, true).unwrap();
+        assert!(ndjson.ends_with(b"\n"));
+        let row = serde_json::from_slice::<HourlyRedirects>(&ndjson).unwrap();
+        assert_eq!(row.target_url, "https://crates.io/");
+    }
+
+    #[test]
+    fn test_format_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(ExportFormat::from_headers(&headers), ExportFormat::Csv);
+
+        headers.insert(ACCEPT, HeaderValue::from_static("text/csv"));
+        assert_eq!(ExportFormat::from_headers(&headers), ExportFormat::Csv);
+
+        headers.insert(ACCEPT, HeaderValue::from_static("application/x-ndjson"));
+        assert_eq!(ExportFormat::from_headers(&headers), ExportFormat::Ndjson);
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/ndjson, */*;q=0.8"),
+        );
+        assert_eq!(ExportFormat::from_headers(&headers), ExportFormat::Ndjson);
+    }
+}
@@ -1,20 +1,30 @@
-use axum::{extract::State, http::StatusCode};
+use axum::{extract::State, response::Response};
 
-use crate::{AppState, database::{Link, get_link}, error::{Error, ErrorResponse, Result}, extractors::{Json, Path}, routes::Route};
+use super::conditional::{conditional_json, link_etag};
+use crate::{AppState, database::Link, error::{Error, ErrorResponse, Result}, extractors::{Admin, Conditions, Path}, routes::Route, schedule::apply_due_changes};
 
 #[utoipa::path(
     get,
     tags = [ "links" ],
-    description = "Get a specific link by the given ID",
+    description = "Get a specific link by the given ID, showing whether it is on legal hold only \
+        to admins. Supports conditional requests using `If-None-Match` or `If-Modified-Since`, \
+        responding with 304 if the link is unchanged.",
     path = Route::LinkGet.as_str(),
     responses(
-        (status = 200, description = "Successfully fetched request link", content(
+        (status = 200, description = "Successfully fetched request link", headers(
+            ("ETag"),
+            ("Last-Modified"),
+        ), content(
             ("application/json", examples(
                 ( "OK" = (summary="Shortened link found", value = json!(
                         Link::new(None, "https://crates.io/".into())
                 )))
             )),
         )),
+        (status = 304, description = "Link unchanged since the client fetched it", headers(
+            ("ETag"),
+            ("Last-Modified"),
+        )),
         (status = 404, description = "Link matching ID not found", content(
             ("application/json", examples(
                 ("Link not found" = (summary="No link matching the specified ID could be found",
@@ -30,16 +40,31 @@ use crate::{AppState, database::{Link, get_link}, error::{Error, ErrorResponse,
     )
 )]
 pub async fn get_specific_link(
+    admin: Option<Admin>,
     State(state): State<AppState>,
     Path(link_id): Path<String>,
-) -> Result<(StatusCode, Json<Link>)> {
-    // Increment count of redirects for the link
-    let link = get_link(&state.db, &link_id)
+    conditions: Conditions,
+) -> Result<Response> {
+    let link = state
+        .read(|s| s.get_link(&link_id))
         .await?
         // The link with the given ID could not be found
         .ok_or_else(|| Error::LinkNotFound(link_id.clone()))?;
+    let link = state
+        .breaker
+        .call(apply_due_changes(state.store.as_ref(), link))
+        .await?;
 
     tracing::debug!("Found link with ID {}", link_id);
 
-    Ok((StatusCode::OK, Json(link)))
+    let updated_at = link.updated_at;
+    let link = if admin.is_some() {
+        link
+    } else {
+        link.for_public()
+    };
+    // Computed on what the client can see, so hidden fields don't leak
+    let etag = link_etag(&link);
+
+    Ok(conditional_json(&conditions, etag, Some(updated_at), link))
 }
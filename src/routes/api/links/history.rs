@@ -0,0 +1,185 @@
+use axum::{extract::State, response::Response};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::conditional::{conditional_json, link_etag, matches_link_version};
+use crate::{AppState, database::{Actor, Link, LinkRevision, LinkUpdate}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Conditions, Json, Path}, routes::Route};
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RollbackRequest {
+    /// Number of the revision to restore, as listed in the link's history.
+    pub revision: i32,
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Get the history of a specific link by the given ID (admin only), as the \
+        revisions of its target URL, expiration time, conversion tracking, immutability and legal \
+        hold, oldest first. Each revision records when the change was made and who made it, such \
+        as who placed or lifted a legal hold. The history of a link is kept after it is deleted \
+        or purged.",
+    path = Route::LinkHistory.as_str(),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Successfully fetched the link's revisions", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Link created, then updated by an admin", value = json!([
+                    LinkRevision::new(&Link::new(Some("abc".into()), "https://crates.io/".into()), 1, Actor::Anonymous, None),
+                    LinkRevision::new(&Link::new(Some("abc".into()), "https://docs.rs/".into()), 2, Actor::Admin, None),
+                ])))
+            )),
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn link_history(
+    _: Admin,
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Vec<LinkRevision>>> {
+    let revisions = state
+        .breaker
+        .call(state.store.get_link_revisions(&link_id))
+        .await?;
+
+    // Every link, even once deleted, has at least the revision it was created
+    // with
+    if revisions.is_empty() {
+        return Err(Error::LinkNotFound(link_id));
+    }
+
+    Ok(Json(revisions))
+}
+
+#[utoipa::path(
+    post,
+    tags = [ "links" ],
+    description = "Roll back a specific link by the given ID to one of its revisions (admin \
+        only), restoring the target URL, expiration time and conversion tracking it had. The \
+        rollback is recorded as a new revision. If `If-Match` is provided, the link is only \
+        rolled back if it is unchanged since the ETag was fetched.",
+    path = Route::LinkRollback.as_str(),
+    security(("admin_token" = [])),
+    request_body = RollbackRequest,
+    responses(
+        (status = 200, description = "Shortened link rolled back successfully", headers(
+            ("ETag"),
+            ("Last-Modified"),
+        ), content(
+            ("application/json", examples(
+                ( "OK" = (summary="Shortened link rolled back", value = json!(
+                    Link::new(None, "https://crates.io/".into())
+                )))
+            )),
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link or revision not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string()))))),
+                ("Revision not found" = (summary="The link has no revision with the given number",
+                    value=json!(ErrorResponse::from(Error::LinkRevisionNotFound("bmdkw".to_string(), 7)))))
+            ))
+        )),
+        (status = 409, description = "Link is immutable", content(
+            ("application/json", examples(
+                ("Link immutable" = (summary="The link's target URL and expiration time can't be changed",
+                    value=json!(ErrorResponse::from(Error::LinkImmutable("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 412, description = "Link changed since the ETag in `If-Match` was fetched", content(
+            ("application/json", examples(
+                ("Link changed" = (value=json!(ErrorResponse::from(Error::LinkChanged))))
+            ))
+        )),
+        (status = 422, description = "Revision can't be restored", content(
+            ("application/json", examples(
+                ("Revision expired" = (summary="The revision's expiration time has passed",
+                    value=json!(ErrorResponse::from(Error::LinkExpirationTimeNotValid(DateTime::<Utc>::default()))))),
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn rollback_link(
+    _: Admin,
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+    conditions: Conditions,
+    Json(rollback): Json<RollbackRequest>,
+) -> Result<Response> {
+    let revisions = state
+        .breaker
+        .call(state.store.get_link_revisions(&link_id))
+        .await?;
+    if revisions.is_empty() {
+        return Err(Error::LinkNotFound(link_id));
+    }
+    let revision = revisions
+        .into_iter()
+        .find(|r| r.revision == rollback.revision)
+        .ok_or_else(|| Error::LinkRevisionNotFound(link_id.clone(), rollback.revision))?;
+
+    let link = state
+        .breaker
+        .call(state.store.update_link(
+            &link_id,
+            LinkUpdate {
+                target_url: Some(revision.target_url),
+                expires_at: Some(revision.expires_at),
+                track_conversions: Some(revision.track_conversions),
+                immutable: None,
+                legal_hold: None,
+                changed_by: Actor::Admin,
+                restored_revision: Some(revision.revision),
+            },
+            Box::new(|link| conditions.check_match(|etag| matches_link_version(link, etag))),
+        ))
+        .await?;
+
+    tracing::debug!(
+        "Rolled back link with ID {} to revision {}",
+        link_id,
+        revision.revision
+    );
+
+    // Write through to the cache, as with updates
+    if let Some(cache) = state.cache.as_ref() {
+        cache.insert(link.clone()).await;
+    }
+
+    Ok(conditional_json(
+        &Conditions::default(),
+        link_etag(&link),
+        Some(link.updated_at),
+        link,
+    ))
+}
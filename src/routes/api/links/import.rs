@@ -0,0 +1,120 @@
+use axum::extract::State;
+use axum_extra::extract::Host;
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use crate::{AppState, error::{Error, ErrorResponse, Result}, extractors::{Admin, Json, Query}, import::{self, ConflictPolicy, ImportEntry, ImportFormat, ImportOutcome, ImportReport}, routes::Route};
+
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportQuery {
+    /// Format of the links in the request body.
+    #[param(inline)]
+    pub format: ImportFormat,
+    /// How to handle links whose ID is already in use. Defaults to skipping
+    /// them.
+    #[serde(default)]
+    #[param(inline)]
+    pub policy: ConflictPolicy,
+}
+
+#[utoipa::path(
+    post,
+    tags = [ "links" ],
+    description = "Import links exported from YOURLS, Shlink or Bitly, or from a Netscape \
+        bookmark file (admin only). Original IDs, creation times and click counts are kept \
+        where possible, and IDs which are already in use are handled using the given policy. \
+        The report lists what happened to every link.",
+    path = Route::LinksImport.as_str(),
+    params(ImportQuery),
+    request_body(content = String, content_type = "text/plain", description = "Export to import links from"),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Links imported, with a report of every link", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Links imported", value = json!(
+                    ImportReport {
+                        imported: 1,
+                        skipped: 1,
+                        entries: vec![
+                            ImportEntry {
+                                position: 1,
+                                original_id: Some("crates".into()),
+                                id: Some("crates".into()),
+                                target_url: Some("https://crates.io/".into()),
+                                outcome: ImportOutcome::Imported,
+                                reason: None,
+                            },
+                            ImportEntry {
+                                position: 2,
+                                original_id: Some("docs".into()),
+                                id: None,
+                                target_url: Some("https://docs.rs/".into()),
+                                outcome: ImportOutcome::Skipped,
+                                reason: Some(Error::LinkIdNotUnique("docs".into()).to_string()),
+                            },
+                        ],
+                        ..Default::default()
+                    }
+                )))
+            )),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Malformed export" = (summary="User provided an export which can't be parsed in the given format",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("malformed JSON: expected value at line 1 column 1".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn import_links(
+    _: Admin,
+    State(state): State<AppState>,
+    Host(host): Host,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Result<Json<ImportReport>> {
+    let links = import::parse_links(query.format, &body)?;
+
+    tracing::debug!(
+        "Importing {} links as {:?} with policy {:?}",
+        links.len(),
+        query.format,
+        query.policy
+    );
+
+    let report = state
+        .breaker
+        .call(import::import_links(
+            state.store.as_ref(),
+            &state.id_generator,
+            links,
+            query.policy,
+            Some(&host),
+        ))
+        .await?;
+
+    // Overwritten links may be cached by this and other instances
+    if let Some(cache) = state.cache.as_ref() {
+        for entry in &report.entries {
+            if entry.outcome == ImportOutcome::Overwritten
+                && let Some(id) = entry.id.as_ref()
+            {
+                cache.remove(id).await;
+            }
+        }
+    }
+
+    Ok(Json(report))
+}
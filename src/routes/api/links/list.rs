@@ -1,14 +1,19 @@
-use axum::{extract::State, http::StatusCode};
+use axum::{extract::State, response::Response};
 
-use crate::{AppState, database::{Link, get_links}, error::{Error, ErrorResponse, Result}, extractors::Json, routes::Route};
+use super::conditional::{conditional_json, links_etag};
+use crate::{AppState, database::Link, error::{Error, ErrorResponse, Result}, extractors::{Admin, Conditions}, routes::Route};
 
 #[utoipa::path(
     get,
     tags = [ "links" ],
-    description = "Get all existing shortened links",
+    description = "Get all existing shortened links, showing which are on legal hold only to \
+        admins. Supports conditional requests using `If-None-Match`, responding with 304 if no \
+        links changed.",
     path = Route::Links.as_str(),
     responses(
-        (status = 200, description = "Successfully fetched all shortened links", content(
+        (status = 200, description = "Successfully fetched all shortened links", headers(
+            ("ETag"),
+        ), content(
             ("application/json", examples(
                 ( "OK" = (summary="Shortened links found", value = json!(
                     vec![
@@ -18,6 +23,9 @@ use crate::{AppState, database::{Link, get_links}, error::{Error, ErrorResponse,
                 )))
             )),
         )),
+        (status = 304, description = "No links changed since the client fetched them", headers(
+            ("ETag"),
+        )),
         (status = 500, description = "Internal server error", content(
             ("application/json", examples(
                 ("Internal server error" =
@@ -26,8 +34,20 @@ use crate::{AppState, database::{Link, get_links}, error::{Error, ErrorResponse,
         )),
     )
 )]
-pub async fn list_links(State(state): State<AppState>) -> Result<(StatusCode, Json<Vec<Link>>)> {
-    let links = get_links(&state.db).await?;
+pub async fn list_links(
+    admin: Option<Admin>,
+    State(state): State<AppState>,
+    conditions: Conditions,
+) -> Result<Response> {
+    let links = state.read(|s| s.get_links()).await?;
+    let links: Vec<_> = if admin.is_some() {
+        links
+    } else {
+        links.into_iter().map(Link::for_public).collect()
+    };
+    let etag = links_etag(&links);
 
-    Ok((StatusCode::OK, Json(links)))
+    // Links can be deleted, so the last modification time of the list is
+    // unknown
+    Ok(conditional_json(&conditions, etag, None, links))
 }
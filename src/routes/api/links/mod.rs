@@ -1,7 +1,16 @@
+mod conditional;
 pub mod create;
+pub mod events;
+pub mod export;
 pub mod get;
+pub mod history;
+pub mod import;
 pub mod list;
 pub mod redirect;
+pub mod redirect_map;
+pub mod schedule;
+pub mod top;
+pub mod update;
 
 use utoipa_axum::{router::OpenApiRouter, routes};
 
@@ -12,5 +21,19 @@ pub fn routes() -> OpenApiRouter<AppState> {
         .routes(routes!(create::create_new_link))
         .routes(routes!(redirect::redirect_links))
         .routes(routes!(list::list_links))
-        .routes(routes!(get::get_specific_link))
+        .routes(routes!(
+            get::get_specific_link,
+            update::update_specific_link,
+            update::delete_specific_link
+        ))
+        .routes(routes!(history::link_history))
+        .routes(routes!(history::rollback_link))
+        .routes(routes!(schedule::link_schedule, schedule::schedule_change))
+        .routes(routes!(schedule::cancel_scheduled_change))
+        .routes(routes!(top::top_links))
+        .routes(routes!(export::export_links))
+        .routes(routes!(import::import_links))
+        .routes(routes!(redirect_map::redirect_map))
+        .routes(routes!(events::link_events))
+        .routes(routes!(events::all_events))
 }
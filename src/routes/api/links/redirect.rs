@@ -1,10 +1,16 @@
-use axum::{body::Body, extract::{RawQuery, State}, http::{HeaderMap, HeaderValue, StatusCode, header::{ACCEPT_LANGUAGE, CONTENT_SECURITY_POLICY, CONTENT_TYPE, COOKIE, HOST, SET_COOKIE, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS, X_XSS_PROTECTION}, response::Builder}, response::Response};
+use std::sync::Arc;
+
+use axum::{body::Body, extract::{RawQuery, State}, http::{HeaderMap, HeaderValue, StatusCode, header::{ACCEPT_LANGUAGE, CONTENT_SECURITY_POLICY, CONTENT_TYPE, COOKIE, HOST, REFERER, SET_COOKIE, USER_AGENT, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS, X_XSS_PROTECTION}, response::Builder}, response::Response};
+use axum_prometheus::metrics::counter;
 use url::Url;
+use uuid::Uuid;
 
-use crate::{AppState, database::{Link, increment_link_redirect_count}, error::{Error, ErrorResponse, Result}, extractors::Path, routes::Route};
+use crate::{AppState, database::{Link, RedirectEvent, now}, error::{Error, ErrorResponse, Result}, extractors::{ClientIp, Path}, routes::Route, schedule::apply_due_changes};
 
-const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str =
-    "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300";
+/// Redirects carrying a click ID must not be re-used.
+const TRACKED_CACHE_CONTROL_HEADER_VALUE: &str = "no-store";
+/// Query parameter used to pass click IDs to the target URL.
+pub const CLICK_ID_PARAM: &str = "curto_click_id";
 
 #[utoipa::path(
     get,
@@ -22,32 +28,93 @@ const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str =
                     value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
             ))
         )),
+        (status = 429, description = "Too many links not found for this client", headers(
+            ("Retry-After"),
+        ), content(
+            ("application/json", examples(
+                ("Too many links not found" = (summary="The client made too many redirects to links which could not be found",
+                    value=json!(ErrorResponse::from(Error::TooManyNotFound(60)))))
+            ))
+        )),
         (status = 500, description = "Internal server error", content(
             ("application/json", examples(
                 ("Internal server error" =
                     (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
             ))
         )),
+        (status = 503, description = "Database unavailable, and link not cached", headers(
+            ("Retry-After"),
+        ), content(
+            ("application/json", examples(
+                ("Service unavailable" =
+                    (value=json!(ErrorResponse::from(Error::DatabaseUnavailable(10)))))
+            ))
+        )),
     )
 )]
 pub async fn redirect_links(
     State(state): State<AppState>,
     Path(link_id): Path<String>,
+    ClientIp(client): ClientIp,
     raw_query: RawQuery,
     headers: HeaderMap,
 ) -> Result<Response> {
+    // Refuse clients which appear to be guessing link IDs
+    let throttle = state.not_found_throttle.as_ref().zip(client);
+    if let Some((throttle, client)) = throttle {
+        throttle.check(client).await?;
+    }
+
     // Increment count of redirects for the link
-    let link = increment_link_redirect_count(&state.db, &link_id)
-        .await?
+    let Some(link) = find_and_count_redirect(&state, &link_id).await? else {
+        if let Some((throttle, client)) = throttle {
+            throttle.record_not_found(client).await;
+        }
+
         // The link with the given ID could not be found
-        .ok_or_else(|| Error::LinkNotFound(link_id.clone()))?;
+        return Err(Error::LinkNotFound(link_id));
+    };
 
     tracing::debug!("Redirecting link ID {} to {}", link_id, link.target_url);
 
+    // Publish the redirect for live event streams, without delaying the
+    // redirect. Events are dropped while too many are waiting to be published.
+    let event = RedirectEvent::new(
+        link_id,
+        headers.get(USER_AGENT).and_then(|v| v.to_str().ok()),
+        headers.get(REFERER).and_then(|v| v.to_str().ok()),
+    );
+    if state.redirect_event_queue.try_send(event).is_err() {
+        counter!("events.redirect_events_dropped").increment(1);
+    }
+
+    // Links tracking conversions carry a unique click ID. If it can't be
+    // recorded, still redirect, just without the click ID.
+    let click_id = if link.track_conversions {
+        state
+            .breaker
+            .call(state.store.record_click(&link.id))
+            .await
+            .inspect_err(|e| tracing::error!("Failed to record click for link {}: {e}", link.id))
+            .ok()
+    } else {
+        None
+    };
+
+    let cache_control = if link.track_conversions {
+        HeaderValue::from_static(TRACKED_CACHE_CONTROL_HEADER_VALUE)
+    } else if let Some(next_change_at) = link.next_change_at {
+        // The redirect must not be re-used once the link changes
+        let seconds = (next_change_at - now()).num_seconds().max(0) as u64;
+        cache_control_for(&state.redirect_cache_control, seconds)
+    } else {
+        state.redirect_cache_control.clone()
+    };
+
     let mut resp = Response::builder()
         .status(StatusCode::TEMPORARY_REDIRECT)
-        .header("Location", forward_query_params(&link, raw_query))
-        .header("Cache-Control", DEFAULT_CACHE_CONTROL_HEADER_VALUE);
+        .header("Location", forward_query_params(&link, raw_query, click_id))
+        .header("Cache-Control", cache_control);
 
     resp = forward_headers(resp, headers);
 
@@ -56,6 +123,84 @@ pub async fn redirect_links(
         .expect("This response should always be constructable"))
 }
 
+/// Find the link with the given ID, counting a redirect from it.
+///
+/// Cached links are used if available, in which case the redirect is counted
+/// in the database later. If the database is unavailable, the last known
+/// version of the link is used, if it was ever cached.
+///
+/// Scheduled changes to the link which are due are made first, so that they
+/// take effect at exactly their scheduled time.
+async fn find_and_count_redirect(state: &AppState, link_id: &str) -> Result<Option<Arc<Link>>> {
+    let increment = async {
+        let link = state
+            .breaker
+            .call(state.store.increment_link_redirect_count(link_id))
+            .await?;
+        match link {
+            Some(link) => state
+                .breaker
+                .call(apply_due_changes(state.store.as_ref(), link))
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    };
+
+    let Some(cache) = state.cache.as_ref() else {
+        return Ok(increment.await?.map(Arc::new));
+    };
+
+    if let Some(link) = cache.get(link_id).await {
+        cache.add_redirect(link_id);
+        return Ok(Some(link));
+    }
+
+    match increment.await {
+        Ok(Some(link)) => Ok(Some(cache.insert(link).await)),
+        Ok(None) => Ok(None),
+        Err(e @ (Error::Internal(_) | Error::DatabaseUnavailable(_))) => {
+            let Some(link) = cache.get_stale(link_id).await else {
+                return Err(e);
+            };
+
+            tracing::warn!("Serving stale link {link_id} while the database is unavailable: {e}");
+            cache.add_redirect(link_id);
+            Ok(Some(link))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Limit how long a redirect can be cached for to the given number of seconds,
+/// by capping the `max-age` and `s-maxage` directives of the configured
+/// `Cache-Control` header. Directives which allow serving it once stale are
+/// removed.
+fn cache_control_for(configured: &HeaderValue, seconds: u64) -> HeaderValue {
+    let Ok(configured) = configured.to_str() else {
+        return configured.clone();
+    };
+
+    let directives: Vec<_> = configured
+        .split(',')
+        .map(str::trim)
+        .filter_map(|directive| {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" | "s-maxage" => {
+                    let max = value.parse::<u64>().map_or(seconds, |v| v.min(seconds));
+                    Some(format!("{name}={max}"))
+                }
+                "stale-while-revalidate" | "stale-if-error" | "immutable" => None,
+                _ => Some(directive.to_string()),
+            }
+        })
+        .collect();
+
+    HeaderValue::from_str(&directives.join(", "))
+        .expect("directives of a valid header value should be a valid header value")
+}
+
 /// Forward certain headers from the request on to the response
 fn forward_headers(mut resp: Builder, request_headers: HeaderMap) -> Builder {
     let existing_headers = resp
@@ -95,9 +240,9 @@ fn forward_headers(mut resp: Builder, request_headers: HeaderMap) -> Builder {
     resp
 }
 
-/// Build the target URL from the base target URL and any received query
-/// parameters
-fn forward_query_params(link: &Link, raw_query: RawQuery) -> String {
+/// Build the target URL from the base target URL, any received query
+/// parameters and the click ID (if any)
+fn forward_query_params(link: &Link, raw_query: RawQuery, click_id: Option<Uuid>) -> String {
     let mut url = Url::parse(&link.target_url).unwrap_or_else(|e| {
         tracing::error!(
             "Invalid URL stored in database for link with ID '{}'. Error: {e}",
@@ -110,6 +255,11 @@ fn forward_query_params(link: &Link, raw_query: RawQuery) -> String {
         url.set_query(Some(q.as_str()));
     }
 
+    if let Some(click_id) = click_id {
+        url.query_pairs_mut()
+            .append_pair(CLICK_ID_PARAM, &click_id.to_string());
+    }
+
     url.to_string()
 }
 
@@ -147,6 +297,25 @@ mod test {
         assert_eq!(resp.headers_ref().unwrap().get(HOST).unwrap(), "host");
     }
 
+    #[test]
+    fn test_cache_control_for() {
+        let configured = HeaderValue::from_static(
+            "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300",
+        );
+        assert_eq!(
+            cache_control_for(&configured, 60),
+            "public, max-age=60, s-maxage=60"
+        );
+        assert_eq!(
+            cache_control_for(&configured, 3600),
+            "public, max-age=300, s-maxage=300"
+        );
+        assert_eq!(
+            cache_control_for(&HeaderValue::from_static("no-cache"), 60),
+            "no-cache"
+        );
+    }
+
     #[test]
     fn test_forward_query_params() {
         let base_url = "https://github.com/".to_string();
@@ -156,17 +325,27 @@ mod test {
         };
 
         assert_eq!(
-            forward_query_params(&link, RawQuery(None)),
+            forward_query_params(&link, RawQuery(None), None),
             base_url.clone()
         );
 
         assert_eq!(
-            forward_query_params(&link, RawQuery(Some("test=value".into()))),
+            forward_query_params(&link, RawQuery(Some("test=value".into())), None),
             format!("{}?{}", base_url, "test=value")
         );
         assert_eq!(
-            forward_query_params(&link, RawQuery(Some("test=value&test2=value".into()))),
+            forward_query_params(&link, RawQuery(Some("test=value&test2=value".into())), None),
             format!("{}?{}", base_url, "test=value&test2=value")
         );
+
+        let click_id = Uuid::nil();
+        assert_eq!(
+            forward_query_params(&link, RawQuery(None), Some(click_id)),
+            format!("{}?{}={}", base_url, CLICK_ID_PARAM, click_id)
+        );
+        assert_eq!(
+            forward_query_params(&link, RawQuery(Some("test=value".into())), Some(click_id)),
+            format!("{}?test=value&{}={}", base_url, CLICK_ID_PARAM, click_id)
+        );
     }
 }
@@ -0,0 +1,86 @@
+use axum::{extract::State, http::{StatusCode, header::{CONTENT_DISPOSITION, CONTENT_TYPE}}, response::{IntoResponse, Response}};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
+
+use crate::{AppState, error::{Error, ErrorResponse, Result}, extractors::{Admin, Query}, redirect_map::{self, RedirectMapFormat}, routes::Route};
+
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct RedirectMapQuery {
+    /// Format of the redirect map. Defaults to an nginx `map` block. HTML
+    /// pages can only be written to a directory, using `curto redirect-map`
+    /// or the `redirectmap` configuration.
+    #[serde(default)]
+    #[param(inline)]
+    pub format: RedirectMapFormat,
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Render all active links, including unlisted links, as a static redirect map \
+        for nginx, Apache, Caddy or Netlify, so that redirects can be served without this \
+        service (admin only). Links which have expired are left out, so the map should be \
+        regenerated whenever a link in it expires.",
+    path = Route::LinksRedirectMap.as_str(),
+    params(RedirectMapQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Redirect map", content(
+            ("text/plain", example = json!(concat!(
+                "# Generated by curto at 2025-07-01T12:00:00, with 1 links\n",
+                "map $uri $curto_redirect {\n",
+                "    default \"\";\n",
+                "    /bmdkw \"https://crates.io/\";\n",
+                "}\n",
+            ))),
+        ), headers(
+            ("Content-Disposition"),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("HTML pages" = (summary="User requested a map which is a directory of pages",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("HTML redirect maps are a directory of pages, which can only be written to a directory".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn redirect_map(
+    _: Admin,
+    State(state): State<AppState>,
+    Query(query): Query<RedirectMapQuery>,
+) -> Result<Response> {
+    tracing::debug!("Generating {:?} redirect map", query.format);
+
+    let map = state
+        .breaker
+        .call(redirect_map::generate_redirect_map(
+            state.store.as_ref(),
+            query.format,
+        ))
+        .await?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", query.format.file_name()),
+            ),
+        ],
+        map,
+    )
+        .into_response())
+}
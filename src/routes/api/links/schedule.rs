@@ -0,0 +1,245 @@
+use axum::{extract::State, http::StatusCode};
+use axum_extra::extract::Host;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::create::validate_target_url;
+use crate::{AppState, database::{ScheduledChange, now}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Json, Path}, routes::Route, schedule::apply_due_changes, time::Expiry};
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleChangeRequest {
+    /// URL that the shortened link should redirect to from the scheduled time.
+    pub target_url: String,
+    /// Time at which the change should be made, given either as an RFC 3339
+    /// timestamp like "2025-07-01T12:00:00+02:00", or as a duration from now
+    /// like "7d" or "PT12H".
+    pub scheduled_at: Expiry,
+}
+
+/// Write the current version of a link through to the cache, after the
+/// changes scheduled for it changed.
+async fn write_through(state: &AppState, link_id: &str) -> Result<()> {
+    if let Some(cache) = state.cache.as_ref()
+        && let Some(link) = state.breaker.call(state.store.get_link(link_id)).await?
+    {
+        cache.insert(link).await;
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Get the changes scheduled for a specific link by the given ID (admin only), \
+        which haven't been made yet, earliest first.",
+    path = Route::LinkSchedule.as_str(),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Successfully fetched the link's scheduled changes", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Link switching target once an event is over", value = json!([
+                    ScheduledChange {
+                        id: 1,
+                        link_id: "abc".into(),
+                        target_url: "https://www.youtube.com/@RustVideos".into(),
+                        scheduled_at: DateTime::<Utc>::default(),
+                        created_at: DateTime::<Utc>::default(),
+                    },
+                ])))
+            )),
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn link_schedule(
+    _: Admin,
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Vec<ScheduledChange>>> {
+    let link = state
+        .breaker
+        .call(state.store.get_link(&link_id))
+        .await?
+        .ok_or_else(|| Error::LinkNotFound(link_id.clone()))?;
+
+    // Changes which are due are made first, so that they aren't listed
+    state
+        .breaker
+        .call(apply_due_changes(state.store.as_ref(), link))
+        .await?;
+
+    let changes = state
+        .breaker
+        .call(state.store.get_scheduled_changes(&link_id))
+        .await?;
+
+    Ok(Json(changes))
+}
+
+#[utoipa::path(
+    post,
+    tags = [ "links" ],
+    description = "Schedule a change to the target URL of a specific link by the given ID (admin \
+        only). The link redirects to the new target URL from exactly the scheduled time, and \
+        redirects are only cached by clients until then. The change is recorded in the link's \
+        history once it is made.",
+    path = Route::LinkSchedule.as_str(),
+    security(("admin_token" = [])),
+    request_body = ScheduleChangeRequest,
+    responses(
+        (status = 201, description = "Change scheduled successfully", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Change scheduled", value = json!(
+                    ScheduledChange {
+                        id: 1,
+                        link_id: "abc".into(),
+                        target_url: "https://www.youtube.com/@RustVideos".into(),
+                        scheduled_at: DateTime::<Utc>::default(),
+                        created_at: DateTime::<Utc>::default(),
+                    }
+                )))
+            )),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Malformed URL" = (summary="User provided a malformed URL",
+                    value=json!(ErrorResponse::from(Error::MalformedURL("hppts://googlecom".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 409, description = "Link is immutable", content(
+            ("application/json", examples(
+                ("Link immutable" = (summary="The link's target URL and expiration time can't be changed",
+                    value=json!(ErrorResponse::from(Error::LinkImmutable("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 422, description = "Request parameter(s) invalid", content(
+            ("application/json", examples(
+                ("Time in the past" = (summary="User provided a time which has already passed",
+                    value=json!(ErrorResponse::from(Error::ScheduledChangeTimeNotValid(DateTime::<Utc>::default()))))),
+                ("URL without host" = (summary="User provided a URL which does not have a host",
+                    value=json!(ErrorResponse::from(Error::URLWithoutHost("/path/to/file".to_string()))))),
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn schedule_change(
+    _: Admin,
+    State(state): State<AppState>,
+    Host(host): Host,
+    Path(link_id): Path<String>,
+    Json(request): Json<ScheduleChangeRequest>,
+) -> Result<(StatusCode, Json<ScheduledChange>)> {
+    let target_url = validate_target_url(&host, &request.target_url)?;
+    let now = now();
+    let scheduled_at = request.scheduled_at.resolve(now);
+    if scheduled_at <= now {
+        return Err(Error::ScheduledChangeTimeNotValid(scheduled_at));
+    }
+
+    let change = state
+        .breaker
+        .call(
+            state
+                .store
+                .schedule_change(&link_id, target_url.as_str(), scheduled_at),
+        )
+        .await?;
+
+    tracing::debug!(
+        "Scheduled change {} to link with ID {} at {}",
+        change.id,
+        link_id,
+        change.scheduled_at
+    );
+
+    write_through(&state, &link_id).await?;
+
+    Ok((StatusCode::CREATED, Json(change)))
+}
+
+#[utoipa::path(
+    delete,
+    tags = [ "links" ],
+    description = "Cancel a change scheduled for a specific link by the given ID (admin only), \
+        before it is made.",
+    path = Route::LinkScheduledChange.as_str(),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Scheduled change cancelled successfully"),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link or scheduled change not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string()))))),
+                ("Scheduled change not found" = (summary="The link has no scheduled change with the given ID, or it was already made",
+                    value=json!(ErrorResponse::from(Error::ScheduledChangeNotFound("bmdkw".to_string(), 7)))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn cancel_scheduled_change(
+    _: Admin,
+    State(state): State<AppState>,
+    Path((link_id, change_id)): Path<(String, i64)>,
+) -> Result<StatusCode> {
+    state
+        .breaker
+        .call(state.store.cancel_scheduled_change(&link_id, change_id))
+        .await?;
+
+    tracing::debug!(
+        "Cancelled scheduled change {} to link with ID {}",
+        change_id,
+        link_id
+    );
+
+    write_through(&state, &link_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
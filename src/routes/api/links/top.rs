@@ -0,0 +1,113 @@
+use axum::{extract::State, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppState, database::{Link, TopLink}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Json, Query}, routes::Route};
+
+/// Maximum number of links which can be requested from the leaderboard.
+const MAX_LIMIT: i64 = 100;
+
+/// Sliding time window over which redirects are counted.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, ToSchema)]
+pub enum TopLinksWindow {
+    #[serde(rename = "1h")]
+    Hour,
+    #[default]
+    #[serde(rename = "24h")]
+    Day,
+    #[serde(rename = "7d")]
+    Week,
+    #[serde(rename = "30d")]
+    Month,
+}
+
+impl TopLinksWindow {
+    pub fn hours(&self) -> i32 {
+        match self {
+            Self::Hour => 1,
+            Self::Day => 24,
+            Self::Week => 24 * 7,
+            Self::Month => 24 * 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct TopLinksQuery {
+    /// The time window over which redirects are counted. Defaults to "24h".
+    #[serde(default)]
+    #[param(inline)]
+    pub window: TopLinksWindow,
+    /// The maximum number of links to return, between 1 and 100. Defaults to
+    /// 20.
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    20
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "links" ],
+    description = "Get the most redirected-to active links over a sliding time window, showing \
+        which are on legal hold only to admins.",
+    path = Route::LinksTop.as_str(),
+    params(TopLinksQuery),
+    responses(
+        (status = 200, description = "Successfully fetched the most redirected-to links", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Most redirected-to links found", value = json!(
+                    vec![
+                        TopLink {
+                            link: Link::new(None, "https://crates.io/".into()),
+                            window_redirects: 42,
+                        },
+                    ]
+                )))
+            )),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Invalid limit" = (summary="User provided a limit outside of the accepted range",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("limit must be between 1 and 100".to_string())))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn top_links(
+    admin: Option<Admin>,
+    State(state): State<AppState>,
+    Query(query): Query<TopLinksQuery>,
+) -> Result<(StatusCode, Json<Vec<TopLink>>)> {
+    if !(1..=MAX_LIMIT).contains(&query.limit) {
+        return Err(Error::InvalidRequest(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+
+    let links = state
+        .read(|s| s.get_top_links(query.window.hours(), query.limit))
+        .await?;
+    let links = if admin.is_some() {
+        links
+    } else {
+        links
+            .into_iter()
+            .map(|l| TopLink {
+                link: l.link.for_public(),
+                ..l
+            })
+            .collect()
+    };
+
+    Ok((StatusCode::OK, Json(links)))
+}
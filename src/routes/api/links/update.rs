@@ -0,0 +1,224 @@
+use axum::{extract::State, http::StatusCode, response::Response};
+use axum_extra::extract::Host;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use utoipa::ToSchema;
+
+use super::{conditional::{conditional_json, link_etag, matches_link_version}, create::validate_target_url};
+use crate::{AppState, database::{Actor, Link, LinkUpdate, now}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Conditions, Json, Path}, routes::Route, time::Expiry};
+
+#[derive(Debug, Default, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateLinkRequest {
+    /// A new target URL for the shortened link. Unchanged if omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_url: Option<String>,
+    /// A new expiration time for the shortened link, given either as an RFC
+    /// 3339 timestamp or as a duration from now like "7d", or null to never
+    /// expire. Unchanged if omitted.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<Expiry>)]
+    pub expires_at: Option<Option<Expiry>>,
+    /// Whether redirects from the shortened link should carry a click ID.
+    /// Unchanged if omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track_conversions: Option<bool>,
+    /// Whether the target URL and expiration time of the shortened link are
+    /// fixed for good, which also prevents it from being deleted. Only links
+    /// which never expire and have no scheduled changes can be made
+    /// immutable, and they can't be made mutable again. Unchanged if omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub immutable: Option<bool>,
+    /// Whether the shortened link is on legal hold, which prevents it from
+    /// being deleted or purged. Unchanged if omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legal_hold: Option<bool>,
+}
+
+/// Custom de-serialiser distinguishing fields which are null from fields which
+/// are omitted (and so default to [`None`]).
+fn deserialize_present<'de, T, D>(deserializer: D) -> core::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[utoipa::path(
+    patch,
+    tags = [ "links" ],
+    description = "Update a specific link by the given ID (admin only). The target URL and \
+        expiration time of immutable links can't be changed. If `If-Match` is provided, the link \
+        is only updated if it is unchanged since the ETag was fetched.",
+    path = Route::LinkGet.as_str(),
+    security(("admin_token" = [])),
+    request_body = UpdateLinkRequest,
+    responses(
+        (status = 200, description = "Shortened link updated successfully", headers(
+            ("ETag"),
+            ("Last-Modified"),
+        ), content(
+            ("application/json", examples(
+                ( "OK" = (summary="Shortened link updated", value = json!(
+                    Link::new(None, "https://crates.io/".into())
+                )))
+            )),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Immutable link expiring" = (summary="User made a link which expires immutable",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("immutable links can't expire".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 409, description = "Link is immutable", content(
+            ("application/json", examples(
+                ("Link immutable" = (summary="The link's target URL and expiration time can't be changed",
+                    value=json!(ErrorResponse::from(Error::LinkImmutable("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 412, description = "Link changed since the ETag in `If-Match` was fetched", content(
+            ("application/json", examples(
+                ("Link changed" = (value=json!(ErrorResponse::from(Error::LinkChanged))))
+            ))
+        )),
+        (status = 422, description = "Request parameter(s) invalid", content(
+            ("application/json", examples(
+                ("URL without host" = (summary="User provided a URL which does not have a host",
+                    value=json!(ErrorResponse::from(Error::URLWithoutHost("/path/to/file".to_string()))))),
+                ("Expiration time invalid" = (summary="User provided an expiration time in the past",
+                    value=json!(ErrorResponse::from(Error::LinkExpirationTimeNotValid(DateTime::<Utc>::default()))))),
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn update_specific_link(
+    _: Admin,
+    State(state): State<AppState>,
+    Host(host): Host,
+    Path(link_id): Path<String>,
+    conditions: Conditions,
+    Json(update): Json<UpdateLinkRequest>,
+) -> Result<Response> {
+    let target_url = update
+        .target_url
+        .map(|url| validate_target_url(&host, &url).map(|url| url.to_string()))
+        .transpose()?;
+
+    let link = state
+        .breaker
+        .call(state.store.update_link(
+            &link_id,
+            LinkUpdate {
+                target_url,
+                expires_at: update.expires_at.map(|e| e.map(|e| e.resolve(now()))),
+                track_conversions: update.track_conversions,
+                immutable: update.immutable,
+                legal_hold: update.legal_hold,
+                changed_by: Actor::Admin,
+                restored_revision: None,
+            },
+            Box::new(|link| conditions.check_match(|etag| matches_link_version(link, etag))),
+        ))
+        .await?;
+
+    tracing::debug!("Updated link with ID {}", link_id);
+
+    // Write through to the cache, as with new links
+    if let Some(cache) = state.cache.as_ref() {
+        cache.insert(link.clone()).await;
+    }
+
+    Ok(conditional_json(
+        &Conditions::default(),
+        link_etag(&link),
+        Some(link.updated_at),
+        link,
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    tags = [ "links" ],
+    description = "Delete a specific link by the given ID (admin only). Links which are immutable \
+        or on legal hold can't be deleted. If `If-Match` is provided, the link is only deleted if \
+        it is unchanged since the ETag was fetched.",
+    path = Route::LinkGet.as_str(),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Shortened link deleted successfully"),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 404, description = "Link matching ID not found", content(
+            ("application/json", examples(
+                ("Link not found" = (summary="No link matching the specified ID could be found",
+                    value=json!(ErrorResponse::from(Error::LinkNotFound("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 409, description = "Link is immutable or on legal hold", content(
+            ("application/json", examples(
+                ("Link immutable" = (summary="Immutable links can't be deleted",
+                    value=json!(ErrorResponse::from(Error::LinkImmutable("bmdkw".to_string()))))),
+                ("Link on legal hold" = (summary="Links on legal hold can't be deleted",
+                    value=json!(ErrorResponse::from(Error::LinkOnLegalHold("bmdkw".to_string())))))
+            ))
+        )),
+        (status = 412, description = "Link changed since the ETag in `If-Match` was fetched", content(
+            ("application/json", examples(
+                ("Link changed" = (value=json!(ErrorResponse::from(Error::LinkChanged))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+pub async fn delete_specific_link(
+    _: Admin,
+    State(state): State<AppState>,
+    Path(link_id): Path<String>,
+    conditions: Conditions,
+) -> Result<StatusCode> {
+    state
+        .breaker
+        .call(state.store.delete_link(
+            &link_id,
+            Box::new(|link| conditions.check_match(|etag| matches_link_version(link, etag))),
+        ))
+        .await?;
+
+    tracing::debug!("Deleted link with ID {}", link_id);
+
+    if let Some(cache) = state.cache.as_ref() {
+        cache.remove(&link_id).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,19 +1,28 @@
+use std::sync::atomic::Ordering;
+
 use axum::{extract::State, http};
+use serde::{Deserialize, Serialize};
+use utoipa::IntoParams;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
-use crate::{AppState, routes::Route};
+use crate::{AppState, database::{DailyCount, InstanceStats}, error::{Error, ErrorResponse, Result}, extractors::{Admin, Json, Query}, routes::Route};
+
+/// Maximum number of days which link creation counts can be requested for.
+const MAX_STATS_DAYS: i32 = 366;
 
 pub fn routes() -> OpenApiRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes!(handle_health))
+        .routes(routes!(handle_ready))
         .routes(routes!(handle_metrics))
+        .routes(routes!(handle_stats))
 }
 
 #[utoipa::path(
     get,
     tags = [ "misc" ],
     path = Route::Health.as_str(),
-    description="Simple API health check",
+    description="Simple API health check, which succeeds as long as the API is running",
     responses(
         (status = 200, description = "Health check OK", content(
             ("text/plain", examples(
@@ -26,6 +35,32 @@ async fn handle_health() -> (http::StatusCode, String) {
     (http::StatusCode::OK, "OK".into())
 }
 
+#[utoipa::path(
+    get,
+    tags = [ "misc" ],
+    path = Route::Ready.as_str(),
+    description="API readiness check, which only succeeds once the database is usable after startup",
+    responses(
+        (status = 200, description = "API ready", content(
+            ("text/plain", examples(
+                ( "OK" = (summary="API is ready to serve requests", value = json!("OK") )),
+            ))
+        )),
+        (status = 503, description = "API not ready", content(
+            ("text/plain", examples(
+                ( "Not ready" = (summary="API is still connecting to the database", value = json!("Not ready") )),
+            ))
+        )),
+    )
+)]
+async fn handle_ready(State(state): State<AppState>) -> (http::StatusCode, String) {
+    if state.ready.load(Ordering::Relaxed) {
+        (http::StatusCode::OK, "OK".into())
+    } else {
+        (http::StatusCode::SERVICE_UNAVAILABLE, "Not ready".into())
+    }
+}
+
 #[utoipa::path(
     get,
     tags = [ "misc" ],
@@ -47,3 +82,80 @@ axum_http_requests_total{method="GET",status="200",endpoint="/api/v0/health"} 1
 async fn handle_metrics(State(state): State<AppState>) -> (http::StatusCode, String) {
     (http::StatusCode::OK, state.metric_handle.clone().render())
 }
+
+#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsQuery {
+    /// The number of days, including today, to count created links for.
+    /// Between 1 and 366, and defaults to 30.
+    #[serde(default = "default_stats_days")]
+    pub days: i32,
+}
+
+fn default_stats_days() -> i32 {
+    30
+}
+
+#[utoipa::path(
+    get,
+    tags = [ "misc" ],
+    path = Route::Stats.as_str(),
+    description="Summary statistics about the links stored by this instance (admin only)",
+    params(StatsQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Summary statistics found", content(
+            ("application/json", examples(
+                ( "OK" = (summary="Summary statistics found", value = json!(
+                    InstanceStats {
+                        total_links: 4,
+                        active_links: 3,
+                        expired_links: 1,
+                        custom_id_links: 1,
+                        generated_id_links: 3,
+                        custom_id_ratio: 0.25,
+                        total_redirects: 120,
+                        redirects_last_day: 12,
+                        redirects_last_week: 80,
+                        links_created_per_day: vec![DailyCount {
+                            date: chrono::NaiveDate::from_ymd_opt(2025, 6, 3).unwrap(),
+                            count: 4,
+                        }],
+                    }
+                )))
+            )),
+        )),
+        (status = 400, description = "Bad request", content(
+            ("application/json", examples(
+                ("Invalid days" = (summary="User provided a number of days outside of the accepted range",
+                    value=json!(ErrorResponse::from(Error::InvalidRequest("days must be between 1 and 366".to_string())))))
+            ))
+        )),
+        (status = 401, description = "Missing or invalid admin credentials", content(
+            ("application/json", examples(
+                ("Unauthorized" = (value=json!(ErrorResponse::from(Error::Unauthorized))))
+            ))
+        )),
+        (status = 500, description = "Internal server error", content(
+            ("application/json", examples(
+                ("Internal server error" =
+                    (value=json!(ErrorResponse::from(Error::Internal(String::new())))))
+            ))
+        )),
+    )
+)]
+async fn handle_stats(
+    _: Admin,
+    State(state): State<AppState>,
+    Query(query): Query<StatsQuery>,
+) -> Result<(http::StatusCode, Json<InstanceStats>)> {
+    if !(1..=MAX_STATS_DAYS).contains(&query.days) {
+        return Err(Error::InvalidRequest(format!(
+            "days must be between 1 and {MAX_STATS_DAYS}"
+        )));
+    }
+
+    let stats = state.read(|s| s.get_instance_stats(query.days)).await?;
+
+    Ok((http::StatusCode::OK, Json(stats)))
+}
@@ -1,3 +1,5 @@
+pub mod backup;
+pub mod conversions;
 pub mod docs;
 pub mod links;
 pub mod misc;
@@ -4,22 +4,54 @@ use strum_macros::EnumIter;
 #[derive(Debug, EnumIter)]
 pub enum Route {
     Health,
+    Ready,
     Metrics,
+    Stats,
+    Events,
+    Backup,
+    Conversions,
+    ConversionPixel,
     Docs,
     LinkRedirect,
     Links,
+    LinksTop,
+    LinksExport,
+    LinksImport,
+    LinksRedirectMap,
     LinkGet,
+    LinkEvents,
+    LinkConversions,
+    LinkHistory,
+    LinkRollback,
+    LinkSchedule,
+    LinkScheduledChange,
 }
 
 impl Route {
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Health => "/health",
+            Self::Ready => "/health/ready",
             Self::Metrics => "/metrics",
+            Self::Stats => "/stats",
+            Self::Events => "/events",
+            Self::Backup => "/backup",
+            Self::Conversions => "/conversions",
+            Self::ConversionPixel => "/conversions/pixel.gif",
             Self::Docs => "/docs",
             Self::LinkRedirect => "/{link_id}",
             Self::Links => "/links",
+            Self::LinksTop => "/links/top",
+            Self::LinksExport => "/links/export",
+            Self::LinksImport => "/links/import",
+            Self::LinksRedirectMap => "/links/redirect-map",
             Self::LinkGet => "/links/{link_id}",
+            Self::LinkEvents => "/links/{link_id}/events",
+            Self::LinkConversions => "/links/{link_id}/conversions",
+            Self::LinkHistory => "/links/{link_id}/history",
+            Self::LinkRollback => "/links/{link_id}/rollback",
+            Self::LinkSchedule => "/links/{link_id}/schedule",
+            Self::LinkScheduledChange => "/links/{link_id}/schedule/{change_id}",
         }
     }
 }
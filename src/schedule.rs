@@ -0,0 +1,114 @@
+//! Scheduled changes to the target URLs of links, which are made at exactly
+//! their scheduled time.
+
+use std::{sync::Arc, time::Duration};
+
+use axum_prometheus::metrics::counter;
+
+use crate::{database::{Link, LinkStore, now}, error::Result};
+
+/// Make any scheduled changes to a link which are due, returning its current
+/// version.
+pub async fn apply_due_changes(store: &dyn LinkStore, link: Link) -> Result<Link> {
+    if link.next_change_at.is_none_or(|t| t > now()) {
+        return Ok(link);
+    }
+
+    let changed = store.apply_scheduled_changes(Some(&link.id)).await?;
+    counter!("schedule.changes_applied").increment(changed.len() as u64);
+    match changed.into_iter().next() {
+        Some(link) => Ok(link),
+        // Another request or instance made the changes first
+        None => Ok(store.get_link(&link.id).await?.unwrap_or(link)),
+    }
+}
+
+/// Make scheduled changes to all links periodically, as they become due, every
+/// `interval`.
+pub fn spawn_schedule_job(store: Arc<dyn LinkStore>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if store.is_closed() {
+                break;
+            }
+
+            match store.apply_scheduled_changes(None).await {
+                Ok(links) if links.is_empty() => {}
+                Ok(links) => {
+                    counter!("schedule.changes_applied").increment(links.len() as u64);
+                    tracing::info!(
+                        "Made scheduled changes to {} links: {}",
+                        links.len(),
+                        links
+                            .iter()
+                            .map(|l| l.id.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                }
+                Err(e) => {
+                    counter!("schedule.failed").increment(1);
+                    tracing::error!("Failed to make scheduled changes: {e}");
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeDelta;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::database::{Actor, MemoryStore};
+
+    #[tokio::test]
+    async fn test_apply_due_changes() {
+        let store = MemoryStore::default();
+        let link = Link::new(Some("abc".into()), "https://crates.io/".into());
+        store.insert_link(&link, Actor::Admin).await.unwrap();
+
+        // Changes which aren't due yet are left alone
+        let later = store
+            .schedule_change("abc", "https://docs.rs/", now() + TimeDelta::hours(1))
+            .await
+            .unwrap();
+        let link = store.get_link("abc").await.unwrap().unwrap();
+        assert_eq!(link.next_change_at, Some(later.scheduled_at));
+        let link = apply_due_changes(&store, link).await.unwrap();
+        assert_eq!(link.target_url, "https://crates.io/");
+
+        // Only the latest of several due changes is made
+        store
+            .schedule_change(
+                "abc",
+                "https://blog.rust-lang.org/",
+                now() - TimeDelta::minutes(2),
+            )
+            .await
+            .unwrap();
+        store
+            .schedule_change(
+                "abc",
+                "https://rust-lang.org/",
+                now() - TimeDelta::minutes(1),
+            )
+            .await
+            .unwrap();
+        let link = store.get_link("abc").await.unwrap().unwrap();
+        let link = apply_due_changes(&store, link).await.unwrap();
+        assert_eq!(link.target_url, "https://rust-lang.org/");
+
+        assert_eq!(
+            store.get_scheduled_changes("abc").await.unwrap(),
+            vec![later.clone()]
+        );
+        assert_eq!(link.next_change_at, Some(later.scheduled_at));
+
+        let revisions = store.get_link_revisions("abc").await.unwrap();
+        assert_eq!(revisions.last().unwrap().changed_by, Actor::Schedule);
+    }
+}
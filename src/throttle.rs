@@ -0,0 +1,109 @@
+use std::{net::IpAddr, sync::{Arc, atomic::{AtomicU32, Ordering}}, time::Duration};
+
+use axum_prometheus::metrics::counter;
+use moka::future::Cache;
+
+use crate::{config::AppConfig, error::{Error, Result}};
+
+/// Maximum number of clients whose redirects to unknown links are counted.
+const MAX_TRACKED_CLIENTS: u64 = 100_000;
+
+/// Throttles clients which make many redirects to links which could not be
+/// found, as they are likely to be guessing link IDs.
+///
+/// Redirects to unknown links are counted per client over a fixed window,
+/// starting with the first one. Once the limit is reached, all redirects from
+/// the client are refused until the window ends.
+#[derive(Debug, Clone)]
+pub struct NotFoundThrottle {
+    clients: Cache<IpAddr, Arc<AtomicU32>>,
+    limit: u32,
+    window: Duration,
+}
+
+impl NotFoundThrottle {
+    /// Create a throttle from the configuration, or [`None`] if throttling is
+    /// disabled.
+    pub fn new(config: &AppConfig) -> Option<Self> {
+        if config.enumerationlimit == 0 {
+            return None;
+        }
+
+        let window = Duration::from_secs(config.enumerationwindowseconds);
+        Some(Self {
+            clients: Cache::builder()
+                .max_capacity(MAX_TRACKED_CLIENTS)
+                .time_to_live(window)
+                .build(),
+            limit: config.enumerationlimit,
+            window,
+        })
+    }
+
+    /// Refuse requests from the client if it has reached the limit.
+    pub async fn check(&self, client: IpAddr) -> Result<()> {
+        let Some(count) = self.clients.get(&client).await else {
+            return Ok(());
+        };
+
+        if count.load(Ordering::Relaxed) >= self.limit {
+            counter!("links.enumeration_throttled").increment(1);
+            return Err(Error::TooManyNotFound(self.window.as_secs()));
+        }
+
+        Ok(())
+    }
+
+    /// Count a redirect from the client to a link which could not be found.
+    pub async fn record_not_found(&self, client: IpAddr) {
+        let count = self
+            .clients
+            .get_with(client, async { Arc::new(AtomicU32::new(0)) })
+            .await;
+
+        if count.fetch_add(1, Ordering::Relaxed) + 1 == self.limit {
+            counter!("links.enumeration_detected").increment(1);
+            tracing::warn!(
+                "Throttling client {client} after {} redirects to unknown links",
+                self.limit
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_not_found_throttle() {
+        let config = AppConfig {
+            enumerationlimit: 3,
+            ..Default::default()
+        };
+        let throttle = NotFoundThrottle::new(&config).unwrap();
+        let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        for _ in 0..3 {
+            assert!(throttle.check(client).await.is_ok());
+            throttle.record_not_found(client).await;
+        }
+        assert!(matches!(
+            throttle.check(client).await,
+            Err(Error::TooManyNotFound(60))
+        ));
+
+        // Other clients are unaffected
+        assert!(throttle.check(other).await.is_ok());
+
+        // Disabled with a limit of 0
+        let config = AppConfig {
+            enumerationlimit: 0,
+            ..Default::default()
+        };
+        assert!(NotFoundThrottle::new(&config).is_none());
+    }
+}
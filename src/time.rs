@@ -0,0 +1,264 @@
+//! Parsing of times given in requests, either as timestamps or relative to
+//! the current time.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
+use utoipa::{PartialSchema, ToSchema, openapi::{KnownFormat, ObjectBuilder, RefOr, Schema, SchemaFormat, Type}};
+
+/// Parse an RFC 3339 timestamp, e.g. "2025-07-01T12:00:00+02:00".
+///
+/// Timestamps without an offset, as accepted before times were stored with
+/// their time zone, are taken to be in UTC.
+pub fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, String> {
+    let s = s.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.to_utc());
+    }
+
+    NaiveDateTime::from_str(s)
+        .map(|time| time.and_utc())
+        .map_err(|_| {
+            format!("invalid timestamp '{s}', expected RFC 3339 like \"2025-07-01T12:00:00+02:00\"")
+        })
+}
+
+/// Parse a positive duration, either as a number with a unit of `s`, `m`,
+/// `h`, `d` or `w` (e.g. "7d"), or in ISO 8601 (e.g. "PT12H" or "P1DT6H").
+///
+/// Years and months aren't accepted, as their length varies.
+pub fn parse_duration(s: &str) -> Result<TimeDelta, String> {
+    let s = s.trim();
+    let invalid = || {
+        format!(
+            "invalid duration '{s}', expected a number with a unit of s, m, h, d or w like \
+             \"7d\", or ISO 8601 like \"PT12H\""
+        )
+    };
+
+    let duration = if let Some(iso) = s.strip_prefix(['P', 'p']) {
+        parse_iso_duration(iso).ok_or_else(invalid)?
+    } else {
+        let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let (n, unit) = s.split_at(split);
+        let n: i64 = n.parse().map_err(|_| invalid())?;
+        unit_duration(unit, n).ok_or_else(invalid)?
+    };
+
+    if duration <= TimeDelta::zero() {
+        return Err(format!("duration '{s}' must be positive"));
+    }
+
+    Ok(duration)
+}
+
+/// A number of the given unit, if it can be represented.
+fn unit_duration(unit: &str, n: i64) -> Option<TimeDelta> {
+    match unit {
+        "s" | "S" => TimeDelta::try_seconds(n),
+        "m" | "M" => TimeDelta::try_minutes(n),
+        "h" | "H" => TimeDelta::try_hours(n),
+        "d" | "D" => TimeDelta::try_days(n),
+        "w" | "W" => TimeDelta::try_weeks(n),
+        _ => None,
+    }
+}
+
+/// Parse an ISO 8601 duration, after the leading `P`.
+fn parse_iso_duration(s: &str) -> Option<TimeDelta> {
+    let (date, time) = match s.split_once(['T', 't']) {
+        Some((date, time)) if !time.is_empty() => (date, Some(time)),
+        Some(_) => return None,
+        None => (s, None),
+    };
+
+    let mut total = TimeDelta::zero();
+    let mut any = false;
+    for (part, units) in [(date, "WD"), (time.unwrap_or_default(), "HMS")] {
+        let mut rest = part;
+        let mut allowed = units;
+        while !rest.is_empty() {
+            let split = rest.find(|c: char| !c.is_ascii_digit())?;
+            let (n, tail) = rest.split_at(split);
+            let unit = tail.chars().next()?.to_ascii_uppercase();
+            // Units must be in order, and each given at most once
+            let position = allowed.find(unit)?;
+            allowed = &allowed[position + 1..];
+
+            total = total.checked_add(&unit_duration(&unit.to_string(), n.parse().ok()?)?)?;
+            rest = &tail[1..];
+            any = true;
+        }
+    }
+
+    any.then_some(total)
+}
+
+/// An expiration time, either as a timestamp or relative to when it is
+/// given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    At(DateTime<Utc>),
+    After(TimeDelta),
+}
+
+impl Expiry {
+    /// The time this expires at, if given at the time `now`.
+    pub fn resolve(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::At(time) => time,
+            Self::After(duration) => now
+                .checked_add_signed(duration)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        }
+    }
+}
+
+impl From<DateTime<Utc>> for Expiry {
+    fn from(time: DateTime<Utc>) -> Self {
+        Self::At(time)
+    }
+}
+
+impl FromStr for Expiry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Unlike timestamps, durations never contain dashes or colons
+        let s = s.trim();
+        if s.starts_with(['P', 'p']) || !s.contains(['-', ':']) {
+            parse_duration(s).map(Self::After)
+        } else {
+            parse_timestamp(s).map(Self::At)
+        }
+    }
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::At(time) => write!(f, "{}", time.to_rfc3339()),
+            Self::After(duration) => write!(f, "PT{}S", duration.num_seconds()),
+        }
+    }
+}
+
+impl Serialize for Expiry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expiry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+impl PartialSchema for Expiry {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime)))
+            .description(Some(
+                "An RFC 3339 timestamp with an offset, e.g. \"2025-07-01T12:00:00+02:00\", or a \
+                 duration from now, e.g. \"7d\" or \"PT12H\"",
+            ))
+            .examples(["2025-07-01T12:00:00+02:00", "7d", "PT12H"])
+            .into()
+    }
+}
+
+impl ToSchema for Expiry {}
+
+/// Custom de-serialiser for timestamps, accepting RFC 3339 with or without an
+/// offset, so that times written before they had one can still be read.
+pub fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_timestamp(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// Custom de-serialiser for optional timestamps, accepting RFC 3339 with or
+/// without an offset.
+pub fn deserialize_optional_timestamp<'de, D>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_timestamp(&s).map_err(D::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let utc = Utc.with_ymd_and_hms(2025, 7, 1, 10, 0, 0).unwrap();
+        for s in [
+            "2025-07-01T12:00:00+02:00",
+            "2025-07-01T10:00:00Z",
+            "2025-07-01T05:00:00-05:00",
+            "2025-07-01T10:00:00",
+            "2025-07-01T10:00:00.000",
+        ] {
+            assert_eq!(parse_timestamp(s), Ok(utc), "{s}");
+        }
+        assert!(parse_timestamp("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        for (s, expected) in [
+            ("30s", TimeDelta::seconds(30)),
+            ("15m", TimeDelta::minutes(15)),
+            ("12h", TimeDelta::hours(12)),
+            ("7d", TimeDelta::days(7)),
+            ("2w", TimeDelta::weeks(2)),
+            ("PT12H", TimeDelta::hours(12)),
+            ("P7D", TimeDelta::days(7)),
+            ("P1W", TimeDelta::weeks(1)),
+            ("P1DT6H30M", TimeDelta::hours(30) + TimeDelta::minutes(30)),
+            ("PT90S", TimeDelta::seconds(90)),
+            ("pt1h", TimeDelta::hours(1)),
+        ] {
+            assert_eq!(parse_duration(s), Ok(expected), "{s}");
+        }
+        for s in [
+            "", "7", "d", "7y", "-7d", "0d", "P", "PT", "P1Y", "P1M", "PT1H1H", "PT1M1H", "P1H",
+            "PT1D", "7 days",
+        ] {
+            assert!(parse_duration(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let now = Utc.with_ymd_and_hms(2025, 7, 1, 10, 0, 0).unwrap();
+        for (s, expected) in [
+            ("7d", now + TimeDelta::days(7)),
+            ("PT12H", now + TimeDelta::hours(12)),
+            ("2025-07-01T12:00:00+02:00", now),
+            ("2025-07-01T10:00:00", now),
+        ] {
+            let expiry: Expiry = serde_json::from_value(serde_json::json!(s)).unwrap();
+            assert_eq!(expiry.resolve(now), expected, "{s}");
+
+            // Expiries can be sent on as they were given
+            let round_trip: Expiry =
+                serde_json::from_str(&serde_json::to_string(&expiry).unwrap()).unwrap();
+            assert_eq!(round_trip, expiry);
+        }
+        assert!(serde_json::from_value::<Expiry>(serde_json::json!("soon")).is_err());
+    }
+}
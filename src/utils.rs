@@ -24,8 +24,3 @@ pub async fn shutdown_signal() {
         _ = terminate => {},
     }
 }
-
-/// Returns the default timeout duration for database actions.
-pub fn get_default_db_timeout() -> tokio::time::Duration {
-    tokio::time::Duration::from_millis(400)
-}
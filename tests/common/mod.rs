@@ -1,13 +1,133 @@
-use std::net::SocketAddr;
+#![allow(dead_code)]
 
+use std::{net::SocketAddr, path::PathBuf};
+
+use axum::{Router, extract::connect_info::IntoMakeServiceWithConnectInfo};
 use axum_test::TestServer;
-use curto::{config::{AppConfig, DbConfig}, get_app};
+use curto::{App, config::{AppConfig, CacheConfig, Config, DbConfig, PurgeConfig, RedirectMapConfig}, get_app};
 use testcontainers_modules::{postgres::{self, Postgres}, testcontainers::{ContainerAsync, runners::AsyncRunner}};
 use url::Url;
+use uuid::Uuid;
+
+/// Admin token configured for the test server
+pub const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Storage backends which tests can be run against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+    Memory,
+}
+
+/// Database used by a test server, which is removed once dropped
+pub enum TestDb {
+    Postgres(Box<ContainerAsync<Postgres>>),
+    Sqlite(PathBuf),
+    Memory,
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        if let Self::Sqlite(path) = self {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut file = path.clone().into_os_string();
+                file.push(suffix);
+                _ = std::fs::remove_file(file);
+            }
+        }
+    }
+}
 
 /// Get a test server using the router that will be used for the actual server
 pub async fn get_server() -> (ContainerAsync<Postgres>, TestServer) {
-    // Setup test DB
+    get_server_with_config(|_| {}).await
+}
+
+/// Get a test server, with changes made to the default test configuration
+pub async fn get_server_with_config(
+    configure: impl FnOnce(&mut Config),
+) -> (ContainerAsync<Postgres>, TestServer) {
+    let (container, url) = start_postgres().await;
+
+    let mut config = get_config(url);
+    configure(&mut config);
+
+    (container, serve(config).await)
+}
+
+/// Get a test server storing links with the given backend, with changes made
+/// to the default test configuration
+pub async fn get_backend_server(
+    backend: Backend,
+    configure: impl FnOnce(&mut Config),
+) -> (TestDb, TestServer) {
+    let (db, config) = backend_config(backend, configure).await;
+
+    (db, serve(config).await)
+}
+
+/// Get a test server like [`get_backend_server`], but served over HTTP on a
+/// random local port, for tests which need to read streamed responses
+pub async fn get_backend_http_server(
+    backend: Backend,
+    configure: impl FnOnce(&mut Config),
+) -> (TestDb, TestServer) {
+    let (db, config) = backend_config(backend, configure).await;
+    let app = ready_app(config).await;
+
+    (
+        db,
+        TestServer::builder().http_transport().build(app).unwrap(),
+    )
+}
+
+/// Get the application along with a test server serving it, with changes
+/// made to the default test configuration, for tests which shut it down
+pub async fn get_app_with_config(
+    configure: impl FnOnce(&mut Config),
+) -> (ContainerAsync<Postgres>, App, TestServer) {
+    let (container, url) = start_postgres().await;
+
+    let mut config = get_config(url);
+    configure(&mut config);
+
+    let mut app = get_app(config).expect("Failed to build app");
+    app.ready().await.expect("Failed to start app");
+    let server = TestServer::new(
+        app.router
+            .clone()
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .unwrap();
+
+    (container, app, server)
+}
+
+/// Configuration for a test server storing links with the given backend, with
+/// changes made to the default test configuration
+async fn backend_config(backend: Backend, configure: impl FnOnce(&mut Config)) -> (TestDb, Config) {
+    let (db, url) = match backend {
+        Backend::Postgres => {
+            let (container, url) = start_postgres().await;
+            (TestDb::Postgres(Box::new(container)), url)
+        }
+        Backend::Sqlite => {
+            let path = std::env::temp_dir().join(format!("curto-test-{}.db", Uuid::new_v4()));
+            let url = Url::parse(&format!("sqlite://{}", path.display())).unwrap();
+            (TestDb::Sqlite(path), url)
+        }
+        Backend::Memory => (TestDb::Memory, Url::parse("memory://").unwrap()),
+    };
+
+    let mut config = get_config(url);
+    configure(&mut config);
+
+    (db, config)
+}
+
+/// Start a PostgreSQL container, returning it along with its URL
+async fn start_postgres() -> (ContainerAsync<Postgres>, Url) {
     let container = postgres::Postgres::default()
         .with_password("postgres")
         .with_user("postgres")
@@ -20,25 +140,75 @@ pub async fn get_server() -> (ContainerAsync<Postgres>, TestServer) {
         .await
         .expect("could not get container port");
 
-    // Test config
-    let config = curto::config::Config {
+    let url = Url::parse(&format!(
+        "postgresql://0.0.0.0:{port}/curto-db?user=postgres&password=postgres"
+    ))
+    .unwrap();
+
+    (container, url)
+}
+
+/// Stop the PostgreSQL container, so that the database can no longer be
+/// reached
+pub async fn stop_postgres(container: &ContainerAsync<Postgres>) {
+    container.stop().await.expect("Failed to stop test db");
+}
+
+/// Serve the application with the given configuration, once it is ready
+async fn serve(config: Config) -> TestServer {
+    TestServer::new(ready_app(config).await).unwrap()
+}
+
+/// Build the application with the given configuration, waiting until it is
+/// ready
+async fn ready_app(config: Config) -> IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    get_app(config)
+        .expect("Failed to build app")
+        .wait_until_ready()
+        .await
+        .expect("Failed to start app")
+        .into_make_service_with_connect_info::<SocketAddr>()
+}
+
+/// Default test configuration, using the database with the given URL
+fn get_config(url: Url) -> Config {
+    Config {
         application: AppConfig {
             shouldratelimit: false,
+            admintoken: Some(ADMIN_TOKEN.into()),
             ..Default::default()
         },
         database: DbConfig {
-            url: Url::parse(&format!(
-                "postgresql://0.0.0.0:{port}/curto-db?user=postgres&password=postgres"
-            ))
-            .unwrap(),
+            url,
             requiressl: false,
+            breakerthreshold: 5,
+            breakeropenseconds: 10,
+            minconnections: 2,
+            maxconnections: 20,
+            timeoutms: 400,
+            replicaurls: Vec::new(),
+            replicahealthcheckseconds: 5,
+            startuptimeoutseconds: 60,
         },
-    };
+        // Redirect counts are only written to the database periodically when
+        // caching, so it's only enabled for tests which need it
+        cache: CacheConfig {
+            capacity: 0,
+            ..Default::default()
+        },
+        redirectmap: RedirectMapConfig::default(),
+        purge: PurgeConfig::default(),
+    }
+}
 
-    // Setup application
+/// Get a test server whose database can't be reached, so it never becomes
+/// ready
+pub fn get_unready_server() -> TestServer {
+    let config = get_config(Url::parse("postgresql://127.0.0.1:1/curto-db").unwrap());
     let app = get_app(config)
-        .await
+        .expect("Failed to build app")
+        .router
         .into_make_service_with_connect_info::<SocketAddr>();
 
-    (container, TestServer::new(app).unwrap())
+    TestServer::new(app).unwrap()
 }
@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use axum::http::{StatusCode, header::{LOCATION, RETRY_AFTER}};
+use chrono::TimeDelta;
+use curto::{config::CacheConfig, database::Link, routes::{Route, api::links::create::CreateLinkRequest}, time::Expiry};
+use pretty_assertions::assert_eq;
+use redis::AsyncCommands;
+use testcontainers_modules::{redis::{REDIS_PORT, Redis}, testcontainers::runners::AsyncRunner};
+
+mod common;
+use common::{get_app_with_config, get_server_with_config, stop_postgres};
+
+#[tokio::test]
+async fn test_cached_redirects() {
+    let (_db_container, server) = get_server_with_config(|c| {
+        c.cache = CacheConfig {
+            capacity: 100,
+            ttlseconds: 60,
+            flushintervalms: 50,
+            ..Default::default()
+        }
+    })
+    .await;
+
+    let link = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+
+    for _ in 0..3 {
+        let response = server.get(&format!("/{}", link.id)).await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.header(LOCATION), "https://crates.io/");
+    }
+
+    // Redirect counts of cached links are eventually written to the database
+    let mut count_redirects = 0;
+    for _ in 0..40 {
+        count_redirects = server
+            .get(&format!("/links/{}", link.id))
+            .await
+            .json::<Link>()
+            .count_redirects;
+        if count_redirects == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(count_redirects, 3);
+
+    server
+        .get("/unknown")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // Cache metrics are exported
+    let metrics = server.get(Route::Metrics.as_str()).await.text();
+    assert!(metrics.contains("cache_hits"));
+    assert!(metrics.contains("cache_misses"));
+}
+
+#[tokio::test]
+async fn test_redirect_counts_flushed_on_shutdown() {
+    let (_db_container, app, server) = get_app_with_config(|c| {
+        c.cache = CacheConfig {
+            capacity: 100,
+            ttlseconds: 60,
+            flushintervalms: 3_600_000,
+            ..Default::default()
+        }
+    })
+    .await;
+
+    let link = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+
+    for _ in 0..3 {
+        let response = server.get(&format!("/{}", link.id)).await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    // New links are cached, so all redirects are buffered
+    let count_redirects = || async {
+        server
+            .get(&format!("/links/{}", link.id))
+            .await
+            .json::<Link>()
+            .count_redirects
+    };
+    assert_eq!(count_redirects().await, 0);
+
+    app.shutdown().await;
+    assert_eq!(count_redirects().await, 3);
+}
+
+#[tokio::test]
+async fn test_cached_redirects_expire() {
+    let (_db_container, server) = get_server_with_config(|c| {
+        c.cache = CacheConfig {
+            capacity: 100,
+            ..Default::default()
+        }
+    })
+    .await;
+
+    let link = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            custom_expires_at: Some(Expiry::After(TimeDelta::seconds(1))),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+
+    server
+        .get(&format!("/{}", link.id))
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // Expired links are not served from the cache
+    server
+        .get(&format!("/{}", link.id))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_database_unavailable() {
+    let (db_container, server) = get_server_with_config(|c| {
+        c.cache = CacheConfig {
+            capacity: 100,
+            ttlseconds: 1,
+            ..Default::default()
+        }
+    })
+    .await;
+
+    let link = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+
+    stop_postgres(&db_container).await;
+
+    // Wait until the link is no longer fresh in the cache
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    // The last known version of cached links is still served
+    let response = server.get(&format!("/{}", link.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+
+    // Requests which need the database fail fast once enough have failed
+    let create = || {
+        server.post(Route::Links.as_str()).json(&CreateLinkRequest {
+            target_url: "https://docs.rs/".into(),
+            ..Default::default()
+        })
+    };
+    let mut response = create().await;
+    for _ in 0..10 {
+        if response.status_code() == StatusCode::SERVICE_UNAVAILABLE {
+            break;
+        }
+        response = create().await;
+    }
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert!(
+        response
+            .header(RETRY_AFTER)
+            .to_str()
+            .unwrap()
+            .parse::<u64>()
+            .is_ok_and(|s| s > 0)
+    );
+}
+
+#[tokio::test]
+async fn test_shared_cache() {
+    let redis_container = Redis::default()
+        .start()
+        .await
+        .expect("Failed to start test redis");
+    let redis_url = format!(
+        "redis://127.0.0.1:{}",
+        redis_container
+            .get_host_port_ipv4(REDIS_PORT)
+            .await
+            .unwrap()
+    );
+    let mut redis = redis::Client::open(redis_url.as_str())
+        .unwrap()
+        .get_multiplexed_async_connection()
+        .await
+        .unwrap();
+
+    let (_db_container, server) = get_server_with_config(|c| {
+        c.cache = CacheConfig {
+            capacity: 100,
+            redisurl: Some(redis_url.parse().unwrap()),
+            ..Default::default()
+        }
+    })
+    .await;
+
+    // New links are written through to the shared cache
+    let link = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+    // Links are stored along with their version
+    let cached: String = redis.get(format!("curto:link:{}", link.id)).await.unwrap();
+    let (version, cached) = cached.split_once(':').unwrap();
+    assert_eq!(
+        version.parse::<i64>().unwrap(),
+        link.updated_at.timestamp_micros()
+    );
+    assert_eq!(
+        serde_json::from_str::<Link>(cached).unwrap().target_url,
+        "https://crates.io/"
+    );
+
+    // Links cached by other instances are used before the database
+    let shared = Link::new(Some("shared".into()), "https://docs.rs/".into());
+    let _: () = redis
+        .set(
+            "curto:link:shared",
+            format!(
+                "{}:{}",
+                shared.updated_at.timestamp_micros(),
+                serde_json::to_string(&shared).unwrap()
+            ),
+        )
+        .await
+        .unwrap();
+    let response = server.get("/shared").await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://docs.rs/");
+}
+
+#[tokio::test]
+async fn test_shared_cache_unavailable() {
+    // Nothing should be listening on this port
+    let (_db_container, server) = get_server_with_config(|c| {
+        c.cache = CacheConfig {
+            capacity: 100,
+            redisurl: Some("redis://127.0.0.1:1".parse().unwrap()),
+            ..Default::default()
+        }
+    })
+    .await;
+
+    let link = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+
+    // Redirects fall back to the database
+    let response = server.get(&format!("/{}", link.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+}
@@ -0,0 +1,140 @@
+use axum::http::{StatusCode, header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION}};
+use curto::{database::{ConversionStats, Link}, routes::{Route, api::links::{create::CreateLinkRequest, redirect::CLICK_ID_PARAM}}};
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use url::Url;
+use uuid::Uuid;
+
+mod common;
+use common::{ADMIN_TOKEN, get_server};
+
+#[tokio::test]
+async fn test_conversions() {
+    let (_db_container, server) = get_server().await;
+
+    // Links only carry a click ID if they track conversions
+    let untracked = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io".into(),
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+    assert!(!untracked.track_conversions);
+    let response = server.get(&format!("/{}", untracked.id)).await;
+    assert!(
+        !response
+            .header(LOCATION)
+            .to_str()
+            .unwrap()
+            .contains(CLICK_ID_PARAM)
+    );
+
+    let tracked = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io".into(),
+            track_conversions: true,
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+    assert!(tracked.track_conversions);
+
+    let response = server
+        .get(&format!("/{}?utm_source=test", tracked.id))
+        .await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(CACHE_CONTROL), "no-store");
+    let location = Url::parse(response.header(LOCATION).to_str().unwrap()).unwrap();
+    assert!(
+        location
+            .query_pairs()
+            .any(|(k, v)| k == "utm_source" && v == "test")
+    );
+    let click_id = location
+        .query_pairs()
+        .find(|(k, _)| k == CLICK_ID_PARAM)
+        .map(|(_, v)| Uuid::parse_str(&v).unwrap())
+        .unwrap();
+
+    // Postback
+    let response = server
+        .post(Route::Conversions.as_str())
+        .json(&json!({ "clickId": click_id, "goal": "signup" }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let response = server
+        .post(Route::Conversions.as_str())
+        .json(&json!({ "clickId": click_id, "goal": "signup" }))
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    // Pixel
+    let response = server
+        .get(Route::ConversionPixel.as_str())
+        .add_query_param("clickId", click_id)
+        .add_query_param("goal", "purchase")
+        .await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.header(CONTENT_TYPE), "image/gif");
+
+    // Invalid conversions
+    server
+        .post(Route::Conversions.as_str())
+        .json(&json!({ "clickId": Uuid::nil(), "goal": "signup" }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .post(Route::Conversions.as_str())
+        .json(&json!({ "clickId": click_id, "goal": "sign up" }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    server
+        .post(Route::Conversions.as_str())
+        .json(&json!({ "clickId": "not-a-uuid", "goal": "signup" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Another tracked click which does not convert
+    server.get(&format!("/{}", tracked.id)).await;
+
+    // Per link
+    let response = server
+        .get(&format!("/links/{}/conversions", tracked.id))
+        .await;
+    response.assert_status_ok();
+    let stats = response.json::<ConversionStats>();
+    assert_eq!(stats.tracked_clicks, 2);
+    assert_eq!(stats.goals.len(), 2);
+    assert!(stats.goals.iter().all(|g| g.conversions == 1));
+    assert!(stats.goals.iter().all(|g| g.conversion_rate == 0.5));
+
+    let response = server
+        .get(&format!("/links/{}/conversions", untracked.id))
+        .await;
+    response.assert_status_ok();
+    let stats = response.json::<ConversionStats>();
+    assert_eq!(stats.tracked_clicks, 0);
+    assert!(stats.goals.is_empty());
+
+    server
+        .get("/links/noid/conversions")
+        .await
+        .assert_status_not_found();
+
+    // Across all links
+    server
+        .get(Route::Conversions.as_str())
+        .await
+        .assert_status_unauthorized();
+    let response = server
+        .get(Route::Conversions.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    let stats = response.json::<ConversionStats>();
+    assert_eq!(stats.tracked_clicks, 2);
+    assert_eq!(stats.goals.len(), 2);
+}
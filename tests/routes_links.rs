@@ -1,28 +1,76 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, time::Duration};
 
-use axum::http::{StatusCode, header::LOCATION};
+use axum::http::{StatusCode, header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION, REFERER, USER_AGENT}};
 use axum_test::TestServer;
-use chrono::{NaiveDateTime, Utc};
-use curto::{database::Link, routes::{Route, api::links::create::CreateLinkRequest}};
+use chrono::{DateTime, SubsecRound, TimeDelta, Utc};
+use curto::{backup::RestoreSummary, database::{Actor, HourlyRedirects, IdStrategy, Link, LinkRevision, RedirectEvent, ScheduledChange, TopLink, UNLISTED_ID_LENGTH, UserAgentClass, schema_version}, import::{ImportOutcome, ImportReport}, purge::PurgeMode, redirect_map::RedirectMapFormat, routes::{Route, api::links::{create::CreateLinkRequest, history::RollbackRequest, schedule::ScheduleChangeRequest, update::UpdateLinkRequest}}, time::Expiry};
 use pretty_assertions::assert_eq;
 
 mod common;
-use common::get_server;
+use common::{ADMIN_TOKEN, Backend, get_backend_http_server, get_backend_server, get_server_with_config, stop_postgres};
 use url::Url;
 
+/// Run each of the given tests against every persistent storage backend
+macro_rules! backend_tests {
+    ($($name:ident),* $(,)?) => {
+        mod postgres {
+            use super::*;
+            $(#[tokio::test]
+            async fn $name() {
+                super::$name(Backend::Postgres).await
+            })*
+        }
+
+        mod sqlite {
+            use super::*;
+            $(#[tokio::test]
+            async fn $name() {
+                super::$name(Backend::Sqlite).await
+            })*
+        }
+    };
+}
+
+backend_tests!(
+    test_create_links,
+    test_expiry_formats,
+    test_get_link,
+    test_id_strategies,
+    test_unlisted_links,
+    test_conditional_requests,
+    test_update_links,
+    test_link_history,
+    test_list_links,
+    test_redirect_links,
+    test_enumeration_throttling,
+    test_spoofed_proxy_headers,
+    test_expired_links_not_found,
+    test_top_links,
+    test_export_links,
+    test_import_links,
+    test_backup_restore,
+    test_redirect_map,
+    test_purge_expired_links,
+    test_link_events,
+    test_scheduled_changes,
+    test_immutable_links,
+    test_legal_hold,
+);
+
 #[inline]
 async fn request_create_link(
     server: &TestServer,
     target_url: impl Display,
     custom_id: Option<String>,
-    custom_expires_at: Option<NaiveDateTime>,
+    custom_expires_at: Option<DateTime<Utc>>,
 ) -> axum_test::TestResponse {
     server
         .post(Route::Links.as_str())
         .json(&CreateLinkRequest {
             target_url: target_url.to_string(),
             custom_id,
-            custom_expires_at,
+            custom_expires_at: custom_expires_at.map(Expiry::from),
+            ..Default::default()
         })
         .await
 }
@@ -33,7 +81,7 @@ async fn assert_create_link(
     server: &TestServer,
     target_url: impl Display,
     custom_id: Option<String>,
-    custom_expires_at: Option<NaiveDateTime>,
+    custom_expires_at: Option<DateTime<Utc>>,
 ) -> Link {
     let response = request_create_link(server, &target_url, custom_id, custom_expires_at).await;
     response.assert_status(StatusCode::CREATED);
@@ -49,9 +97,8 @@ async fn assert_create_link(
     link
 }
 
-#[tokio::test]
-async fn test_create_links() {
-    let (_db_container, server) = get_server().await;
+async fn test_create_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
 
     // SUCCESS
     assert_create_link(&server, "https://crates.io", None, None).await;
@@ -87,7 +134,7 @@ async fn test_create_links() {
         &server,
         "https://crates.io",
         Some("2".into()),
-        Some(NaiveDateTime::from_str("3045-01-01T00:00:00").unwrap()),
+        Some(DateTime::<Utc>::from_str("3045-01-01T00:00:00Z").unwrap()),
     )
     .await;
 
@@ -116,15 +163,78 @@ async fn test_create_links() {
     }
 
     // Invalid Expiration times
-    for invalid in [NaiveDateTime::default(), Utc::now().naive_utc()] {
+    for invalid in [DateTime::<Utc>::default(), Utc::now()] {
         let response = request_create_link(&server, "https://crates.io", None, Some(invalid)).await;
         response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
     }
 }
 
-#[tokio::test]
-async fn test_get_link() {
-    let (_db_container, server) = get_server().await;
+async fn test_expiry_formats(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    // Offsets are converted to UTC, and times are returned in UTC
+    let response = server
+        .post(Route::Links.as_str())
+        .json(&serde_json::json!({
+            "targetUrl": "https://crates.io/",
+            "customExpiresAt": "3045-01-01T12:00:00+02:00",
+        }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let created = response.json::<serde_json::Value>();
+    assert_eq!(created["expiresAt"], "3045-01-01T10:00:00Z");
+    assert!(created["createdAt"].as_str().unwrap().ends_with('Z'));
+
+    let link_route = format!("/links/{}", created["id"].as_str().unwrap());
+    let response = server.get(&link_route).await;
+    response.assert_status_ok();
+    assert_eq!(
+        response.json::<serde_json::Value>()["expiresAt"],
+        "3045-01-01T10:00:00Z"
+    );
+
+    // Durations are relative to the time of the request
+    for (expiry, duration) in [("7d", TimeDelta::days(7)), ("PT12H", TimeDelta::hours(12))] {
+        let before = Utc::now().trunc_subsecs(6);
+        let response = server
+            .post(Route::Links.as_str())
+            .json(&serde_json::json!({
+                "targetUrl": "https://crates.io/",
+                "customExpiresAt": expiry,
+            }))
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        let expires_at = response.json::<Link>().expires_at.unwrap();
+        assert!(expires_at >= before + duration, "{expiry}");
+        assert!(expires_at <= Utc::now() + duration, "{expiry}");
+    }
+
+    let before = Utc::now().trunc_subsecs(6);
+    let response = server
+        .patch(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&serde_json::json!({ "expiresAt": "2w" }))
+        .await;
+    response.assert_status_ok();
+    let expires_at = response.json::<Link>().expires_at.unwrap();
+    assert!(expires_at >= before + TimeDelta::weeks(2));
+    assert!(expires_at <= Utc::now() + TimeDelta::weeks(2));
+
+    // Invalid expiration times
+    for expiry in ["soon", "-7d", "0s", "P1Y", "2020-01-01T00:00:00+00:00"] {
+        let response = server
+            .post(Route::Links.as_str())
+            .json(&serde_json::json!({
+                "targetUrl": "https://crates.io/",
+                "customExpiresAt": expiry,
+            }))
+            .await;
+        assert!(response.status_code().is_client_error(), "{expiry}");
+    }
+}
+
+async fn test_get_link(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
 
     let target_url = Url::parse("https://crates.io/").unwrap();
 
@@ -140,9 +250,513 @@ async fn test_get_link() {
     assert_eq!(link.id, id);
 }
 
+async fn test_id_strategies(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |c| {
+        c.application.idstrategy = IdStrategy::Words;
+    })
+    .await;
+
+    let create = async |target_url: &str, id_strategy: Option<IdStrategy>| {
+        let response = server
+            .post(Route::Links.as_str())
+            .json(&CreateLinkRequest {
+                target_url: target_url.into(),
+                id_strategy,
+                ..Default::default()
+            })
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        response.json::<Link>()
+    };
+
+    // The configured strategy is used by default
+    let link = create("https://crates.io/", None).await;
+    assert_eq!(
+        link.id.chars().filter(char::is_ascii_uppercase).count(),
+        2,
+        "{}",
+        link.id
+    );
+    assert!(Link::validate_id(&link.id), "{}", link.id);
+    assert!(!link.is_custom_id);
+    let response = server.get(&format!("/{}", link.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+
+    // Sequential IDs are all distinct
+    let first = create("https://crates.io/", Some(IdStrategy::Sequential)).await;
+    let second = create("https://crates.io/", Some(IdStrategy::Sequential)).await;
+    assert_ne!(first.id, second.id);
+    assert!(first.id.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    // Hash IDs return the existing link for the same URL
+    let first = create("https://docs.rs/", Some(IdStrategy::Hash)).await;
+    let second = create("https://docs.rs/", Some(IdStrategy::Hash)).await;
+    let other = create("https://blessed.rs/", Some(IdStrategy::Hash)).await;
+    assert_eq!(first.id, second.id);
+    assert_eq!(first.created_at, second.created_at);
+    assert_ne!(first.id, other.id);
+
+    // Unless the link is created with different options
+    let expires_at = (Utc::now() + TimeDelta::days(1)).trunc_subsecs(6);
+    let create_with = async |expires_at: Option<DateTime<Utc>>, track_conversions: bool| {
+        let response = server
+            .post(Route::Links.as_str())
+            .json(&CreateLinkRequest {
+                target_url: "https://docs.rs/".into(),
+                custom_expires_at: expires_at.map(Expiry::At),
+                track_conversions,
+                id_strategy: Some(IdStrategy::Hash),
+                ..Default::default()
+            })
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        response.json::<Link>()
+    };
+    let expiring = create_with(Some(expires_at), false).await;
+    let tracked = create_with(None, true).await;
+    assert_ne!(expiring.id, first.id);
+    assert_eq!(expiring.expires_at, Some(expires_at));
+    assert_ne!(tracked.id, first.id);
+    assert_ne!(tracked.id, expiring.id);
+    assert!(tracked.track_conversions);
+    assert_eq!(create_with(Some(expires_at), false).await.id, expiring.id);
+    assert_eq!(create_with(None, true).await.id, tracked.id);
+
+    // Custom IDs take precedence
+    let response = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://crates.io/".into(),
+            custom_id: Some("custom".into()),
+            id_strategy: Some(IdStrategy::Words),
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(response.json::<Link>().id, "custom");
+}
+
+async fn test_unlisted_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let listed = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let response = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://docs.rs/".into(),
+            unlisted: true,
+            id_strategy: Some(IdStrategy::Sequential),
+            ..Default::default()
+        })
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let unlisted = response.json::<Link>();
+    assert!(unlisted.is_unlisted);
+    assert!(!listed.is_unlisted);
+    assert_eq!(unlisted.id.len(), UNLISTED_ID_LENGTH);
+
+    // Redirects work as usual
+    let response = server.get(&format!("/{}", unlisted.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://docs.rs/");
+    server.get(&format!("/{}", listed.id)).await;
+
+    // Left out of listings, unless asked for by ID
+    let links = server.get(Route::Links.as_str()).await.json::<Vec<Link>>();
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].id, listed.id);
+
+    let links = server
+        .get(Route::LinksTop.as_str())
+        .await
+        .json::<Vec<TopLink>>();
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].link.id, listed.id);
+
+    let response = server
+        .get(Route::LinksExport.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("format", "ndjson")
+        .await;
+    assert!(!response.text().contains(&unlisted.id));
+    let response = server
+        .get(Route::LinksExport.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("format", "ndjson")
+        .add_query_param("ids", &unlisted.id)
+        .await;
+    assert!(response.text().contains(&unlisted.id));
+
+    let response = server.get(&format!("/links/{}", unlisted.id)).await;
+    response.assert_status(StatusCode::OK);
+
+    // Unlisted links can't have custom IDs
+    let response = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://docs.rs/".into(),
+            custom_id: Some("custom".into()),
+            unlisted: true,
+            ..Default::default()
+        })
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+async fn test_conditional_requests(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let link_route = format!("/links/{}", link.id);
+
+    let response = server.get(&link_route).await;
+    response.assert_status(StatusCode::OK);
+    let etag = response.header(ETAG);
+    let last_modified = response.header(LAST_MODIFIED);
+
+    // Unchanged links aren't sent again
+    let response = server
+        .get(&link_route)
+        .add_header(IF_NONE_MATCH, etag.clone())
+        .await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+    assert_eq!(response.header(ETAG), etag);
+    assert!(response.as_bytes().is_empty());
+
+    let response = server
+        .get(&link_route)
+        .add_header(IF_MODIFIED_SINCE, last_modified.clone())
+        .await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+
+    let list_etag = server.get(Route::Links.as_str()).await.header(ETAG);
+    server
+        .get(Route::Links.as_str())
+        .add_header(IF_NONE_MATCH, list_etag.clone())
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+
+    // Redirects change the ETag
+    server.get(&format!("/{}", link.id)).await;
+    let response = server
+        .get(&link_route)
+        .add_header(IF_NONE_MATCH, etag.clone())
+        .await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.json::<Link>().count_redirects, 1);
+    server
+        .get(Route::Links.as_str())
+        .add_header(IF_NONE_MATCH, list_etag)
+        .await
+        .assert_status(StatusCode::OK);
+}
+
+async fn test_update_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let link_route = format!("/links/{}", link.id);
+    let etag = server.get(&link_route).await.header(ETAG);
+
+    // Requires admin credentials
+    server
+        .patch(&link_route)
+        .json(&UpdateLinkRequest::default())
+        .await
+        .assert_status_unauthorized();
+    server
+        .delete(&link_route)
+        .await
+        .assert_status_unauthorized();
+
+    // Redirects don't prevent edits
+    server.get(&format!("/{}", link.id)).await;
+
+    // Stored with microsecond precision
+    let expires_at = (Utc::now() + chrono::Duration::days(1)).trunc_subsecs(6);
+    let response = server
+        .patch(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_header(IF_MATCH, etag.clone())
+        .json(&UpdateLinkRequest {
+            target_url: Some("https://docs.rs/".into()),
+            expires_at: Some(Some(expires_at.into())),
+            ..Default::default()
+        })
+        .await;
+    response.assert_status_ok();
+    let new_etag = response.header(ETAG);
+    let updated = response.json::<Link>();
+    assert_eq!(updated.target_url, "https://docs.rs/");
+    assert_eq!(updated.expires_at, Some(expires_at));
+    assert_eq!(updated.count_redirects, 1);
+
+    let response = server.get(&format!("/{}", link.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://docs.rs/");
+
+    // Edits based on an outdated version are rejected
+    let response = server
+        .patch(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_header(IF_MATCH, etag.clone())
+        .json(&UpdateLinkRequest {
+            target_url: Some("https://blessed.rs/".into()),
+            ..Default::default()
+        })
+        .await;
+    response.assert_status(StatusCode::PRECONDITION_FAILED);
+    server
+        .delete(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_header(IF_MATCH, etag)
+        .await
+        .assert_status(StatusCode::PRECONDITION_FAILED);
+
+    // Expiration can be removed
+    let response = server
+        .patch(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&serde_json::json!({ "expiresAt": null }))
+        .await;
+    response.assert_status_ok();
+    let updated = response.json::<Link>();
+    assert_eq!(updated.expires_at, None);
+    assert_eq!(updated.target_url, "https://docs.rs/");
+
+    // Invalid changes
+    for update in [
+        UpdateLinkRequest {
+            target_url: Some("/no/host".into()),
+            ..Default::default()
+        },
+        UpdateLinkRequest {
+            expires_at: Some(Some(DateTime::<Utc>::default().into())),
+            ..Default::default()
+        },
+    ] {
+        let response = server
+            .patch(&link_route)
+            .authorization_bearer(ADMIN_TOKEN)
+            .json(&update)
+            .await;
+        assert!(response.status_code().is_client_error());
+    }
+
+    // Deletion
+    let etag = server.get(&link_route).await.header(ETAG);
+    assert_ne!(etag, new_etag);
+    server
+        .delete(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_header(IF_MATCH, etag)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .get(&link_route)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .get(&format!("/{}", link.id))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .delete(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
 #[tokio::test]
-async fn test_list_links() {
-    let (_db_container, server) = get_server().await;
+async fn test_read_replicas() {
+    // A separate database stands in for a healthy replica, alongside an
+    // unavailable one
+    let mut replica_url = None;
+    let (replica_container, replica) = get_server_with_config(|c| {
+        replica_url = Some(c.database.url.clone());
+    })
+    .await;
+    let (_db_container, server) = get_server_with_config(|c| {
+        c.database.replicaurls = vec![
+            replica_url.unwrap(),
+            Url::parse("postgresql://127.0.0.1:1/curto-db").unwrap(),
+        ];
+    })
+    .await;
+
+    // Reads are routed to the healthy replica once it passes a health check,
+    // so links only on the replica can be read
+    let replica_link = assert_create_link(&replica, "https://docs.rs/", None, None).await;
+    let replica_link_route = format!("/links/{}", replica_link.id);
+    let mut status = StatusCode::NOT_FOUND;
+    for _ in 0..40 {
+        status = server.get(&replica_link_route).await.status_code();
+        if status == StatusCode::OK {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    assert_eq!(status, StatusCode::OK);
+
+    // Writes always go to the primary
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    replica
+        .get(&format!("/links/{}", link.id))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // Reads which fail on the replica are retried on the primary, without
+    // tripping the primary's circuit breaker
+    stop_postgres(&replica_container).await;
+    for _ in 0..10 {
+        let response = server.get(&format!("/links/{}", link.id)).await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.json::<Link>().id, link.id);
+
+        server
+            .get(Route::Links.as_str())
+            .await
+            .assert_status(StatusCode::OK);
+    }
+    assert_create_link(&server, "https://lib.rs/", None, None).await;
+}
+
+async fn test_link_history(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let link_route = format!("/links/{}", link.id);
+    let history_route = format!("/links/{}/history", link.id);
+    let rollback_route = format!("/links/{}/rollback", link.id);
+    let history = || async {
+        let response = server
+            .get(&history_route)
+            .authorization_bearer(ADMIN_TOKEN)
+            .await;
+        response.assert_status_ok();
+        response.json::<Vec<LinkRevision>>()
+    };
+
+    // Requires admin credentials
+    server
+        .get(&history_route)
+        .await
+        .assert_status_unauthorized();
+    server
+        .post(&rollback_route)
+        .json(&RollbackRequest { revision: 1 })
+        .await
+        .assert_status_unauthorized();
+
+    // Links start with the revision they were created with
+    let revisions = history().await;
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0].revision, 1);
+    assert_eq!(revisions[0].target_url, "https://crates.io/");
+    assert_eq!(revisions[0].changed_by, Actor::Anonymous);
+
+    // Every change is recorded, but not redirects
+    server.get(&format!("/{}", link.id)).await;
+    for update in [
+        serde_json::json!({ "targetUrl": "https://docs.rs/" }),
+        serde_json::json!({ "expiresAt": "7d", "trackConversions": true }),
+    ] {
+        server
+            .patch(&link_route)
+            .authorization_bearer(ADMIN_TOKEN)
+            .json(&update)
+            .await
+            .assert_status_ok();
+    }
+    let revisions = history().await;
+    assert_eq!(
+        revisions
+            .iter()
+            .map(|r| (
+                r.revision,
+                r.target_url.as_str(),
+                r.expires_at.is_some(),
+                r.track_conversions,
+                r.changed_by
+            ))
+            .collect::<Vec<_>>(),
+        [
+            (1, "https://crates.io/", false, false, Actor::Anonymous),
+            (2, "https://docs.rs/", false, false, Actor::Admin),
+            (3, "https://docs.rs/", true, true, Actor::Admin),
+        ]
+    );
+    assert!(
+        revisions
+            .windows(2)
+            .all(|r| r[0].changed_at <= r[1].changed_at)
+    );
+
+    // Rolling back restores every field, and is recorded as a new revision
+    let etag = server.get(&link_route).await.header(ETAG);
+    let response = server
+        .post(&rollback_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_header(IF_MATCH, etag.clone())
+        .json(&RollbackRequest { revision: 1 })
+        .await;
+    response.assert_status_ok();
+    let rolled_back = response.json::<Link>();
+    assert_eq!(rolled_back.target_url, "https://crates.io/");
+    assert_eq!(rolled_back.expires_at, None);
+    assert!(!rolled_back.track_conversions);
+    assert_eq!(rolled_back.count_redirects, 1);
+
+    let response = server.get(&format!("/{}", link.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+
+    let revisions = history().await;
+    assert_eq!(revisions.len(), 4);
+    assert_eq!(revisions[3].restored_revision, Some(1));
+    assert_eq!(revisions[3].changed_by, Actor::Admin);
+
+    // The ETag from before the rollback is stale
+    server
+        .post(&rollback_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_header(IF_MATCH, etag)
+        .json(&RollbackRequest { revision: 2 })
+        .await
+        .assert_status(StatusCode::PRECONDITION_FAILED);
+
+    // Missing revisions and links
+    server
+        .post(&rollback_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&RollbackRequest { revision: 99 })
+        .await
+        .assert_status_not_found();
+    server
+        .post("/links/nolink/rollback")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&RollbackRequest { revision: 1 })
+        .await
+        .assert_status_not_found();
+    assert_eq!(history().await.len(), 4);
+
+    // History is kept after the link is deleted, but can't be rolled back to
+    server
+        .delete(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(history().await.len(), 4);
+    server
+        .post(&rollback_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&RollbackRequest { revision: 1 })
+        .await
+        .assert_status_not_found();
+}
+
+async fn test_list_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
 
     let target_urls = [
         Url::parse("https://crates.io/").unwrap(),
@@ -178,9 +792,8 @@ async fn test_list_links() {
     assert_eq!(links.len(), target_urls.len() + 1);
 }
 
-#[tokio::test]
-async fn test_redirect_links() {
-    let (_db_container, server) = get_server().await;
+async fn test_redirect_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
 
     let target_url = Url::parse("https://crates.io").unwrap();
     let link = assert_create_link(&server, &target_url, None, None).await;
@@ -200,13 +813,91 @@ async fn test_redirect_links() {
     response.assert_status(StatusCode::NOT_FOUND);
 }
 
-#[tokio::test]
-async fn test_expired_links_not_found() {
-    let (_db_container, server) = get_server().await;
+async fn test_enumeration_throttling(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |c| {
+        c.application.enumerationlimit = 3;
+        c.application.trustproxyheaders = true;
+    })
+    .await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+
+    // Clients guessing IDs are throttled, even for links which exist
+    for id in ["guess1", "guess2", "guess3"] {
+        let response = server
+            .get(&format!("/{id}"))
+            .add_header("x-forwarded-for", "10.0.0.1")
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+    for id in ["guess4", &link.id] {
+        let response = server
+            .get(&format!("/{id}"))
+            .add_header("x-forwarded-for", "10.0.0.1")
+            .await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.header("retry-after"), "60");
+    }
+
+    // Entries added by clients in front of the proxy's are ignored, and
+    // X-Real-IP is preferred
+    let response = server
+        .get("/guess5")
+        .add_header("x-forwarded-for", "10.0.0.9, 10.0.0.1")
+        .await;
+    response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    let response = server
+        .get("/guess6")
+        .add_header("x-forwarded-for", "10.0.0.9")
+        .add_header("x-real-ip", "10.0.0.1")
+        .await;
+    response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    // Other clients are unaffected
+    let response = server
+        .get(&format!("/{}", link.id))
+        .add_header("x-forwarded-for", "10.0.0.1, 10.0.0.2")
+        .await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    // Successful redirects don't count towards the limit
+    for _ in 0..5 {
+        server
+            .get(&format!("/{}", link.id))
+            .add_header("x-forwarded-for", "10.0.0.3")
+            .await
+            .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    }
+}
+
+async fn test_spoofed_proxy_headers(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |c| {
+        c.application.enumerationlimit = 3;
+    })
+    .await;
+
+    // Proxy headers aren't trusted by default, so clients can't avoid being
+    // throttled by changing them
+    for (i, id) in ["guess1", "guess2", "guess3"].iter().enumerate() {
+        let response = server
+            .get(&format!("/{id}"))
+            .add_header("x-forwarded-for", format!("10.0.0.{i}"))
+            .await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+    for header in ["x-forwarded-for", "x-real-ip"] {
+        let response = server.get("/guess4").add_header(header, "10.0.0.9").await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+}
+
+#[allow(clippy::clone_on_copy)]
+async fn test_expired_links_not_found(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
 
     let target_url = Url::parse("https://crates.io").unwrap();
 
-    let beginning = Utc::now().naive_utc() + chrono::Duration::seconds(1);
+    let beginning = Utc::now() + chrono::Duration::seconds(1);
 
     // Create links
     let link_with_expiration =
@@ -248,3 +939,894 @@ async fn test_expired_links_not_found() {
     let link = response.json::<Link>();
     assert_eq!(link.id, link_with_expiration.id);
 }
+
+#[tokio::test]
+async fn test_memory_store() {
+    let (_db, server) = get_backend_server(Backend::Memory, |_| {}).await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    request_create_link(&server, "https://crates.io/", Some(link.id.clone()), None)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = server.get(&format!("/{}", link.id)).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+
+    let links = server.get(Route::Links.as_str()).await.json::<Vec<Link>>();
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].count_redirects, 1);
+
+    let top = server
+        .get(Route::LinksTop.as_str())
+        .await
+        .json::<Vec<TopLink>>();
+    assert_eq!(top[0].window_redirects, 1);
+
+    let link_route = format!("/links/{}", link.id);
+    let response = server
+        .patch(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&UpdateLinkRequest {
+            target_url: Some("https://docs.rs/".into()),
+            ..Default::default()
+        })
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Link>().target_url, "https://docs.rs/");
+
+    server
+        .delete(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .get(&format!("/{}", link.id))
+        .await
+        .assert_status_not_found();
+}
+
+async fn test_top_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let target_url = Url::parse("https://crates.io").unwrap();
+    let least = assert_create_link(&server, &target_url, None, None).await;
+    let most = assert_create_link(&server, &target_url, None, None).await;
+    let never = assert_create_link(&server, &target_url, None, None).await;
+
+    server.get(&format!("/{}", least.id)).await;
+    for _ in 0..3 {
+        server.get(&format!("/{}", most.id)).await;
+    }
+
+    let response = server.get(Route::LinksTop.as_str()).await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.header("content-type"), "application/json");
+
+    let links = response.json::<Vec<TopLink>>();
+    assert_eq!(links.len(), 2);
+    assert_eq!(links[0].link.id, most.id);
+    assert_eq!(links[0].window_redirects, 3);
+    assert_eq!(links[1].link.id, least.id);
+    assert_eq!(links[1].window_redirects, 1);
+    assert!(!links.iter().any(|l| l.link.id == never.id));
+
+    // Limit and window
+    let response = server
+        .get(Route::LinksTop.as_str())
+        .add_query_param("window", "1h")
+        .add_query_param("limit", 1)
+        .await;
+    response.assert_status(StatusCode::OK);
+    let links = response.json::<Vec<TopLink>>();
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].link.id, most.id);
+
+    // Invalid parameters
+    for (key, value) in [("window", "2h"), ("limit", "0"), ("limit", "101")] {
+        let response = server
+            .get(Route::LinksTop.as_str())
+            .add_query_param(key, value)
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
+
+async fn test_export_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let first = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let second = assert_create_link(&server, "https://www.rust-lang.org/", None, None).await;
+    for _ in 0..2 {
+        server.get(&format!("/{}", first.id)).await;
+    }
+    server.get(&format!("/{}", second.id)).await;
+
+    // Requires admin credentials
+    server
+        .get(Route::LinksExport.as_str())
+        .await
+        .assert_status_unauthorized();
+
+    // CSV by default
+    let response = server
+        .get(Route::LinksExport.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "text/csv; charset=utf-8");
+    let csv = response.text();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "link_id,target_url,hour,redirects");
+    assert!(
+        lines.iter().any(
+            |l| l.starts_with(&format!("{},https://crates.io/,", first.id)) && l.ends_with(",2")
+        )
+    );
+
+    // NDJSON, chosen by the `Accept` header or the query
+    for response in [
+        server
+            .get(Route::LinksExport.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .add_header("accept", "application/x-ndjson")
+            .await,
+        server
+            .get(Route::LinksExport.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .add_query_param("format", "ndjson")
+            .await,
+    ] {
+        response.assert_status_ok();
+        assert_eq!(response.header("content-type"), "application/x-ndjson");
+        let rows = response
+            .text()
+            .lines()
+            .map(|l| serde_json::from_str::<HourlyRedirects>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+    }
+
+    // Filtered by link ID and time range
+    let response = server
+        .get(Route::LinksExport.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("format", "ndjson")
+        .add_query_param("ids", &second.id)
+        .await;
+    let rows = response
+        .text()
+        .lines()
+        .map(|l| serde_json::from_str::<HourlyRedirects>(l).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].link_id, second.id);
+    assert_eq!(rows[0].redirects, 1);
+
+    let response = server
+        .get(Route::LinksExport.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("to", "2000-01-01T00:00:00")
+        .await;
+    assert_eq!(response.text().lines().count(), 1);
+
+    // Invalid time range
+    server
+        .get(Route::LinksExport.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("from", "2000-01-01T00:00:00")
+        .add_query_param("to", "1999-01-01T00:00:00")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+async fn test_import_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+    let taken = assert_create_link(&server, "https://crates.io/", Some("taken".into()), None).await;
+
+    let import = |format: &'static str, policy: &'static str, body: &'static str| {
+        server
+            .post(Route::LinksImport.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .add_query_param("format", format)
+            .add_query_param("policy", policy)
+            .text(body)
+    };
+    let yourls = "keyword,url,title,timestamp,ip,clicks\n\
+        taken,https://docs.rs/,Docs,2020-01-02 03:04:05,127.0.0.1,5\n\
+        fresh,https://lib.rs/,Lib,2020-01-02 03:04:05,127.0.0.1,42\n\
+        bad-id,https://blog.rust-lang.org/,Blog,2020-01-02 03:04:05,127.0.0.1,1\n\
+        nourl,,Nothing,2020-01-02 03:04:05,127.0.0.1,0\n";
+
+    // Conflicting IDs are skipped by default
+    let response = import("yourls", "skip", yourls).await;
+    response.assert_status_ok();
+    let report = response.json::<ImportReport>();
+    assert_eq!(
+        report.entries.iter().map(|e| e.outcome).collect::<Vec<_>>(),
+        vec![
+            ImportOutcome::Skipped,
+            ImportOutcome::Imported,
+            ImportOutcome::Renamed,
+            ImportOutcome::Failed,
+        ]
+    );
+    assert_eq!(
+        (
+            report.imported,
+            report.renamed,
+            report.skipped,
+            report.failed
+        ),
+        (1, 1, 1, 1)
+    );
+    assert_eq!(report.entries[1].id.as_deref(), Some("fresh"));
+    assert_ne!(report.entries[2].id.as_deref(), Some("bad-id"));
+
+    // History of imported links is kept
+    let fresh = server.get("/links/fresh").await.json::<Link>();
+    assert_eq!(fresh.target_url, "https://lib.rs/");
+    assert_eq!(fresh.count_redirects, 42);
+    assert_eq!(
+        fresh.created_at,
+        DateTime::<Utc>::from_str("2020-01-02T03:04:05Z").unwrap()
+    );
+    assert!(fresh.is_custom_id);
+    let unchanged = server.get("/links/taken").await.json::<Link>();
+    assert_eq!(unchanged.target_url, taken.target_url);
+
+    // Conflicting IDs can be renamed or overwritten instead
+    let report = import("yourls", "rename", yourls)
+        .await
+        .json::<ImportReport>();
+    assert_eq!(report.entries[0].outcome, ImportOutcome::Renamed);
+    assert_eq!(report.entries[1].outcome, ImportOutcome::Renamed);
+    let renamed = report.entries[0].id.clone().unwrap();
+    assert_eq!(
+        server
+            .get(&format!("/links/{renamed}"))
+            .await
+            .json::<Link>()
+            .target_url,
+        "https://docs.rs/"
+    );
+
+    let report = import("yourls", "overwrite", yourls)
+        .await
+        .json::<ImportReport>();
+    assert_eq!(report.entries[0].outcome, ImportOutcome::Overwritten);
+    assert_eq!(report.overwritten, 2);
+    let overwritten = server.get("/links/taken").await.json::<Link>();
+    assert_eq!(overwritten.target_url, "https://docs.rs/");
+    assert_eq!(overwritten.count_redirects, 5);
+
+    // Bookmarks always get generated IDs
+    let report = import(
+        "bookmarks",
+        "skip",
+        r#"<DL><p><DT><A HREF="https://www.rust-lang.org/" ADD_DATE="1577934245">Rust</A></DL>"#,
+    )
+    .await
+    .json::<ImportReport>();
+    assert_eq!(report.imported, 1);
+    assert_eq!(report.entries[0].original_id, None);
+
+    // Malformed exports are rejected as a whole
+    import("shlink", "skip", "not json")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    import("bitly", "skip", "title,clicks\nRust,1\n")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Importing requires admin credentials
+    server
+        .post(Route::LinksImport.as_str())
+        .add_query_param("format", "yourls")
+        .text(yourls)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+async fn test_backup_restore(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+    let kept = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let deleted =
+        assert_create_link(&server, "https://docs.rs/", Some("rustdocs".into()), None).await;
+    server
+        .get(&format!("/{}", kept.id))
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    let list = || async {
+        server
+            .get(Route::Links.as_str())
+            .await
+            .json::<Vec<Link>>()
+            .into_iter()
+            .map(|l| (l.id, l.target_url, l.count_redirects, l.created_at))
+            .collect::<Vec<_>>()
+    };
+    let links = list().await;
+
+    let response = server
+        .get(Route::Backup.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header(CONTENT_TYPE), "application/x-ndjson");
+    let backup = response.text();
+    let lines: Vec<_> = backup.lines().collect();
+    assert!(lines[0].contains(r#""type":"header""#));
+    assert!(lines.last().unwrap().contains(r#""type":"end""#));
+
+    // Changes since the backup are undone by restoring it
+    server
+        .delete(&format!("/links/{}", deleted.id))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    let created = assert_create_link(&server, "https://lib.rs/", None, None).await;
+
+    let response = server
+        .post(Route::Backup.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .text(backup.clone())
+        .await;
+    response.assert_status_ok();
+    let summary = response.json::<RestoreSummary>();
+    assert_eq!(
+        (summary.links, summary.redirects, summary.revisions),
+        (2, 1, 2)
+    );
+    assert_eq!(list().await, links);
+    let response = server
+        .get(&format!("/links/{}/history", deleted.id))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Vec<LinkRevision>>().len(), 1);
+    server
+        .get(&format!("/links/{}", created.id))
+        .await
+        .assert_status_not_found();
+
+    // Truncated backups are rejected without changing anything
+    let truncated = lines[..lines.len() - 1].join("\n");
+    server
+        .post(Route::Backup.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .text(truncated)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(list().await, links);
+
+    // Backups from newer versions are rejected
+    let newer = backup.replacen(
+        &format!(r#""schemaVersion":{}"#, schema_version()),
+        r#""schemaVersion":99991231235959"#,
+        1,
+    );
+    server
+        .post(Route::Backup.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .text(newer)
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Backing up and restoring requires admin credentials
+    server
+        .get(Route::Backup.as_str())
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .post(Route::Backup.as_str())
+        .text(backup)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+async fn test_redirect_map(backend: Backend) {
+    let path = std::env::temp_dir().join(format!(
+        "curto-{}-{backend:?}-redirects",
+        std::process::id()
+    ));
+    let (_db, server) = get_backend_server(backend, |c| {
+        c.redirectmap.path = Some(path.clone());
+        c.redirectmap.format = RedirectMapFormat::Netlify;
+        c.redirectmap.intervalseconds = 1;
+    })
+    .await;
+
+    let listed = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let unlisted = server
+        .post(Route::Links.as_str())
+        .json(&CreateLinkRequest {
+            target_url: "https://docs.rs/".into(),
+            unlisted: true,
+            ..Default::default()
+        })
+        .await
+        .json::<Link>();
+    let expires_at = Utc::now() + chrono::Duration::seconds(1);
+    let expiring = assert_create_link(&server, "https://lib.rs/", None, Some(expires_at)).await;
+
+    let redirect_map = |format: &'static str| {
+        server
+            .get(Route::LinksRedirectMap.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .add_query_param("format", format)
+    };
+    let response = redirect_map("netlify").await;
+    response.assert_status_ok();
+    let map = response.text();
+    for link in [&listed, &unlisted, &expiring] {
+        assert!(map.contains(&format!("/{} {} 302\n", link.id, link.target_url)));
+    }
+    let response = redirect_map("nginx").await;
+    response.assert_status_ok();
+    assert!(
+        response
+            .text()
+            .contains(&format!("    /{} \"https://crates.io/\";\n", listed.id))
+    );
+
+    // Expired links are left out, including from the map written on a
+    // schedule, which is regenerated once they expire
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let map = redirect_map("netlify").await.text();
+    assert!(map.contains(&format!("/{} ", listed.id)));
+    assert!(!map.contains(&format!("/{} ", expiring.id)));
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        written.lines().skip(1).collect::<Vec<_>>(),
+        map.lines().skip(1).collect::<Vec<_>>()
+    );
+
+    // HTML pages can't be returned as a single file
+    redirect_map("html")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Redirect maps require admin credentials
+    server
+        .get(Route::LinksRedirectMap.as_str())
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+async fn test_purge_expired_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |c| {
+        c.purge.mode = PurgeMode::Archive;
+        c.purge.graceperiodseconds = 0;
+        c.purge.batchsize = 1;
+        c.purge.intervalseconds = 1;
+    })
+    .await;
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(1);
+    let expiring = assert_create_link(&server, "https://crates.io/", None, Some(expires_at)).await;
+    let expiring_too =
+        assert_create_link(&server, "https://docs.rs/", None, Some(expires_at)).await;
+    let kept = assert_create_link(&server, "https://lib.rs/", None, None).await;
+    server.get(&format!("/{}", expiring.id)).await;
+
+    // Expired links are only filtered out until they are purged, after which
+    // they are gone from backups too
+    let backed_up_links = || async {
+        server
+            .get(Route::Backup.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .await
+            .text()
+            .lines()
+            .filter(|l| l.contains(r#""type":"link""#))
+            .count()
+    };
+    assert_eq!(backed_up_links().await, 3);
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    assert_eq!(backed_up_links().await, 1);
+
+    server
+        .get(&format!("/links/{}", expiring_too.id))
+        .await
+        .assert_status_not_found();
+    server
+        .get(&format!("/links/{}", kept.id))
+        .await
+        .assert_status_ok();
+
+    // The history of purged links is kept
+    let response = server
+        .get(&format!("/links/{}/history", expiring_too.id))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Vec<LinkRevision>>().len(), 1);
+}
+
+async fn test_link_events(backend: Backend) {
+    let (_db, server) = get_backend_http_server(backend, |_| {}).await;
+
+    // Link must exist
+    let response = server.get("/links/noid/events").await;
+    response.assert_status(StatusCode::NOT_FOUND);
+
+    // Instance-wide events require admin credentials
+    let response = server.get(Route::Events.as_str()).await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+
+    // Redirects are streamed to subscribers of the link's events
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let address = server.server_address().unwrap();
+    let client = httpc_test::new_client(address.as_str()).unwrap();
+    let mut events = client
+        .reqwest_client()
+        .get(address.join(&format!("/links/{}/events", link.id)).unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(events.status(), StatusCode::OK);
+
+    server
+        .get(&format!("/{}", link.id))
+        .add_header(USER_AGENT, "curl/8.7.1")
+        .add_header(REFERER, "https://news.ycombinator.com/item?id=1")
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    // Skip keep-alive comments until the event arrives
+    let mut received = String::new();
+    let data = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let chunk = events.chunk().await.unwrap().expect("event stream ended");
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+            if let Some(line) = received.lines().find_map(|l| l.strip_prefix("data:")) {
+                break line.trim().to_string();
+            }
+        }
+    })
+    .await
+    .expect("no redirect event received");
+
+    let event: RedirectEvent = serde_json::from_str(&data).unwrap();
+    assert_eq!(event.link_id, link.id);
+    assert!((Utc::now() - event.timestamp).abs() < TimeDelta::seconds(5));
+    assert_eq!(event.user_agent, UserAgentClass::Cli);
+    assert_eq!(
+        event.referrer_domain.as_deref(),
+        Some("news.ycombinator.com")
+    );
+}
+
+async fn test_scheduled_changes(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let link_route = format!("/links/{}", link.id);
+    let schedule_route = format!("/links/{}/schedule", link.id);
+    let schedule = |target_url: &str, scheduled_at: DateTime<Utc>| {
+        server
+            .post(&schedule_route)
+            .authorization_bearer(ADMIN_TOKEN)
+            .json(&ScheduleChangeRequest {
+                target_url: target_url.to_string(),
+                scheduled_at: Expiry::from(scheduled_at),
+            })
+    };
+    let scheduled_changes = || async {
+        let response = server
+            .get(&schedule_route)
+            .authorization_bearer(ADMIN_TOKEN)
+            .await;
+        response.assert_status_ok();
+        response.json::<Vec<ScheduledChange>>()
+    };
+
+    // Requires admin credentials
+    server
+        .get(&schedule_route)
+        .await
+        .assert_status_unauthorized();
+    server
+        .post(&schedule_route)
+        .json(&ScheduleChangeRequest {
+            target_url: "https://docs.rs/".to_string(),
+            scheduled_at: Expiry::from(Utc::now() + TimeDelta::days(1)),
+        })
+        .await
+        .assert_status_unauthorized();
+
+    // Changes can't be scheduled in the past, or for missing links
+    schedule("https://docs.rs/", Utc::now() - TimeDelta::minutes(1))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    server
+        .post("/links/noid/schedule")
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&ScheduleChangeRequest {
+            target_url: "https://docs.rs/".to_string(),
+            scheduled_at: Expiry::from(Utc::now() + TimeDelta::days(1)),
+        })
+        .await
+        .assert_status_not_found();
+
+    // Scheduled changes are listed, and the next one is visible on the link
+    let later = schedule("https://lib.rs/", Utc::now() + TimeDelta::days(2)).await;
+    later.assert_status(StatusCode::CREATED);
+    let later = later.json::<ScheduledChange>();
+    let sooner = schedule("https://docs.rs/", Utc::now() + TimeDelta::days(1))
+        .await
+        .json::<ScheduledChange>();
+    assert_eq!(scheduled_changes().await, [sooner.clone(), later.clone()]);
+    let response = server.get(&link_route).await.json::<Link>();
+    assert_eq!(response.next_change_at, Some(sooner.scheduled_at));
+    assert_eq!(response.target_url, "https://crates.io/");
+
+    // Changes can be cancelled before they are made, but only once
+    let cancel_route = format!("{schedule_route}/{}", sooner.id);
+    server
+        .delete(&cancel_route)
+        .await
+        .assert_status_unauthorized();
+    server
+        .delete(&cancel_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .delete(&cancel_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status_not_found();
+    assert_eq!(scheduled_changes().await, std::slice::from_ref(&later));
+    let response = server.get(&link_route).await.json::<Link>();
+    assert_eq!(response.next_change_at, Some(later.scheduled_at));
+
+    // Redirects are only cached until the change, and switch at its time
+    let redirect_route = format!("/{}", link.id);
+    let response = server.get(&redirect_route).await;
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+    schedule("https://docs.rs/", Utc::now() + TimeDelta::seconds(1))
+        .await
+        .assert_status(StatusCode::CREATED);
+    let response = server.get(&redirect_route).await;
+    assert_eq!(response.header(LOCATION), "https://crates.io/");
+    let cache_control = response.header("cache-control");
+    let max_age = cache_control
+        .to_str()
+        .unwrap()
+        .split(',')
+        .find_map(|d| d.trim().strip_prefix("max-age="))
+        .map(|s| s.parse::<u64>().unwrap());
+    assert!(max_age.is_none_or(|s| s <= 1), "{cache_control:?}");
+
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let response = server.get(&redirect_route).await;
+    assert_eq!(response.header(LOCATION), "https://docs.rs/");
+
+    // Made changes are no longer scheduled, and are recorded in the history
+    assert_eq!(scheduled_changes().await, [later]);
+    let revisions = server
+        .get(&format!("{link_route}/history"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json::<Vec<LinkRevision>>();
+    let revision = revisions.last().unwrap();
+    assert_eq!(revision.target_url, "https://docs.rs/");
+    assert_eq!(revision.changed_by, Actor::Schedule);
+}
+
+async fn test_immutable_links(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |_| {}).await;
+
+    let link = assert_create_link(&server, "https://crates.io/", None, None).await;
+    let link_route = format!("/links/{}", link.id);
+    let patch = |update: serde_json::Value| {
+        server
+            .patch(&link_route)
+            .authorization_bearer(ADMIN_TOKEN)
+            .json(&update)
+    };
+
+    // Links which expire can't be made immutable
+    patch(serde_json::json!({ "expiresAt": "7d", "immutable": true }))
+        .await
+        .assert_status_bad_request();
+    let mutable_backup = server
+        .get(Route::Backup.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .text();
+    let response = patch(serde_json::json!({ "immutable": true })).await;
+    response.assert_status_ok();
+    assert!(response.json::<Link>().is_immutable);
+
+    // Restoring a backup can't make the link mutable again
+    server
+        .post(Route::Backup.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .text(mutable_backup)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    // The target URL and expiration time can't be changed, and the link can't
+    // be deleted or made mutable again
+    for update in [
+        serde_json::json!({ "targetUrl": "https://docs.rs/" }),
+        serde_json::json!({ "expiresAt": "7d" }),
+        serde_json::json!({ "immutable": false }),
+    ] {
+        patch(update).await.assert_status(StatusCode::CONFLICT);
+    }
+    server
+        .delete(&link_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+    server
+        .post(&format!("{link_route}/schedule"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&ScheduleChangeRequest {
+            target_url: "https://docs.rs/".to_string(),
+            scheduled_at: Expiry::from(Utc::now() + TimeDelta::days(1)),
+        })
+        .await
+        .assert_status(StatusCode::CONFLICT);
+    server
+        .post(&format!("{link_route}/rollback"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&RollbackRequest { revision: 1 })
+        .await
+        .assert_status_ok();
+
+    // Other changes are still allowed
+    let response = patch(serde_json::json!({ "trackConversions": true })).await;
+    response.assert_status_ok();
+    let response = response.json::<Link>();
+    assert_eq!(response.target_url, "https://crates.io/");
+    assert!(response.is_immutable && response.track_conversions);
+
+    // Making a link immutable is recorded in its history
+    let revisions = server
+        .get(&format!("{link_route}/history"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json::<Vec<LinkRevision>>();
+    assert_eq!(revisions.len(), 4);
+    assert!(
+        revisions
+            .iter()
+            .all(|r| r.target_url == "https://crates.io/")
+    );
+    assert!(!revisions[0].is_immutable);
+    assert!(revisions[1..].iter().all(|r| r.is_immutable));
+    assert_eq!(revisions[1].changed_by, Actor::Admin);
+}
+
+async fn test_legal_hold(backend: Backend) {
+    let (_db, server) = get_backend_server(backend, |c| {
+        c.purge.mode = PurgeMode::Delete;
+        c.purge.graceperiodseconds = 0;
+        c.purge.intervalseconds = 1;
+    })
+    .await;
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(1);
+    let held = assert_create_link(&server, "https://crates.io/", None, Some(expires_at)).await;
+    let purged = assert_create_link(&server, "https://docs.rs/", None, Some(expires_at)).await;
+    let held_route = format!("/links/{}", held.id);
+    let backup = || async {
+        server
+            .get(Route::Backup.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .await
+            .text()
+    };
+    let restore = |backup: String| {
+        server
+            .post(Route::Backup.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .text(backup)
+    };
+    let unheld_backup = backup().await;
+    server
+        .get(&format!("/{}", held.id))
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    let public_etag = server.get(&held_route).await.header(ETAG);
+    let public_list_etag = server.get("/links").await.header(ETAG);
+
+    let response = server
+        .patch(&held_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&serde_json::json!({ "legalHold": true }))
+        .await;
+    response.assert_status_ok();
+    assert!(response.json::<Link>().legal_hold);
+
+    // Only admins can see whether links are on legal hold
+    let response = server.get(&held_route).await;
+    response.assert_status_ok();
+    assert!(!response.text().contains("legalHold"));
+    assert!(!server.get("/links").await.text().contains("legalHold"));
+    let response = server.get(Route::LinksTop.as_str()).await;
+    assert!(response.text().contains(&held.id));
+    assert!(!response.text().contains("legalHold"));
+
+    // Nor through the ETags of links
+    assert_eq!(server.get(&held_route).await.header(ETAG), public_etag);
+    assert_eq!(server.get("/links").await.header(ETAG), public_list_etag);
+    let response = server
+        .get(&held_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    assert!(response.json::<Link>().legal_hold);
+    let response = server.get("/links").authorization_bearer(ADMIN_TOKEN).await;
+    assert!(response.json::<Vec<Link>>().iter().any(|l| l.legal_hold));
+    let response = server
+        .get(Route::LinksTop.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    assert!(
+        response
+            .json::<Vec<TopLink>>()
+            .iter()
+            .any(|l| l.link.legal_hold)
+    );
+
+    // Links on legal hold can't be deleted, and aren't purged once expired
+    server
+        .delete(&held_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    // Nor can restoring a backup remove them or lift their hold
+    let held_backup = backup().await;
+    let without_held = held_backup.replace(&format!(r#""id":"{}""#, held.id), r#""id":"unheld""#);
+    for backup in [unheld_backup, without_held] {
+        let response = restore(backup).await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert!(response.text().contains("legal hold"));
+    }
+    restore(held_backup).await.assert_status_ok();
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    server.get(&held_route).await.assert_status_ok();
+    server
+        .get(&format!("/links/{}", purged.id))
+        .await
+        .assert_status_not_found();
+
+    // Once the hold is lifted, the link can be deleted
+    server
+        .patch(&held_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .json(&serde_json::json!({ "legalHold": false }))
+        .await
+        .assert_status_ok();
+    server
+        .delete(&held_route)
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    // Placing and lifting the hold are recorded in the link's history
+    let revisions = server
+        .get(&format!("{held_route}/history"))
+        .authorization_bearer(ADMIN_TOKEN)
+        .await
+        .json::<Vec<LinkRevision>>();
+    assert_eq!(
+        revisions
+            .iter()
+            .map(|r| (r.legal_hold, r.changed_by))
+            .collect::<Vec<_>>(),
+        vec![
+            (false, Actor::Anonymous),
+            (true, Actor::Admin),
+            (false, Actor::Admin)
+        ]
+    );
+}
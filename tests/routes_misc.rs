@@ -1,9 +1,10 @@
-use axum::http::header::CONTENT_TYPE;
-use curto::routes::Route;
+use axum::http::{StatusCode, header::CONTENT_TYPE};
+use curto::{database::{InstanceStats, Link}, routes::Route};
 use pretty_assertions::assert_eq;
+use serde_json::json;
 
 mod common;
-use common::get_server;
+use common::{ADMIN_TOKEN, get_server, get_unready_server};
 
 #[tokio::test]
 async fn test_routes_misc() {
@@ -16,6 +17,11 @@ async fn test_routes_misc() {
     response.assert_text_contains("OK");
     assert_eq!(response.header(CONTENT_TYPE), "text/plain; charset=utf-8");
 
+    // READINESS
+    let response = server.get(Route::Ready.as_str()).await;
+    response.assert_status_ok();
+    response.assert_text_contains("OK");
+
     // 404
     let response = server.get("/not/a/route").await;
     response.assert_status_not_found();
@@ -36,3 +42,89 @@ async fn test_routes_misc() {
             > 1000
     );
 }
+
+#[tokio::test]
+async fn test_not_ready() {
+    let server = get_unready_server();
+
+    // Health checks and metrics are available straight away
+    server.get(Route::Health.as_str()).await.assert_status_ok();
+    server.get(Route::Metrics.as_str()).await.assert_status_ok();
+
+    let response = server.get(Route::Ready.as_str()).await;
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    response.assert_text_contains("Not ready");
+
+    // Requests which need the database are refused until it is usable
+    for route in [Route::Links.as_str(), "/abcde"] {
+        let response = server.get(route).await;
+        response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.header("retry-after"), "5");
+    }
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let (_db_container, server) = get_server().await;
+
+    // Requires admin credentials
+    let response = server.get(Route::Stats.as_str()).await;
+    response.assert_status_unauthorized();
+    let response = server
+        .get(Route::Stats.as_str())
+        .authorization_bearer("not-the-token")
+        .await;
+    response.assert_status_unauthorized();
+
+    // Empty instance
+    let response = server
+        .get(Route::Stats.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .await;
+    response.assert_status_ok();
+    let stats = response.json::<InstanceStats>();
+    assert_eq!(stats.total_links, 0);
+    assert_eq!(stats.custom_id_ratio, 0.0);
+    assert_eq!(stats.links_created_per_day.len(), 30);
+
+    // Populated instance
+    for custom_id in [None, None, None, Some("custom")] {
+        server
+            .post(Route::Links.as_str())
+            .json(&json!({ "targetUrl": "https://crates.io", "customId": custom_id }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    let links = server.get(Route::Links.as_str()).await.json::<Vec<Link>>();
+    server.get(&format!("/{}", links[0].id)).await;
+    server.get(&format!("/{}", links[1].id)).await;
+
+    let response = server
+        .get(Route::Stats.as_str())
+        .authorization_bearer(ADMIN_TOKEN)
+        .add_query_param("days", 7)
+        .await;
+    response.assert_status_ok();
+    let stats = response.json::<InstanceStats>();
+    assert_eq!(stats.total_links, 4);
+    assert_eq!(stats.active_links, 4);
+    assert_eq!(stats.expired_links, 0);
+    assert_eq!(stats.custom_id_links, 1);
+    assert_eq!(stats.generated_id_links, 3);
+    assert_eq!(stats.custom_id_ratio, 0.25);
+    assert_eq!(stats.total_redirects, 2);
+    assert_eq!(stats.redirects_last_day, 2);
+    assert_eq!(stats.redirects_last_week, 2);
+    assert_eq!(stats.links_created_per_day.len(), 7);
+    assert_eq!(stats.links_created_per_day.last().unwrap().count, 4);
+
+    // Invalid parameters
+    for days in ["0", "367", "many"] {
+        server
+            .get(Route::Stats.as_str())
+            .authorization_bearer(ADMIN_TOKEN)
+            .add_query_param("days", days)
+            .await
+            .assert_status_bad_request();
+    }
+}